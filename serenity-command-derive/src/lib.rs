@@ -18,6 +18,12 @@ struct CommandOption {
     getter: proc_macro2::TokenStream,
     kind: proc_macro2::TokenStream,
     description: String,
+    // type implementing CommandChoice, for options with a fixed set of choices
+    choices: Option<Type>,
+    // calls setting the option's min/max values, length or channel types
+    constraints: proc_macro2::TokenStream,
+    // number of options a Vec field is spread over
+    repeat: Option<usize>,
 }
 
 fn get_attr_value(attrs: &[Attr], name: &str) -> syn::Result<Option<String>> {
@@ -43,6 +49,8 @@ fn get_attr_list(attrs: &[Attribute]) -> Option<Vec<Attr>> {
                         let key = ident.to_string();
                         let value = match nv.lit {
                             Lit::Str(s) => s.value(),
+                            Lit::Int(i) => i.base10_digits().to_string(),
+                            Lit::Float(f) => f.base10_digits().to_string(),
                             _ => String::new(),
                         };
                         Some(Attr { key, value })
@@ -115,6 +123,201 @@ fn analyze_message_command_fields(
     )
 }
 
+// User commands get the targeted user as a User, its id, or its member data in guilds
+fn user_command_value(span: Span, ty: &Type) -> syn::Result<proc_macro2::TokenStream> {
+    let parts = match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .iter()
+            .map(|s| s.ident.to_string())
+            .collect::<Vec<_>>()
+            .join("::"),
+        _ => String::new(),
+    };
+    let value = match parts.as_str() {
+        "UserId" | "serenity::model::id::UserId" => quote!(user_id),
+        "User" | "serenity::model::user::User" => quote!(opts
+            .resolved
+            .users
+            .get(&user_id)
+            .cloned()
+            .expect("User is not resolved")),
+        "PartialMember" | "serenity::model::guild::PartialMember" => quote!(opts
+            .resolved
+            .members
+            .get(&user_id)
+            .cloned()
+            .expect("Member is not resolved")),
+        _ => {
+            return Err(syn::Error::new(
+                span,
+                "Command on users must have one field of type User, UserId or PartialMember",
+            ))
+        }
+    };
+    Ok(value)
+}
+
+fn analyze_user_command_fields(
+    ident: &syn::Ident,
+    fields: Fields,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let setter = match fields {
+        Fields::Named(FieldsNamed { named, .. }) if named.len() == 1 => {
+            let f = named.first().unwrap();
+            let value = user_command_value(f.span(), &f.ty)?;
+            let fident = f.ident.as_ref().unwrap();
+            quote!(#ident {
+                #fident: #value,
+            })
+        }
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) if unnamed.len() == 1 => {
+            let f = unnamed.first().unwrap();
+            let value = user_command_value(f.span(), &f.ty)?;
+            quote!(#ident(#value))
+        }
+        _ => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "Command on users must have one field of type User, UserId or PartialMember",
+            ))
+        }
+    };
+    Ok(quote!({
+        let user_id = opts
+            .target_id
+            .expect("No user received for user command")
+            .to_user_id();
+        #setter
+    }))
+}
+
+fn parse_attr<T: std::str::FromStr>(
+    span: Span,
+    attrs: &[Attr],
+    name: &str,
+    expected: &str,
+) -> syn::Result<Option<T>> {
+    get_attr_value(attrs, name)?
+        .map(|v| {
+            v.parse()
+                .map_err(|_| syn::Error::new(span, format!("`{name}` must be {expected}")))
+        })
+        .transpose()
+}
+
+fn channel_type(span: Span, name: &str) -> syn::Result<proc_macro2::TokenStream> {
+    let variant = match name {
+        "text" => "Text",
+        "voice" => "Voice",
+        "category" => "Category",
+        "news" | "announcement" => "News",
+        "news_thread" | "announcement_thread" => "NewsThread",
+        "public_thread" => "PublicThread",
+        "private_thread" => "PrivateThread",
+        "stage" => "Stage",
+        "directory" => "Directory",
+        "forum" => "Forum",
+        _ => {
+            return Err(syn::Error::new(
+                span,
+                format!("Unknown channel type `{name}`"),
+            ))
+        }
+    };
+    let variant = Ident::new(variant, Span::call_site());
+    Ok(quote!(serenity::model::channel::ChannelType::#variant))
+}
+
+// Constraints declared with `#[cmd(min = 1, max = 31)]` on number options,
+// `#[cmd(min_len = 2, max_len = 100)]` on strings and `#[cmd(channel_types = "text,forum")]`
+// on channels
+fn option_constraints(
+    span: Span,
+    parts: &str,
+    is_choice: bool,
+    attrs: &[Attr],
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut constraints = Vec::new();
+    let has = |name: &str| attrs.iter().any(|a| a.key == name);
+    match parts {
+        "i64" => {
+            // serenity only takes unsigned integer bounds, negative ones are sent as numbers
+            for (name, int_setter, number_setter) in [
+                ("min", quote!(min_int_value), quote!(min_number_value)),
+                ("max", quote!(max_int_value), quote!(max_number_value)),
+            ] {
+                if let Some(bound) = parse_attr::<i64>(span, attrs, name, "an integer")? {
+                    constraints.push(match u64::try_from(bound) {
+                        Ok(bound) => quote!(.#int_setter(#bound)),
+                        Err(_) => {
+                            let bound = bound as f64;
+                            quote!(.#number_setter(#bound))
+                        }
+                    });
+                }
+            }
+        }
+        "u64" | "usize" => {
+            if let Some(min) = parse_attr::<u64>(span, attrs, "min", "a positive integer")? {
+                constraints.push(quote!(.min_int_value(#min)));
+            }
+            if let Some(max) = parse_attr::<u64>(span, attrs, "max", "a positive integer")? {
+                constraints.push(quote!(.max_int_value(#max)));
+            }
+        }
+        "f64" => {
+            if let Some(min) = parse_attr::<f64>(span, attrs, "min", "a number")? {
+                constraints.push(quote!(.min_number_value(#min)));
+            }
+            if let Some(max) = parse_attr::<f64>(span, attrs, "max", "a number")? {
+                constraints.push(quote!(.max_number_value(#max)));
+            }
+        }
+        _ if has("min") || has("max") => {
+            return Err(syn::Error::new(
+                span,
+                "`min` and `max` only apply to number options",
+            ))
+        }
+        _ => (),
+    }
+    if matches!(parts, "String" | "std::str::String") && !is_choice {
+        if let Some(min) = parse_attr::<u16>(span, attrs, "min_len", "an integer up to 6000")? {
+            constraints.push(quote!(.min_length(#min)));
+        }
+        if let Some(max) = parse_attr::<u16>(span, attrs, "max_len", "an integer up to 6000")? {
+            constraints.push(quote!(.max_length(#max)));
+        }
+    } else if has("min_len") || has("max_len") {
+        return Err(syn::Error::new(
+            span,
+            "`min_len` and `max_len` only apply to string options",
+        ));
+    }
+    if let Some(types) = get_attr_value(attrs, "channel_types")? {
+        if !matches!(
+            parts,
+            "ChannelId"
+                | "serenity::model::id::ChannelId"
+                | "PartialChannel"
+                | "serenity::model::channel::PartialChannel"
+        ) {
+            return Err(syn::Error::new(
+                span,
+                "`channel_types` only applies to channel options",
+            ));
+        }
+        let types = types
+            .split(',')
+            .map(|t| channel_type(span, t.trim()))
+            .collect::<syn::Result<Vec<_>>>()?;
+        constraints.push(quote!(.channel_types(vec![#(#types),*])));
+    }
+    Ok(quote!(#(#constraints)*))
+}
+
 fn analyze_field(
     ident: &syn::Ident,
     mut ty: &Type,
@@ -127,10 +330,17 @@ fn analyze_field(
     let opt_value = quote!(serenity::model::application::CommandDataOptionValue);
     let mut required = true;
     let autocomplete = get_attr_value(&attrs, "autocomplete")?.is_some();
+    // the type derives CommandChoice
+    let is_choice = get_attr_value(&attrs, "choice")?.is_some();
+    let mut choices = None;
+    // Vec fields become `count` optional options, numbered from 1, e.g. tag1, tag2...
+    let repeat = parse_attr::<usize>(ident.span(), &attrs, "count", "a number of options")?;
+    let mut is_vec = false;
     if let Type::Path(path) = ty {
         let segs = &path.path.segments;
-        if segs.len() == 1 && segs[0].ident == "Option" {
+        if segs.len() == 1 && (segs[0].ident == "Option" || segs[0].ident == "Vec") {
             required = false;
+            is_vec = segs[0].ident == "Vec";
             if let PathArguments::AngleBracketed(args) = &segs[0].arguments {
                 ty = match &args.args[0] {
                     GenericArgument::Type(ty) => ty,
@@ -139,6 +349,27 @@ fn analyze_field(
             }
         }
     }
+    match (is_vec, repeat) {
+        (true, None) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "Vec options need a number of options, e.g. `#[cmd(count = 5)]`",
+            ))
+        }
+        (true, Some(count)) if !(1..=25).contains(&count) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "`count` must be between 1 and 25",
+            ))
+        }
+        (false, Some(_)) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "`count` only applies to Vec options",
+            ))
+        }
+        _ => (),
+    }
     match ty {
         Type::Path(path) => {
             let segs = &path.path.segments;
@@ -149,6 +380,13 @@ fn analyze_field(
                 .join("::");
             let parts_str = parts.as_str();
             let (matcher, kind) = match parts_str {
+                _ if is_choice => {
+                    choices = Some(ty.clone());
+                    (
+                        quote!(#opt_value::String(v)),
+                        quote!(serenity::model::application::CommandOptionType::String),
+                    )
+                }
                 "String" | "std::str::String" => (
                     quote!(#opt_value::String(v)),
                     quote!(serenity::model::application::CommandOptionType::String),
@@ -177,6 +415,22 @@ fn analyze_field(
                     quote!(#opt_value::User(v)),
                     quote!(serenity::model::application::CommandOptionType::User),
                 ),
+                "Attachment" | "serenity::model::channel::Attachment" => (
+                    quote!(#opt_value::Attachment(v)),
+                    quote!(serenity::model::application::CommandOptionType::Attachment),
+                ),
+                "ChannelId" | "serenity::model::id::ChannelId" => (
+                    quote!(#opt_value::Channel(v)),
+                    quote!(serenity::model::application::CommandOptionType::Channel),
+                ),
+                "PartialChannel" | "serenity::model::channel::PartialChannel" => (
+                    quote!(#opt_value::Channel(v)),
+                    quote!(serenity::model::application::CommandOptionType::Channel),
+                ),
+                "Mentionable" | "serenity_command::Mentionable" => (
+                    quote!(#opt_value::Mentionable(v)),
+                    quote!(serenity::model::application::CommandOptionType::Mentionable),
+                ),
                 other => {
                     return Err(syn::Error::new(
                         ident.span(),
@@ -184,21 +438,59 @@ fn analyze_field(
                     ))
                 }
             };
+            let constraints =
+                option_constraints(ident.span(), parts_str, choices.is_some(), &attrs)?;
             let cast = if let "i64" | "u64" | "usize" | "isize" | "u32" | "i32" = parts_str {
                 let id = Ident::new(parts_str, Span::call_site());
                 quote!( as #id )
             } else {
                 quote!()
             };
-            let getter = if required {
+            // attachment and channel options only hold an id, the attachment or channel itself
+            // is in the resolved data
+            let value = match parts_str {
+                "Attachment" | "serenity::model::channel::Attachment" => quote!(opts
+                    .resolved
+                    .attachments
+                    .get(v)
+                    .cloned()
+                    .expect("Attachment is not resolved")),
+                "PartialChannel" | "serenity::model::channel::PartialChannel" => quote!(opts
+                    .resolved
+                    .channels
+                    .get(v)
+                    .cloned()
+                    .expect("Channel is not resolved")),
+                // mentionable options don't say whether they hold a user or a role
+                "Mentionable" | "serenity_command::Mentionable" => {
+                    quote!(serenity_command::Mentionable::resolve(*v, &opts.resolved)
+                        .expect("Mentionable is not resolved"))
+                }
+                _ if choices.is_some() => quote!(
+                    <#ty as serenity_command::CommandChoice>::from_value(v)
+                        .expect("Invalid choice")
+                ),
+                _ => quote!(v.clone() #cast),
+            };
+            let getter = if let Some(count) = repeat {
+                quote!((1..=#count)
+                    .filter_map(|i| {
+                        let name = format!("{}{}", #name, i);
+                        match opts.options.iter().find(|o| o.name == name).map(|o| &o.value) {
+                            Some(#matcher) => Some(#value),
+                            _ => None,
+                        }
+                    })
+                    .collect())
+            } else if required {
                 quote!(if let Some(#matcher) = #find_opt {
-                    v.clone() #cast
+                    #value
                 } else {
                     panic!("Value is required")
                 })
             } else {
                 quote!(if let Some(#matcher) = #find_opt {
-                    Some(v.clone() #cast)
+                    Some(#value)
                 } else {
                     None
                 })
@@ -210,6 +502,9 @@ fn analyze_field(
                 getter,
                 kind,
                 description: desc,
+                choices,
+                constraints,
+                repeat,
             })
         }
         _ => Err(syn::Error::new(ident.span(), "Unsupported type")),
@@ -223,13 +518,32 @@ impl CommandOption {
         let kind = &self.kind;
         let required = self.required;
         let autocomplete = self.autocomplete;
-        quote!(builder = builder.add_option({
-            let mut opt = serenity::builder::CreateCommandOption::new(#kind, #name, #desc)
+        let constraints = &self.constraints;
+        let add_choices = self.choices.as_ref().map(|ty| {
+            quote!(for (choice, value) in <#ty as serenity_command::CommandChoice>::CHOICES {
+                opt = opt.add_string_choice(*choice, *value);
+            })
+        });
+        let create = quote!({
+            let mut opt = serenity::builder::CreateCommandOption::new(#kind, opt_name, #desc)
                 .required(#required)
-                .set_autocomplete(#autocomplete);
+                .set_autocomplete(#autocomplete)
+                #constraints;
+            #add_choices
             opt = (&extras)(#name, opt);
             opt
-        });)
+        });
+        // setup_options gets the name of the field for each of the numbered options
+        match self.repeat {
+            Some(count) => quote!(for i in 1..=#count {
+                let opt_name = format!("{}{}", #name, i);
+                builder = builder.add_option(#create);
+            }),
+            None => quote!(builder = builder.add_option({
+                let opt_name = #name;
+                #create
+            });),
+        }
     }
 }
 
@@ -261,6 +575,8 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = attr_name.unwrap_or_else(|| ident.to_string());
     let desc = get_attr_value(&attrs, "desc")?.unwrap_or_else(|| ident.to_string());
     let message = get_attr_value(&attrs, "message")?.is_some();
+    let user = get_attr_value(&attrs, "user")?.is_some();
+    let guild_only = get_attr_value(&attrs, "guild_only")?.is_some();
     let (constructor, builders, set_desc, set_type) = if message {
         let constructor = analyze_message_command_fields(&ident, s.fields)?;
         let builder =
@@ -270,6 +586,15 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
                 serenity::model::application::CommandType::Message;
         );
         (constructor, vec![builder], quote!(), set_type)
+    } else if user {
+        let constructor = analyze_user_command_fields(&ident, s.fields)?;
+        let builder =
+            quote!(builder = builder.kind(serenity::model::application::CommandType::User););
+        let set_type = quote!(
+            const TYPE: serenity::model::application::CommandType =
+                serenity::model::application::CommandType::User;
+        );
+        (constructor, vec![builder], quote!(), set_type)
     } else {
         let fields = match s.fields {
             Fields::Named(f) => f,
@@ -303,7 +628,67 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let runner_ident = Ident::new(&format!("__{}_runner", &ident), Span::call_site());
     let app_command = quote!(serenity::model::application);
     let data_ident = quote!(<#ident as serenity_command::BotCommand>::Data);
+    let bot_command = quote!(<#ident as serenity_command::BotCommand>);
+    // guild only commands get the guild id from the runner, and are hidden in DMs
+    let run = if guild_only {
+        quote!(
+            let Some(guild_id) = interaction.guild_id else {
+                return Err(serenity_command::NotInGuild.into());
+            };
+            let cmd = #ident::from(&interaction.data);
+            <#ident as serenity_command::GuildCommand>::run_in_guild(cmd, data, ctx, interaction, guild_id).await
+        )
+    } else {
+        quote!(#ident::from(&interaction.data).run(data, ctx, interaction).await)
+    };
+    let set_guild_only = if guild_only {
+        quote!(builder = builder.dm_permission(false);)
+    } else {
+        quote!()
+    };
+    // guild only commands implement GuildCommand, BotCommand is forwarded to it
+    let guild_command_impl = if guild_only {
+        let guild_command = quote!(<#ident as serenity_command::GuildCommand>);
+        quote!(
+            #[async_trait]
+            impl serenity_command::BotCommand for #ident {
+                type Data = #guild_command::Data;
+
+                async fn run(
+                    self,
+                    _data: &#data_ident,
+                    _ctx: &serenity::prelude::Context,
+                    _interaction: &#app_command::CommandInteraction,
+                    ) -> anyhow::Result<serenity_command::CommandResponse> {
+                    Err(serenity_command::NotInGuild.into())
+                }
+
+                async fn can_complete(
+                    data: &#data_ident,
+                    ctx: &serenity::prelude::Context,
+                    interaction: &#app_command::CommandInteraction,
+                    ) -> anyhow::Result<bool> {
+                    #guild_command::can_complete(data, ctx, interaction).await
+                }
+
+                fn setup_options(
+                    opt_name: &'static str,
+                    opt: serenity::builder::CreateCommandOption,
+                    ) -> serenity::builder::CreateCommandOption {
+                    #guild_command::setup_options(opt_name, opt)
+                }
+
+                const PERMISSIONS: serenity::model::Permissions = #guild_command::PERMISSIONS;
+                const GUILD: Option<serenity::model::prelude::GuildId> = #guild_command::GUILD;
+                const SCHEDULABLE: bool = #guild_command::SCHEDULABLE;
+            }
+        )
+    } else {
+        quote!()
+    };
     Ok(quote!(
+            #guild_command_impl
+
             impl<'a> From<&'a #app_command::CommandData> for #ident {
                 fn from(opts: &'a #app_command::CommandData) -> Self {
                     #constructor
@@ -321,7 +706,16 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
                     ctx: &serenity::prelude::Context,
                     interaction: &#app_command::CommandInteraction,
                     ) -> anyhow::Result<serenity_command::CommandResponse> {
-                    #ident::from(&interaction.data).run(data, ctx, interaction).await
+                    #run
+                }
+
+                async fn can_complete(
+                    &self,
+                    data: &#data_ident,
+                    ctx: &serenity::prelude::Context,
+                    interaction: &#app_command::CommandInteraction,
+                    ) -> anyhow::Result<bool> {
+                    #bot_command::can_complete(data, ctx, interaction).await
                 }
 
                 fn name(&self) -> serenity_command::CommandKey<'static> {
@@ -331,15 +725,27 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
                 fn register<'a>(&self) -> serenity::builder::CreateCommand {
                     use serenity_command::CommandBuilder;
                     let mut builder = serenity::builder::CreateCommand::new(<#ident as serenity_command::CommandBuilder>::NAME);
-                    builder = #ident::create_extras(builder, <#ident as serenity_command::BotCommand>::setup_options);
-                    if !#ident::PERMISSIONS.is_empty() {
-                        builder = builder.default_member_permissions(#ident::PERMISSIONS);
+                    builder = #ident::create_extras(builder, #bot_command::setup_options);
+                    if !#bot_command::PERMISSIONS.is_empty() {
+                        builder = builder.default_member_permissions(#bot_command::PERMISSIONS);
                     }
                     builder
                 }
 
                 fn guild(&self) -> Option<serenity::model::prelude::GuildId> {
-                    #ident::GUILD
+                    #bot_command::GUILD
+                }
+
+                fn permissions(&self) -> serenity::model::Permissions {
+                    #bot_command::PERMISSIONS
+                }
+
+                fn schedulable(&self) -> bool {
+                    #bot_command::SCHEDULABLE
+                }
+
+                fn guild_only(&self) -> bool {
+                    #guild_only
                 }
             }
 
@@ -350,6 +756,7 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
         ) -> serenity::builder::CreateCommand {
             #set_desc
             builder = builder.name(#name);
+            #set_guild_only
             #(#builders)*
             builder
         }
@@ -362,14 +769,76 @@ fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
         }
 
         const NAME: &'static str = #name;
+        const GUILD_ONLY: bool = #guild_only;
         #set_type
 
-        fn runner() -> Box<dyn serenity_command::CommandRunner<Self::Data> + Send + Sync> {
+        fn runner() -> Box<dyn serenity_command::CommandRunner<#data_ident> + Send + Sync> {
             Box::new(#runner_ident)
         }
     }))
 }
 
+// Fieldless enums, parsed from the lowercased variant names. Variants are shown under
+// their name unless given another with `#[cmd(name = "...")]`.
+fn derive_choice(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let DeriveInput { ident, data, .. } = input;
+    let e = match data {
+        Data::Enum(e) => e,
+        _ => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "Derive target must be an enum",
+            ))
+        }
+    };
+    let mut choices = Vec::new();
+    let mut parsers = Vec::new();
+    let mut values = Vec::new();
+    for variant in e.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.ident.span(),
+                "Choice variants cannot have fields",
+            ));
+        }
+        let attrs = get_attr_list(&variant.attrs).unwrap_or_default();
+        let var = &variant.ident;
+        let value = var.to_string().to_lowercase();
+        let name = get_attr_value(&attrs, "name")?.unwrap_or_else(|| value.clone());
+        // variants can be behind features
+        let cfgs = variant.attrs.iter().filter(|a| a.path.is_ident("cfg"));
+        let cfg = quote!(#(#cfgs)*);
+        choices.push(quote!(#cfg (#name, #value)));
+        parsers.push(quote!(#cfg #value => Some(#ident::#var),));
+        values.push(quote!(#cfg #ident::#var => #value,));
+    }
+    Ok(quote!(
+        impl serenity_command::CommandChoice for #ident {
+            const CHOICES: &'static [(&'static str, &'static str)] = &[#(#choices),*];
+
+            fn from_value(value: &str) -> Option<Self> {
+                match value {
+                    #(#parsers)*
+                    _ => None,
+                }
+            }
+
+            fn value(&self) -> &'static str {
+                match self {
+                    #(#values)*
+                }
+            }
+        }
+    ))
+}
+
+#[proc_macro_derive(CommandChoice, attributes(cmd))]
+pub fn derive_command_choice(input: TokenStream) -> TokenStream {
+    derive_choice(parse_macro_input!(input))
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
 #[proc_macro_derive(Command, attributes(cmd))]
 pub fn derive_serenity_command(input: TokenStream) -> TokenStream {
     derive(parse_macro_input!(input))
@@ -0,0 +1,50 @@
+// Minimal bot using an in-memory database seeded with sample data.
+// Only modules that don't need external API keys are loaded.
+//
+// Run with `DISCORD_TOKEN=... GUILD_ID=... cargo run --example basic_bot`
+// GUILD_ID is optional, and used to seed sample quotes, birthdays and autoreacts.
+// All events are left to the modules through `FrameworkEventHandler`.
+use std::env;
+use std::sync::Arc;
+
+use serenity::all::GatewayIntents;
+use serenity::model::id::GuildId;
+use serenity::Client;
+
+use serenity_command_handler::modules::{bdays::Bdays, sql::Sql};
+use serenity_command_handler::modules::{Karma, ModAutoreacts, ModPoll, Quotes};
+use serenity_command_handler::{FrameworkEventHandler, Handler};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let token = env::var("DISCORD_TOKEN")?;
+    let mut builder = Handler::builder_in_memory()?
+        .module::<Sql>()
+        .await?
+        .module::<Quotes>()
+        .await?
+        .module::<ModPoll>()
+        .await?
+        .module::<ModAutoreacts>()
+        .await?
+        .module::<Bdays>()
+        .await?
+        .module::<Karma>()
+        .await?
+        .log_to_stderr();
+    if let Some(guild_id) = env::var("GUILD_ID").ok().and_then(|id| id.parse().ok()) {
+        builder = builder.seed_fixtures(GuildId::new(guild_id))?;
+    }
+    let handler = Arc::new(builder.build());
+    handler
+        .module::<ModAutoreacts>()?
+        .load_reacts(&mut *handler.db.lock().await)
+        .await?;
+
+    let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
+    let mut client = Client::builder(token, intents)
+        .event_handler(FrameworkEventHandler(handler))
+        .await?;
+    client.start().await?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// Existing serenity bot that only uses the framework for some of its commands.
+// The bot keeps its own EventHandler and command registration, and hands interactions
+// it doesn't know about to the Handler.
+//
+// Run with `DISCORD_TOKEN=... cargo run --example embedded_bot`
+use std::env;
+use std::sync::Arc;
+
+use serenity::all::{
+    Command, CreateCommand, CreateInteractionResponse, CreateInteractionResponseMessage,
+    GatewayIntents, Interaction, Ready,
+};
+use serenity::prelude::{Context, EventHandler};
+use serenity::{async_trait, Client};
+
+use serenity_command_handler::modules::{ModPoll, Quotes};
+use serenity_command_handler::Handler;
+
+struct Bot(Arc<Handler>);
+
+#[async_trait]
+impl EventHandler for Bot {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        println!("Connected as {}", ready.user.name);
+        _ = self.0.self_id.set(ready.user.id);
+        _ = self.0.http.set(Arc::clone(&ctx.http));
+        let mut commands = vec![CreateCommand::new("ping").description("Check the bot is up")];
+        commands.extend(self.0.create_commands(None).await);
+        if let Err(e) = Command::set_global_commands(&ctx.http, commands).await {
+            eprintln!("Failed to register commands: {e:?}");
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if self.0.handle_interaction(&ctx, &interaction).await {
+            return;
+        }
+        if let Interaction::Command(command) = interaction {
+            if command.data.name == "ping" {
+                let msg = CreateInteractionResponseMessage::new().content("Pong!");
+                if let Err(e) = command
+                    .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+                    .await
+                {
+                    eprintln!("Failed to respond to ping: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let token = env::var("DISCORD_TOKEN")?;
+    let handler = Handler::builder_in_memory()?
+        .module::<Quotes>()
+        .await?
+        .module::<ModPoll>()
+        .await?
+        .build();
+    // Modules can be borrowed without failing when they aren't loaded
+    if handler.try_module::<Quotes>().is_some() {
+        println!("Quotes enabled");
+    }
+
+    let intents = GatewayIntents::non_privileged();
+    let mut client = Client::builder(token, intents)
+        .event_handler(Bot(Arc::new(handler)))
+        .await?;
+    client.start().await?;
+    Ok(())
+}
@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
 use chrono::Duration;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::async_trait;
 
-#[derive(Debug, Default)]
+// Serialized for the album lookup cache
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Album {
     pub name: Option<String>,
     pub artist: Option<String>,
@@ -11,7 +13,59 @@ pub struct Album {
     pub release_date: Option<String>,
     pub url: Option<String>,
     pub is_playlist: bool,
+    pub is_episode: bool,
+    // number of podcast episodes in a playlist
+    pub episodes: usize,
+    #[serde(with = "opt_duration_secs")]
     pub duration: Option<Duration>,
+    pub cover: Option<String>,
+    // community ratings, formatted for display
+    pub rating: Option<String>,
+    // pressing details, from providers that catalog physical releases
+    pub physical: Option<PhysicalRelease>,
+    // tracklist, from providers that give track durations
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Track {
+    pub name: String,
+    #[serde(with = "duration_secs")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PhysicalRelease {
+    // e.g. "Vinyl, LP, Album, Reissue"
+    pub format: Option<String>,
+    // label names and their catalog number for this release
+    pub labels: Vec<(String, Option<String>)>,
+    pub country: Option<String>,
+}
+
+// chrono durations have no serde support, they are stored as seconds
+mod duration_secs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        d.num_seconds().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        i64::deserialize(d).map(Duration::seconds)
+    }
+}
+
+mod opt_duration_secs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        d.map(|d| d.num_seconds()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<i64>::deserialize(d).map(|secs| secs.map(Duration::seconds))
+    }
 }
 
 #[async_trait]
@@ -43,6 +97,36 @@ impl Album {
         }))
     }
 
+    pub fn format_episodes(&self) -> Option<String> {
+        if self.is_episode {
+            return Some("Podcast episode".to_string());
+        }
+        match self.episodes {
+            0 => None,
+            1 => Some("Includes 1 podcast episode".to_string()),
+            n => Some(format!("Includes {n} podcast episodes")),
+        }
+    }
+
+    pub fn format_physical(&self) -> Option<String> {
+        let physical = self.physical.as_ref()?;
+        let labels = physical.labels.iter().map(|(label, catno)| match catno {
+            Some(catno) => format!("{label} ({catno})"),
+            None => label.clone(),
+        });
+        let parts = physical
+            .format
+            .iter()
+            .cloned()
+            .chain(labels)
+            .chain(physical.country.iter().cloned())
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            return None;
+        }
+        Some(parts.join(" • "))
+    }
+
     pub fn format_name(&self) -> String {
         match (&self.name, &self.artist) {
             (Some(n), Some(a)) => format!("{a} - {n}"),
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use serenity::model::application::CommandInteraction;
+
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+// Message catalog used to translate command responses.
+// Messages are identified by a key, and each key can have one translation per locale.
+// Keys are the English text of the message, with placeholders such as `{command}` that the
+// caller replaces after the lookup.
+#[derive(Default)]
+pub struct Catalog {
+    default_locale: Option<String>,
+    messages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    pub fn add(&mut self, locale: &str, key: &str, text: &str) {
+        self.messages
+            .entry(key.to_string())
+            .or_default()
+            .insert(locale.to_string(), text.to_string());
+    }
+
+    pub fn set_default_locale(&mut self, locale: &str) {
+        self.default_locale = Some(locale.to_string());
+    }
+
+    pub fn default_locale(&self) -> &str {
+        self.default_locale.as_deref().unwrap_or(DEFAULT_LOCALE)
+    }
+
+    fn find<'a>(translations: &'a HashMap<String, String>, locale: &str) -> Option<&'a str> {
+        if let Some(text) = translations.get(locale) {
+            return Some(text);
+        }
+        // e.g. fall back from "en-GB" to "en"
+        let (lang, _) = locale.split_once('-')?;
+        translations.get(lang).map(String::as_str)
+    }
+
+    // Look up a message, trying each locale in order, then the default locale.
+    // Returns the key itself if no translation exists.
+    pub fn get<'a>(&'a self, key: &'a str, locales: &[&str]) -> &'a str {
+        let Some(translations) = self.messages.get(key) else {
+            return key;
+        };
+        locales
+            .iter()
+            .copied()
+            .chain(std::iter::once(self.default_locale()))
+            .find_map(|locale| Self::find(translations, locale))
+            .unwrap_or(key)
+    }
+
+    // Look up a message using the invoking user's locale, falling back to the guild's locale
+    pub fn for_interaction<'a>(
+        &'a self,
+        interaction: &CommandInteraction,
+        key: &'a str,
+    ) -> &'a str {
+        let mut locales = vec![interaction.locale.as_str()];
+        locales.extend(interaction.guild_locale.as_deref());
+        self.get(key, &locales)
+    }
+}
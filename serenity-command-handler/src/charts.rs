@@ -0,0 +1,153 @@
+// Simple charts drawn with the image crate, meant to be composed into dashboards.
+// There is no text rendering, labels and legends go in the accompanying message.
+use std::io::Cursor;
+
+use image::{GenericImage, ImageOutputFormat, Rgba, RgbaImage};
+
+pub const BACKGROUND: Rgba<u8> = Rgba([0x2b, 0x2d, 0x31, 0xff]);
+const GRID: Rgba<u8> = Rgba([0x4e, 0x50, 0x58, 0xff]);
+const AXIS: Rgba<u8> = Rgba([0xb5, 0xba, 0xc1, 0xff]);
+const MARGIN: u32 = 16;
+const GRID_LINES: u32 = 4;
+const LINE_WIDTH: i64 = 3;
+
+// Colors used for the panels of a dashboard, in order
+pub const PALETTE: [Rgba<u8>; 4] = [
+    Rgba([0x58, 0x65, 0xf2, 0xff]),
+    Rgba([0x57, 0xf2, 0x87, 0xff]),
+    Rgba([0xfe, 0xe7, 0x5c, 0xff]),
+    Rgba([0xeb, 0x45, 0x9e, 0xff]),
+];
+
+pub enum Chart<'a> {
+    Bars(&'a [f64]),
+    Line(&'a [f64]),
+}
+
+fn fill_rect(img: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, color: Rgba<u8>) {
+    for py in y..(y + height).min(img.height()) {
+        for px in x..(x + width).min(img.width()) {
+            img.put_pixel(px, py, color);
+        }
+    }
+}
+
+// Bresenham's algorithm, with a square brush for thickness
+fn draw_line(img: &mut RgbaImage, from: (i64, i64), to: (i64, i64), color: Rgba<u8>) {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        for bx in x - LINE_WIDTH / 2..=x + LINE_WIDTH / 2 {
+            for by in y - LINE_WIDTH / 2..=y + LINE_WIDTH / 2 {
+                if (0..img.width() as i64).contains(&bx) && (0..img.height() as i64).contains(&by) {
+                    img.put_pixel(bx as u32, by as u32, color);
+                }
+            }
+        }
+        if (x, y) == to {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+// Draw a chart on a background with horizontal grid lines.
+// The y axis always starts at 0 and ends at the largest value.
+pub fn draw(chart: &Chart, width: u32, height: u32, color: Rgba<u8>) -> RgbaImage {
+    let mut img = RgbaImage::from_pixel(width, height, BACKGROUND);
+    let (left, top) = (MARGIN, MARGIN);
+    let (plot_width, plot_height) = (width - 2 * MARGIN, height - 2 * MARGIN);
+    let bottom = top + plot_height;
+    for i in 0..GRID_LINES {
+        let y = top + i * plot_height / GRID_LINES;
+        fill_rect(&mut img, left, y, plot_width, 1, GRID);
+    }
+    let values = match chart {
+        Chart::Bars(values) | Chart::Line(values) => *values,
+    };
+    let max = values.iter().copied().fold(0.0, f64::max);
+    let scale = |v: f64| {
+        if max > 0.0 {
+            (v / max * plot_height as f64).round() as u32
+        } else {
+            0
+        }
+    };
+    if !values.is_empty() {
+        match chart {
+            Chart::Bars(values) => {
+                let slot = plot_width / values.len() as u32;
+                let bar_width = (slot * 3 / 4).max(1);
+                for (i, &v) in values.iter().enumerate() {
+                    let bar_height = scale(v);
+                    let x = left + i as u32 * slot + slot.saturating_sub(bar_width) / 2;
+                    fill_rect(
+                        &mut img,
+                        x,
+                        bottom - bar_height,
+                        bar_width,
+                        bar_height,
+                        color,
+                    );
+                }
+            }
+            Chart::Line(values) => {
+                let step = plot_width as f64 / (values.len().max(2) - 1) as f64;
+                let points: Vec<(i64, i64)> = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &v)| {
+                        let x = left as f64 + i as f64 * step;
+                        (x.round() as i64, (bottom - scale(v)) as i64)
+                    })
+                    .collect();
+                for pair in points.windows(2) {
+                    draw_line(&mut img, pair[0], pair[1], color);
+                }
+                if let [point] = points.as_slice() {
+                    draw_line(&mut img, *point, *point, color);
+                }
+            }
+        }
+    }
+    fill_rect(&mut img, left, bottom, plot_width, 1, AXIS);
+    fill_rect(&mut img, left, top, 1, plot_height, AXIS);
+    img
+}
+
+// Arrange panels of the same size in a grid, left to right then top to bottom
+pub fn compose(panels: &[RgbaImage], columns: u32) -> anyhow::Result<RgbaImage> {
+    let Some(first) = panels.first() else {
+        anyhow::bail!("Nothing to draw");
+    };
+    let (width, height) = first.dimensions();
+    let rows = (panels.len() as u32).div_ceil(columns);
+    let mut out = RgbaImage::from_pixel(
+        width * columns.min(panels.len() as u32),
+        height * rows,
+        BACKGROUND,
+    );
+    for (i, panel) in panels.iter().enumerate() {
+        let (x, y) = (i as u32 % columns, i as u32 / columns);
+        out.copy_from(panel, x * width, y * height)?;
+    }
+    Ok(out)
+}
+
+pub fn encode_png(img: &RgbaImage) -> anyhow::Result<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, ImageOutputFormat::Png)?;
+    Ok(out.into_inner())
+}
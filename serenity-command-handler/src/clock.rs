@@ -0,0 +1,45 @@
+// Source of the current time for time-based features (LP times, background loops, token
+// expiry). Handlers use the system clock unless given another with `HandlerBuilder::clock`,
+// e.g. a `MockClock` to check what a loop does at a given time without waiting for it.
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Local, Utc};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    fn local_now(&self) -> DateTime<Local> {
+        self.now().with_timezone(&Local)
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// Clock that only moves when told to
+pub struct MockClock(Mutex<DateTime<Utc>>);
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        MockClock(Mutex::new(start))
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -1,14 +1,42 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use chrono::DateTime;
+use futures::{future::BoxFuture, FutureExt};
 use serenity::{
     async_trait,
-    builder::{CreateAllowedMentions, CreateInteractionResponse, CreateInteractionResponseMessage},
+    builder::{
+        CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+        CreateInteractionResponseMessage, CreateMessage,
+    },
     http::Http,
+    json::{to_value, Value},
     model::{
-        application::{CommandDataOption, CommandDataOptionValue, CommandInteraction},
-        channel::Message,
+        application::{
+            ButtonStyle, CommandDataOption, CommandDataOptionValue, CommandInteraction,
+            ComponentInteraction,
+        },
+        channel::{Attachment, GuildChannel, Message},
+        id::{ChannelId, RoleId},
+        webhook::Webhook,
     },
+    prelude::Context,
 };
 
-use serenity_command::CommandResponse;
+use serenity_command::{CommandResponse, ResponseType};
+
+use crate::date_format::{Timestamp, TimestampStyle};
+use crate::mentions::MentionPolicy;
+use crate::Handler;
+
+const MAX_MESSAGE_LEN: usize = 2000;
+const MAX_DESCRIPTION_LEN: usize = 4096;
+const PAGE_PREFIX: &str = "pages:";
+const PAGE_LINES: usize = 15;
+// How long the buttons of a paginated response keep working
+const PAGES_TIMEOUT: Duration = Duration::from_secs(15 * 60);
 
 #[async_trait]
 pub trait Responder {
@@ -17,6 +45,7 @@ pub trait Responder {
         http: &Http,
         contents: CommandResponse,
         role_id: Option<u64>,
+        policy: MentionPolicy,
     ) -> anyhow::Result<Option<Message>>;
 }
 
@@ -27,6 +56,7 @@ impl Responder for CommandInteraction {
         http: &Http,
         contents: CommandResponse,
         role_id: Option<u64>,
+        policy: MentionPolicy,
     ) -> anyhow::Result<Option<Message>> {
         let (contents, embeds, flags) = match contents.to_contents_and_flags() {
             None => return Ok(None),
@@ -41,7 +71,7 @@ impl Responder for CommandInteraction {
             msg = msg
                 .content(&contents)
                 .flags(flags)
-                .allowed_mentions(CreateAllowedMentions::new().roles(role_id));
+                .allowed_mentions(policy.allowed_mentions(role_id.map(RoleId::new), []));
             CreateInteractionResponse::Message(msg)
         })
         .await?;
@@ -52,6 +82,339 @@ impl Responder for CommandInteraction {
     }
 }
 
+// Send a command's response as a regular message, e.g. from a scheduled job.
+// Private responses cannot be sent outside of an interaction.
+#[async_trait]
+impl Responder for ChannelId {
+    async fn respond(
+        &self,
+        http: &Http,
+        contents: CommandResponse,
+        role_id: Option<u64>,
+        policy: MentionPolicy,
+    ) -> anyhow::Result<Option<Message>> {
+        let resp = match contents {
+            CommandResponse::None => return Ok(None),
+            CommandResponse::Public(resp) => resp,
+            CommandResponse::Private(_) => bail!("Private responses can only reply to a command"),
+        };
+        let (text, embeds) = resp.to_content();
+        let mut msg = CreateMessage::new()
+            .embeds(embeds.unwrap_or_default())
+            .allowed_mentions(policy.allowed_mentions(role_id.map(RoleId::new), []));
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+            msg = msg.content(text);
+        }
+        Ok(Some(self.send_message(http, msg).await?))
+    }
+}
+
+// Send a command's response through a webhook, with the webhook's own name and avatar
+#[async_trait]
+impl Responder for Webhook {
+    async fn respond(
+        &self,
+        http: &Http,
+        contents: CommandResponse,
+        role_id: Option<u64>,
+        policy: MentionPolicy,
+    ) -> anyhow::Result<Option<Message>> {
+        let resp = match contents {
+            CommandResponse::None => return Ok(None),
+            CommandResponse::Public(resp) => resp,
+            CommandResponse::Private(_) => bail!("Private responses can only reply to a command"),
+        };
+        let (text, embeds) = resp.to_content();
+        let mut webhook = policy
+            .execute_webhook(role_id.map(RoleId::new), [])
+            .embeds(embeds.unwrap_or_default());
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+            webhook = webhook.content(text);
+        }
+        Ok(self.execute(http, true, webhook).await?)
+    }
+}
+
+// Thread a command was run in, from the channel data sent along with the interaction
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadContext {
+    pub id: ChannelId,
+    pub parent_id: ChannelId,
+    pub archived: bool,
+    pub locked: bool,
+}
+
+pub trait ThreadExt {
+    fn thread(&self) -> Option<ThreadContext>;
+
+    // The channel the command was run in, or the thread's parent channel
+    fn parent_channel_id(&self) -> ChannelId;
+}
+
+impl ThreadExt for CommandInteraction {
+    fn thread(&self) -> Option<ThreadContext> {
+        let channel = self.channel.as_ref()?;
+        // only threads have metadata, the parent of other channels is their category
+        let metadata = channel.thread_metadata?;
+        Some(ThreadContext {
+            id: channel.id,
+            parent_id: channel.parent_id?,
+            archived: metadata.archived,
+            locked: metadata.locked,
+        })
+    }
+
+    fn parent_channel_id(&self) -> ChannelId {
+        self.thread()
+            .map(|thread| thread.parent_id)
+            .unwrap_or(self.channel_id)
+    }
+}
+
+// Same as ThreadExt::thread, for channels that were fetched
+pub fn thread_parent(channel: &GuildChannel) -> Option<ChannelId> {
+    channel.thread_metadata.and(channel.parent_id)
+}
+
+// Download the file of an attachment option, refusing files over `max_size` bytes.
+// The size Discord reports is checked first, then the download stops once it goes over.
+pub async fn download_attachment(
+    attachment: &Attachment,
+    max_size: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let too_large = || anyhow!("File is too large, the limit is {} KB", max_size / 1024);
+    if attachment.size > max_size {
+        return Err(too_large());
+    }
+    let mut resp = reqwest::get(&attachment.url).await?.error_for_status()?;
+    let mut data = Vec::with_capacity(attachment.size as usize);
+    while let Some(chunk) = resp.chunk().await? {
+        if data.len() + chunk.len() > max_size as usize {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+// Render an embed as markdown, for guilds where embeds are not displayed
+pub fn embed_to_text(embed: &CreateEmbed) -> String {
+    let Ok(value) = to_value(embed) else {
+        return String::new();
+    };
+    let str_at = |path: &[&str]| {
+        path.iter()
+            .try_fold(&value, |v, key| v.get(key))
+            .and_then(Value::as_str)
+            .filter(|s| !s.is_empty())
+    };
+    let mut lines = Vec::new();
+    if let Some(author) = str_at(&["author", "name"]) {
+        lines.push(format!("**{author}**"));
+    }
+    match (str_at(&["title"]), str_at(&["url"])) {
+        (Some(title), Some(url)) => lines.push(format!("**[{title}](<{url}>)**")),
+        (Some(title), None) => lines.push(format!("**{title}**")),
+        (None, Some(url)) => lines.push(format!("<{url}>")),
+        (None, None) => {}
+    }
+    if let Some(description) = str_at(&["description"]) {
+        lines.push(description.to_string());
+    }
+    for field in value
+        .get("fields")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let name = field
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let val = field
+            .get("value")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        lines.push(format!("**{name}**\n{val}"));
+    }
+    if let Some(image) = str_at(&["image", "url"]) {
+        lines.push(image.to_string());
+    }
+    // the embed's timestamp is shown next to its footer
+    let timestamp = str_at(&["timestamp"])
+        .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+        .map(|ts| Timestamp::new(ts.into(), TimestampStyle::ShortDateTime).to_string());
+    match (str_at(&["footer", "text"]), timestamp) {
+        (Some(footer), Some(ts)) => lines.push(format!("-# {footer} • {ts}")),
+        (Some(footer), None) => lines.push(format!("-# {footer}")),
+        (None, Some(ts)) => lines.push(format!("-# {ts}")),
+        (None, None) => {}
+    }
+    lines.join("\n")
+}
+
+// Replace the embeds of a response with their markdown rendering
+pub fn plain_text_response(resp: CommandResponse) -> CommandResponse {
+    let to_text = |resp: ResponseType| {
+        let (text, embeds) = resp.to_content();
+        let mut out = text
+            .into_iter()
+            .chain(embeds.iter().flatten().map(embed_to_text))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if out.chars().count() > MAX_MESSAGE_LEN {
+            out = out.chars().take(MAX_MESSAGE_LEN - 1).collect();
+            out.push('…');
+        }
+        ResponseType::Text(out)
+    };
+    match resp {
+        CommandResponse::Public(r) => CommandResponse::Public(to_text(r)),
+        CommandResponse::Private(r) => CommandResponse::Private(to_text(r)),
+        CommandResponse::None => CommandResponse::None,
+    }
+}
+
+// Pages of paginated responses, by the id of the interaction they answered
+#[derive(Default)]
+pub struct PageStore(Mutex<HashMap<u64, (Vec<CreateEmbed>, Instant)>>);
+
+impl PageStore {
+    fn insert(&self, key: u64, pages: Vec<CreateEmbed>) {
+        let mut store = self.0.lock().unwrap();
+        store.retain(|_, (_, created)| created.elapsed() < PAGES_TIMEOUT);
+        store.insert(key, (pages, Instant::now()));
+    }
+
+    fn page(&self, key: u64, page: usize) -> Option<(CreateEmbed, usize)> {
+        let store = self.0.lock().unwrap();
+        let (pages, created) = store.get(&key)?;
+        if created.elapsed() >= PAGES_TIMEOUT {
+            return None;
+        }
+        Some((pages.get(page)?.clone(), pages.len()))
+    }
+}
+
+fn page_buttons(key: u64, page: usize, pages: usize) -> Vec<CreateActionRow> {
+    let nav_button = |label: &str, target: usize, disabled: bool| {
+        CreateButton::new(format!("{PAGE_PREFIX}{key}:{target}"))
+            .label(label)
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled)
+    };
+    vec![CreateActionRow::Buttons(vec![
+        nav_button("Previous", page.saturating_sub(1), page == 0),
+        nav_button("Next", page + 1, page + 1 >= pages),
+    ])]
+}
+
+// Response too long for a single message (e.g. a list of entries), browsed with previous and
+// next buttons until it expires
+pub struct Paginator {
+    pages: Vec<CreateEmbed>,
+    ephemeral: bool,
+}
+
+impl Paginator {
+    pub fn from_embeds(pages: Vec<CreateEmbed>) -> Self {
+        Paginator {
+            pages,
+            ephemeral: false,
+        }
+    }
+
+    // Split lines into embeds with the same title, numbered in their footer
+    pub fn from_lines<I: IntoIterator<Item = String>>(title: &str, lines: I) -> Self {
+        let mut descriptions = vec![String::new()];
+        let mut count = 0;
+        for line in lines {
+            let current = descriptions.last_mut().unwrap();
+            if count == PAGE_LINES || current.len() + line.len() + 1 > MAX_DESCRIPTION_LEN {
+                descriptions.push(String::new());
+                count = 0;
+            }
+            let current = descriptions.last_mut().unwrap();
+            if count > 0 {
+                current.push('\n');
+            }
+            current.push_str(&line);
+            count += 1;
+        }
+        let total = descriptions.len();
+        let pages = descriptions
+            .into_iter()
+            .enumerate()
+            .map(|(i, description)| {
+                CreateEmbed::new()
+                    .title(title)
+                    .description(description)
+                    .footer(CreateEmbedFooter::new(format!("Page {}/{total}", i + 1)))
+            })
+            .collect();
+        Self::from_embeds(pages)
+    }
+
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    // Send the first page, buttons are only added if there are more
+    pub async fn respond(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let Paginator { pages, ephemeral } = self;
+        let key = command.id.get();
+        let mut msg = CreateInteractionResponseMessage::new().ephemeral(ephemeral);
+        if let Some(first) = pages.first() {
+            msg = msg.embed(first.clone());
+        }
+        if pages.len() > 1 {
+            msg = msg.components(page_buttons(key, 0, pages.len()));
+            handler.pages.insert(key, pages);
+        }
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+pub(crate) fn handle_page<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    component: &'a ComponentInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        let Some(target) = component.data.custom_id.strip_prefix(PAGE_PREFIX) else {
+            return Ok(false);
+        };
+        let Some((key, page)) = target.split_once(':') else {
+            return Ok(false);
+        };
+        let (key, page) = (key.parse()?, page.parse()?);
+        let resp = match handler.pages.page(key, page) {
+            Some((embed, pages)) => CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(page_buttons(key, page, pages)),
+            ),
+            None => CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This list expired, run the command again")
+                    .ephemeral(true),
+            ),
+        };
+        component.create_response(&ctx.http, resp).await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
 pub fn get_str_opt_ac<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
     options
         .iter()
@@ -0,0 +1,590 @@
+// Small web dashboard served by the bot, for server admins to view and edit settings, see
+// server activity and browse quotes.
+// Users log in with Discord and can only see servers where they have Manage Server.
+// Pages are plain HTML forms, sessions are kept in memory and lost on restart.
+//
+//     tokio::spawn(dashboard::serve(Arc::clone(&handler), DashboardConfig { .. }));
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use axum::extract::{Form, Path, Query, State};
+use axum::http::header::{CONTENT_TYPE, COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::types::Value;
+use serde::Deserialize;
+use serenity::http::GuildPagination;
+use serenity::model::prelude::{GuildId, UserId};
+use serenity::model::Permissions;
+use tokio::sync::Mutex;
+
+use crate::db::{SettingsConflict, SECRET_GUILD_FIELDS};
+use crate::modules::quotes::browse_quotes;
+use crate::modules::stats::Dashboard;
+use crate::Handler;
+
+const DISCORD_API: &str = "https://discord.com/api/v10";
+const SESSION_COOKIE: &str = "session";
+const SESSION_SECS: i64 = 7 * 24 * 3600;
+// How long a login can take on Discord's side before its OAuth state is dropped
+const LOGIN_SECS: i64 = 10 * 60;
+// Discord's limit for the bot's guild list
+const GUILDS_PER_PAGE: u64 = 200;
+const QUOTES_PER_PAGE: usize = 25;
+
+pub struct DashboardConfig {
+    pub bind: SocketAddr,
+    // OAuth2 application credentials, from the Discord developer portal
+    pub client_id: String,
+    pub client_secret: String,
+    // URL the dashboard is reachable at, e.g. https://bot.example.com
+    pub public_url: String,
+}
+
+struct Session {
+    user_id: UserId,
+    username: String,
+    // servers the user could manage when logging in, that the bot is in. Changes are
+    // checked against their current permissions, see `can_manage`.
+    guilds: BTreeMap<GuildId, String>,
+    // sent with forms, so that other sites can't submit them for the user
+    csrf_token: String,
+    expires: i64,
+}
+
+struct DashboardState {
+    handler: Arc<Handler>,
+    config: DashboardConfig,
+    sessions: Mutex<HashMap<String, Session>>,
+    // OAuth states of logins in progress, with their expiry
+    logins: Mutex<HashMap<String, i64>>,
+}
+
+type AppState = State<Arc<DashboardState>>;
+
+struct DashboardError(StatusCode, String);
+
+impl From<anyhow::Error> for DashboardError {
+    fn from(e: anyhow::Error) -> Self {
+        // the details stay in the logs, they may say more than users should see
+        tracing::error!("dashboard error: {e:?}");
+        DashboardError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong, please try again later".into(),
+        )
+    }
+}
+
+impl IntoResponse for DashboardError {
+    fn into_response(self) -> Response {
+        (self.0, page("Error", &escape(&self.1))).into_response()
+    }
+}
+
+type PageResult = Result<Response, DashboardError>;
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title></head>\
+         <body><h1>{0}</h1>{body}</body></html>",
+        escape(title)
+    ))
+}
+
+fn random_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
+
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then(|| value.to_string())
+        })
+}
+
+impl DashboardState {
+    // Run `f` with the user's session, or redirect to the login page
+    async fn with_session<T>(
+        &self,
+        headers: &HeaderMap,
+        f: impl FnOnce(&Session) -> T,
+    ) -> Result<T, Response> {
+        let now = chrono::Utc::now().timestamp();
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, session| session.expires > now);
+        session_token(headers)
+            .and_then(|token| sessions.get(&token))
+            .map(f)
+            .ok_or_else(|| Redirect::to("/login").into_response())
+    }
+
+    // Name of a guild the user can manage
+    async fn guild_name(&self, headers: &HeaderMap, guild_id: GuildId) -> Result<String, Response> {
+        self.with_session(headers, |session| session.guilds.get(&guild_id).cloned())
+            .await?
+            .ok_or_else(|| {
+                DashboardError(
+                    StatusCode::FORBIDDEN,
+                    "You cannot manage this server".into(),
+                )
+                .into_response()
+            })
+    }
+
+    // Whether the user can manage the server now, their rights may have been revoked since
+    // they logged in
+    async fn can_manage(&self, guild_id: GuildId, user_id: UserId) -> anyhow::Result<bool> {
+        let http = self
+            .handler
+            .http
+            .get()
+            .ok_or_else(|| anyhow!("The bot is not connected yet"))?;
+        let guild = guild_id.to_partial_guild(http).await?;
+        // members who left the server can't be fetched
+        let Ok(member) = guild.member(http, user_id).await else {
+            return Ok(false);
+        };
+        Ok(guild.member_permissions(&member).manage_guild())
+    }
+
+    async fn oauth_session(&self, code: &str) -> anyhow::Result<Session> {
+        let config = &self.config;
+        let client = reqwest::Client::new();
+        let redirect_uri = format!("{}/callback", config.public_url);
+        let token: OAuthToken = client
+            .post(format!("{DISCORD_API}/oauth2/token"))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &redirect_uri),
+                ("client_id", &config.client_id),
+                ("client_secret", &config.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let bearer = format!("Bearer {}", token.access_token);
+        let user: OAuthUser = client
+            .get(format!("{DISCORD_API}/users/@me"))
+            .header("Authorization", &bearer)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let guilds: Vec<OAuthGuild> = client
+            .get(format!("{DISCORD_API}/users/@me/guilds"))
+            .header("Authorization", &bearer)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let http = self
+            .handler
+            .http
+            .get()
+            .ok_or_else(|| anyhow!("The bot is not connected yet"))?;
+        let mut bot_guilds = HashSet::new();
+        let mut after = None;
+        loop {
+            let guilds = http
+                .get_guilds(after.map(GuildPagination::After), Some(GUILDS_PER_PAGE))
+                .await?;
+            bot_guilds.extend(guilds.iter().map(|guild| guild.id));
+            match guilds.last() {
+                Some(last) if guilds.len() as u64 == GUILDS_PER_PAGE => after = Some(last.id),
+                _ => break,
+            }
+        }
+        let guilds = guilds
+            .into_iter()
+            .filter(|guild| {
+                let permissions = guild
+                    .permissions
+                    .parse()
+                    .map(Permissions::from_bits_truncate)
+                    .unwrap_or_default();
+                permissions.manage_guild() && bot_guilds.contains(&guild.id)
+            })
+            .map(|guild| (guild.id, guild.name))
+            .collect();
+        Ok(Session {
+            user_id: user.id.parse::<u64>().map(UserId::new)?,
+            username: user.username,
+            guilds,
+            csrf_token: random_token(),
+            expires: chrono::Utc::now().timestamp() + SESSION_SECS,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct OAuthToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct OAuthUser {
+    id: String,
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct OAuthGuild {
+    id: GuildId,
+    name: String,
+    permissions: String,
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+async fn login(State(state): AppState) -> Redirect {
+    let oauth_state = random_token();
+    let now = state.handler.clock.now().timestamp();
+    {
+        let mut logins = state.logins.lock().await;
+        logins.retain(|_, expires| *expires > now);
+        logins.insert(oauth_state.clone(), now + LOGIN_SECS);
+    }
+    let query = serde_urlencoded::to_string([
+        ("client_id", state.config.client_id.as_str()),
+        (
+            "redirect_uri",
+            &format!("{}/callback", state.config.public_url),
+        ),
+        ("response_type", "code"),
+        ("scope", "identify guilds"),
+        ("state", &oauth_state),
+    ])
+    .unwrap_or_default();
+    Redirect::to(&format!("https://discord.com/oauth2/authorize?{query}"))
+}
+
+async fn callback(State(state): AppState, Query(params): Query<CallbackParams>) -> PageResult {
+    let now = state.handler.clock.now().timestamp();
+    let expires = state.logins.lock().await.remove(&params.state);
+    if expires.is_none_or(|expires| expires <= now) {
+        return Err(DashboardError(
+            StatusCode::BAD_REQUEST,
+            "This login has expired, please try again".into(),
+        ));
+    }
+    let session = state.oauth_session(&params.code).await?;
+    let token = random_token();
+    state.sessions.lock().await.insert(token.clone(), session);
+    let cookie = format!(
+        "{SESSION_COOKIE}={token}; Path=/; HttpOnly; Secure; SameSite=Lax; Max-Age={SESSION_SECS}"
+    );
+    Ok(([(SET_COOKIE, cookie)], Redirect::to("/")).into_response())
+}
+
+async fn logout(State(state): AppState, headers: HeaderMap) -> Response {
+    if let Some(token) = session_token(&headers) {
+        state.sessions.lock().await.remove(&token);
+    }
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; Max-Age=0");
+    ([(SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+async fn index(State(state): AppState, headers: HeaderMap) -> Response {
+    let body = state
+        .with_session(&headers, |session| {
+            let mut body = format!(
+                "<p>Logged in as {} (<a href=\"/logout\">log out</a>)</p><ul>",
+                escape(&session.username)
+            );
+            for (guild_id, name) in &session.guilds {
+                _ = write!(
+                    body,
+                    "<li><a href=\"/guilds/{guild_id}\">{}</a></li>",
+                    escape(name)
+                );
+            }
+            if session.guilds.is_empty() {
+                body.push_str("<li>No servers you can manage</li>");
+            }
+            body.push_str("</ul>");
+            body
+        })
+        .await;
+    match body {
+        Ok(body) => page("Dashboard", &body).into_response(),
+        Err(redirect) => redirect,
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Blob(_) => "(binary)".to_string(),
+    }
+}
+
+async fn guild_page(
+    State(state): AppState,
+    headers: HeaderMap,
+    Path(guild_id): Path<GuildId>,
+) -> PageResult {
+    let name = match state.guild_name(&headers, guild_id).await {
+        Ok(name) => name,
+        Err(resp) => return Ok(resp),
+    };
+    let csrf_token = match state.with_session(&headers, |s| s.csrf_token.clone()).await {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let (fields, types, version) = {
+        let db = state.handler.db.lock().await;
+        (
+            db.get_guild_fields(guild_id)?,
+            db.guild_field_types()?,
+            db.guild_version(guild_id)?,
+        )
+    };
+    let mut body = format!(
+        "<p><a href=\"/\">Servers</a> · <a href=\"/guilds/{guild_id}/quotes\">Quotes</a></p>\
+         <h2>Settings</h2><table>"
+    );
+    for (field, value) in fields {
+        if SECRET_GUILD_FIELDS.contains(&field.as_str()) {
+            continue;
+        }
+        let kind = types.get(&field).map(String::as_str).unwrap_or_default();
+        _ = write!(
+            body,
+            "<tr><td><code>{field}</code></td><td><form method=\"post\" \
+             action=\"/guilds/{guild_id}/settings\">\
+             <input type=\"hidden\" name=\"field\" value=\"{field}\">\
+             <input type=\"hidden\" name=\"version\" value=\"{version}\">\
+             <input type=\"hidden\" name=\"csrf_token\" value=\"{csrf_token}\">\
+             <input name=\"value\" value=\"{}\" placeholder=\"{}\">\
+             <button>Save</button></form></td></tr>",
+            escape(&format_value(&value)),
+            escape(kind)
+        );
+    }
+    body.push_str("</table>");
+    if state
+        .handler
+        .try_module::<crate::modules::Stats>()
+        .is_some()
+    {
+        _ = write!(
+            body,
+            "<h2>Activity</h2><img src=\"/guilds/{guild_id}/activity.png\" \
+             alt=\"Commands, listening parties, quotes and top hosts per week\">"
+        );
+    }
+    Ok(page(&name, &body).into_response())
+}
+
+#[derive(Deserialize)]
+struct SettingForm {
+    field: String,
+    value: String,
+    version: i64,
+    csrf_token: String,
+}
+
+// Convert a submitted value to the setting's SQL type, empty values unset the setting
+fn parse_setting(kind: &str, value: &str) -> anyhow::Result<Value> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Value::Null);
+    }
+    Ok(match kind.to_uppercase().as_str() {
+        "BOOLEAN" => match value.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Value::Integer(1),
+            "0" | "false" | "no" | "off" => Value::Integer(0),
+            _ => bail!("`{value}` is not a boolean"),
+        },
+        "INTEGER" => Value::Integer(value.parse()?),
+        "REAL" => Value::Real(value.parse()?),
+        _ => Value::Text(value.to_string()),
+    })
+}
+
+async fn update_setting(
+    State(state): AppState,
+    headers: HeaderMap,
+    Path(guild_id): Path<GuildId>,
+    Form(form): Form<SettingForm>,
+) -> PageResult {
+    if let Err(resp) = state.guild_name(&headers, guild_id).await {
+        return Ok(resp);
+    }
+    let session = state
+        .with_session(&headers, |s| (s.user_id, s.csrf_token.clone()))
+        .await;
+    let (user_id, csrf_token) = match session {
+        Ok(session) => session,
+        Err(resp) => return Ok(resp),
+    };
+    if form.csrf_token != csrf_token {
+        return Err(DashboardError(
+            StatusCode::FORBIDDEN,
+            "This form has expired, reload the page and try again".into(),
+        ));
+    }
+    if !state.can_manage(guild_id, user_id).await? {
+        return Err(DashboardError(
+            StatusCode::FORBIDDEN,
+            "You cannot manage this server".into(),
+        ));
+    }
+    let types = state.handler.db.lock().await.guild_field_types()?;
+    let kind = match types.get(&form.field) {
+        Some(_) if SECRET_GUILD_FIELDS.contains(&form.field.as_str()) => None,
+        kind => kind,
+    }
+    .ok_or_else(|| DashboardError(StatusCode::BAD_REQUEST, "Unknown setting".into()))?;
+    let value = parse_setting(kind, &form.value)
+        .map_err(|e| DashboardError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let res = state
+        .handler
+        .set_guild_field_versioned(guild_id, user_id, &form.field, value, Some(form.version))
+        .await;
+    match res {
+        Ok(_) => Ok(Redirect::to(&format!("/guilds/{guild_id}")).into_response()),
+        Err(e) if e.downcast_ref::<SettingsConflict>().is_some() => Err(DashboardError(
+            StatusCode::CONFLICT,
+            format!("{e}. Reload the settings page to see them."),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn activity_chart(
+    State(state): AppState,
+    headers: HeaderMap,
+    Path(guild_id): Path<GuildId>,
+) -> PageResult {
+    if let Err(resp) = state.guild_name(&headers, guild_id).await {
+        return Ok(resp);
+    }
+    let image = {
+        let db = state.handler.db.lock().await;
+        Dashboard::load(&db, guild_id)?
+    }
+    .image()?;
+    Ok(([(CONTENT_TYPE, "image/png")], image).into_response())
+}
+
+#[derive(Deserialize)]
+struct QuotesParams {
+    #[serde(default)]
+    search: String,
+    #[serde(default)]
+    page: usize,
+}
+
+async fn quotes_page(
+    State(state): AppState,
+    headers: HeaderMap,
+    Path(guild_id): Path<GuildId>,
+    Query(params): Query<QuotesParams>,
+) -> PageResult {
+    let name = match state.guild_name(&headers, guild_id).await {
+        Ok(name) => name,
+        Err(resp) => return Ok(resp),
+    };
+    let quotes = browse_quotes(
+        &state.handler,
+        guild_id,
+        &params.search,
+        QUOTES_PER_PAGE,
+        params.page * QUOTES_PER_PAGE,
+    )
+    .await?;
+    let search = escape(&params.search);
+    let mut body = format!(
+        "<p><a href=\"/guilds/{guild_id}\">Back to settings</a></p>\
+         <form><input name=\"search\" value=\"{search}\"><button>Search</button></form>"
+    );
+    for (number, ts, author, contents) in &quotes {
+        _ = write!(
+            body,
+            "<h3>#{number} {} <small>{}</small></h3><pre>{}</pre>",
+            escape(author),
+            ts.format("%Y-%m-%d"),
+            escape(contents)
+        );
+    }
+    if quotes.is_empty() {
+        body.push_str("<p>No quotes found</p>");
+    }
+    let query = |page: usize| {
+        serde_urlencoded::to_string([
+            ("search", params.search.as_str()),
+            ("page", &page.to_string()),
+        ])
+        .unwrap_or_default()
+    };
+    if params.page > 0 {
+        _ = write!(body, "<a href=\"?{}\">Newer</a> ", query(params.page - 1));
+    }
+    if quotes.len() == QUOTES_PER_PAGE {
+        _ = write!(body, "<a href=\"?{}\">Older</a>", query(params.page + 1));
+    }
+    Ok(page(&format!("Quotes in {name}"), &body).into_response())
+}
+
+// Serve the dashboard until the process exits
+pub async fn serve(handler: Arc<Handler>, config: DashboardConfig) -> anyhow::Result<()> {
+    let bind = config.bind;
+    let state = Arc::new(DashboardState {
+        handler,
+        config,
+        sessions: Default::default(),
+        logins: Default::default(),
+    });
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+        .route("/logout", get(logout))
+        .route("/guilds/:guild_id", get(guild_page))
+        .route("/guilds/:guild_id/settings", post(update_setting))
+        .route("/guilds/:guild_id/activity.png", get(activity_chart))
+        .route("/guilds/:guild_id/quotes", get(quotes_page))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+// Formatting of dates and times shown in messages.
+// Discord timestamp markup is displayed in each reader's own locale and timezone, and is
+// preferred whenever there is an actual point in time. Dates that are not tied to a
+// timezone (e.g. birthdays) follow the guild's `date_locale` and `utc_offset` settings.
+use std::fmt::{self, Display};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use serenity::model::prelude::{CommandInteraction, GuildId};
+
+use crate::Handler;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampStyle {
+    ShortTime,
+    LongTime,
+    ShortDate,
+    LongDate,
+    ShortDateTime,
+    LongDateTime,
+    Relative,
+}
+
+impl TimestampStyle {
+    fn flag(self) -> char {
+        match self {
+            TimestampStyle::ShortTime => 't',
+            TimestampStyle::LongTime => 'T',
+            TimestampStyle::ShortDate => 'd',
+            TimestampStyle::LongDate => 'D',
+            TimestampStyle::ShortDateTime => 'f',
+            TimestampStyle::LongDateTime => 'F',
+            TimestampStyle::Relative => 'R',
+        }
+    }
+}
+
+// Discord timestamp markup, e.g. <t:1700000000:R>
+pub struct Timestamp(pub i64, pub TimestampStyle);
+
+impl Timestamp {
+    pub fn new(time: DateTime<Utc>, style: TimestampStyle) -> Self {
+        Timestamp(time.timestamp(), style)
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<t:{}:{}>", self.0, self.1.flag())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateOrder {
+    #[default]
+    DayMonth,
+    MonthDay,
+    YearFirst,
+}
+
+impl DateOrder {
+    // Order used by a locale, e.g. en-US or fr
+    pub fn from_locale(locale: &str) -> Self {
+        let lang = locale.split(['-', '_']).next().unwrap_or_default();
+        match (lang, locale) {
+            (_, "en-US" | "en_US") => DateOrder::MonthDay,
+            ("ja" | "zh" | "ko" | "hu" | "lt", _) | (_, "sv-SE" | "sv_SE") => DateOrder::YearFirst,
+            _ => DateOrder::DayMonth,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DateFormat {
+    pub order: DateOrder,
+    pub offset: FixedOffset,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat {
+            order: DateOrder::default(),
+            offset: FixedOffset::east_opt(0).unwrap(),
+        }
+    }
+}
+
+impl DateFormat {
+    // Format configured for a guild. The settings only exist when the settings module is
+    // loaded, `fallback_locale` is used when no locale was set.
+    pub async fn for_guild(
+        handler: &Handler,
+        guild_id: GuildId,
+        fallback_locale: Option<&str>,
+    ) -> Self {
+        let locale: Option<String> = handler
+            .get_guild_field(guild_id, "date_locale")
+            .await
+            .unwrap_or_default();
+        let offset: Option<i32> = handler
+            .get_guild_field(guild_id, "utc_offset")
+            .await
+            .unwrap_or_default();
+        DateFormat {
+            order: locale
+                .as_deref()
+                .or(fallback_locale)
+                .map(DateOrder::from_locale)
+                .unwrap_or_default(),
+            offset: offset
+                .and_then(|minutes| FixedOffset::east_opt(minutes * 60))
+                .unwrap_or_else(|| DateFormat::default().offset),
+        }
+    }
+
+    // Format for the guild a command was run in, falling back to the guild's Discord locale
+    pub async fn for_interaction(handler: &Handler, command: &CommandInteraction) -> Self {
+        match command.guild_id {
+            Some(guild_id) => {
+                Self::for_guild(handler, guild_id, command.guild_locale.as_deref()).await
+            }
+            None => DateFormat::default(),
+        }
+    }
+
+    // Date at `now` in the guild's timezone
+    pub fn today(&self, now: DateTime<Utc>) -> NaiveDate {
+        now.with_timezone(&self.offset).date_naive()
+    }
+
+    // Day and month without a year, e.g. 25/12, 12/25 or 12-25
+    pub fn day_month(&self, day: u8, month: u8) -> String {
+        match self.order {
+            DateOrder::DayMonth => format!("{day:02}/{month:02}"),
+            DateOrder::MonthDay => format!("{month:02}/{day:02}"),
+            DateOrder::YearFirst => format!("{month:02}-{day:02}"),
+        }
+    }
+
+    pub fn date(&self, date: NaiveDate) -> String {
+        let fmt = match self.order {
+            DateOrder::DayMonth => "%d/%m/%Y",
+            DateOrder::MonthDay => "%m/%d/%Y",
+            DateOrder::YearFirst => "%Y-%m-%d",
+        };
+        date.format(fmt).to_string()
+    }
+
+    // Date and time in the guild's timezone, for places where markup is not rendered
+    // (e.g. embed footers)
+    pub fn datetime(&self, time: DateTime<Utc>) -> String {
+        let local = time.with_timezone(&self.offset);
+        format!(
+            "{} {}",
+            self.date(local.date_naive()),
+            local.format("%H:%M")
+        )
+    }
+}
@@ -1,27 +1,153 @@
-use anyhow;
+use anyhow::{self, Context as _};
+use fallible_iterator::FallibleIterator;
 use rusqlite::{
     params,
-    types::{FromSql, ValueRef},
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef},
     Connection, ToSql,
 };
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
 
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 
 use crate::Handler;
 
+// Typed wrappers around Discord IDs, usable as query parameters and column values.
+// Module queries should bind and read these rather than raw u64s, so that passing e.g. a
+// channel ID where a guild ID is expected fails to compile:
+//
+//     db.conn.execute(
+//         "INSERT INTO foo (guild_id, channel_id) VALUES (?1, ?2)",
+//         params![SqlGuildId(guild_id), SqlChannelId(channel_id)],
+//     )?;
+//     let channel: SqlChannelId = row.get(0)?;
+macro_rules! sql_id {
+    ($($name:ident($id:ident)),* $(,)?) => {$(
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub struct $name(pub $id);
+
+        impl From<$id> for $name {
+            fn from(id: $id) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for $id {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                let id = i64::try_from(self.0.get())
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                Ok(ToSqlOutput::from(id))
+            }
+        }
+
+        impl FromSql for $name {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+                match u64::column_result(value)? {
+                    0 => Err(FromSqlError::OutOfRange(0)),
+                    id => Ok($name($id::new(id))),
+                }
+            }
+        }
+    )*};
+}
+
+sql_id!(
+    SqlGuildId(GuildId),
+    SqlChannelId(ChannelId),
+    SqlUserId(UserId),
+    SqlMessageId(MessageId),
+);
+
+pub struct AuditEntry {
+    pub user_id: UserId,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub ts: i64,
+}
+
+// Settings that grant access to something (e.g. webhook URLs), never shown or exported
+pub const SECRET_GUILD_FIELDS: [&str; 2] = ["webhook", "pinboard_webhook"];
+
+// Returned when guild settings changed between the moment they were read and the moment
+// a change based on them was applied
+#[derive(Debug)]
+pub struct SettingsConflict {
+    pub field: String,
+}
+
+impl fmt::Display for SettingsConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Settings were changed by someone else in the meantime (`{}`), check the current values and try again",
+            self.field
+        )
+    }
+}
+
+impl std::error::Error for SettingsConflict {}
+
+// A versioned schema change of a module.
+// Modules list their migrations in `Module::MIGRATIONS`, in increasing version order, and
+// the ones newer than the last version recorded for the module are applied when it is set up.
+// Migrations are never edited once released, changes go in a new migration. The first one
+// usually creates the module's tables with IF NOT EXISTS so that it also applies to databases
+// created before the module had migrations.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub step: MigrationStep,
+}
+
+pub enum MigrationStep {
+    Sql(&'static str),
+    // for changes that need to read or transform rows
+    Run(fn(&rusqlite::Transaction) -> anyhow::Result<()>),
+}
+
+impl Migration {
+    pub const fn sql(version: u32, name: &'static str, sql: &'static str) -> Self {
+        Migration {
+            version,
+            name,
+            step: MigrationStep::Sql(sql),
+        }
+    }
+
+    pub const fn run(
+        version: u32,
+        name: &'static str,
+        f: fn(&rusqlite::Transaction) -> anyhow::Result<()>,
+    ) -> Self {
+        Migration {
+            version,
+            name,
+            step: MigrationStep::Run(f),
+        }
+    }
+}
+
 pub struct Db {
     pub conn: Connection,
 }
 
 impl Db {
     pub fn get_guild_field<T: FromSql + Default>(
-        &mut self,
-        guild_id: u64,
+        &self,
+        guild_id: GuildId,
         field: &str,
     ) -> anyhow::Result<T> {
         match self.conn.query_row(
             &format!("SELECT {field} FROM guild WHERE id = ?1"),
-            [guild_id],
+            [SqlGuildId(guild_id)],
             |row| row.get(0),
         ) {
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Default::default()),
@@ -30,26 +156,180 @@ impl Db {
         .map_err(anyhow::Error::from)
     }
 
+    // All settings of a guild by field name, NULL for guilds without a row yet
+    pub fn get_guild_fields(&self, guild_id: GuildId) -> anyhow::Result<BTreeMap<String, Value>> {
+        let mut stmt = self.conn.prepare("SELECT * FROM guild WHERE id = ?1")?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut rows = stmt.query([SqlGuildId(guild_id)])?;
+        let row = rows.next()?;
+        let mut fields = BTreeMap::new();
+        for (i, name) in names.into_iter().enumerate() {
+            if name == "id" || name == "version" {
+                continue;
+            }
+            let value = match row {
+                Some(row) => row.get(i)?,
+                None => Value::Null,
+            };
+            fields.insert(name, value);
+        }
+        Ok(fields)
+    }
+
+    // Declared SQL type of every guild setting, e.g. BOOLEAN or STRING
+    pub fn guild_field_types(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        let res = self
+            .conn
+            .prepare("SELECT name, type FROM pragma_table_info('guild')")?
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .filter(|(name, _): &(String, String)| Ok(name != "id" && name != "version"))
+            .collect()?;
+        Ok(res)
+    }
+
+    // Users of a guild who turned off a category of notifications with /notify.
+    // Nobody has when the notifications module is not loaded.
+    pub fn muted_users(
+        &self,
+        guild_id: GuildId,
+        category: &str,
+    ) -> anyhow::Result<HashSet<UserId>> {
+        let loaded: bool = self.conn.query_row(
+            "SELECT EXISTS(
+                SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'notification_pref'
+            )",
+            [],
+            |row| row.get(0),
+        )?;
+        if !loaded {
+            return Ok(HashSet::new());
+        }
+        let res = self
+            .conn
+            .prepare(
+                "SELECT user_id FROM notification_pref
+                 WHERE guild_id = ?1 AND category = ?2 AND NOT enabled",
+            )?
+            .query(params![SqlGuildId(guild_id), category])?
+            .map(|row| row.get::<_, SqlUserId>(0).map(|u| u.0))
+            .collect()?;
+        Ok(res)
+    }
+
+    // Version of a guild's settings, incremented on every change. 0 for guilds without a row.
+    pub fn guild_version(&self, guild_id: GuildId) -> anyhow::Result<i64> {
+        match self.conn.query_row(
+            "SELECT version FROM guild WHERE id = ?1",
+            [SqlGuildId(guild_id)],
+            |row| row.get(0),
+        ) {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            res => res.map_err(anyhow::Error::from),
+        }
+    }
+
+    // Update a guild setting, recording the change in the guild_audit table
     pub fn set_guild_field<T: ToSql>(
         &mut self,
-        guild_id: u64,
+        guild_id: GuildId,
+        actor: UserId,
         field: &str,
         value: T,
     ) -> anyhow::Result<()> {
-        self.conn.execute(
-            &format!("UPDATE guild SET {field} = ?2 WHERE id = ?1"),
-            params![guild_id, value],
-        )?;
+        self.set_guild_field_versioned(guild_id, actor, field, value, None)?;
         Ok(())
     }
 
-    pub fn add_guild_field(&mut self, name: &str, def: &str) -> anyhow::Result<()> {
+    // Update a guild setting only if the settings are still at `expected` version, for
+    // changes based on values read earlier (e.g. a preview the user confirms).
+    // Fails with a SettingsConflict otherwise, returns the new version.
+    pub fn set_guild_field_versioned<T: ToSql>(
+        &mut self,
+        guild_id: GuildId,
+        actor: UserId,
+        field: &str,
+        value: T,
+        expected: Option<i64>,
+    ) -> anyhow::Result<i64> {
+        let tx = self.conn.transaction()?;
+        let version = update_guild_field(&tx, guild_id, actor, field, value, expected)?;
+        tx.commit()?;
+        Ok(version)
+    }
+
+    // Most recent setting changes in a guild, optionally filtered by field and user
+    pub fn get_guild_audit(
+        &self,
+        guild_id: GuildId,
+        field: Option<&str>,
+        user: Option<UserId>,
+        limit: usize,
+        offset: usize,
+    ) -> anyhow::Result<Vec<AuditEntry>> {
+        let res = self
+            .conn
+            .prepare(
+                "SELECT user_id, field, old_value, new_value, ts FROM guild_audit
+                 WHERE guild_id = ?1 AND (?2 IS NULL OR field = ?2) AND (?3 IS NULL OR user_id = ?3)
+                 ORDER BY id DESC LIMIT ?4 OFFSET ?5",
+            )?
+            .query(params![
+                SqlGuildId(guild_id),
+                field,
+                user.map(SqlUserId),
+                limit,
+                offset
+            ])?
+            .map(|row| {
+                Ok(AuditEntry {
+                    user_id: row.get::<_, SqlUserId>(0)?.0,
+                    field: row.get(1)?,
+                    old_value: row.get(2)?,
+                    new_value: row.get(3)?,
+                    ts: row.get(4)?,
+                })
+            })
+            .collect()?;
+        Ok(res)
+    }
+
+    // Create the guild settings table and its audit trail
+    pub fn create_guild_tables(&mut self) -> anyhow::Result<()> {
         self.conn
             .execute(
                 "CREATE TABLE IF NOT EXISTS guild(id INTEGER PRIMARY KEY)",
                 [],
             )
             .map_err(anyhow::Error::from)?;
+        let has_version: bool = self.conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('guild') WHERE name = 'version'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_version {
+            self.conn.execute(
+                "ALTER TABLE guild ADD COLUMN version INTEGER NOT NULL DEFAULT(0)",
+                [],
+            )?;
+        }
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS guild_audit(
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                field STRING NOT NULL,
+                old_value STRING,
+                new_value STRING,
+                ts INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_guild_field(&mut self, name: &str, def: &str) -> anyhow::Result<()> {
+        self.create_guild_tables()?;
         let count: usize = self.conn.query_row(
             "SELECT COUNT(*) FROM pragma_table_info('guild') WHERE name = ?1",
             [name],
@@ -63,6 +343,139 @@ impl Db {
             .map_err(anyhow::Error::from)?;
         Ok(())
     }
+
+    // Last migration version applied for a module, 0 if none
+    pub fn schema_version(&self, module: &str) -> anyhow::Result<u32> {
+        self.create_migrations_table()?;
+        let version: Option<u32> = self.conn.query_row(
+            "SELECT MAX(version) FROM schema_migrations WHERE module = ?1",
+            [module],
+            |row| row.get(0),
+        )?;
+        Ok(version.unwrap_or(0))
+    }
+
+    fn create_migrations_table(&self) -> anyhow::Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                module STRING NOT NULL,
+                version INTEGER NOT NULL,
+                name STRING NOT NULL,
+                applied_at INTEGER NOT NULL,
+                PRIMARY KEY(module, version)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // Apply the migrations of a module that are newer than its recorded version, each in its
+    // own transaction so that a failing migration leaves the previous ones applied
+    pub fn apply_migrations(
+        &mut self,
+        module: &str,
+        migrations: &[Migration],
+    ) -> anyhow::Result<()> {
+        if migrations.is_empty() {
+            return Ok(());
+        }
+        if let Some(w) = migrations.windows(2).find(|w| w[0].version >= w[1].version) {
+            anyhow::bail!(
+                "Migrations of {module} are out of order: {} comes after {}",
+                w[1].version,
+                w[0].version
+            );
+        }
+        let current = self.schema_version(module)?;
+        for migration in migrations.iter().filter(|m| m.version > current) {
+            self.apply_migration(module, migration).with_context(|| {
+                format!(
+                    "migration {} of {module} ({}) failed",
+                    migration.version, migration.name
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn apply_migration(&mut self, module: &str, migration: &Migration) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        match migration.step {
+            MigrationStep::Sql(sql) => tx.execute_batch(sql)?,
+            MigrationStep::Run(f) => f(&tx)?,
+        }
+        tx.execute(
+            "INSERT INTO schema_migrations (module, version, name, applied_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                module,
+                migration.version,
+                migration.name,
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// Body of Db::set_guild_field_versioned, for callers that already hold a transaction
+pub(crate) fn update_guild_field<T: ToSql>(
+    conn: &Connection,
+    guild_id: GuildId,
+    actor: UserId,
+    field: &str,
+    value: T,
+    expected: Option<i64>,
+) -> anyhow::Result<i64> {
+    let version: Option<i64> = match conn.query_row(
+        "SELECT version FROM guild WHERE id = ?1",
+        [SqlGuildId(guild_id)],
+        |row| row.get(0),
+    ) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        res => Some(res?),
+    };
+    if let (Some(expected), Some(version)) = (expected, version) {
+        if expected != version {
+            return Err(SettingsConflict {
+                field: field.to_string(),
+            }
+            .into());
+        }
+    }
+    let old_value = match conn.query_row(
+        &format!("SELECT {field} FROM guild WHERE id = ?1"),
+        [SqlGuildId(guild_id)],
+        |row| value_as_text(row.get_ref(0)?),
+    ) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        res => res?,
+    };
+    let new_value = match value.to_sql()? {
+        ToSqlOutput::Borrowed(v) => value_as_text(v)?,
+        ToSqlOutput::Owned(v) => value_as_text((&v).into())?,
+        _ => None,
+    };
+    let updated = conn.execute(
+        &format!("UPDATE guild SET {field} = ?2, version = version + 1 WHERE id = ?1"),
+        params![SqlGuildId(guild_id), value],
+    )?;
+    if updated > 0 && old_value != new_value {
+        conn.execute(
+            "INSERT INTO guild_audit (guild_id, user_id, field, old_value, new_value, ts)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                SqlGuildId(guild_id),
+                SqlUserId(actor),
+                field,
+                old_value,
+                new_value,
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+    }
+    Ok(version.map_or(0, |v| v + 1))
 }
 
 pub fn escape_str(s: &str) -> Cow<'_, str> {
@@ -83,21 +496,52 @@ pub fn column_as_string(val: ValueRef<'_>) -> rusqlite::Result<String> {
     })
 }
 
+fn value_as_text(val: ValueRef<'_>) -> rusqlite::Result<Option<String>> {
+    match val {
+        ValueRef::Null => Ok(None),
+        val => column_as_string(val).map(Some),
+    }
+}
+
 impl Handler {
-    pub async fn get_guild_field<T: FromSql + Default>(
+    pub async fn get_guild_field<T: FromSql + Default + Send + 'static>(
         &self,
-        guild_id: u64,
+        guild_id: GuildId,
         field: &str,
     ) -> anyhow::Result<T> {
-        self.db.lock().await.get_guild_field(guild_id, field)
+        let field = field.to_string();
+        self.db_read(move |db| db.get_guild_field(guild_id, &field))
+            .await
+    }
+
+    pub async fn guild_version(&self, guild_id: GuildId) -> anyhow::Result<i64> {
+        self.db_read(move |db| db.guild_version(guild_id)).await
     }
 
     pub async fn set_guild_field<T: ToSql>(
         &self,
-        guild_id: u64,
+        guild_id: GuildId,
+        actor: UserId,
         field: &str,
         value: T,
     ) -> anyhow::Result<()> {
-        self.db.lock().await.set_guild_field(guild_id, field, value)
+        self.db
+            .lock()
+            .await
+            .set_guild_field(guild_id, actor, field, value)
+    }
+
+    pub async fn set_guild_field_versioned<T: ToSql>(
+        &self,
+        guild_id: GuildId,
+        actor: UserId,
+        field: &str,
+        value: T,
+        expected: Option<i64>,
+    ) -> anyhow::Result<i64> {
+        self.db
+            .lock()
+            .await
+            .set_guild_field_versioned(guild_id, actor, field, value, expected)
     }
 }
@@ -0,0 +1,91 @@
+// Database work off the async executor.
+// `Handler::db_call` runs a closure on the main connection in a blocking thread, so that
+// waiting on SQLite doesn't block other tasks. With `HandlerBuilder::concurrent_reads`,
+// `Handler::db_read` runs read-only work on separate connections to the same file, so
+// concurrent reads don't queue behind the main connection's lock. Otherwise, and for
+// databases in memory which can't be opened twice, reads use the main connection.
+// Code holding `handler.db.lock()` keeps working, new code should prefer these.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::{Connection, OpenFlags};
+use tokio::task::spawn_blocking;
+
+use crate::db::Db;
+use crate::Handler;
+
+// Idle read connections kept open, more are opened when needed
+const MAX_IDLE_READERS: usize = 4;
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct ReadPool {
+    path: Option<String>,
+    idle: Mutex<Vec<Db>>,
+}
+
+impl ReadPool {
+    pub(crate) fn for_db(db: &Db) -> anyhow::Result<Self> {
+        let path = db.conn.path().filter(|p| !p.is_empty()).map(String::from);
+        if path.is_some() {
+            // lets readers run while the main connection writes
+            db.conn.pragma_update(None, "journal_mode", "WAL")?;
+        }
+        Ok(ReadPool {
+            path,
+            idle: Default::default(),
+        })
+    }
+
+    fn take(&self) -> anyhow::Result<Option<Db>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        if let Some(db) = self.idle.lock().unwrap().pop() {
+            return Ok(Some(db));
+        }
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(path, flags)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(Some(Db { conn }))
+    }
+
+    fn put_back(&self, db: Db) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_READERS {
+            idle.push(db);
+        }
+    }
+}
+
+impl Handler {
+    // Run database work on the main connection, e.g. writes, without blocking the executor
+    pub async fn db_call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Db) -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut db = self.db.clone().lock_owned().await;
+        spawn_blocking(move || f(&mut db)).await?
+    }
+
+    // Run read-only database work, concurrently with other reads when the database is a file
+    pub async fn db_read<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&Db) -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let Some(reader) = self.read_pool.take()? else {
+            return self.db_call(move |db| f(db)).await;
+        };
+        let (reader, res) = spawn_blocking(move || {
+            let res = f(&reader);
+            (reader, res)
+        })
+        .await?;
+        self.read_pool.put_back(reader);
+        res
+    }
+}
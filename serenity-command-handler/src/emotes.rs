@@ -0,0 +1,110 @@
+// Validation and autocompletion of emote options.
+// Emotes given to commands are checked when the command is run, rather than failing later
+// when the bot first tries to react with them.
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use serenity::builder::{CreateAutocompleteResponse, CreateInteractionResponse};
+use serenity::model::prelude::{CommandInteraction, Emoji, GuildId, ReactionType};
+use serenity::prelude::Context;
+
+// Discord limits autocomplete responses to 25 choices
+const MAX_CHOICES: usize = 25;
+const KEYCAP: char = '\u{20e3}';
+
+// Whether a string looks like a unicode emoji rather than arbitrary text.
+// Keycap emojis (e.g. 1️⃣) are the only ones containing ASCII alphanumerics.
+pub fn is_unicode_emote(s: &str) -> bool {
+    !s.is_empty()
+        && !s.contains(char::is_whitespace)
+        && (s.contains(KEYCAP) || !s.contains(|c: char| c.is_ascii_alphanumeric()))
+}
+
+// Custom emotes of a guild, from the cache if possible
+pub async fn guild_emotes(ctx: &Context, guild_id: GuildId) -> anyhow::Result<Vec<Emoji>> {
+    let cached = ctx
+        .cache
+        .guild(guild_id)
+        .map(|guild| guild.emojis.values().cloned().collect());
+    match cached {
+        Some(emotes) => Ok(emotes),
+        None => Ok(guild_id.emojis(&ctx.http).await?),
+    }
+}
+
+// Parse an emote option and make sure the bot can react with it in the guild.
+// Custom emotes can also be given by name, e.g. `blobcat` or `:blobcat:`.
+pub async fn validate_emote(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    s: &str,
+) -> anyhow::Result<ReactionType> {
+    let s = s.trim();
+    if is_unicode_emote(s) {
+        return Ok(ReactionType::Unicode(s.to_string()));
+    }
+    let Some(guild_id) = guild_id else {
+        bail!("Custom emotes can only be used in a server");
+    };
+    let emotes = guild_emotes(ctx, guild_id).await?;
+    let emote = match ReactionType::from_str(s) {
+        Ok(ReactionType::Custom { id, name, .. }) => {
+            emotes.into_iter().find(|e| e.id == id).ok_or_else(|| {
+                let name = name.unwrap_or_else(|| id.to_string());
+                anyhow!("`{name}` is not an emote from this server, upload it here first")
+            })?
+        }
+        _ => {
+            let name = s.trim_matches(':');
+            emotes
+                .into_iter()
+                .find(|e| e.name == name)
+                .ok_or_else(|| anyhow!("`{s}` is not an emoji or an emote from this server"))?
+        }
+    };
+    if !emote.available {
+        bail!(
+            "`{}` is unavailable, the server may have lost the boost level it needs",
+            emote.name
+        );
+    }
+    if !emote.roles.is_empty() {
+        let bot_id = ctx.cache.current_user().id;
+        let member = guild_id.member(ctx, bot_id).await?;
+        if !emote.roles.iter().any(|role| member.roles.contains(role)) {
+            bail!(
+                "`{}` is restricted to some roles, give one of them to the bot to use it",
+                emote.name
+            );
+        }
+    }
+    Ok(ReactionType::Custom {
+        animated: emote.animated,
+        id: emote.id,
+        name: Some(emote.name),
+    })
+}
+
+// Respond to an autocomplete request with the guild's custom emotes matching `partial`
+pub async fn complete_emotes(
+    ctx: &Context,
+    ac: &CommandInteraction,
+    partial: &str,
+) -> anyhow::Result<()> {
+    let guild_id = ac
+        .guild_id
+        .ok_or_else(|| anyhow!("must be run in a guild"))?;
+    let partial = partial.trim_matches(':').to_lowercase();
+    let mut emotes = guild_emotes(ctx, guild_id).await?;
+    emotes.retain(|e| e.available && e.name.to_lowercase().contains(&partial));
+    emotes.sort_by(|a, b| a.name.cmp(&b.name));
+    let resp = emotes
+        .into_iter()
+        .take(MAX_CHOICES)
+        .fold(CreateAutocompleteResponse::new(), |resp, emote| {
+            resp.add_string_choice(format!(":{}:", emote.name), emote.to_string())
+        });
+    ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+        .await?;
+    Ok(())
+}
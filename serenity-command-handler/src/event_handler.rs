@@ -0,0 +1,116 @@
+// serenity EventHandler for bots that leave all of their events to the Handler, instead of
+// each binary wiring interactions, messages and reactions to it:
+//
+//     Client::builder(token, intents).event_handler(FrameworkEventHandler::new(handler))
+//
+// On the first ready event it starts the presence rotation and the scheduler, and syncs
+// commands if the bot_management feature is enabled. Other events go to the handlers modules
+// registered for them, `Ready` included.
+use std::sync::Arc;
+
+use serenity::all::{
+    ChannelId, ChannelPinsUpdateEvent, GuildId, Interaction, Member, Message, MessageId,
+    MessageUpdateEvent, Reaction, Ready, User,
+};
+use serenity::async_trait;
+use serenity::prelude::{Context, EventHandler};
+
+use crate::gateway::{
+    ChannelPinsUpdate, MemberJoin, MemberLeave, MessageCreate, MessageDelete, MessageUpdate,
+    ReactionAdd, ReactionRemove,
+};
+use crate::presence::presence_loop;
+use crate::{scheduler, Handler};
+
+pub struct FrameworkEventHandler(pub Arc<Handler>);
+
+impl FrameworkEventHandler {
+    pub fn new(handler: Handler) -> Self {
+        FrameworkEventHandler(Arc::new(handler))
+    }
+}
+
+#[async_trait]
+impl EventHandler for FrameworkEventHandler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let handler = &self.0;
+        // ready is sent again on reconnects
+        if handler.self_id.set(ready.user.id).is_ok() {
+            let presence = presence_loop(Arc::clone(&handler.presence), ctx.clone());
+            handler.spawn_task("presence", presence);
+            let jobs = scheduler::run(Arc::clone(handler), Arc::clone(&ctx.http));
+            handler.spawn_task("scheduler", jobs);
+            #[cfg(feature = "bot_management")]
+            if let Err(e) = handler.sync_commands(&ctx.http, &[], false).await {
+                tracing::error!("Failed to register commands: {e:?}");
+            }
+        }
+        _ = handler.http.set(Arc::clone(&ctx.http));
+        handler.dispatch(&ctx, &ready).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        self.0.process_interaction(ctx, interaction).await;
+    }
+
+    async fn message(&self, ctx: Context, message: Message) {
+        self.0.dispatch(&ctx, &MessageCreate(message)).await;
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        old: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        let event = MessageUpdate { old, new, event };
+        self.0.dispatch(&ctx, &event).await;
+    }
+
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        let event = MessageDelete {
+            channel_id,
+            message_id,
+            guild_id,
+        };
+        self.0.dispatch(&ctx, &event).await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        self.0.dispatch(&ctx, &ReactionAdd(reaction)).await;
+    }
+
+    async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        self.0.dispatch(&ctx, &ReactionRemove(reaction)).await;
+    }
+
+    async fn guild_member_addition(&self, ctx: Context, member: Member) {
+        self.0.dispatch(&ctx, &MemberJoin(member)).await;
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        member: Option<Member>,
+    ) {
+        let event = MemberLeave {
+            guild_id,
+            user,
+            member,
+        };
+        self.0.dispatch(&ctx, &event).await;
+    }
+
+    async fn channel_pins_update(&self, ctx: Context, pin: ChannelPinsUpdateEvent) {
+        self.0.dispatch(&ctx, &ChannelPinsUpdate(pin)).await;
+    }
+}
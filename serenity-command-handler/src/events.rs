@@ -27,16 +27,13 @@ impl EventHandlers {
         handler: F,
     ) {
         let e = self.0.entry::<EventHandlerKey<E>>();
-        e.or_insert(Vec::new()).push(Box::new(handler));
+        e.or_default().push(Box::new(handler));
     }
 
     pub fn emit<E: Sync + Send + 'static>(&self, event: &E) {
-        match self.0.get::<EventHandlerKey<E>>() {
-            None => return (),
-            Some(handlers) => {
-                for h in handlers {
-                    tokio::spawn(h(event));
-                }
+        if let Some(handlers) = self.0.get::<EventHandlerKey<E>>() {
+            for h in handlers {
+                tokio::spawn(h(event));
             }
         }
     }
@@ -0,0 +1,101 @@
+// Sample data for trying out the framework, see examples/basic_bot.rs.
+// Each fixture only seeds tables of modules that were added to the builder.
+use serenity::model::id::GuildId;
+
+use crate::HandlerBuilder;
+
+// Placeholder IDs used as quote authors and birthday users
+#[cfg(any(feature = "quotes", feature = "bdays"))]
+const SAMPLE_USERS: [u64; 3] = [80351110224678912, 81384788765712384, 83010416610906112];
+
+#[cfg(feature = "quotes")]
+const SAMPLE_QUOTES: [&str; 3] = [
+    "this album is a grower, give it a few more listens",
+    "I've had it on repeat all week",
+    "that's not a real genre",
+];
+
+#[cfg(feature = "autoreact")]
+const SAMPLE_AUTOREACTS: [(&str, &str); 2] = [("crab", "🦀"), ("banger", "🔥")];
+
+impl HandlerBuilder {
+    // Seed the tables of the loaded modules with sample data for the given guild.
+    // Meant for in-memory databases (see `Handler::builder_in_memory`).
+    #[allow(unused_variables)]
+    pub fn seed_fixtures(self, guild_id: GuildId) -> anyhow::Result<Self> {
+        #[cfg(feature = "quotes")]
+        if self.modules.contains::<crate::modules::Quotes>() {
+            seed_quotes(&self, guild_id)?;
+        }
+        #[cfg(feature = "bdays")]
+        if self.modules.contains::<crate::modules::bdays::Bdays>() {
+            seed_bdays(&self, guild_id)?;
+        }
+        #[cfg(feature = "autoreact")]
+        if self.modules.contains::<crate::modules::ModAutoreacts>() {
+            seed_autoreacts(&self, guild_id)?;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "quotes")]
+fn seed_quotes(builder: &HandlerBuilder, guild_id: GuildId) -> anyhow::Result<()> {
+    use crate::db::{SqlChannelId, SqlGuildId, SqlMessageId, SqlUserId};
+    use rusqlite::params;
+
+    let now = chrono::Utc::now().timestamp();
+    for (i, (&contents, &author)) in SAMPLE_QUOTES.iter().zip(&SAMPLE_USERS).enumerate() {
+        builder.db.conn.execute(
+            "INSERT OR IGNORE INTO quote (
+                guild_id, channel_id, message_id, ts, quote_number,
+                author_id, author_name, contents
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                SqlGuildId(guild_id),
+                SqlChannelId(guild_id.get().into()),
+                SqlMessageId((guild_id.get() + i as u64 + 1).into()),
+                now,
+                i + 1,
+                SqlUserId(author.into()),
+                format!("user{}", i + 1),
+                contents,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bdays")]
+fn seed_bdays(builder: &HandlerBuilder, guild_id: GuildId) -> anyhow::Result<()> {
+    use crate::db::{SqlGuildId, SqlUserId};
+    use rusqlite::params;
+
+    for (i, &user) in SAMPLE_USERS.iter().enumerate() {
+        builder.db.conn.execute(
+            "INSERT OR IGNORE INTO bdays (guild_id, user_id, day, month)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                SqlGuildId(guild_id),
+                SqlUserId(user.into()),
+                10 + i,
+                1 + i * 4
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "autoreact")]
+fn seed_autoreacts(builder: &HandlerBuilder, guild_id: GuildId) -> anyhow::Result<()> {
+    use crate::db::SqlGuildId;
+    use rusqlite::params;
+
+    for (trigger, emote) in SAMPLE_AUTOREACTS {
+        builder.db.conn.execute(
+            "INSERT INTO autoreact (guild_id, trigger, emote) VALUES (?1, ?2, ?3)",
+            params![SqlGuildId(guild_id), trigger, emote],
+        )?;
+    }
+    Ok(())
+}
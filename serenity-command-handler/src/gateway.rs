@@ -0,0 +1,82 @@
+// Gateway events (messages, reactions, members...) handled by modules.
+// Modules register typed handlers in `Module::register_gateway_handlers`, e.g.
+// `handlers.add::<ReactionAdd>(on_reaction)`. `FrameworkEventHandler` passes every event to
+// them, bots with their own EventHandler call `Handler::dispatch` instead.
+use std::any::type_name;
+use std::marker::PhantomData;
+
+use futures::future::{join_all, BoxFuture};
+use serenity::all::{
+    ChannelId, ChannelPinsUpdateEvent, GuildId, Member, Message, MessageId, MessageUpdateEvent,
+    Reaction, User,
+};
+use serenity::prelude::Context;
+use typemap_rev::{TypeMap, TypeMapKey};
+
+use crate::Handler;
+
+pub struct MessageCreate(pub Message);
+
+pub struct MessageUpdate {
+    pub old: Option<Message>,
+    pub new: Option<Message>,
+    pub event: MessageUpdateEvent,
+}
+
+pub struct MessageDelete {
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub guild_id: Option<GuildId>,
+}
+
+pub struct ReactionAdd(pub Reaction);
+
+pub struct ReactionRemove(pub Reaction);
+
+pub struct MemberJoin(pub Member);
+
+pub struct MemberLeave {
+    pub guild_id: GuildId,
+    pub user: User,
+    pub member: Option<Member>,
+}
+
+pub struct ChannelPinsUpdate(pub ChannelPinsUpdateEvent);
+
+pub type GatewayHandler<E> = for<'a> fn(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a E,
+) -> BoxFuture<'a, anyhow::Result<()>>;
+
+struct GatewayHandlerKey<E>(PhantomData<E>);
+
+impl<E: 'static> TypeMapKey for GatewayHandlerKey<E> {
+    type Value = Vec<GatewayHandler<E>>;
+}
+
+#[derive(Default)]
+pub struct GatewayHandlers(TypeMap);
+
+impl GatewayHandlers {
+    pub fn add<E: 'static>(&mut self, handler: GatewayHandler<E>) {
+        self.0
+            .entry::<GatewayHandlerKey<E>>()
+            .or_default()
+            .push(handler);
+    }
+}
+
+impl Handler {
+    // Run the handlers registered for an event, at once
+    pub async fn dispatch<E: Send + Sync + 'static>(&self, ctx: &Context, event: &E) {
+        let Some(handlers) = self.gateway_handlers.0.get::<GatewayHandlerKey<E>>() else {
+            return;
+        };
+        let results = join_all(handlers.iter().map(|h| h(self, ctx, event))).await;
+        for e in results.into_iter().filter_map(Result::err) {
+            let event = type_name::<E>().rsplit("::").next().unwrap_or_default();
+            tracing::error!(event, "Error handling gateway event: {e:?}");
+        }
+    }
+}
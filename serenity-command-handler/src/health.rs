@@ -0,0 +1,112 @@
+// Health checks of the loaded modules, run by /bot_health.
+// Modules implement `Module::health_check` to verify what they depend on (e.g. an API token
+// or a table). Until a later check passes, commands of a module whose last check failed
+// answer with a notice instead of failing with an opaque error. Bots can also call
+// `Handler::check_health` at startup to log the report.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use tokio::time::timeout;
+
+use crate::{module_name, Handler, Module};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum HealthStatus {
+    Healthy,
+    Degraded(String),
+}
+
+impl HealthStatus {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, HealthStatus::Healthy)
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Healthy => f.write_str("healthy"),
+            HealthStatus::Degraded(reason) => write!(f, "degraded ({reason})"),
+        }
+    }
+}
+
+type HealthCheck = for<'a> fn(&'a Handler) -> BoxFuture<'a, HealthStatus>;
+
+fn check_module<M: Module>(handler: &Handler) -> BoxFuture<'_, HealthStatus> {
+    async move {
+        match handler.module::<M>() {
+            Ok(m) => m.health_check(handler).await,
+            Err(e) => HealthStatus::Degraded(e.to_string()),
+        }
+    }
+    .boxed()
+}
+
+#[derive(Default)]
+pub struct Health {
+    checks: Vec<(&'static str, HealthCheck)>,
+    // module each command was registered by
+    command_modules: HashMap<&'static str, &'static str>,
+    // modules whose last check failed, with the reason
+    degraded: RwLock<HashMap<&'static str, String>>,
+}
+
+impl Health {
+    pub(crate) fn add_module<M: Module>(
+        &mut self,
+        commands: impl IntoIterator<Item = &'static str>,
+    ) {
+        let name = module_name::<M>();
+        self.checks.push((name, check_module::<M>));
+        for command in commands {
+            self.command_modules.insert(command, name);
+        }
+    }
+
+    // Names of the loaded modules
+    pub fn modules(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.checks.iter().map(|(name, _)| *name)
+    }
+
+    // Module that registered a command
+    pub fn command_module(&self, command: &str) -> Option<&'static str> {
+        self.command_modules.get(command).copied()
+    }
+
+    // Notice shown instead of running a command of a degraded module
+    pub(crate) fn unavailable_notice(&self, command: &str) -> Option<String> {
+        let module = self.command_modules.get(command)?;
+        self.degraded.read().unwrap().get(module)?;
+        Some(format!(
+            "/{command} is temporarily unavailable, please try again later"
+        ))
+    }
+}
+
+impl Handler {
+    // Run the checks of every module at once, and update which modules are degraded
+    pub async fn check_health(&self) -> Vec<(&'static str, HealthStatus)> {
+        let checks = self.health.checks.iter().map(|&(name, check)| async move {
+            let status = timeout(CHECK_TIMEOUT, check(self))
+                .await
+                .unwrap_or_else(|_| HealthStatus::Degraded("check timed out".to_string()));
+            (name, status)
+        });
+        let report = join_all(checks).await;
+        let mut degraded = self.health.degraded.write().unwrap();
+        degraded.clear();
+        for (name, status) in &report {
+            if let HealthStatus::Degraded(reason) = status {
+                degraded.insert(*name, reason.clone());
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,63 @@
+// Hooks run around every command, for checks and bookkeeping that apply to all commands
+// (blocklists, audit logs, latency reports) without changing each of them.
+// Pre-command hooks run in the order they were added, before the command. An error refuses
+// the command and is shown to the user like the command's own errors, e.g.
+// `Err(CommandError::PermissionDenied(..).into())`, and the remaining hooks and the command
+// don't run. Post-command hooks get the command's result and how long it took, before the
+// response is sent, so they should be quick.
+use std::time::Duration;
+
+use futures::future::{join_all, BoxFuture};
+use serenity::model::application::CommandInteraction;
+use serenity::prelude::Context;
+use serenity_command::CommandResponse;
+
+use crate::Handler;
+
+pub type PreCommandHook = for<'a> fn(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    command: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<()>>;
+
+pub type PostCommandHook = for<'a> fn(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    command: &'a CommandInteraction,
+    result: &'a anyhow::Result<CommandResponse>,
+    elapsed: Duration,
+) -> BoxFuture<'a, anyhow::Result<()>>;
+
+#[derive(Default)]
+pub struct CommandHooks {
+    pub pre: Vec<PreCommandHook>,
+    pub post: Vec<PostCommandHook>,
+}
+
+impl Handler {
+    pub(crate) async fn run_pre_command_hooks(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<()> {
+        for hook in &self.command_hooks.pre {
+            hook(self, ctx, command).await?;
+        }
+        Ok(())
+    }
+
+    // Post-command hooks don't depend on each other, run them at once
+    pub(crate) async fn run_post_command_hooks(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        result: &anyhow::Result<CommandResponse>,
+        elapsed: Duration,
+    ) {
+        let hooks = self.command_hooks.post.iter();
+        let results = join_all(hooks.map(|hook| hook(self, ctx, command, result, elapsed))).await;
+        for e in results.into_iter().filter_map(Result::err) {
+            tracing::error!("Error in post-command hook: {e:?}");
+        }
+    }
+}
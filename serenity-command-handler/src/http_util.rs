@@ -0,0 +1,147 @@
+// HTTP client for modules calling external APIs and websites (Last.fm, Bandcamp...).
+// Requests to the same host are limited to a few at once, and rate limited requests (429)
+// are retried after the delay asked by the server, or with exponential backoff and jitter.
+// Requests and retries are counted in the metrics by client name, e.g.
+//
+//     let client = RateLimitedClient::new("bandcamp");
+//     let page = client.get(url).await?.text().await?;
+//
+// `on_response` adds a hook called after each response, to log or time requests.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context as _;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode, Url};
+use tokio::sync::Semaphore;
+
+use crate::metrics::metrics;
+
+const DEFAULT_HOST_CONCURRENCY: usize = 4;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+// Servers asking to wait longer than this are not retried
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub type ResponseHook = fn(client: &'static str, url: &Url, status: StatusCode, elapsed: Duration);
+
+pub struct RateLimitedClient {
+    name: &'static str,
+    client: Client,
+    host_concurrency: usize,
+    max_retries: u32,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    hooks: Vec<ResponseHook>,
+}
+
+// Delay before retrying for the `attempt`th time, doubled each time, plus up to 50% of jitter
+// so that concurrent requests don't retry all at once
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY * 2u32.pow(attempt.min(10));
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    delay + delay.mul_f64(nanos as f64 / 2e9)
+}
+
+// Retry-After is either a number of seconds or a date, only the former is supported
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let secs = resp
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+impl RateLimitedClient {
+    pub fn new(name: &'static str) -> Self {
+        Self::with_client(name, Client::new())
+    }
+
+    pub fn with_client(name: &'static str, client: Client) -> Self {
+        RateLimitedClient {
+            name,
+            client,
+            host_concurrency: DEFAULT_HOST_CONCURRENCY,
+            max_retries: DEFAULT_MAX_RETRIES,
+            hosts: Default::default(),
+            hooks: Vec::new(),
+        }
+    }
+
+    // Maximum number of requests to the same host at once
+    pub fn host_concurrency(mut self, n: usize) -> Self {
+        self.host_concurrency = n.max(1);
+        self
+    }
+
+    pub fn max_retries(mut self, n: u32) -> Self {
+        self.max_retries = n;
+        self
+    }
+
+    pub fn on_response(mut self, hook: ResponseHook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    // Request to send with `send`, for requests that need headers or a body
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub async fn get<U: IntoUrl>(&self, url: U) -> anyhow::Result<Response> {
+        self.send(self.client.get(url)).await
+    }
+
+    fn host_semaphore(&self, url: &Url) -> Arc<Semaphore> {
+        let host = url.host_str().unwrap_or_default().to_string();
+        let mut hosts = self.hosts.lock().unwrap();
+        let semaphore = hosts
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.host_concurrency)));
+        Arc::clone(semaphore)
+    }
+
+    // Send a request, retrying it while it is rate limited. The last response is returned
+    // as is, whatever its status.
+    pub async fn send(&self, req: RequestBuilder) -> anyhow::Result<Response> {
+        let req = req.build()?;
+        let semaphore = self.host_semaphore(req.url());
+        // the permit is kept while waiting to retry, to slow down the other requests to the host
+        let _permit = semaphore.acquire().await?;
+        let mut attempt = 0;
+        loop {
+            let this_try = req
+                .try_clone()
+                .context("streamed requests can't be rate limited")?;
+            metrics().increment("http_requests", self.name);
+            let start = Instant::now();
+            let resp = self.client.execute(this_try).await?;
+            for hook in &self.hooks {
+                hook(self.name, req.url(), resp.status(), start.elapsed());
+            }
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= self.max_retries {
+                return Ok(resp);
+            }
+            metrics().increment("http_rate_limited", self.name);
+            let delay = retry_after(&resp).unwrap_or_else(|| backoff(attempt));
+            if delay > MAX_DELAY {
+                return Ok(resp);
+            }
+            tracing::debug!(
+                client = self.name,
+                url = %req.url(),
+                "Rate limited, retrying in {delay:?}"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Leases for background loops, so that only one bot instance runs them when several share
+// the same database (e.g. during blue/green deploys).
+// The instance holding a loop's lease renews it on every tick. Other instances skip the
+// loop's work until the lease expires, at which point the first one to tick takes it over.
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use fallible_iterator::FallibleIterator;
+use rusqlite::params;
+
+use crate::db::Db;
+use crate::Handler;
+
+// Set to give instances a recognizable name in /bot_health
+const INSTANCE_VAR: &str = "BOT_INSTANCE_ID";
+// Ticks a holder can miss before its lease expires
+const MISSED_TICKS: u32 = 2;
+const LEASE_MARGIN_SECS: i64 = 30;
+
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
+// Identifies this process among the instances sharing the database
+pub fn instance_id() -> &'static str {
+    INSTANCE_ID.get_or_init(|| {
+        std::env::var(INSTANCE_VAR).unwrap_or_else(|_| {
+            let started = chrono::Utc::now().timestamp_millis();
+            format!("{}-{started:x}", std::process::id())
+        })
+    })
+}
+
+#[derive(Debug)]
+pub struct Lease {
+    pub job: String,
+    pub holder: String,
+    pub renewed_at: i64,
+    pub expires_at: i64,
+}
+
+impl Lease {
+    pub fn is_ours(&self) -> bool {
+        self.holder == instance_id()
+    }
+}
+
+impl Db {
+    pub fn create_lease_table(&self) -> anyhow::Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_lease (
+                job STRING PRIMARY KEY,
+                holder STRING NOT NULL,
+                renewed_at INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // Take or renew the lease of a job until `expires_at` (unix timestamp).
+    // Returns whether this instance holds the lease.
+    pub fn acquire_lease(&self, job: &str, now: i64, expires_at: i64) -> anyhow::Result<bool> {
+        let changed = self.conn.execute(
+            "INSERT INTO job_lease (job, holder, renewed_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(job) DO UPDATE SET holder = ?2, renewed_at = ?3, expires_at = ?4
+             WHERE holder = ?2 OR expires_at <= ?3",
+            params![job, instance_id(), now, expires_at],
+        )?;
+        Ok(changed > 0)
+    }
+
+    // Give up the leases of this instance, e.g. when shutting down
+    pub fn release_leases(&self) -> anyhow::Result<()> {
+        self.create_lease_table()?;
+        self.conn
+            .execute("DELETE FROM job_lease WHERE holder = ?1", [instance_id()])?;
+        Ok(())
+    }
+
+    pub fn leases(&self) -> anyhow::Result<Vec<Lease>> {
+        let leases = self
+            .conn
+            .prepare("SELECT job, holder, renewed_at, expires_at FROM job_lease ORDER BY job")?
+            .query([])?
+            .map(|row| {
+                Ok(Lease {
+                    job: row.get(0)?,
+                    holder: row.get(1)?,
+                    renewed_at: row.get(2)?,
+                    expires_at: row.get(3)?,
+                })
+            })
+            .collect()?;
+        Ok(leases)
+    }
+}
+
+// Called by a loop on every tick, `period` being the loop's interval.
+// Returns whether this instance should run the loop's work, which is never the case in
+// maintenance mode.
+pub async fn holds_lease(handler: &Handler, job: &str, period: Duration) -> bool {
+    let validity = (period * MISSED_TICKS).as_secs() as i64 + LEASE_MARGIN_SECS;
+    let now = handler.clock.now().timestamp();
+    let db = handler.db.lock().await;
+    match db.maintenance() {
+        Ok(None) => (),
+        Ok(Some(_)) => return false,
+        Err(e) => tracing::error!("Error checking maintenance mode: {e:?}"),
+    }
+    match db.acquire_lease(job, now, now + validity) {
+        Ok(held) => held,
+        Err(e) => {
+            // skipping is safer than posting twice
+            tracing::error!("Error acquiring lease for {job}: {e:?}");
+            false
+        }
+    }
+}
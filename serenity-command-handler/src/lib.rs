@@ -1,33 +1,72 @@
+use std::collections::HashSet;
 use std::fmt::Write;
 use std::{collections::HashMap, marker::PhantomData, sync::Arc, time::Instant};
 
 use anyhow::{anyhow, bail};
 use rusqlite::Connection;
 use serenity::model::prelude::{GuildId, UserId};
+use serenity::model::Permissions;
 use serenity::{
     async_trait,
+    builder::{CreateAutocompleteResponse, CreateCommand, CreateInteractionResponse},
     futures::future::BoxFuture,
     http::Http,
     model::application::{
-        CommandDataOption, CommandDataOptionValue, CommandInteraction, Interaction,
+        CommandDataOption, CommandDataOptionValue, CommandInteraction, ComponentInteraction,
+        Interaction, ModalInteraction,
     },
     prelude::{Context, Mutex, RwLock, TypeMap, TypeMapKey},
 };
 use tokio::sync::OnceCell;
+use tracing::Instrument;
 
-use serenity_command::{CommandKey, CommandResponse};
+use serenity_command::{CommandError, CommandKey, CommandResponse, NotInGuild};
 
 pub mod album;
+pub mod catalog;
+pub mod clock;
+#[cfg(feature = "charts")]
+pub mod charts;
 pub mod command_context;
+pub mod csv;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod date_format;
 pub mod db;
+pub mod db_pool;
+pub mod emotes;
+pub mod event_handler;
+pub mod fixtures;
+pub mod gateway;
+pub mod health;
+pub mod hooks;
+pub mod http_util;
+pub mod lease;
+pub mod logging;
+pub mod maintenance;
+pub mod modal;
+pub mod mentions;
+pub mod metrics;
 pub mod modules;
+pub mod normalize;
+pub mod presence;
+pub mod retry_queue;
+pub mod scheduler;
+pub mod schema;
+pub mod soft_delete;
+pub mod tasks;
+pub mod time_parse;
 
 pub mod events;
 
-use db::Db;
+use catalog::Catalog;
+use clock::{Clock, SystemClock};
+use db::{Db, Migration};
 
 use command_context::Responder;
 
+pub use event_handler::FrameworkEventHandler;
+
 pub type CommandStore = serenity_command::CommandStore<'static, Handler>;
 
 type SpecialCommand = for<'a> fn(
@@ -62,6 +101,26 @@ pub type CompletionHandler = for<'a> fn(
 
 pub type CompletionStore = Vec<CompletionHandler>;
 
+// Handles interactions with message components (e.g. buttons).
+// Returns Ok(true) if the interaction was handled, Ok(false) to let other handlers try.
+pub type ComponentHandler = for<'a> fn(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    interaction: &'a ComponentInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+pub type ComponentStore = Vec<ComponentHandler>;
+
+// Handlers for submitted modals, returning whether they handled the modal like component
+// handlers
+pub type ModalHandler = for<'a> fn(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    interaction: &'a ModalInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+pub type ModalStore = Vec<ModalHandler>;
+
 #[derive(Default)]
 pub struct ModuleMap(TypeMap);
 
@@ -92,12 +151,21 @@ impl ModuleMap {
 
 pub trait InteractionExt {
     fn guild_id(&self) -> anyhow::Result<GuildId>;
+    fn locale(&self) -> &str;
+    fn guild_locale(&self) -> Option<&str>;
 }
 
 impl InteractionExt for CommandInteraction {
     fn guild_id(&self) -> anyhow::Result<GuildId> {
-        self.guild_id
-            .ok_or_else(|| anyhow!("Must be run in a server"))
+        self.guild_id.ok_or_else(|| NotInGuild.into())
+    }
+
+    fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    fn guild_locale(&self) -> Option<&str> {
+        self.guild_locale.as_deref()
     }
 }
 
@@ -108,9 +176,21 @@ pub struct Handler {
     pub modules: ModuleMap,
     pub special_commands: HashMap<String, SpecialCommand>,
     pub completion_handlers: CompletionStore,
+    pub component_handlers: ComponentStore,
+    pub modal_handlers: ModalStore,
     pub default_command_handler: Option<SpecialCommand>,
     pub self_id: OnceCell<UserId>,
     pub event_handlers: Arc<events::EventHandlers>,
+    pub catalog: Catalog,
+    pub presence: Arc<presence::Presence>,
+    pub clock: Arc<dyn Clock>,
+    pub health: health::Health,
+    pub pages: command_context::PageStore,
+    pub read_pool: db_pool::ReadPool,
+    pub tasks: tasks::TaskManager,
+    pub jobs: scheduler::JobStore,
+    pub gateway_handlers: gateway::GatewayHandlers,
+    pub command_hooks: hooks::CommandHooks,
 }
 
 impl Handler {
@@ -122,11 +202,28 @@ impl Handler {
             modules: Default::default(),
             special_commands: Default::default(),
             completion_handlers: Default::default(),
+            // pages of Paginator responses
+            component_handlers: vec![command_context::handle_page],
+            modal_handlers: Default::default(),
             default_command_handler: None,
             event_handlers: events::EventHandlers::default(),
+            catalog: Catalog::default(),
+            clock: Arc::new(SystemClock),
+            health: Default::default(),
+            jobs: Default::default(),
+            gateway_handlers: Default::default(),
+            command_hooks: Default::default(),
+            log_to_stderr: false,
+            log_callback: None,
+            concurrent_reads: false,
         }
     }
 
+    // Builder backed by a fresh in-memory database, nothing is persisted
+    pub fn builder_in_memory() -> anyhow::Result<HandlerBuilder> {
+        Ok(Self::builder(Connection::open_in_memory()?))
+    }
+
     pub fn module<M: Module>(&self) -> anyhow::Result<&M> {
         self.modules.module()
     }
@@ -135,15 +232,60 @@ impl Handler {
         self.modules.module_arc()
     }
 
+    // Borrow a module without requiring it to be loaded
+    pub fn try_module<M: Module>(&self) -> Option<&M> {
+        self.modules.module().ok()
+    }
+
+    // Translate a message in the language of the user who ran the command
+    pub fn tr<'a>(&'a self, interaction: &CommandInteraction, key: &'a str) -> &'a str {
+        self.catalog.for_interaction(interaction, key)
+    }
+
+    // Render embeds as text in guilds that enabled the plain text fallback.
+    // The setting only exists when the settings module is loaded, hence the default.
+    async fn text_fallback(
+        &self,
+        guild_id: Option<GuildId>,
+        resp: CommandResponse,
+    ) -> CommandResponse {
+        let enabled = match guild_id {
+            Some(guild_id) => self
+                .get_guild_field(guild_id, "plain_text")
+                .await
+                .unwrap_or(false),
+            None => false,
+        };
+        if enabled {
+            command_context::plain_text_response(resp)
+        } else {
+            resp
+        }
+    }
+
     async fn process_command(
         &self,
         ctx: &Context,
         cmd: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
         let name = cmd.data.name.as_str();
+        let user_id = cmd.user.id;
+        let notice = self
+            .db_read(move |db| maintenance::notice_for(db, user_id))
+            .await?;
+        if let Some(notice) = notice {
+            return CommandResponse::private(notice);
+        }
+        if let Some(notice) = self.health.unavailable_notice(name) {
+            return CommandResponse::private(notice);
+        }
         if let Some(special) = self.special_commands.get(name) {
             return special(self, ctx, cmd).await;
         }
+        #[cfg(feature = "command_channels")]
+        if let Some(denied) = modules::command_channels::check_restrictions(self, cmd).await? {
+            return CommandResponse::private(denied);
+        }
         let key = (name, cmd.data.kind);
         if let Some(runner) = self.commands.read().await.0.get(&key) {
             runner.run(self, ctx, cmd).await
@@ -154,55 +296,197 @@ impl Handler {
         }
     }
 
-    pub async fn process_interaction(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Autocomplete(ac) = interaction {
-            let name = ac.data.name.clone();
-            let key = (name.as_str(), ac.data.kind);
-            for h in &self.completion_handlers {
-                match h(self, &ctx, key, &ac).await {
-                    Err(e) => {
-                        eprintln!("Autocomplete interaction failed for command {name}: {e:?}");
-                        return;
+    // Whether a command is handled by the framework, either from the store or by a special
+    // or default command handler
+    pub async fn has_command(&self, cmd: &CommandInteraction) -> bool {
+        let name = cmd.data.name.as_str();
+        let key = (name, cmd.data.kind);
+        self.special_commands.contains_key(name)
+            || self.default_command_handler.is_some()
+            || self.commands.read().await.0.contains_key(&key)
+    }
+
+    // Whether completions for a command can be shown to the user typing it: they must have
+    // the command's default permissions, and pass the command's own check.
+    // Autocomplete interactions can be sent for commands the user cannot see.
+    async fn can_complete(&self, ctx: &Context, ac: &CommandInteraction) -> anyhow::Result<bool> {
+        let key = (ac.data.name.as_str(), ac.data.kind);
+        let commands = self.commands.read().await;
+        let Some(runner) = commands.0.get(&key) else {
+            return Ok(true);
+        };
+        // permissions only apply in guilds
+        if let Some(member) = &ac.member {
+            let granted = member.permissions.unwrap_or(Permissions::empty());
+            if !granted.contains(runner.permissions()) {
+                return Ok(false);
+            }
+        }
+        runner.can_complete(self, ctx, ac).await
+    }
+
+    // Command registrations for a scope (None for global commands), for bots that register
+    // commands themselves instead of calling `sync_commands`
+    pub async fn create_commands(&self, guild: Option<GuildId>) -> Vec<CreateCommand> {
+        self.commands
+            .read()
+            .await
+            .0
+            .values()
+            .filter(|runner| runner.guild() == guild)
+            .map(|runner| runner.register())
+            .collect()
+    }
+
+    async fn run_command(&self, ctx: &Context, command: &CommandInteraction) {
+        let span = tracing::info_span!(
+            "command",
+            command = %command.data.name,
+            guild_id = command.guild_id.map(|g| g.get()),
+            user_id = %command.user.id,
+        );
+        if self.tasks.is_shutting_down() {
+            let notice = self.tr(command, tasks::SHUTDOWN_NOTICE);
+            let resp = CommandResponse::Private(notice.into());
+            let respond = command.respond(&ctx.http, resp, None, Default::default());
+            if let Err(e) = respond.await {
+                tracing::error!("cannot respond to slash command: {e:?}");
+            }
+            return;
+        }
+        let run = self.run_command_inner(ctx, command).instrument(span);
+        self.tasks.track_command(run).await
+    }
+
+    async fn run_command_inner(&self, ctx: &Context, command: &CommandInteraction) {
+        let params = format_options(&command.data.options);
+        tracing::info!(user = %command.user.name, %params, "command received");
+
+        let start = Instant::now();
+        let resp = match self.run_pre_command_hooks(ctx, command).await {
+            Ok(()) => self.process_command(ctx, command).await,
+            Err(e) => Err(e),
+        };
+        let latency = start.elapsed();
+        metrics::metrics().record_command(&command.data.name, latency, resp.is_err());
+        self.run_post_command_hooks(ctx, command, &resp, latency)
+            .await;
+        let latency_ms = latency.as_millis() as u64;
+        #[cfg(feature = "stats")]
+        if let Err(e) = modules::stats::record_command(self, command).await {
+            tracing::warn!("cannot record command usage: {e:?}");
+        }
+        let resp = match resp {
+            Ok(resp) => {
+                tracing::info!(latency_ms, "command finished: {resp:?}");
+                resp
+            }
+            Err(e) => {
+                let e = CommandError::from(e);
+                if e.is_internal() {
+                    tracing::error!(latency_ms, "command failed: {e:?}");
+                } else {
+                    tracing::info!(latency_ms, "command refused: {e}");
+                }
+                let msg = e.user_message();
+                CommandResponse::Private(self.tr(command, &msg).into())
+            }
+        };
+        let resp = self.text_fallback(command.guild_id, resp).await;
+        let policy = self
+            .mention_policy(command.guild_id, &command.data.name)
+            .await;
+
+        if let Err(why) = command.respond(&ctx.http, resp, None, policy).await {
+            tracing::error!("cannot respond to slash command: {why:?}");
+        }
+    }
+
+    // Returns whether a handler answered the autocomplete, and whether it failed
+    async fn run_autocomplete(&self, ctx: &Context, ac: &CommandInteraction) -> (bool, bool) {
+        let name = ac.data.name.as_str();
+        let key = (name, ac.data.kind);
+        match self.can_complete(ctx, ac).await {
+            Ok(true) => (),
+            allowed => {
+                let failed = allowed.is_err();
+                if let Err(e) = allowed {
+                    tracing::error!("Autocomplete check failed for command {name}: {e:?}");
+                }
+                let resp = CreateAutocompleteResponse::new();
+                let resp = CreateInteractionResponse::Autocomplete(resp);
+                if let Err(e) = ac.create_response(&ctx.http, resp).await {
+                    tracing::error!("Cannot respond to autocomplete for {name}: {e:?}");
+                }
+                return (true, failed);
+            }
+        }
+        for h in &self.completion_handlers {
+            match h(self, ctx, key, ac).await {
+                Err(e) => {
+                    tracing::error!("Autocomplete interaction failed for command {name}: {e:?}");
+                    return (true, true);
+                }
+                Ok(true) => return (true, false),
+                Ok(false) => continue,
+            }
+        }
+        (false, false)
+    }
+
+    // Handle a single interaction, for bots that already have their own EventHandler.
+    // Returns false if no command, completion or component handler matched, in which case
+    // nothing was sent to Discord and the caller is free to handle the interaction.
+    pub async fn handle_interaction(&self, ctx: &Context, interaction: &Interaction) -> bool {
+        match interaction {
+            Interaction::Autocomplete(ac) => {
+                let start = Instant::now();
+                let (handled, failed) = self.run_autocomplete(ctx, ac).await;
+                metrics::metrics().record_autocomplete(&ac.data.name, start.elapsed(), failed);
+                handled
+            }
+            Interaction::Component(component) => {
+                let custom_id = &component.data.custom_id;
+                for h in &self.component_handlers {
+                    match h(self, ctx, component).await {
+                        Err(e) => {
+                            tracing::error!("Component interaction failed for {custom_id}: {e:?}");
+                            return true;
+                        }
+                        Ok(true) => return true,
+                        Ok(false) => continue,
                     }
-                    Ok(true) => break,
-                    Ok(false) => continue,
                 }
+                false
             }
-            // if let Some(handler) = self.completion_handlers.get(&key) {
-            //     _ = handler(self, key, ac).await;
-            // }
-        } else if let Interaction::Command(command) = interaction {
-            // log command
-            let guild_name = if let Some(guild) = command.guild_id {
-                guild
-                    .to_partial_guild(&ctx.http)
-                    .await
-                    .map(|guild| format!("[{}] ", &guild.name))
-                    .unwrap_or_default()
-            } else {
-                String::new()
-            };
-            let user = &command.user.name;
-            let name = &command.data.name;
-            let params = format_options(&command.data.options);
-            eprintln!("{guild_name}{user}: /{name} {params}");
-
-            let start = Instant::now();
-            let resp = self.process_command(&ctx, &command).await;
-            let elapsed = start.elapsed();
-            eprintln!(
-                "{guild_name}{user}: /{name} -({:.1?})-> {:?}",
-                elapsed, &resp
-            );
-            let resp = match resp {
-                Ok(resp) => resp,
-                Err(e) => CommandResponse::Private(e.to_string().into()),
-            };
-
-            if let Err(why) = command.respond(&ctx.http, resp, None).await {
-                eprintln!("cannot respond to slash command: {why:?}");
-                return;
+            Interaction::Modal(modal) => {
+                let custom_id = &modal.data.custom_id;
+                for h in &self.modal_handlers {
+                    match h(self, ctx, modal).await {
+                        Err(e) => {
+                            tracing::error!("Modal interaction failed for {custom_id}: {e:?}");
+                            return true;
+                        }
+                        Ok(true) => return true,
+                        Ok(false) => continue,
+                    }
+                }
+                false
             }
+            Interaction::Command(command) if self.has_command(command).await => {
+                self.run_command(ctx, command).await;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn process_interaction(&self, ctx: Context, interaction: Interaction) {
+        // unknown commands still get an error response here
+        if let Interaction::Command(command) = &interaction {
+            self.run_command(&ctx, command).await;
+        } else {
+            self.handle_interaction(&ctx, &interaction).await;
         }
     }
 }
@@ -213,8 +497,19 @@ pub struct HandlerBuilder {
     pub modules: ModuleMap,
     pub special_commands: HashMap<String, SpecialCommand>,
     pub completion_handlers: CompletionStore,
+    pub component_handlers: ComponentStore,
+    pub modal_handlers: ModalStore,
     pub default_command_handler: Option<SpecialCommand>,
-    pub event_handlers: events::EventHandlers
+    pub event_handlers: events::EventHandlers,
+    pub catalog: Catalog,
+    pub clock: Arc<dyn Clock>,
+    pub health: health::Health,
+    pub jobs: scheduler::JobStore,
+    pub gateway_handlers: gateway::GatewayHandlers,
+    pub command_hooks: hooks::CommandHooks,
+    pub log_to_stderr: bool,
+    pub log_callback: Option<logging::LogCallback>,
+    pub concurrent_reads: bool,
 }
 
 impl HandlerBuilder {
@@ -223,22 +518,40 @@ impl HandlerBuilder {
             return Ok(self);
         }
         self = M::add_dependencies(self).await?;
-        let mut m = M::init(&self.modules).await?;
-        m.setup(&mut self.db).await?;
-        m.register_commands(&mut self.commands, &mut self.completion_handlers);
-        m.register_event_handlers(&mut self.event_handlers);
-        self.modules.add(m);
-        Ok(self)
+        let m = M::init(&self.modules).await?;
+        self.add_module(m).await
     }
 
-    pub async fn with_module<M: Module>(mut self, mut m: M) -> anyhow::Result<Self> {
+    pub async fn with_module<M: Module>(mut self, m: M) -> anyhow::Result<Self> {
         if self.modules.contains::<M>() {
             return Ok(self);
         }
         self = M::add_dependencies(self).await?;
+        self.add_module(m).await
+    }
+
+    async fn add_module<M: Module>(mut self, mut m: M) -> anyhow::Result<Self> {
+        if !M::MIGRATIONS.is_empty() && M::NAME.is_empty() {
+            anyhow::bail!("{} has migrations but no Module::NAME", module_name::<M>());
+        }
+        self.db.apply_migrations(M::NAME, M::MIGRATIONS)?;
         m.setup(&mut self.db).await?;
+        let before: HashSet<_> = self.commands.0.keys().map(|(name, _)| *name).collect();
         m.register_commands(&mut self.commands, &mut self.completion_handlers);
+        // remember which commands the module added, to disable them while it is degraded
+        let added = self
+            .commands
+            .0
+            .keys()
+            .map(|(name, _)| *name)
+            .filter(|name| !before.contains(name))
+            .collect::<Vec<_>>();
+        self.health.add_module::<M>(added);
         m.register_event_handlers(&mut self.event_handlers);
+        m.register_component_handlers(&mut self.component_handlers);
+        m.register_modal_handlers(&mut self.modal_handlers);
+        m.register_jobs(&mut self.jobs);
+        m.register_gateway_handlers(&mut self.gateway_handlers);
         self.modules.add(m);
         Ok(self)
     }
@@ -248,6 +561,59 @@ impl HandlerBuilder {
         self
     }
 
+    // Run a hook before every command, which can refuse it by returning an error
+    pub fn add_pre_command_hook(mut self, hook: hooks::PreCommandHook) -> Self {
+        self.command_hooks.pre.push(hook);
+        self
+    }
+
+    // Run a hook after every command, with its result and how long it took
+    pub fn add_post_command_hook(mut self, hook: hooks::PostCommandHook) -> Self {
+        self.command_hooks.post.push(hook);
+        self
+    }
+
+    pub fn default_locale(mut self, locale: &str) -> Self {
+        self.catalog.set_default_locale(locale);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Run `Handler::db_read` on separate read-only connections instead of the main one.
+    // This switches the database file to WAL journaling, which persists: SQLite then keeps
+    // -wal and -shm files next to it, and the database can no longer be on a network drive.
+    pub fn concurrent_reads(mut self) -> Self {
+        self.concurrent_reads = true;
+        self
+    }
+
+    // Print the handler's log events, unless the bot installed its own tracing subscriber
+    pub fn log_to_stderr(mut self) -> Self {
+        self.log_to_stderr = true;
+        self
+    }
+
+    // Pass the handler's log events to a callback, with the fields of their spans
+    pub fn log_callback(mut self, f: impl Fn(&logging::LogEvent) + Send + Sync + 'static) -> Self {
+        self.log_callback = Some(Arc::new(f));
+        self
+    }
+
+    pub fn translations<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(
+        mut self,
+        locale: &str,
+        messages: I,
+    ) -> Self {
+        messages
+            .into_iter()
+            .for_each(|(key, text)| self.catalog.add(locale, key, text));
+        self
+    }
+
     pub fn build(self) -> Handler {
         let HandlerBuilder {
             db,
@@ -255,9 +621,31 @@ impl HandlerBuilder {
             modules,
             special_commands,
             completion_handlers,
+            component_handlers,
+            modal_handlers,
             default_command_handler,
             event_handlers,
+            catalog,
+            clock,
+            health,
+            jobs,
+            gateway_handlers,
+            command_hooks,
+            log_to_stderr,
+            log_callback,
+            concurrent_reads,
         } = self;
+        if log_to_stderr || log_callback.is_some() {
+            logging::install(log_to_stderr, log_callback);
+        }
+        let read_pool = if concurrent_reads {
+            db_pool::ReadPool::for_db(&db).unwrap_or_else(|e| {
+                tracing::warn!("Cannot open read connections, reads will use the main one: {e:?}");
+                Default::default()
+            })
+        } else {
+            Default::default()
+        };
         Handler {
             db: Arc::new(Mutex::new(db)),
             commands: RwLock::new(commands),
@@ -265,9 +653,21 @@ impl HandlerBuilder {
             modules,
             special_commands,
             completion_handlers,
+            component_handlers,
+            modal_handlers,
             default_command_handler,
             self_id: OnceCell::default(),
             event_handlers: Arc::new(event_handlers),
+            catalog,
+            presence: Arc::new(presence::Presence::new(Arc::clone(&clock))),
+            clock,
+            health,
+            pages: Default::default(),
+            read_pool,
+            tasks: Default::default(),
+            jobs,
+            gateway_handlers,
+            command_hooks,
         }
     }
 }
@@ -294,7 +694,36 @@ pub trait Module: 'static + Send + Sync + Sized {
     ) {
     }
 
+    fn register_component_handlers(&self, _handlers: &mut ComponentStore) {}
+
+    fn register_modal_handlers(&self, _handlers: &mut ModalStore) {}
+
+    // Messages, reactions, members... forwarded by `FrameworkEventHandler`
+    fn register_gateway_handlers(&self, _handlers: &mut gateway::GatewayHandlers) {}
+
+    // Jobs run by `scheduler::run` when they are due
+    fn register_jobs(&self, _jobs: &mut scheduler::JobStore) {}
+
+    // Checked by /bot_health, the module's commands are disabled while it is degraded
+    async fn health_check(&self, _handler: &Handler) -> health::HealthStatus {
+        health::HealthStatus::Healthy
+    }
+
     const AUTOCOMPLETES: &'static [&'static str] = &[];
+
+    // Name the module's migrations are recorded under, required once it has migrations.
+    // It must not change afterwards, even if the type is renamed.
+    const NAME: &'static str = "";
+
+    // Versioned schema changes, applied before `setup`
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+// Short name of a module type, without its path and generic parameters
+pub(crate) fn module_name<M: Module>() -> &'static str {
+    let name = std::any::type_name::<M>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 pub trait ModuleKey {
@@ -308,7 +737,9 @@ impl<T: 'static + Send + Sync + Module> TypeMapKey for KeyWrapper<T> {
 }
 
 pub mod prelude {
+    pub use super::health::HealthStatus;
     pub use super::{
-        CommandStore, CompletionStore, Handler, HandlerBuilder, InteractionExt, Module, ModuleMap,
+        CommandStore, CompletionStore, ComponentStore, Handler, HandlerBuilder, InteractionExt,
+        ModalStore, Module, ModuleMap,
     };
 }
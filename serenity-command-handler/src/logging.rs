@@ -0,0 +1,98 @@
+// Log output of the handler, which logs with `tracing`.
+// Commands run in a `command` span with the guild id, user id and command name, and log their
+// latency when they finish. Bots that install their own subscriber get these events as is.
+// Others can ask the builder for one: `log_to_stderr` prints events, filtered with RUST_LOG
+// (info by default), and `log_callback` passes them to a function, e.g. to forward errors to
+// a log channel.
+use std::fmt;
+use std::sync::Arc;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    // fields of the spans the event happened in, outermost first, then of the event
+    pub fields: Vec<(&'static str, String)>,
+}
+
+pub type LogCallback = Arc<dyn Fn(&LogEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields.push((field.name(), format!("{value:?}")));
+        }
+    }
+}
+
+// Fields recorded when a span was created, kept in its extensions
+struct SpanFields(Vec<(&'static str, String)>);
+
+struct CallbackLayer(LogCallback);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CallbackLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let mut fields = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.0.iter().cloned());
+                }
+            }
+        }
+        fields.extend(visitor.fields);
+        let metadata = event.metadata();
+        (self.0)(&LogEvent {
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields,
+        });
+    }
+}
+
+// Install the global subscriber, does nothing if the bot already installed one
+pub(crate) fn install(stderr: bool, callback: Option<LogCallback>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let stderr = stderr.then(|| tracing_subscriber::fmt::layer().with_writer(std::io::stderr));
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr)
+        .with(callback.map(CallbackLayer))
+        .try_init();
+}
@@ -0,0 +1,75 @@
+// Maintenance mode, turned on with /maintenance during deploys and migrations.
+// While it is on, commands from non-admins are answered with a notice instead of running,
+// and background loops are paused. It is stored in the database so that it applies to every
+// instance sharing it.
+use rusqlite::{params, OptionalExtension};
+use serenity::model::prelude::UserId;
+
+use crate::db::Db;
+
+pub const DEFAULT_NOTICE: &str = "The bot is under maintenance, please try again in a few minutes";
+
+impl Db {
+    pub fn create_maintenance_table(&self) -> anyhow::Result<()> {
+        // single row table, present while maintenance mode is on
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS maintenance (
+                id INTEGER PRIMARY KEY CHECK(id = 0),
+                message STRING NOT NULL,
+                since INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // Turn maintenance mode on with a notice for users, or off with None
+    pub fn set_maintenance(&self, message: Option<&str>) -> anyhow::Result<()> {
+        self.create_maintenance_table()?;
+        match message {
+            Some(message) => self.conn.execute(
+                "INSERT INTO maintenance (id, message, since) VALUES (0, ?1, ?2)
+                 ON CONFLICT(id) DO UPDATE SET message = ?1",
+                params![message, chrono::Utc::now().timestamp()],
+            )?,
+            None => self.conn.execute("DELETE FROM maintenance", [])?,
+        };
+        Ok(())
+    }
+
+    // The notice shown to users if maintenance mode is on.
+    // It is always off when the bot management module was never loaded.
+    pub fn maintenance(&self) -> anyhow::Result<Option<String>> {
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS(
+                SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'maintenance'
+            )",
+            [],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(None);
+        }
+        let message = self
+            .conn
+            .query_row("SELECT message FROM maintenance WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(message)
+    }
+}
+
+// The notice to answer a user's command with, None if the command can run.
+// Admins can still run commands, e.g. to turn maintenance mode off.
+#[cfg_attr(not(feature = "sql"), allow(unused_variables))]
+pub fn notice_for(db: &Db, user: UserId) -> anyhow::Result<Option<String>> {
+    let Some(notice) = db.maintenance()? else {
+        return Ok(None);
+    };
+    #[cfg(feature = "sql")]
+    if crate::modules::sql::is_admin(&db.conn, user)? {
+        return Ok(None);
+    }
+    Ok(Some(notice))
+}
@@ -0,0 +1,119 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::bail;
+use rusqlite::OptionalExtension;
+use serenity::builder::{CreateAllowedMentions, ExecuteWebhook};
+use serenity::model::prelude::{GuildId, RoleId, UserId};
+
+use crate::db::SqlGuildId;
+use crate::Handler;
+
+// Which mentions found in a message's content are allowed to ping.
+// Roles and users passed explicitly (e.g. the LP role) can always ping, the policy only
+// covers mentions that may come from user-provided content. Nothing pings by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MentionPolicy {
+    pub users: bool,
+    pub roles: bool,
+    pub everyone: bool,
+}
+
+impl MentionPolicy {
+    pub fn allowed_mentions<R, U>(&self, roles: R, users: U) -> CreateAllowedMentions
+    where
+        R: IntoIterator<Item = RoleId>,
+        U: IntoIterator<Item = UserId>,
+    {
+        // Discord rejects explicit ids along with parsing the same kind of mentions
+        let mut mentions = CreateAllowedMentions::new().everyone(self.everyone);
+        mentions = if self.users {
+            mentions.all_users(true)
+        } else {
+            mentions.users(users)
+        };
+        if self.roles {
+            mentions.all_roles(true)
+        } else {
+            mentions.roles(roles)
+        }
+    }
+
+    // Webhook message builder with the policy applied, webhooks ping everything by default
+    pub fn execute_webhook<R, U>(&self, roles: R, users: U) -> ExecuteWebhook
+    where
+        R: IntoIterator<Item = RoleId>,
+        U: IntoIterator<Item = UserId>,
+    {
+        ExecuteWebhook::new().allowed_mentions(self.allowed_mentions(roles, users))
+    }
+}
+
+// Formatted as a comma-separated list of mention kinds, e.g. "users,roles" or "none"
+impl FromStr for MentionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut policy = MentionPolicy::default();
+        for kind in s.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            match kind {
+                "none" => {}
+                "users" => policy.users = true,
+                "roles" => policy.roles = true,
+                "everyone" => policy.everyone = true,
+                _ => bail!("Unknown mention kind `{kind}`"),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl Display for MentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds = [
+            (self.users, "users"),
+            (self.roles, "roles"),
+            (self.everyone, "everyone"),
+        ]
+        .into_iter()
+        .filter_map(|(allowed, kind)| allowed.then_some(kind))
+        .collect::<Vec<_>>();
+        if kinds.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&kinds.join(","))
+        }
+    }
+}
+
+impl Handler {
+    // Policy for messages sent by a command: the command's override if the guild set one,
+    // otherwise the guild's default.
+    // The settings only exist when the settings module is loaded, hence the default.
+    pub async fn mention_policy(&self, guild_id: Option<GuildId>, command: &str) -> MentionPolicy {
+        let Some(guild_id) = guild_id else {
+            return MentionPolicy::default();
+        };
+        let db = self.db.lock().await;
+        let overridden: Option<String> = db
+            .conn
+            .query_row(
+                "SELECT policy FROM mention_policy WHERE guild_id = ?1 AND command = ?2",
+                rusqlite::params![SqlGuildId(guild_id), command],
+                |row| row.get(0),
+            )
+            .optional()
+            .ok()
+            .flatten();
+        let policy = match overridden {
+            Some(policy) => Some(policy),
+            None => db
+                .get_guild_field::<Option<String>>(guild_id, "mention_policy")
+                .ok()
+                .flatten(),
+        };
+        policy
+            .and_then(|policy| policy.parse().ok())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,203 @@
+// Usage metrics of the bot, in the Prometheus text format.
+// The handler counts command and autocomplete calls, their failures and latency per command.
+// Modules count their own calls, e.g. to external APIs, with `metrics().increment`. Counters
+// are kept for the whole process, since modules like Lastfm don't have access to the
+// handler.
+// Read them with `Handler::metrics_snapshot`, or serve them for Prometheus to scrape with the
+// `metrics_endpoint` feature:
+//
+//     tokio::spawn(metrics::serve("0.0.0.0:9100".parse()?));
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::Handler;
+
+// Upper bounds of the latency histogram buckets, in milliseconds
+const LATENCY_BUCKETS_MS: [u64; 10] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    // number of observations in each bucket, not cumulative
+    pub buckets: [u64; LATENCY_BUCKETS_MS.len()],
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let ms = value.as_millis() as u64;
+        if let Some(i) = LATENCY_BUCKETS_MS.iter().position(|&bound| ms <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallMetrics {
+    pub calls: u64,
+    pub errors: u64,
+    pub latency: Histogram,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    // by command name
+    pub commands: BTreeMap<String, CallMetrics>,
+    pub autocompletes: BTreeMap<String, CallMetrics>,
+    // module counters by name and label, e.g. Last.fm calls by API method
+    pub counters: BTreeMap<(&'static str, String), u64>,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_calls(out: &mut String, prefix: &str, what: &str, calls: &BTreeMap<String, CallMetrics>) {
+    let _ = writeln!(out, "# HELP {prefix}_total {what} run");
+    let _ = writeln!(out, "# TYPE {prefix}_total counter");
+    for (name, m) in calls {
+        let _ = writeln!(
+            out,
+            "{prefix}_total{{command=\"{}\"}} {}",
+            escape_label(name),
+            m.calls
+        );
+    }
+    let _ = writeln!(out, "# HELP {prefix}_errors_total {what} that failed");
+    let _ = writeln!(out, "# TYPE {prefix}_errors_total counter");
+    for (name, m) in calls {
+        let _ = writeln!(
+            out,
+            "{prefix}_errors_total{{command=\"{}\"}} {}",
+            escape_label(name),
+            m.errors
+        );
+    }
+    let _ = writeln!(out, "# HELP {prefix}_latency_seconds Time taken by {what}");
+    let _ = writeln!(out, "# TYPE {prefix}_latency_seconds histogram");
+    for (name, m) in calls {
+        let name = escape_label(name);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(&m.latency.buckets) {
+            cumulative += count;
+            let le = *bound as f64 / 1000.;
+            let _ = writeln!(
+                out,
+                "{prefix}_latency_seconds_bucket{{command=\"{name}\",le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let hist = &m.latency;
+        let _ = writeln!(
+            out,
+            "{prefix}_latency_seconds_bucket{{command=\"{name}\",le=\"+Inf\"}} {}",
+            hist.count
+        );
+        let _ = writeln!(
+            out,
+            "{prefix}_latency_seconds_sum{{command=\"{name}\"}} {}",
+            hist.sum.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "{prefix}_latency_seconds_count{{command=\"{name}\"}} {}",
+            hist.count
+        );
+    }
+}
+
+impl MetricsSnapshot {
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        write_calls(&mut out, "discord_commands", "Commands", &self.commands);
+        write_calls(
+            &mut out,
+            "discord_autocompletes",
+            "Autocompletions",
+            &self.autocompletes,
+        );
+        let mut last = None;
+        for ((counter, label), value) in &self.counters {
+            if last != Some(counter) {
+                let _ = writeln!(out, "# TYPE discord_{counter}_total counter");
+                last = Some(counter);
+            }
+            let label = escape_label(label);
+            let _ = writeln!(out, "discord_{counter}_total{{kind=\"{label}\"}} {value}");
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics(Mutex<MetricsSnapshot>);
+
+impl Metrics {
+    pub(crate) fn record_command(&self, name: &str, latency: Duration, failed: bool) {
+        let mut metrics = self.0.lock().unwrap();
+        let m = metrics.commands.entry(name.to_string()).or_default();
+        m.calls += 1;
+        m.errors += failed as u64;
+        m.latency.observe(latency);
+    }
+
+    pub(crate) fn record_autocomplete(&self, name: &str, latency: Duration, failed: bool) {
+        let mut metrics = self.0.lock().unwrap();
+        let m = metrics.autocompletes.entry(name.to_string()).or_default();
+        m.calls += 1;
+        m.errors += failed as u64;
+        m.latency.observe(latency);
+    }
+
+    // Count an event of a module, e.g. `increment("lastfm_api_calls", "user.getTopAlbums")`
+    pub fn increment(&self, counter: &'static str, label: &str) {
+        let mut metrics = self.0.lock().unwrap();
+        *metrics
+            .counters
+            .entry((counter, label.to_string()))
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Default::default)
+}
+
+impl Handler {
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        metrics().snapshot()
+    }
+}
+
+// Serve the metrics on /metrics until the process exits
+#[cfg(feature = "metrics_endpoint")]
+pub async fn serve(bind: std::net::SocketAddr) -> anyhow::Result<()> {
+    use axum::http::header::CONTENT_TYPE;
+    use axum::routing::get;
+    use axum::Router;
+
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics().snapshot().to_prometheus(),
+            )
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
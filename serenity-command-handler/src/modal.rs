@@ -0,0 +1,71 @@
+// Modal forms, opened in response to a command or a component interaction.
+// A form is a list of text inputs identified by their custom id. Its submission is handled
+// by a `ModalHandler` matching the form's custom id, which reads the fields back with
+// `input_value` or `required_value`.
+use anyhow::anyhow;
+use serenity::builder::{CreateActionRow, CreateInputText, CreateInteractionResponse, CreateModal};
+use serenity::model::application::{ActionRowComponent, InputTextStyle, ModalInteraction};
+
+pub struct ModalForm {
+    custom_id: String,
+    title: String,
+    inputs: Vec<CreateInputText>,
+}
+
+impl ModalForm {
+    pub fn new(custom_id: impl Into<String>, title: impl Into<String>) -> Self {
+        ModalForm {
+            custom_id: custom_id.into(),
+            title: title.into(),
+            inputs: Vec::new(),
+        }
+    }
+
+    // Single line input, required unless changed with `input`
+    pub fn short(self, id: &str, label: &str) -> Self {
+        self.input(CreateInputText::new(InputTextStyle::Short, label, id))
+    }
+
+    pub fn paragraph(self, id: &str, label: &str) -> Self {
+        self.input(CreateInputText::new(InputTextStyle::Paragraph, label, id))
+    }
+
+    // Discord allows up to 5 inputs per modal
+    pub fn input(mut self, input: CreateInputText) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn build(self) -> CreateModal {
+        CreateModal::new(self.custom_id, self.title).components(
+            self.inputs
+                .into_iter()
+                .map(CreateActionRow::InputText)
+                .collect(),
+        )
+    }
+
+    // Response opening the form, for `create_response` on commands and components
+    pub fn response(self) -> CreateInteractionResponse {
+        CreateInteractionResponse::Modal(self.build())
+    }
+}
+
+// Trimmed value of a submitted input, None if it was left empty
+pub fn input_value<'a>(modal: &'a ModalInteraction, id: &str) -> Option<&'a str> {
+    modal
+        .data
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find_map(|c| match c {
+            ActionRowComponent::InputText(input) if input.custom_id == id => input.value.as_deref(),
+            _ => None,
+        })
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+pub fn required_value<'a>(modal: &'a ModalInteraction, id: &str) -> anyhow::Result<&'a str> {
+    input_value(modal, id).ok_or_else(|| anyhow!("Missing value for {id}"))
+}
@@ -0,0 +1,194 @@
+// Cache of album lookups and searches, so that autocompleting or looking up the same album
+// again doesn't hit the providers and their rate limits.
+// Recently used entries are kept in memory, all of them are saved in the database until they
+// expire so that they survive restarts. Last.fm's album_cache table is unrelated, it only
+// holds release years.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::{future::BoxFuture, FutureExt};
+use rusqlite::{params, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use serenity::http::Http;
+
+use crate::db::Db;
+use crate::normalize::fold;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::Handler;
+
+const MEMORY_ENTRIES: usize = 500;
+// albums hardly change, searches do as new albums are released
+pub(crate) const ALBUM_TTL_SECS: i64 = 7 * 24 * 3600;
+pub(crate) const SEARCH_TTL_SECS: i64 = 6 * 3600;
+
+pub(crate) fn album_key(provider: &str, query: &str) -> String {
+    format!("album:{provider}:{}", fold(query))
+}
+
+pub(crate) fn url_key(provider: &str, url: &str) -> String {
+    format!("url:{provider}:{}", url.trim())
+}
+
+pub(crate) fn search_key(provider: &str, query: &str) -> String {
+    format!("search:{provider}:{}", fold(query))
+}
+
+struct Entry {
+    // serialized as JSON, like in the database
+    value: String,
+    expires_at: i64,
+}
+
+// Least recently used entries are evicted first
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    order: VecDeque<String>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str, now: i64) -> Option<String> {
+        let entry = self.entries.get(key)?;
+        if entry.expires_at <= now {
+            self.entries.remove(key);
+            self.order.retain(|k| k != key);
+            return None;
+        }
+        let value = entry.value.clone();
+        self.touch(key);
+        Some(value)
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            if let Some(key) = self.order.remove(pos) {
+                self.order.push_back(key);
+            }
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        if self.entries.insert(key.clone(), entry).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > MEMORY_ENTRIES {
+            if let Some(old) = self.order.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AlbumCache {
+    memory: Mutex<Lru>,
+    // set once the bot is connected, only the memory cache is used until then
+    db: OnceLock<Arc<tokio::sync::Mutex<Db>>>,
+}
+
+impl AlbumCache {
+    pub(crate) fn set_db(&self, db: Arc<tokio::sync::Mutex<Db>>) {
+        _ = self.db.set(db);
+    }
+
+    async fn get_saved(&self, key: &str, now: i64) -> anyhow::Result<Option<Entry>> {
+        let Some(db) = self.db.get() else {
+            return Ok(None);
+        };
+        let entry = db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT value, expires_at FROM album_lookup_cache
+                 WHERE key = ?1 AND expires_at > ?2",
+                params![key, now],
+                |row| {
+                    Ok(Entry {
+                        value: row.get(0)?,
+                        expires_at: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    // Cache errors are only logged, the value is looked up again instead
+    pub(crate) async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let now = Utc::now().timestamp();
+        let cached = self.memory.lock().unwrap().get(key, now);
+        let value = match cached {
+            Some(value) => value,
+            None => {
+                let entry = match self.get_saved(key, now).await {
+                    Ok(entry) => entry?,
+                    Err(e) => {
+                        tracing::warn!("Could not read album cache: {e:?}");
+                        return None;
+                    }
+                };
+                let value = entry.value.clone();
+                self.memory.lock().unwrap().insert(key.to_string(), entry);
+                value
+            }
+        };
+        match serde_json::from_str(&value) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!(key, "Invalid album cache entry: {e:?}");
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn insert<T: Serialize>(&self, key: String, value: &T, ttl_secs: i64) {
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!(key, "Could not serialize album cache entry: {e:?}");
+                return;
+            }
+        };
+        let expires_at = Utc::now().timestamp() + ttl_secs;
+        if let Some(db) = self.db.get() {
+            if let Err(e) = db.lock().await.conn.execute(
+                "INSERT OR REPLACE INTO album_lookup_cache (key, value, expires_at)
+                 VALUES (?1, ?2, ?3)",
+                params![key, value, expires_at],
+            ) {
+                tracing::warn!("Could not save album cache entry: {e:?}");
+            }
+        }
+        let entry = Entry { value, expires_at };
+        self.memory.lock().unwrap().insert(key, entry);
+    }
+}
+
+fn prune_album_cache<'a>(
+    handler: &'a Handler,
+    _http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = Utc::now().timestamp();
+        handler.db.lock().await.conn.execute(
+            "DELETE FROM album_lookup_cache WHERE expires_at <= ?1",
+            [now],
+        )?;
+        Ok(())
+    }
+    .boxed()
+}
+
+pub(crate) fn register_jobs(jobs: &mut JobStore) {
+    jobs.push(Job::new(
+        "album_lookup_cache",
+        Schedule::every(Duration::from_secs(3600)),
+        prune_album_cache,
+    ));
+}
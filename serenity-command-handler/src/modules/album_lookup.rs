@@ -1,25 +1,56 @@
-use serenity::model::prelude::CommandInteraction;
+use serenity::model::prelude::{CommandInteraction, Ready};
 use serenity::{async_trait, prelude::Context};
-use serenity_command::{BotCommand, CommandResponse};
-use serenity_command_derive::Command;
+use serenity_command::{BotCommand, CommandChoice, CommandError, CommandResponse};
+use serenity_command_derive::{Command, CommandChoice};
 
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fmt::Write;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout_at, Instant};
 
 use crate::album::{Album, AlbumProvider};
-use crate::db::Db;
+use crate::db::{Db, Migration};
+use crate::gateway::GatewayHandlers;
+use crate::modules::album_cache::{self, AlbumCache, ALBUM_TTL_SECS, SEARCH_TTL_SECS};
 use crate::modules::{Bandcamp, Lastfm, Spotify};
+use crate::normalize::fold;
+use crate::scheduler::JobStore;
 use crate::{CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap};
 
 use anyhow::bail;
 
+// Providers that can be picked in commands, named after their ids. Serialized the same way
+// for LP messages that embed the provider they were looked up with.
+#[derive(CommandChoice, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Spotify,
+    Bandcamp,
+    #[cfg(feature = "discogs")]
+    Discogs,
+}
+
+impl Provider {
+    pub fn id(self) -> &'static str {
+        self.value()
+    }
+}
+
 #[derive(Command)]
 #[cmd(name = "album", desc = "lookup an album")]
 struct LookupAlbum {
     #[cmd(desc = "The album you are looking for (e.g. band - album)")]
     album: String,
-    #[cmd(desc = "Where to look for album info (defaults to spotify)")]
-    provider: Option<String>,
+    #[cmd(desc = "Where to look for album info (defaults to spotify)", choice)]
+    provider: Option<Provider>,
 }
 
 #[async_trait]
@@ -31,11 +62,16 @@ impl BotCommand for LookupAlbum {
         _ctx: &Context,
         _opts: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let mut info = match handler
-            .module::<AlbumLookup>()?
-            .lookup_album(&self.album, self.provider.as_deref())
-            .await?
-        {
+        let lookup = handler.module::<AlbumLookup>()?;
+        let info = if self.album.starts_with("https://") {
+            lookup.get_album_info(&self.album).await
+        } else {
+            lookup
+                .lookup_album(&self.album, self.provider.map(Provider::id))
+                .await
+        };
+        let info = info.map_err(|e| CommandError::external("Album lookup", e))?;
+        let mut info = match info {
             None => bail!("Not found"),
             Some(info) => info,
         };
@@ -47,7 +83,10 @@ impl BotCommand for LookupAlbum {
                 .map(|d| format!(" ({d})"))
                 .unwrap_or_default(),
         );
-        if info.genres.is_empty() {
+        if let Some(episodes) = info.format_episodes() {
+            _ = writeln!(&mut contents, "{episodes}");
+        }
+        if info.genres.is_empty() && !info.is_episode && !info.is_playlist {
             if let Some(artist) = &info.artist {
                 info.genres = handler.module::<Lastfm>()?.artist_top_tags(artist).await?;
             }
@@ -55,34 +94,129 @@ impl BotCommand for LookupAlbum {
         if let Some(genres) = info.format_genres() {
             _ = writeln!(&mut contents, "{genres}");
         }
+        #[cfg(feature = "ratings")]
+        crate::modules::ratings::add_rating(handler, _opts.guild_id, &mut info).await;
+        if let Some(rating) = &info.rating {
+            _ = writeln!(&mut contents, "{rating}");
+        }
+        if let Some(physical) = info.format_physical() {
+            _ = writeln!(&mut contents, "{physical}");
+        }
         contents.push_str(info.url.as_deref().unwrap_or("no link found"));
         CommandResponse::public(contents)
     }
 }
 
+// Discord drops autocomplete responses after 3 seconds
+const AUTOCOMPLETE_BUDGET: Duration = Duration::from_millis(2500);
+// Providers slower than this on average are only used after the others
+const SLOW_LATENCY: Duration = Duration::from_millis(1500);
+// Weight of the latest call in a provider's average latency
+const LATENCY_WEIGHT: f64 = 0.3;
+const MAX_CHOICES: usize = 25;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyStats {
+    pub average: Duration,
+    pub calls: u64,
+    pub timeouts: u64,
+}
+
 pub struct AlbumLookup {
-    providers: Vec<Arc<dyn AlbumProvider>>,
+    // providers can be added after the module is initialized, see `register_provider`
+    providers: RwLock<Vec<Arc<dyn AlbumProvider>>>,
+    // higher priorities are tried first, providers default to 0
+    priorities: RwLock<HashMap<&'static str, i32>>,
+    latency: Mutex<HashMap<&'static str, LatencyStats>>,
+    cache: AlbumCache,
 }
 
 impl AlbumLookup {
-    pub fn get_provider(&self, provider: Option<&str>) -> &dyn AlbumProvider {
-        provider
-            .and_then(|id| self.providers.iter().find(|p| p.id() == id))
-            .or_else(|| self.providers.first())
+    // The requested provider, or the best ranked one if it is not known
+    pub fn get_provider(&self, provider: Option<&str>) -> anyhow::Result<Arc<dyn AlbumProvider>> {
+        let found = provider.and_then(|id| {
+            let providers = self.providers.read().unwrap();
+            providers.iter().find(|p| p.id() == id).cloned()
+        });
+        match found.or_else(|| self.ranked_providers().into_iter().next()) {
+            Some(p) => Ok(p),
+            None => bail!("No album providers available"),
+        }
+    }
+
+    // Providers by priority then registration order, except that slow providers go last
+    pub fn ranked_providers(&self) -> Vec<Arc<dyn AlbumProvider>> {
+        let latency = self.latency.lock().unwrap();
+        let priorities = self.priorities.read().unwrap();
+        self.providers
+            .read()
             .unwrap()
-            .as_ref()
+            .iter()
+            .sorted_by_key(|p| {
+                let slow = latency
+                    .get(p.id())
+                    .is_some_and(|s| s.average > SLOW_LATENCY);
+                (
+                    slow,
+                    Reverse(priorities.get(p.id()).copied().unwrap_or_default()),
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Add a provider, e.g. one implemented out of this crate. A provider with the same id
+    // replaces the existing one.
+    pub fn register_provider(&self, p: Arc<dyn AlbumProvider>) {
+        let mut providers = self.providers.write().unwrap();
+        match providers
+            .iter_mut()
+            .find(|existing| existing.id() == p.id())
+        {
+            Some(existing) => *existing = p,
+            None => providers.push(p),
+        }
+    }
+
+    pub fn set_priority(&self, provider: &'static str, priority: i32) {
+        self.priorities.write().unwrap().insert(provider, priority);
+    }
+
+    pub fn latency_stats(&self) -> HashMap<&'static str, LatencyStats> {
+        self.latency.lock().unwrap().clone()
+    }
+
+    fn record_latency(&self, provider: &'static str, elapsed: Duration, timed_out: bool) {
+        let mut latency = self.latency.lock().unwrap();
+        let stats = latency.entry(provider).or_default();
+        stats.average = if stats.calls == 0 {
+            elapsed
+        } else {
+            stats.average.mul_f64(1.0 - LATENCY_WEIGHT) + elapsed.mul_f64(LATENCY_WEIGHT)
+        };
+        stats.calls += 1;
+        stats.timeouts += timed_out as u64;
     }
 
-    pub fn providers(&self) -> &[Arc<dyn AlbumProvider>] {
-        &self.providers
+    pub fn providers(&self) -> Vec<Arc<dyn AlbumProvider>> {
+        self.providers.read().unwrap().clone()
     }
 
     pub async fn get_album_info(&self, link: &str) -> anyhow::Result<Option<Album>> {
-        if let Some(p) = self.providers.iter().find(|p| p.url_matches(link)) {
-            let info = p.get_from_url(link).await?;
+        let provider = self
+            .ranked_providers()
+            .into_iter()
+            .find(|p| p.url_matches(link));
+        let Some(p) = provider else {
+            return Ok(None);
+        };
+        let key = album_cache::url_key(p.id(), link);
+        if let Some(info) = self.cache.get(&key).await {
             return Ok(Some(info));
         }
-        Ok(None)
+        let info = p.get_from_url(link).await?;
+        self.cache.insert(key, &info, ALBUM_TTL_SECS).await;
+        Ok(Some(info))
     }
 
     pub async fn lookup_album(
@@ -90,27 +224,136 @@ impl AlbumLookup {
         query: &str,
         provider: Option<&str>,
     ) -> anyhow::Result<Option<Album>> {
-        let p = self.get_provider(provider);
-        p.query_album(query).await.map(Some)
+        let p = self.get_provider(provider)?;
+        let key = album_cache::album_key(p.id(), query);
+        if let Some(info) = self.cache.get(&key).await {
+            return Ok(Some(info));
+        }
+        let start = Instant::now();
+        let res = p.query_album(query).await;
+        self.record_latency(p.id(), start.elapsed(), false);
+        let info = res?;
+        self.cache.insert(key, &info, ALBUM_TTL_SECS).await;
+        Ok(Some(info))
     }
 
+    // Meant for autocompletion: without a provider, all providers are queried at once and
+    // whatever arrived before the latency budget ran out is returned, best providers first.
     pub async fn query_albums(
         &self,
         query: &str,
         provider: Option<&str>,
     ) -> anyhow::Result<Vec<(String, String)>> {
-        let p = self.get_provider(provider);
-        let mut choices = p.query_albums(query).await?;
-        choices.iter_mut().for_each(|(name, _)| {
-            if name.len() >= 100 {
-                *name = name.chars().take(100).collect();
+        let mut providers = self.ranked_providers();
+        if providers.iter().any(|p| Some(p.id()) == provider) {
+            providers.retain(|p| Some(p.id()) == provider);
+        }
+        let deadline = Instant::now() + AUTOCOMPLETE_BUDGET;
+        let mut pending: FuturesUnordered<_> = providers
+            .iter()
+            .enumerate()
+            .map(|(rank, p)| async move {
+                let key = album_cache::search_key(p.id(), query);
+                if let Some(choices) = self.cache.get(&key).await {
+                    return (rank, Ok(choices), None);
+                }
+                let start = Instant::now();
+                let res = p.query_albums(query).await;
+                let elapsed = start.elapsed();
+                if let Ok(choices) = &res {
+                    self.cache.insert(key, choices, SEARCH_TTL_SECS).await;
+                }
+                (rank, res, Some(elapsed))
+            })
+            .collect();
+        let mut results = vec![None; providers.len()];
+        let mut finished = vec![false; providers.len()];
+        let mut error = None;
+        while let Ok(Some((rank, res, elapsed))) = timeout_at(deadline, pending.next()).await {
+            // cached results say nothing about the provider's latency
+            if let Some(elapsed) = elapsed {
+                self.record_latency(providers[rank].id(), elapsed, false);
             }
-        });
+            finished[rank] = true;
+            match res {
+                Ok(choices) => results[rank] = Some(choices),
+                Err(e) => error = Some(e),
+            }
+        }
+        // providers that did not answer in time are given the whole budget as their latency
+        for (p, _) in providers.iter().zip(finished).filter(|(_, done)| !done) {
+            self.record_latency(p.id(), AUTOCOMPLETE_BUDGET, true);
+        }
+        if let (Some(e), true) = (error, results.iter().all(Option::is_none)) {
+            return Err(e);
+        }
+        let choices = results
+            .into_iter()
+            .flatten()
+            .flatten()
+            .unique_by(|(_, value)| value.clone())
+            // the same album from several providers
+            .unique_by(|(name, _)| fold(name))
+            .take(MAX_CHOICES)
+            .map(|(name, value)| {
+                if name.len() >= 100 {
+                    (name.chars().take(100).collect(), value)
+                } else {
+                    (name, value)
+                }
+            })
+            .collect();
         Ok(choices)
     }
 
     pub fn add_provider<P: AlbumProvider + 'static>(&mut self, p: Arc<P>) {
-        self.providers.push(p);
+        self.register_provider(p);
+    }
+}
+
+// The cache only uses the database once the handler is built and connected
+fn set_cache_db<'a>(
+    handler: &'a Handler,
+    _ctx: &'a Context,
+    _ready: &'a Ready,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let lookup = handler.module::<AlbumLookup>()?;
+        lookup.cache.set_db(Arc::clone(&handler.db));
+        Ok(())
+    }
+    .boxed()
+}
+
+// Registration of album providers implemented out of this crate, e.g.
+//
+//     Handler::builder(conn)
+//         .with_album_provider(Arc::new(Discogs::new(token)))
+//         .await?
+//         .album_provider_priority("discogs", 10)
+//         .await?
+impl HandlerBuilder {
+    pub async fn with_album_provider(self, p: Arc<dyn AlbumProvider>) -> anyhow::Result<Self> {
+        let builder = self.module::<AlbumLookup>().await?;
+        builder
+            .modules
+            .module::<AlbumLookup>()?
+            .register_provider(p);
+        Ok(builder)
+    }
+
+    // Providers with a higher priority are tried first, the built-in ones have priority 0
+    pub async fn album_provider_priority(
+        self,
+        provider: &'static str,
+        priority: i32,
+    ) -> anyhow::Result<Self> {
+        let builder = self.module::<AlbumLookup>().await?;
+        builder
+            .modules
+            .module::<AlbumLookup>()?
+            .set_priority(provider, priority);
+        Ok(builder)
     }
 }
 
@@ -127,8 +370,13 @@ impl Module for AlbumLookup {
     }
 
     async fn init(m: &ModuleMap) -> anyhow::Result<Self> {
+        let providers: Vec<Arc<dyn AlbumProvider>> =
+            vec![m.module_arc::<Spotify>()?, m.module_arc::<Bandcamp>()?];
         Ok(AlbumLookup {
-            providers: vec![m.module_arc::<Spotify>()?, m.module_arc::<Bandcamp>()?],
+            providers: RwLock::new(providers),
+            priorities: Default::default(),
+            latency: Default::default(),
+            cache: Default::default(),
         })
     }
 
@@ -138,7 +386,27 @@ impl Module for AlbumLookup {
         Ok(())
     }
 
+    const NAME: &'static str = "AlbumLookup";
+    const MIGRATIONS: &'static [Migration] = &[Migration::sql(
+        1,
+        "create album_lookup_cache",
+        "CREATE TABLE IF NOT EXISTS album_lookup_cache (
+            key STRING PRIMARY KEY,
+            value STRING NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS album_lookup_cache_expiry ON album_lookup_cache (expires_at);",
+    )];
+
     fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
         store.register::<LookupAlbum>();
     }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        album_cache::register_jobs(jobs);
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(set_cache_db);
+    }
 }
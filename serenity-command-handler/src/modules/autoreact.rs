@@ -1,4 +1,7 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Context as _};
 use fallible_iterator::FallibleIterator;
@@ -6,18 +9,28 @@ use futures::{future::BoxFuture, FutureExt};
 use rusqlite::{params, Connection};
 use serenity::{
     async_trait,
-    builder::{CreateAutocompleteResponse, CreateInteractionResponse},
-    model::application::CommandType,
-    model::prelude::{CommandInteraction, Message, Permissions, ReactionType},
+    builder::{
+        CreateAttachment, CreateAutocompleteResponse, CreateInteractionResponse,
+        CreateInteractionResponseMessage, EditInteractionResponse,
+    },
+    model::application::{CommandType, ComponentInteraction},
+    model::prelude::{
+        Attachment, CommandInteraction, EmojiId, GuildId, Message, Permissions, ReactionType,
+    },
     prelude::{Context, RwLock},
 };
 
 use crate::{
-    command_context::{get_focused_option, get_str_opt_ac},
-    db::Db,
+    command_context::{download_attachment, get_focused_option, get_str_opt_ac},
+    csv::{csv_field, parse_csv_line},
+    db::{Db, SqlGuildId},
+    emotes::{complete_emotes, is_unicode_emote, validate_emote},
+    gateway::{GatewayHandlers, MessageCreate},
     prelude::*,
+    schema::autoreact,
+    soft_delete::{handle_undo, undo_response},
 };
-use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command::{CommandKey, CommandResponse, GuildCommand};
 use serenity_command_derive::Command;
 
 pub struct AutoReact {
@@ -45,16 +58,16 @@ impl From<(&str, &str)> for AutoReact {
     }
 }
 
-pub type ReactsCache = HashMap<u64, Vec<AutoReact>>;
+pub type ReactsCache = HashMap<GuildId, Vec<AutoReact>>;
 
 pub async fn new(db: &Connection) -> anyhow::Result<ReactsCache> {
     let cache = {
-        db.prepare("SELECT guild_id, trigger, emote FROM autoreact")?
+        db.prepare("SELECT guild_id, trigger, emote FROM autoreact WHERE deleted_at IS NULL")?
             .query([])?
             .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
             .try_fold::<_, anyhow::Error, _>(
-                HashMap::<u64, Vec<AutoReact>>::new(),
-                |mut cache, (guild_id, trigger, emote): (u64, String, String)| {
+                ReactsCache::new(),
+                |mut cache, (SqlGuildId(guild_id), trigger, emote): (SqlGuildId, String, String)| {
                     cache
                         .entry(guild_id)
                         .or_default()
@@ -69,44 +82,45 @@ pub async fn new(db: &Connection) -> anyhow::Result<ReactsCache> {
 #[derive(Command)]
 #[cmd(
     name = "add_autoreact",
-    desc = "Automatically add reactions to messages"
+    desc = "Automatically add reactions to messages",
+    guild_only
 )]
 pub struct AddAutoreact {
     #[cmd(desc = "The word that will trigger the reaction (case-insensitive)")]
     trigger: String,
-    #[cmd(desc = "The emote to react with")]
+    #[cmd(desc = "The emote to react with", autocomplete)]
     emote: String,
 }
 
 #[async_trait]
-impl BotCommand for AddAutoreact {
+impl GuildCommand for AddAutoreact {
     type Data = Handler;
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
-        _ctx: &Context,
-        opts: &CommandInteraction,
+        ctx: &Context,
+        _opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
         let trigger = self.trigger.to_lowercase();
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
-        let parsed = AutoReact::new(&trigger, &self.emote)?;
-        {
-            let db = handler.db.lock().await;
-            db.conn.execute(
-                "INSERT INTO autoreact (guild_id, trigger, emote) VALUES (?1, ?2, ?3)",
-                params![guild_id, &trigger, &self.emote],
-            )?;
-        }
+        let emote = validate_emote(ctx, Some(guild_id), &self.emote).await?;
+        let (sql_trigger, sql_emote) = (trigger.clone(), emote.to_string());
+        handler
+            .db_call(move |db| {
+                db.conn.execute(
+                    "INSERT INTO autoreact (guild_id, trigger, emote) VALUES (?1, ?2, ?3)",
+                    params![SqlGuildId(guild_id), sql_trigger, sql_emote],
+                )?;
+                Ok(())
+            })
+            .await?;
         handler
             .reacts_cache()?
             .write()
             .await
             .entry(guild_id)
             .or_default()
-            .push(parsed);
+            .push(AutoReact { trigger, emote });
         CommandResponse::private("Autoreact added")
     }
 
@@ -114,7 +128,11 @@ impl BotCommand for AddAutoreact {
 }
 
 #[derive(Command)]
-#[cmd(name = "remove_autoreact", desc = "Remove automatic reaction")]
+#[cmd(
+    name = "remove_autoreact",
+    desc = "Remove automatic reaction",
+    guild_only
+)]
 pub struct RemoveAutoreact {
     #[cmd(
         desc = "The word that triggers the reaction (case-insensitive)",
@@ -126,31 +144,36 @@ pub struct RemoveAutoreact {
 }
 
 #[async_trait]
-impl BotCommand for RemoveAutoreact {
+impl GuildCommand for RemoveAutoreact {
     type Data = Handler;
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
-        _ctx: &Context,
+        ctx: &Context,
         opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
         let trigger = self.trigger.to_lowercase();
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
-        {
-            let db = handler.db.lock().await;
-            db.conn.execute(
-                "DELETE FROM autoreact WHERE guild_id = ?1 AND trigger = ?2 AND emote = ?3",
-                params![guild_id, &trigger, self.emote],
-            )?;
-        }
+        let (sql_trigger, sql_emote) = (trigger.clone(), self.emote.clone());
+        let token = handler
+            .db_call(move |db| {
+                db.soft_delete(
+                    "autoreact",
+                    "guild_id = ?1 AND trigger = ?2 AND emote = ?3",
+                    params![SqlGuildId(guild_id), sql_trigger, sql_emote],
+                )
+            })
+            .await?;
+        let Some(token) = token else {
+            return CommandResponse::private("No such autoreact");
+        };
         let emote = parse_emote(&self.emote)?;
         if let Some(reacts) = handler.reacts_cache()?.write().await.get_mut(&guild_id) {
             reacts.retain_mut(|ar| ar.trigger != trigger && ar.emote != emote);
         };
-        CommandResponse::private("Autoreact removed")
+        let resp = undo_response("autoreact", guild_id, token, "Autoreact removed");
+        opts.create_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
     }
 
     const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD_EXPRESSIONS;
@@ -159,25 +182,35 @@ impl BotCommand for RemoveAutoreact {
 impl Handler {
     pub async fn autocomplete_autoreact(
         &self,
-        guild_id: u64,
+        guild_id: GuildId,
         trigger: &str,
         emote: &str,
     ) -> anyhow::Result<Vec<(String, String)>> {
-        let db = self.db.lock().await;
-        let res = db
-            .conn
-            .prepare(
-                "SELECT trigger, emote FROM autoreact WHERE
-                     guild_id = ?1 AND trigger LIKE '%'||?2||'%' AND emote LIKE '%'||?3||'%'
-                     LIMIT 25",
-            )?
-            .query(params![guild_id, trigger, emote])?
-            .map(|row| Ok((row.get(0)?, row.get(1)?)))
-            .collect()?;
-        Ok(res)
+        let (trigger, emote) = (trigger.to_string(), emote.to_string());
+        self.db_read(move |db| matching_reacts(db, guild_id, &trigger, &emote))
+            .await
     }
 }
 
+fn matching_reacts(
+    db: &Db,
+    guild_id: GuildId,
+    trigger: &str,
+    emote: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let res = db
+        .conn
+        .prepare(
+            "SELECT trigger, emote FROM autoreact WHERE
+                 guild_id = ?1 AND trigger LIKE '%'||?2||'%' AND emote LIKE '%'||?3||'%'
+                 AND deleted_at IS NULL LIMIT 25",
+        )?
+        .query(params![SqlGuildId(guild_id), trigger, emote])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    Ok(res)
+}
+
 trait ReactProvider {
     fn reacts_cache(&self) -> anyhow::Result<&RwLock<ReactsCache>>;
 }
@@ -206,7 +239,7 @@ impl ModAutoreacts {
         let mut indices = Vec::new();
         let cache = self.cache.read().await;
         let guild_id = match msg.guild_id {
-            Some(id) => id.get(),
+            Some(id) => id,
             None => return Ok(()),
         };
         let reacts = match cache.get(&guild_id) {
@@ -230,22 +263,14 @@ impl ModAutoreacts {
 
     async fn autocomplete_autoreact(
         handler: &Handler,
-        guild_id: u64,
+        guild_id: GuildId,
         trigger: &str,
         emote: &str,
     ) -> anyhow::Result<Vec<(String, String)>> {
-        let db = handler.db.lock().await;
-        let res = db
-            .conn
-            .prepare(
-                "SELECT trigger, emote FROM autoreact WHERE
-                     guild_id = ?1 AND trigger LIKE '%'||?2||'%' AND emote LIKE '%'||?3||'%'
-                     LIMIT 25",
-            )?
-            .query(params![guild_id, trigger, emote])?
-            .map(|row| Ok((row.get(0)?, row.get(1)?)))
-            .collect()?;
-        Ok(res)
+        let (trigger, emote) = (trigger.to_string(), emote.to_string());
+        handler
+            .db_read(move |db| matching_reacts(db, guild_id, &trigger, &emote))
+            .await
     }
 
     fn complete_reacts<'a>(
@@ -255,14 +280,18 @@ impl ModAutoreacts {
         ac: &'a CommandInteraction,
     ) -> BoxFuture<'a, anyhow::Result<bool>> {
         async move {
+            let options = &ac.data.options;
+            if key == ("add_autoreact", CommandType::ChatInput) {
+                let partial = get_str_opt_ac(options, "emote").unwrap_or("");
+                complete_emotes(ctx, ac, partial).await?;
+                return Ok(true);
+            }
             if key != ("remove_autoreact", CommandType::ChatInput) {
                 return Ok(false);
             }
             let guild_id = ac
                 .guild_id
-                .ok_or_else(|| anyhow!("must be run in a guild"))?
-                .get();
-            let options = &ac.data.options;
+                .ok_or_else(|| anyhow!("must be run in a guild"))?;
             let trigger = get_str_opt_ac(options, "trigger").unwrap_or("");
             let emote = get_str_opt_ac(options, "emote").unwrap_or("");
             let res = Self::autocomplete_autoreact(handler, guild_id, trigger, emote).await?;
@@ -285,6 +314,28 @@ impl ModAutoreacts {
     }
 }
 
+impl ModAutoreacts {
+    fn undo_remove_autoreact<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(restored) = handle_undo(handler, ctx, component, "autoreact").await? else {
+                return Ok(false);
+            };
+            if restored > 0 {
+                handler
+                    .module::<ModAutoreacts>()?
+                    .reload_reacts(handler)
+                    .await?;
+            }
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
 pub async fn add_reacts(handler: &Handler, ctx: &Context, msg: Message) -> anyhow::Result<()> {
     handler
         .module::<ModAutoreacts>()?
@@ -292,29 +343,239 @@ pub async fn add_reacts(handler: &Handler, ctx: &Context, msg: Message) -> anyho
         .await
 }
 
+fn on_message<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a MessageCreate,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    add_reacts(handler, ctx, event.0.clone()).boxed()
+}
+
 impl ModAutoreacts {
     pub async fn load_reacts(&self, db: &mut Db) -> anyhow::Result<()> {
-        let cache = {
-            db.conn
-                .prepare("SELECT guild_id, trigger, emote FROM autoreact")?
-                .query([])?
-                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
-                .try_fold::<_, anyhow::Error, _>(
-                    HashMap::<u64, Vec<AutoReact>>::new(),
-                    |mut cache, (guild_id, trigger, emote): (u64, String, String)| {
-                        cache
-                            .entry(guild_id)
-                            .or_default()
-                            .push(AutoReact::new(&trigger, &emote)?);
-                        Ok(cache)
-                    },
-                )?
-        };
+        *self.cache.write().await = read_reacts(db)?;
+        Ok(())
+    }
+
+    pub async fn reload_reacts(&self, handler: &Handler) -> anyhow::Result<()> {
+        let cache = handler.db_read(read_reacts).await?;
         *self.cache.write().await = cache;
         Ok(())
     }
 }
 
+fn read_reacts(db: &Db) -> anyhow::Result<ReactsCache> {
+    db.conn
+        .prepare("SELECT guild_id, trigger, emote FROM autoreact WHERE deleted_at IS NULL")?
+        .query([])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .try_fold::<_, anyhow::Error, _>(
+            ReactsCache::new(),
+            |mut cache, (SqlGuildId(guild_id), trigger, emote): (SqlGuildId, String, String)| {
+                cache
+                    .entry(guild_id)
+                    .or_default()
+                    .push(AutoReact::new(&trigger, &emote)?);
+                Ok(cache)
+            },
+        )
+}
+
+const CSV_HEADER: &str = "trigger,emote";
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+const MAX_REPORT_LEN: usize = 1800;
+
+// Validate and insert the rows of an autoreact CSV in a single transaction.
+// Returns the number of imported rows and the reasons rows were skipped.
+fn insert_reacts(
+    db: &mut Db,
+    guild_id: GuildId,
+    contents: &str,
+    guild_emotes: &HashSet<EmojiId>,
+) -> anyhow::Result<(usize, Vec<String>)> {
+    let mut existing: HashSet<(String, String)> = db
+        .conn
+        .prepare(
+            "SELECT trigger, emote FROM autoreact
+             WHERE guild_id = ?1 AND deleted_at IS NULL",
+        )?
+        .query([SqlGuildId(guild_id)])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    let mut skipped = Vec::new();
+    let mut imported = 0;
+    let tx = db.conn.transaction()?;
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (n == 0 && line.eq_ignore_ascii_case(CSV_HEADER)) {
+            continue;
+        }
+        let fields = parse_csv_line(line);
+        let [trigger, emote] = fields.as_slice() else {
+            skipped.push(format!("line {}: expected a trigger and an emote", n + 1));
+            continue;
+        };
+        let trigger = trigger.trim().to_lowercase();
+        let emote = emote.trim().to_string();
+        let valid = match parse_emote(&emote) {
+            Ok(ReactionType::Custom { id, .. }) => guild_emotes.contains(&id),
+            Ok(_) => is_unicode_emote(&emote),
+            Err(_) => false,
+        };
+        if trigger.is_empty() {
+            skipped.push(format!("line {}: empty trigger", n + 1));
+        } else if !valid {
+            skipped.push(format!("line {}: {emote} is not usable here", n + 1));
+        } else if !existing.insert((trigger.clone(), emote.clone())) {
+            skipped.push(format!("line {}: {trigger} {emote} already exists", n + 1));
+        } else {
+            tx.execute(
+                "INSERT INTO autoreact (guild_id, trigger, emote) VALUES (?1, ?2, ?3)",
+                params![SqlGuildId(guild_id), trigger, emote],
+            )?;
+            imported += 1;
+        }
+    }
+    tx.commit()?;
+    Ok((imported, skipped))
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "export_autoreacts",
+    desc = "Export this server's autoreacts as a CSV file",
+    guild_only
+)]
+pub struct ExportAutoreacts {}
+
+#[async_trait]
+impl GuildCommand for ExportAutoreacts {
+    type Data = Handler;
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let reacts: Vec<(String, String)> = handler
+            .db_call(move |db| {
+                let reacts = db
+                    .conn
+                    .prepare(
+                        "SELECT trigger, emote FROM autoreact
+                         WHERE guild_id = ?1 AND deleted_at IS NULL ORDER BY trigger",
+                    )?
+                    .query([SqlGuildId(guild_id)])?
+                    .map(|row| Ok((row.get(0)?, row.get(1)?)))
+                    .collect()?;
+                Ok(reacts)
+            })
+            .await?;
+        if reacts.is_empty() {
+            return CommandResponse::private("No autoreacts to export");
+        }
+        let mut csv = format!("{CSV_HEADER}\n");
+        for (trigger, emote) in &reacts {
+            csv.push_str(&format!("{},{}\n", csv_field(trigger), csv_field(emote)));
+        }
+        let msg = CreateInteractionResponseMessage::new()
+            .content(format!(
+                "Exported {} autoreacts, use `/import_autoreacts` to import them in another server",
+                reacts.len()
+            ))
+            .add_file(CreateAttachment::bytes(csv.into_bytes(), "autoreacts.csv"))
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD_EXPRESSIONS;
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "import_autoreacts",
+    desc = "Import autoreacts from a CSV file created with /export_autoreacts",
+    guild_only
+)]
+pub struct ImportAutoreacts {
+    #[cmd(desc = "CSV file with a trigger and an emote on each line")]
+    file: Attachment,
+}
+
+impl ImportAutoreacts {
+    async fn import(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> anyhow::Result<String> {
+        let contents = String::from_utf8(download_attachment(&self.file, MAX_IMPORT_SIZE).await?)
+            .map_err(|_| anyhow!("File is not valid text"))?;
+        // custom emotes can only be used if they belong to this server
+        let guild_emotes = guild_id
+            .emojis(&ctx.http)
+            .await?
+            .into_iter()
+            .map(|e| e.id)
+            .collect::<HashSet<_>>();
+        let (imported, skipped) = handler
+            .db_call(move |db| insert_reacts(db, guild_id, &contents, &guild_emotes))
+            .await?;
+        handler
+            .module::<ModAutoreacts>()?
+            .reload_reacts(handler)
+            .await?;
+        let mut report = format!("Imported {imported} autoreacts");
+        if !skipped.is_empty() {
+            report.push_str(&format!(", skipped {}:", skipped.len()));
+            for line in skipped {
+                if report.len() + line.len() >= MAX_REPORT_LEN {
+                    report.push_str("\n…");
+                    break;
+                }
+                report.push('\n');
+                report.push_str(&line);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl GuildCommand for ImportAutoreacts {
+    type Data = Handler;
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let content = match self.import(handler, ctx, guild_id).await {
+            Ok(report) => report,
+            Err(e) => e.to_string(),
+        };
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD_EXPRESSIONS;
+}
+
 #[async_trait]
 impl Module for ModAutoreacts {
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
@@ -322,21 +583,26 @@ impl Module for ModAutoreacts {
     }
 
     async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
-        db.conn.execute(
-            "CREATE TABLE IF NOT EXISTS autoreact (
-                guild_id INTEGER NOT NULL,
-                trigger STRING NOT NULL,
-                emote STRING NOT NULL
-            )",
-            [],
-        )?;
+        db.create_table(&autoreact::TABLE)?;
+        db.add_soft_delete(autoreact::TABLE.name)?;
+        db.check_schema(&autoreact::TABLE)?;
         Ok(())
     }
 
     fn register_commands(&self, commands: &mut CommandStore, completions: &mut CompletionStore) {
         commands.register::<AddAutoreact>();
         commands.register::<RemoveAutoreact>();
+        commands.register::<ExportAutoreacts>();
+        commands.register::<ImportAutoreacts>();
 
         completions.push(ModAutoreacts::complete_reacts);
     }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(on_message);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(ModAutoreacts::undo_remove_autoreact);
+    }
 }
@@ -1,13 +1,24 @@
 use crate::{Module, ModuleMap};
 use anyhow::anyhow;
-use reqwest::{Client, Url};
+use chrono::Duration;
+use reqwest::Url;
 use scraper::{Html, Selector};
 use serenity::async_trait;
 
-use crate::album::{Album, AlbumProvider};
+use crate::album::{Album, AlbumProvider, Track};
+use crate::http_util::RateLimitedClient;
 
 const SEARCH_URL: &str = "https://bandcamp.com/search";
 
+// Track times are shown as 4:32 or 1:02:03
+fn parse_track_time(time: &str) -> Option<Duration> {
+    let secs = time
+        .trim()
+        .split(':')
+        .try_fold(0, |secs, part| Some(secs * 60 + part.parse::<i64>().ok()?))?;
+    Some(Duration::seconds(secs))
+}
+
 fn contents(html: &Html, selector: &Selector) -> Option<String> {
     Some(
         html.select(selector)
@@ -20,7 +31,7 @@ fn contents(html: &Html, selector: &Selector) -> Option<String> {
 }
 
 pub struct Bandcamp {
-    client: Client,
+    client: RateLimitedClient,
 }
 
 #[async_trait]
@@ -32,7 +43,7 @@ impl AlbumProvider for Bandcamp {
     async fn get_from_url(&self, url: &str) -> anyhow::Result<Album> {
         let mut url = Url::parse(url)?;
         url.query_pairs_mut().clear();
-        let page = self.client.get(url.clone()).send().await?.text().await?;
+        let page = self.client.get(url.clone()).await?.text().await?;
         let html = Html::parse_document(&page);
 
         let title_selector = Selector::parse(".trackTitle").unwrap();
@@ -55,12 +66,42 @@ impl AlbumProvider for Bandcamp {
             .and_then(|s| s.trim().split_once(' '))
             .map(|(_, date)| date.to_string());
 
+        let cover_selector = Selector::parse("#tralbumArt img").unwrap();
+        let cover = html
+            .select(&cover_selector)
+            .next()
+            .and_then(|e| e.value().attr("src"))
+            .map(String::from);
+
+        // tracks without a time can't be played, e.g. unreleased tracks of a preorder, and are
+        // skipped
+        let row_selector = Selector::parse("#track_table .track_row_view").unwrap();
+        let track_title_selector = Selector::parse(".track-title").unwrap();
+        let time_selector = Selector::parse(".time").unwrap();
+        let tracks: Vec<Track> = html
+            .select(&row_selector)
+            .filter_map(|row| {
+                let name = row.select(&track_title_selector).next()?.text().collect();
+                let time = row
+                    .select(&time_selector)
+                    .next()?
+                    .text()
+                    .collect::<String>();
+                let duration = parse_track_time(&time)?;
+                Some(Track { name, duration })
+            })
+            .collect();
+        let duration = (!tracks.is_empty()).then(|| tracks.iter().map(|t| t.duration).sum());
+
         Ok(Album {
             name: Some(title),
             artist,
             genres,
             url: Some(url.to_string()),
             release_date,
+            cover,
+            duration,
+            tracks,
             ..Default::default()
         })
     }
@@ -71,7 +112,7 @@ impl AlbumProvider for Bandcamp {
             .query_pairs_mut()
             .append_pair("q", q)
             .append_pair("item_type", "a");
-        let page = self.client.get(query_url).send().await?.text().await?;
+        let page = self.client.get(query_url).await?.text().await?;
 
         let url_selector = Selector::parse(".result-info>.heading>a").unwrap();
         let url = Html::parse_document(&page)
@@ -95,7 +136,7 @@ impl AlbumProvider for Bandcamp {
             .query_pairs_mut()
             .append_pair("q", q)
             .append_pair("item_type", "a");
-        let page = self.client.get(query_url).send().await?.text().await?;
+        let page = self.client.get(query_url).await?.text().await?;
 
         let url_selector = Selector::parse(".result-info>.heading>a").unwrap();
         let artist_selector = Selector::parse(".result-info>.subhead").unwrap();
@@ -130,7 +171,7 @@ impl AlbumProvider for Bandcamp {
 impl Bandcamp {
     pub fn new() -> Self {
         Bandcamp {
-            client: Client::new(),
+            client: RateLimitedClient::new("bandcamp"),
         }
     }
 }
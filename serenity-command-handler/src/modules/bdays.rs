@@ -1,25 +1,31 @@
-use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
-use chrono::{Datelike, Local, Timelike, Utc};
+use chrono::Datelike;
 use fallible_iterator::FallibleIterator;
-use rusqlite::params;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rusqlite::{params, OptionalExtension};
 use serenity::builder::{CreateCommandOption, CreateEmbed, CreateEmbedAuthor};
 use serenity::http::Http;
 use serenity::model::prelude::CommandInteraction;
-use serenity::model::prelude::GuildId;
+use serenity::model::prelude::{GuildId, UserId};
 use serenity::{async_trait, prelude::Context};
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{CommandResponse, GuildCommand, NotInGuild};
 use serenity_command_derive::Command;
 use tokio::sync::Mutex;
-use tokio::time::interval;
 
-use crate::db::Db;
+use crate::date_format::DateFormat;
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::retry_queue::{end_of_day, retry_due};
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::schema::bdays;
 use crate::{CommandStore, CompletionStore, Handler, Module, ModuleMap};
 
+const RETRY_KIND: &str = "birthday";
+
 pub struct Birthday {
-    pub user_id: u64,
+    pub user_id: UserId,
     pub day: u8,
     pub month: u8,
     pub year: Option<u16>,
@@ -27,8 +33,8 @@ pub struct Birthday {
 
 async fn add_birthday(
     handler: &Handler,
-    guild_id: u64,
-    user_id: u64,
+    guild_id: GuildId,
+    user_id: UserId,
     day: u8,
     month: u8,
     year: Option<u16>,
@@ -40,20 +46,20 @@ async fn add_birthday(
                  ON CONFLICT(guild_id, user_id) DO UPDATE
                  SET day = ?3, month = ?4, year = ?5
                  WHERE guild_id = ?1 AND user_id = ?2",
-        params![guild_id, user_id, day, month, year],
+        params![SqlGuildId(guild_id), SqlUserId(user_id), day, month, year],
     )?;
     Ok(())
 }
 
-async fn get_bdays(handler: &Handler, guild_id: u64) -> anyhow::Result<Vec<Birthday>> {
+async fn get_bdays(handler: &Handler, guild_id: GuildId) -> anyhow::Result<Vec<Birthday>> {
     let db = handler.db.lock().await;
     let res = db
         .conn
         .prepare("SELECT user_id, day, month, year FROM bdays WHERE guild_id = ?1")?
-        .query([guild_id])?
+        .query([SqlGuildId(guild_id)])?
         .map(|row| {
             Ok(Birthday {
-                user_id: row.get(0)?,
+                user_id: row.get::<_, SqlUserId>(0)?.0,
                 day: row.get(1)?,
                 month: row.get(2)?,
                 year: row.get(3)?,
@@ -64,24 +70,24 @@ async fn get_bdays(handler: &Handler, guild_id: u64) -> anyhow::Result<Vec<Birth
 }
 
 #[derive(Command)]
-#[cmd(name = "bdays", desc = "List server birthdays")]
+#[cmd(name = "bdays", desc = "List server birthdays", guild_only)]
 pub struct GetBdays;
 
 #[async_trait]
-impl BotCommand for GetBdays {
+impl GuildCommand for GetBdays {
     type Data = Handler;
-    async fn run(
+    const SCHEDULABLE: bool = true;
+
+    async fn run_in_guild(
         self,
         handler: &Handler,
         ctx: &Context,
         opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
         let mut bdays = get_bdays(handler, guild_id).await?;
-        let today = Utc::now().date_naive();
+        let format = DateFormat::for_interaction(handler, opts).await;
+        let today = format.today(handler.clock.now());
         let current_day = today.day() as u8;
         let current_month = today.month() as u8;
         bdays.sort_unstable_by_key(|Birthday { day, mut month, .. }| {
@@ -92,10 +98,10 @@ impl BotCommand for GetBdays {
         });
         let res = bdays
             .into_iter()
-            .map(|b| format!("`{:02}/{:02}` • <@{}>", b.day, b.month, b.user_id))
+            .map(|b| format!("`{}` • <@{}>", format.day_month(b.day, b.month), b.user_id))
             .collect::<Vec<_>>()
             .join("\n");
-        let header = if let Some(server) = opts.guild_id.and_then(|g| g.name(ctx)) {
+        let header = if let Some(server) = guild_id.name(ctx) {
             format!("Birthdays in {server}")
         } else {
             "Birthdays".to_string()
@@ -108,9 +114,47 @@ impl BotCommand for GetBdays {
 }
 
 #[derive(Command)]
-#[cmd(name = "bday", desc = "Set your birthday")]
+#[cmd(name = "Birthday", user, guild_only)]
+pub struct ShowBday(UserId);
+
+#[async_trait]
+impl GuildCommand for ShowBday {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        opts: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = self.0;
+        let bday = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT day, month FROM bdays WHERE guild_id = ?1 AND user_id = ?2",
+                params![SqlGuildId(guild_id), SqlUserId(user_id)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((day, month)) = bday else {
+            return CommandResponse::private(format!("<@{user_id}> has not set their birthday"));
+        };
+        let format = DateFormat::for_interaction(handler, opts).await;
+        CommandResponse::private(format!(
+            "<@{user_id}>'s birthday is on {}",
+            format.day_month(day, month)
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "bday", desc = "Set your birthday", guild_only)]
 pub struct SetBday {
-    #[cmd(desc = "Day")]
+    #[cmd(desc = "Day", min = 1, max = 31)]
     day: i64,
     #[cmd(desc = "Month")]
     month: i64,
@@ -119,19 +163,16 @@ pub struct SetBday {
 }
 
 #[async_trait]
-impl BotCommand for SetBday {
+impl GuildCommand for SetBday {
     type Data = Handler;
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
         _ctx: &Context,
         opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let user_id = opts.user.id.get();
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
+        let user_id = opts.user.id;
         add_birthday(
             handler,
             guild_id,
@@ -144,38 +185,44 @@ impl BotCommand for SetBday {
         CommandResponse::private("Birthday set!")
     }
 
-    fn setup_options(opt_name: &'static str, mut opt: CreateCommandOption) -> CreateCommandOption {
-        match opt_name {
-            "day" => {
-                opt = opt.min_int_value(1).max_int_value(31);
-            }
-            "month" => {
-                const MONTHS: [&str; 12] = [
-                    "January",
-                    "February",
-                    "March",
-                    "April",
-                    "May",
-                    "June",
-                    "July",
-                    "August",
-                    "September",
-                    "October",
-                    "November",
-                    "December",
-                ];
-                opt = MONTHS.iter().enumerate().fold(opt, |opt, (n, &month)| {
-                    opt.add_int_choice(month, n as i32 + 1)
-                });
-            }
-            _ => {}
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        if opt_name == "month" {
+            const MONTHS: [&str; 12] = [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ];
+            MONTHS.iter().enumerate().fold(opt, |opt, (n, &month)| {
+                opt.add_int_choice(month, n as i32 + 1)
+            })
+        } else {
+            opt
         }
-        opt
     }
 }
 
-async fn wish_bday(http: &Http, user_id: u64, guild_id: GuildId) -> anyhow::Result<()> {
+async fn wish_bday(
+    db: &Mutex<Db>,
+    http: &Http,
+    user_id: UserId,
+    guild_id: GuildId,
+) -> anyhow::Result<()> {
     let member = guild_id.member(http, user_id).await?;
+    // members who turned birthday notifications off are wished without a mention
+    let muted = db.lock().await.muted_users(guild_id, "bdays")?;
+    let name = match muted.contains(&user_id) {
+        true => member.display_name().to_string(),
+        false => format!("<@{}>", member.user.id.get()),
+    };
     let channels = guild_id.channels(http).await?;
     let channel = channels
         .values()
@@ -183,41 +230,69 @@ async fn wish_bday(http: &Http, user_id: u64, guild_id: GuildId) -> anyhow::Resu
         .or_else(|| channels.values().find(|chan| chan.position == 0))
         .ok_or_else(|| anyhow!("Could not find a suitable channel"))?;
     channel
-        .say(
-            http,
-            format!("Happy birthday to <@{}>!", member.user.id.get()),
-        )
+        .say(http, format!("Happy birthday to {name}!"))
         .await?;
     Ok(())
 }
 
-pub async fn bday_loop(db: Arc<Mutex<Db>>, http: Arc<Http>) {
-    let mut interval = interval(Duration::from_secs(3600));
-    loop {
-        interval.tick().await;
-        let now = Local::now();
-        if now.hour() != 10 {
-            continue;
-        }
-        let guilds_and_users = {
-            let db = db.lock().await;
-            let mut stmt = db
-                .conn
-                .prepare("SELECT guild_id, user_id FROM bdays WHERE day = ?1 AND month = ?2")
-                .unwrap();
-            stmt.query([now.day(), now.month()])
-                .unwrap()
-                .map(|row| Ok((row.get(0)?, row.get(1)?)))
-                .iterator()
-                .filter_map(Result::ok)
-                .collect::<Vec<_>>()
-        };
-        for (guild_id, user_id) in guilds_and_users {
-            if let Err(e) = wish_bday(http.as_ref(), user_id, GuildId::new(guild_id)).await {
-                eprintln!("Error wishing user birthday: {e:?}");
+// Wish the birthdays of the day in a guild, at 10:00 in its timezone
+fn wish_bdays<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let guild_id = run.guild_id.ok_or(NotInGuild)?;
+        let (day, month) = (run.due.day(), run.due.month());
+        let users = handler
+            .db_read(move |db| {
+                let users: Vec<SqlUserId> = db
+                    .conn
+                    .prepare(
+                        "SELECT user_id FROM bdays WHERE guild_id = ?1 AND day = ?2 AND month = ?3",
+                    )?
+                    .query(params![SqlGuildId(guild_id), day, month])?
+                    .map(|row| row.get(0))
+                    .collect()?;
+                Ok(users)
+            })
+            .await?;
+        for SqlUserId(user_id) in users {
+            if let Err(e) = wish_bday(&handler.db, http, user_id, guild_id).await {
+                tracing::error!("Error wishing user birthday: {e:?}");
+                let payload = user_id.to_string();
+                let queued = handler.db.lock().await.queue_retry(
+                    guild_id,
+                    RETRY_KIND,
+                    &payload,
+                    end_of_day(run.due),
+                    &e,
+                    handler.clock.now().timestamp(),
+                );
+                if let Err(e) = queued {
+                    tracing::error!("Error queuing birthday retry: {e:?}");
+                }
             }
         }
+        Ok(())
+    }
+    .boxed()
+}
+
+fn retry_bdays<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        retry_due(handler, RETRY_KIND, |guild_id, payload| async move {
+            let user_id = UserId::new(payload.parse()?);
+            wish_bday(&handler.db, http, user_id, guild_id).await
+        })
+        .await;
+        Ok(())
     }
+    .boxed()
 }
 
 pub struct Bdays;
@@ -229,22 +304,24 @@ impl Module for Bdays {
     }
 
     async fn setup(&mut self, db: &mut crate::db::Db) -> anyhow::Result<()> {
-        db.conn.execute(
-            "CREATE TABLE IF NOT EXISTS bdays (
-            guild_id INTEGER NOT NULL,
-            user_id INTEGER NOT NULL,
-            day INTEGER NOT NULL,
-            month INTEGER NOT NULL,
-            year INTEGER,
-            UNIQUE(guild_id, user_id)
-            )",
-            [],
-        )?;
+        db.create_retry_queue()?;
+        db.create_table(&bdays::TABLE)?;
+        db.check_schema(&bdays::TABLE)?;
         Ok(())
     }
 
     fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
         store.register::<GetBdays>();
         store.register::<SetBday>();
+        store.register::<ShowBday>();
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(
+            Job::new("bdays", Schedule::daily_at(10, 0), wish_bdays)
+                .per_guild("SELECT DISTINCT guild_id FROM bdays"),
+        );
+        let hourly = Schedule::every(Duration::from_secs(3600));
+        jobs.push(Job::new("bdays_retries", hourly, retry_bdays));
     }
 }
@@ -0,0 +1,789 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use anyhow::bail;
+use itertools::Itertools;
+use serenity::builder::{
+    CreateActionRow, CreateCommand, CreateCommandOption, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::gateway::ActivityData;
+use serenity::http::{Http, HttpError};
+use serenity::json::{to_value, JsonMap, Value};
+use serenity::model::application::{Command, CommandInteraction};
+use serenity::model::id::{CommandId, GuildId};
+use serenity::model::user::OnlineStatus;
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandError, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::Db;
+use crate::lease::instance_id;
+use crate::maintenance::DEFAULT_NOTICE;
+use crate::modules::sql::{is_admin, Sql};
+use crate::prelude::*;
+use crate::presence::{PresenceEntry, PRIORITY_MAINTENANCE, PRIORITY_OVERRIDE};
+
+const MAX_RESPONSE_LEN: usize = 1900;
+// Space out command creations, on top of the rate limiting done by serenity
+const REREGISTER_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+const PRESENCE_KEY: &str = "owner";
+const ACTIVITY_KINDS: &[&str] = &["playing", "listening", "watching", "competing", "custom"];
+const STATUSES: &[&str] = &["online", "idle", "dnd", "invisible"];
+const MAINTENANCE_KEY: &str = "maintenance";
+
+// fields sent by Discord that are also set when registering a command
+const COMPARED_FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "options",
+    "default_member_permissions",
+    "type",
+    "nsfw",
+];
+
+pub enum ChangeKind {
+    Add(CreateCommand),
+    Update(CommandId, CreateCommand),
+    Delete(CommandId),
+}
+
+pub struct CommandChange {
+    pub name: String,
+    pub kind: ChangeKind,
+    // fields that differ between the registered command and the local one
+    pub differences: Vec<String>,
+}
+
+// changes for global commands (guild is None) or a single guild's commands
+pub struct SyncDiff {
+    pub guild: Option<GuildId>,
+    pub changes: Vec<CommandChange>,
+}
+
+// Remove default values so that registered commands can be compared with local ones
+fn canonicalize(value: Value) -> Option<Value> {
+    match value {
+        Value::Null | Value::Bool(false) => None,
+        Value::String(s) if s.is_empty() => None,
+        Value::Number(n) => n.as_f64().map(Value::from),
+        Value::Array(items) => {
+            let items = items.into_iter().filter_map(canonicalize).collect_vec();
+            (!items.is_empty()).then_some(Value::Array(items))
+        }
+        Value::Object(map) => {
+            let map: JsonMap = map
+                .into_iter()
+                .filter_map(|(k, v)| canonicalize(v).map(|v| (k, v)))
+                .collect();
+            (!map.is_empty()).then_some(Value::Object(map))
+        }
+        v => Some(v),
+    }
+}
+
+fn command_payload(value: Value) -> JsonMap {
+    let Value::Object(map) = value else {
+        return JsonMap::new();
+    };
+    let mut payload: JsonMap = map
+        .into_iter()
+        .filter(|(k, _)| COMPARED_FIELDS.contains(&k.as_str()))
+        .filter_map(|(k, v)| canonicalize(v).map(|v| (k, v)))
+        .collect();
+    // chat input is the default command type
+    payload.entry("type").or_insert(Value::from(1.0));
+    payload
+}
+
+fn payload_key(payload: &JsonMap) -> (String, String) {
+    let name = payload
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let kind = payload
+        .get("type")
+        .map(Value::to_string)
+        .unwrap_or_default();
+    (name, kind)
+}
+
+fn format_value(value: Option<&Value>) -> String {
+    value
+        .map(Value::to_string)
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+fn diff_values(path: &str, old: Option<&Value>, new: Option<&Value>, out: &mut Vec<String>) {
+    let field_path = |field: &dyn std::fmt::Display| {
+        if path.is_empty() {
+            field.to_string()
+        } else {
+            format!("{path}.{field}")
+        }
+    };
+    match (old, new) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            for key in a.keys().chain(b.keys()).unique() {
+                diff_values(&field_path(key), a.get(key), b.get(key), out);
+            }
+        }
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            for i in 0..a.len().max(b.len()) {
+                diff_values(&field_path(&i), a.get(i), b.get(i), out);
+            }
+        }
+        (a, b) if a == b => (),
+        (a, b) => out.push(format!(
+            "{path}: {} -> {}",
+            format_value(a),
+            format_value(b)
+        )),
+    }
+}
+
+fn diff_commands(
+    existing: Vec<Command>,
+    local: Vec<CreateCommand>,
+    ignored: &HashSet<&str>,
+) -> anyhow::Result<Vec<CommandChange>> {
+    let mut remaining = HashMap::new();
+    for cmd in existing {
+        let payload = command_payload(to_value(&cmd)?);
+        remaining.insert(payload_key(&payload), (cmd.id, payload));
+    }
+    let mut changes = Vec::new();
+    for builder in local {
+        let payload = command_payload(to_value(&builder)?);
+        let key = payload_key(&payload);
+        let mut differences = Vec::new();
+        let kind = match remaining.remove(&key) {
+            None => {
+                let empty = Value::Object(JsonMap::new());
+                diff_values(
+                    "",
+                    Some(&empty),
+                    Some(&Value::Object(payload)),
+                    &mut differences,
+                );
+                ChangeKind::Add(builder)
+            }
+            Some((id, old)) => {
+                diff_values(
+                    "",
+                    Some(&Value::Object(old)),
+                    Some(&Value::Object(payload)),
+                    &mut differences,
+                );
+                if differences.is_empty() {
+                    continue;
+                }
+                ChangeKind::Update(id, builder)
+            }
+        };
+        changes.push(CommandChange {
+            name: key.0,
+            kind,
+            differences,
+        });
+    }
+    changes.extend(
+        remaining
+            .into_iter()
+            .filter(|((name, _), _)| !ignored.contains(name.as_str()))
+            .map(|((name, _), (id, _))| CommandChange {
+                name,
+                kind: ChangeKind::Delete(id),
+                differences: Vec::new(),
+            }),
+    );
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(changes)
+}
+
+async fn apply_changes(
+    http: &Http,
+    guild: Option<GuildId>,
+    changes: &[CommandChange],
+) -> anyhow::Result<()> {
+    for change in changes {
+        match (&change.kind, guild) {
+            (ChangeKind::Add(cmd), None) => {
+                Command::create_global_command(http, cmd.clone()).await?;
+            }
+            (ChangeKind::Add(cmd), Some(guild_id)) => {
+                guild_id.create_command(http, cmd.clone()).await?;
+            }
+            (ChangeKind::Update(id, cmd), None) => {
+                Command::edit_global_command(http, *id, cmd.clone()).await?;
+            }
+            (ChangeKind::Update(id, cmd), Some(guild_id)) => {
+                guild_id.edit_command(http, *id, cmd.clone()).await?;
+            }
+            (ChangeKind::Delete(id), None) => Command::delete_global_command(http, *id).await?,
+            (ChangeKind::Delete(id), Some(guild_id)) => guild_id.delete_command(http, *id).await?,
+        }
+    }
+    Ok(())
+}
+
+pub fn format_diffs(diffs: &[SyncDiff], verbose: bool) -> String {
+    let mut out = String::new();
+    for diff in diffs {
+        let scope = match diff.guild {
+            None => "Global".to_string(),
+            Some(guild_id) => format!("Guild {guild_id}"),
+        };
+        if diff.changes.is_empty() {
+            _ = writeln!(&mut out, "  {scope}: up to date");
+            continue;
+        }
+        _ = writeln!(&mut out, "  {scope}:");
+        for change in &diff.changes {
+            let prefix = match change.kind {
+                ChangeKind::Add(_) => '+',
+                ChangeKind::Update(..) => '~',
+                ChangeKind::Delete(_) => '-',
+            };
+            _ = writeln!(&mut out, "{prefix}     {}", &change.name);
+            if verbose {
+                change
+                    .differences
+                    .iter()
+                    .for_each(|d| _ = writeln!(&mut out, "        {d}"));
+            }
+        }
+    }
+    out
+}
+
+impl Handler {
+    // Compare commands registered with Discord to the ones in the CommandStore, and update
+    // them unless `dry_run` is set.
+    // Global commands are always synced, as well as guild commands for guilds that have guild
+    // commands in the store and the `guilds` passed as argument.
+    pub async fn sync_commands(
+        &self,
+        http: &Http,
+        guilds: &[GuildId],
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<SyncDiff>> {
+        let mut local: HashMap<Option<GuildId>, Vec<CreateCommand>> = HashMap::new();
+        local.entry(None).or_default();
+        guilds.iter().for_each(|&guild_id| {
+            local.entry(Some(guild_id)).or_default();
+        });
+        for runner in self.commands.read().await.0.values() {
+            local
+                .entry(runner.guild())
+                .or_default()
+                .push(runner.register());
+        }
+        // special commands are not part of the store, never delete them
+        let ignored: HashSet<&str> = self.special_commands.keys().map(String::as_str).collect();
+        let mut diffs = Vec::with_capacity(local.len());
+        for (guild, commands) in local {
+            let existing = match guild {
+                None => Command::get_global_commands(http).await?,
+                Some(guild_id) => guild_id.get_commands(http).await?,
+            };
+            let changes = diff_commands(existing, commands, &ignored)?;
+            if !dry_run {
+                apply_changes(http, guild, &changes).await?;
+            }
+            diffs.push(SyncDiff { guild, changes });
+        }
+        diffs.sort_by_key(|diff| diff.guild);
+        Ok(diffs)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "sync_commands",
+    desc = "Synchronize registered commands with Discord (admin-only)"
+)]
+pub struct SyncCommands {
+    #[cmd(desc = "Only show what would change")]
+    dry_run: Option<bool>,
+    #[cmd(desc = "Show the differences between registered and local commands")]
+    verbose: Option<bool>,
+}
+
+impl SyncCommands {
+    async fn sync(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<String> {
+        let dry_run = self.dry_run == Some(true);
+        let diffs = handler
+            .sync_commands(&ctx.http, &Vec::from_iter(command.guild_id), dry_run)
+            .await?;
+        let mut resp = if dry_run {
+            "Dry run, no changes applied".to_string()
+        } else {
+            "Commands synced".to_string()
+        };
+        let mut formatted = format_diffs(&diffs, self.verbose == Some(true));
+        if formatted.len() > MAX_RESPONSE_LEN {
+            let end = formatted.floor_char_boundary(MAX_RESPONSE_LEN);
+            formatted.truncate(end);
+            formatted.push_str("\n...");
+        }
+        _ = write!(&mut resp, "\n```diff\n{formatted}```");
+        Ok(resp)
+    }
+}
+
+#[async_trait]
+impl BotCommand for SyncCommands {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        if !is_admin(&handler.db.lock().await.conn, command.user.id)? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
+        }
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let resp = match self.sync(handler, ctx, command).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::warn!("sync commands failed: {e:?}");
+                format!("Failed to sync commands: {e}")
+            }
+        };
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(resp))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+// Outcome of re-registering a single command
+pub enum Reregistered {
+    Done,
+    Failed(String),
+    // not attempted after Discord's command creation limit was hit
+    Skipped,
+}
+
+fn is_rate_limited(e: &serenity::Error) -> bool {
+    matches!(
+        e,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(resp))
+            if resp.status_code.as_u16() == 429
+    )
+}
+
+impl Handler {
+    // Recreate every command of a scope from the CommandStore, even if it looks up to date.
+    // Discord limits command creations per day, so the remaining commands are skipped once
+    // that limit is hit.
+    // Guilds only get the commands specific to them, global commands are left alone.
+    pub async fn reregister_commands(
+        &self,
+        http: &Http,
+        guild: Option<GuildId>,
+    ) -> Vec<(String, Reregistered)> {
+        let commands = self
+            .commands
+            .read()
+            .await
+            .0
+            .values()
+            .filter(|runner| runner.guild() == guild)
+            .map(|runner| (runner.name().0.to_string(), runner.register()))
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect_vec();
+        let mut results = Vec::with_capacity(commands.len());
+        let mut rate_limited = false;
+        for (name, cmd) in commands {
+            if rate_limited {
+                results.push((name, Reregistered::Skipped));
+                continue;
+            }
+            let res = match guild {
+                None => Command::create_global_command(http, cmd).await.map(|_| ()),
+                Some(guild_id) => guild_id.create_command(http, cmd).await.map(|_| ()),
+            };
+            let outcome = match res {
+                Ok(()) => Reregistered::Done,
+                Err(e) => {
+                    rate_limited = is_rate_limited(&e);
+                    Reregistered::Failed(e.to_string())
+                }
+            };
+            results.push((name, outcome));
+            tokio::time::sleep(REREGISTER_DELAY).await;
+        }
+        results
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "reregister_commands",
+    desc = "Recreate commands so Discord shows up to date descriptions and options (admin-only)"
+)]
+pub struct ReregisterCommands {
+    #[cmd(desc = "global, this_guild or a server ID")]
+    scope: String,
+}
+
+impl ReregisterCommands {
+    fn guild(&self, command: &CommandInteraction) -> anyhow::Result<Option<GuildId>> {
+        Ok(match self.scope.trim() {
+            "global" => None,
+            "this_guild" => Some(command.guild_id()?),
+            id => match id.parse() {
+                Ok(id) => Some(GuildId::new(id)),
+                Err(_) => bail!("Scope must be global, this_guild or a server ID"),
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl BotCommand for ReregisterCommands {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        if !is_admin(&handler.db.lock().await.conn, command.user.id)? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
+        }
+        let guild = self.guild(command)?;
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let results = handler.reregister_commands(&ctx.http, guild).await;
+        let done = results
+            .iter()
+            .filter(|(_, r)| matches!(r, Reregistered::Done))
+            .count();
+        let mut resp = format!("Re-registered {done}/{} commands", results.len());
+        if let Some(guild_id) = guild {
+            _ = write!(&mut resp, " in {guild_id}");
+        }
+        let mut details = String::new();
+        for (name, result) in &results {
+            match result {
+                Reregistered::Done => _ = writeln!(&mut details, "+ {name}"),
+                Reregistered::Failed(e) => _ = writeln!(&mut details, "- {name}: {e}"),
+                Reregistered::Skipped => _ = writeln!(&mut details, "  {name}: skipped"),
+            }
+        }
+        if results
+            .iter()
+            .any(|(_, r)| matches!(r, Reregistered::Skipped))
+        {
+            resp.push_str("\nDiscord's daily command creation limit was reached, try again later");
+        }
+        if details.len() > MAX_RESPONSE_LEN {
+            let end = details.floor_char_boundary(MAX_RESPONSE_LEN);
+            details.truncate(end);
+            details.push_str("\n...");
+        }
+        if !details.is_empty() {
+            _ = write!(&mut resp, "\n```diff\n{details}```");
+        }
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(resp))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "bot_health",
+    desc = "Show the state of the bot's external services (admin-only)"
+)]
+pub struct BotHealth;
+
+#[async_trait]
+impl BotCommand for BotHealth {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        if !is_admin(&handler.db.lock().await.conn, command.user.id)? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
+        }
+        let mut resp = String::new();
+        // buttons to fix what can be fixed from Discord, e.g. authorizing Spotify again
+        #[cfg_attr(not(feature = "spotify"), allow(unused_mut))]
+        let mut components: Vec<CreateActionRow> = Vec::new();
+        let (healthy, degraded): (Vec<_>, Vec<_>) = handler
+            .check_health()
+            .await
+            .into_iter()
+            .partition(|(_, status)| status.is_healthy());
+        _ = writeln!(&mut resp, "Modules: {} healthy", healthy.len());
+        for (module, status) in degraded {
+            _ = writeln!(&mut resp, "- {module}: {status}");
+        }
+        #[cfg(feature = "spotify")]
+        {
+            let status = match handler.module::<crate::modules::SpotifyOAuth>() {
+                Ok(spotify) => {
+                    let status = spotify.token_status().await;
+                    if !status.is_valid() {
+                        components.push(spotify.auth_components()?);
+                    }
+                    status.to_string()
+                }
+                Err(_) => "not configured".to_string(),
+            };
+            _ = writeln!(&mut resp, "Spotify OAuth token: {status}");
+        }
+        #[cfg(feature = "album_lookup")]
+        if let Ok(lookup) = handler.module::<crate::modules::AlbumLookup>() {
+            let stats = lookup.latency_stats();
+            for (provider, stats) in stats.iter().sorted_by_key(|(p, _)| **p) {
+                _ = writeln!(
+                    &mut resp,
+                    "{provider}: {:.1?} average, {}/{} timeouts",
+                    stats.average, stats.timeouts, stats.calls
+                );
+            }
+        }
+        let leases = handler.db.lock().await.leases().unwrap_or_default();
+        if !leases.is_empty() {
+            _ = writeln!(
+                &mut resp,
+                "Background jobs (this instance is `{}`):",
+                instance_id()
+            );
+        }
+        let now = handler.clock.now().timestamp();
+        for lease in leases {
+            let state = if lease.expires_at <= now {
+                "expired"
+            } else if lease.is_ours() {
+                "running here"
+            } else {
+                "running elsewhere"
+            };
+            _ = writeln!(
+                &mut resp,
+                "- {}: {state}, held by `{}`, renewed <t:{}:R>",
+                lease.job, lease.holder, lease.renewed_at
+            );
+        }
+        if resp.is_empty() {
+            resp.push_str("Nothing to report");
+        }
+        let msg = CreateInteractionResponseMessage::new()
+            .content(resp)
+            .components(components)
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "presence",
+    desc = "Set the bot's activity and status, leave empty to reset (admin-only)"
+)]
+pub struct SetPresence {
+    #[cmd(desc = "Activity text, e.g. an album name")]
+    text: Option<String>,
+    #[cmd(desc = "Kind of activity (defaults to playing)")]
+    kind: Option<String>,
+    #[cmd(desc = "Online status")]
+    status: Option<String>,
+}
+
+fn parse_activity(kind: Option<&str>, text: String) -> anyhow::Result<ActivityData> {
+    Ok(match kind.unwrap_or("playing") {
+        "playing" => ActivityData::playing(text),
+        "listening" => ActivityData::listening(text),
+        "watching" => ActivityData::watching(text),
+        "competing" => ActivityData::competing(text),
+        "custom" => ActivityData::custom(text),
+        other => bail!("Unknown activity kind {other}"),
+    })
+}
+
+fn parse_status(status: &str) -> anyhow::Result<OnlineStatus> {
+    Ok(match status {
+        "online" => OnlineStatus::Online,
+        "idle" => OnlineStatus::Idle,
+        "dnd" => OnlineStatus::DoNotDisturb,
+        "invisible" => OnlineStatus::Invisible,
+        other => bail!("Unknown status {other}"),
+    })
+}
+
+#[async_trait]
+impl BotCommand for SetPresence {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        if !is_admin(&handler.db.lock().await.conn, command.user.id)? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
+        }
+        let presence = &handler.presence;
+        if let Some(status) = self.status.as_deref() {
+            presence.set_status(parse_status(status)?);
+        }
+        let resp = match self.text {
+            Some(text) => {
+                let activity = parse_activity(self.kind.as_deref(), text)?;
+                presence.set(
+                    PRESENCE_KEY,
+                    PresenceEntry::new(activity, PRIORITY_OVERRIDE),
+                );
+                "Presence updated"
+            }
+            None if self.status.is_some() => "Status updated",
+            None => {
+                presence.remove(PRESENCE_KEY);
+                "Presence reset"
+            }
+        };
+        presence.refresh(ctx);
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        let choices = match opt_name {
+            "kind" => ACTIVITY_KINDS,
+            "status" => STATUSES,
+            _ => return opt,
+        };
+        choices
+            .iter()
+            .fold(opt, |opt, &choice| opt.add_string_choice(choice, choice))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "maintenance",
+    desc = "Answer commands with a notice and pause background jobs (admin-only)"
+)]
+pub struct Maintenance {
+    #[cmd(desc = "Turn maintenance mode on or off")]
+    mode: String,
+    #[cmd(desc = "Notice shown to users running commands")]
+    message: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for Maintenance {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let db = handler.db.lock().await;
+        if !is_admin(&db.conn, command.user.id)? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
+        }
+        let presence = &handler.presence;
+        let resp = match self.mode.as_str() {
+            "on" => {
+                let message = self.message.as_deref().unwrap_or(DEFAULT_NOTICE);
+                db.set_maintenance(Some(message))?;
+                let activity = ActivityData::custom("Under maintenance");
+                presence.set(
+                    MAINTENANCE_KEY,
+                    PresenceEntry::new(activity, PRIORITY_MAINTENANCE),
+                );
+                presence.set_status(OnlineStatus::DoNotDisturb);
+                "Maintenance mode on, commands from non-admins will get the notice"
+            }
+            "off" => {
+                db.set_maintenance(None)?;
+                presence.remove(MAINTENANCE_KEY);
+                presence.set_status(OnlineStatus::Online);
+                "Maintenance mode off"
+            }
+            other => bail!("Unknown mode {other}"),
+        };
+        presence.refresh(ctx);
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "mode" => opt
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off"),
+            _ => opt,
+        }
+    }
+}
+
+pub struct BotManagement;
+
+#[async_trait]
+impl Module for BotManagement {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Sql>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(BotManagement)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_maintenance_table()?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<SyncCommands>();
+        store.register::<ReregisterCommands>();
+        store.register::<BotHealth>();
+        store.register::<SetPresence>();
+        store.register::<Maintenance>();
+    }
+}
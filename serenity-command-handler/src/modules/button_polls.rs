@@ -0,0 +1,733 @@
+// Polls with several options, created with /vote or from a form, and voted on with buttons or
+// a select menu.
+// Unlike reaction polls, polls and votes are stored so that tallies survive restarts and
+// results can be exported with /poll_results after the poll is closed. Polls with a deadline
+// are closed by a scheduled job, which posts a summary of the results.
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, TimeZone, Utc};
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
+use itertools::Itertools;
+use rusqlite::{params, OptionalExtension};
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateAttachment, CreateButton, CreateEmbed,
+    CreateEmbedFooter, CreateInputText, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption, EditMessage,
+};
+use serenity::http::Http;
+use serenity::model::application::{
+    ButtonStyle, ComponentInteraction, ComponentInteractionDataKind, InputTextStyle,
+    ModalInteraction,
+};
+use serenity::model::prelude::{
+    ChannelId, CommandInteraction, GuildId, Message, MessageId, UserId,
+};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::csv::csv_field;
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlMessageId, SqlUserId};
+use crate::modal::{input_value, ModalForm};
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::time_parse::parse_time;
+
+const FORM_ID: &str = "poll_form";
+const VOTE_PREFIX: &str = "poll_vote:";
+const VOTE_MENU_ID: &str = "poll_vote_menu";
+// Leaves room in the last action row, Discord allows 25 buttons per message
+const MAX_OPTIONS: usize = 20;
+// Discord's limit for button labels
+const MAX_LABEL_LEN: usize = 80;
+// Voters listed under each option of public polls
+const MAX_VOTERS_SHOWN: usize = 15;
+// Discord's limit for embed descriptions, with room left for the closing line
+const MAX_DESCRIPTION_LEN: usize = 4096 - 64;
+const TALLY_BAR_LEN: usize = 10;
+const CSV_HEADER: &str = "user_id,choice,voted_at";
+
+struct ButtonPoll {
+    question: String,
+    options: Vec<String>,
+    closes_at: Option<i64>,
+    // list who voted for each option
+    public: bool,
+    // vote with a select menu instead of buttons
+    menu: bool,
+    closed: bool,
+}
+
+impl ButtonPoll {
+    fn is_closed(&self, now: DateTime<Utc>) -> bool {
+        self.closed || self.closes_at.is_some_and(|at| at <= now.timestamp())
+    }
+
+    // Live tally of the votes, with the voters of each option if the poll is public
+    fn embed(&self, voters: &[Vec<UserId>]) -> CreateEmbed {
+        let total: usize = voters.iter().map(Vec::len).sum();
+        let tallies = self
+            .options
+            .iter()
+            .zip(voters)
+            .map(|(option, voters)| {
+                let count = voters.len();
+                let filled = (count * TALLY_BAR_LEN).checked_div(total).unwrap_or(0);
+                let bar = "█".repeat(filled) + &"░".repeat(TALLY_BAR_LEN - filled);
+                let percent = (count * 100).checked_div(total).unwrap_or(0);
+                let s = if count == 1 { "" } else { "s" };
+                format!("**{option}**\n{bar} {count} vote{s} ({percent}%)\n")
+            })
+            .collect_vec();
+        // what is left after the tallies is shared between the voter lists
+        let listed = voters.iter().filter(|v| !v.is_empty()).count().max(1);
+        let voters_len =
+            MAX_DESCRIPTION_LEN.saturating_sub(tallies.iter().map(String::len).sum()) / listed;
+        let mut description = String::new();
+        for (tally, voters) in tallies.iter().zip(voters) {
+            description.push_str(tally);
+            if self.public && !voters.is_empty() {
+                description.push_str(&voter_list(voters, voters_len));
+            }
+        }
+        if self.closed {
+            description.push_str("\nThis poll is closed");
+        } else if let Some(at) = self.closes_at {
+            description.push_str(&format!("\nCloses <t:{at}:R>"));
+        }
+        let mode = if self.public { "Public" } else { "Anonymous" };
+        let footer = CreateEmbedFooter::new(format!("{mode} poll, {total} votes"));
+        CreateEmbed::new()
+            .title(&self.question)
+            .description(description)
+            .footer(footer)
+    }
+
+    fn components(&self) -> Vec<CreateActionRow> {
+        if self.closed {
+            return Vec::new();
+        }
+        let label = |option: &String| option.chars().take(MAX_LABEL_LEN).collect::<String>();
+        if self.menu {
+            let options = self
+                .options
+                .iter()
+                .enumerate()
+                .map(|(i, option)| CreateSelectMenuOption::new(label(option), i.to_string()))
+                .collect();
+            let menu =
+                CreateSelectMenu::new(VOTE_MENU_ID, CreateSelectMenuKind::String { options })
+                    .placeholder("Vote");
+            return vec![CreateActionRow::SelectMenu(menu)];
+        }
+        self.options
+            .iter()
+            .enumerate()
+            .map(|(i, option)| {
+                CreateButton::new(format!("{VOTE_PREFIX}{i}"))
+                    .label(label(option))
+                    .style(ButtonStyle::Secondary)
+            })
+            .chunks(5)
+            .into_iter()
+            .map(|row| CreateActionRow::Buttons(row.collect()))
+            .collect()
+    }
+
+    // Winning options once the poll is closed
+    fn summary(&self, voters: &[Vec<UserId>]) -> String {
+        let top = voters.iter().map(Vec::len).max().unwrap_or(0);
+        if top == 0 {
+            return format!("Poll closed: **{}**\nNobody voted", self.question);
+        }
+        let winners = self
+            .options
+            .iter()
+            .zip(voters)
+            .filter(|(_, voters)| voters.len() == top)
+            .map(|(option, _)| format!("**{option}**"))
+            .collect_vec();
+        let s = if top == 1 { "" } else { "s" };
+        if winners.len() == 1 {
+            format!(
+                "Poll closed: **{}**\nWinner: {} with {top} vote{s}",
+                self.question, winners[0]
+            )
+        } else {
+            format!(
+                "Poll closed: **{}**\nTie between {} with {top} vote{s} each",
+                self.question,
+                winners.join(", ")
+            )
+        }
+    }
+}
+
+// Mentions of the voters for an option, cut off to fit in `max_len` bytes
+fn voter_list(voters: &[UserId], max_len: usize) -> String {
+    let mut list = String::new();
+    let mut shown = 0;
+    for user in voters.iter().take(MAX_VOTERS_SHOWN) {
+        let mention = format!("<@{user}> ");
+        // keep room for the count of voters not shown
+        if list.len() + mention.len() + " +1000\n".len() > max_len {
+            break;
+        }
+        list.push_str(&mention);
+        shown += 1;
+    }
+    if voters.len() > shown {
+        list.push_str(&format!("+{}", voters.len() - shown));
+    }
+    list.truncate(list.trim_end().len());
+    list.push('\n');
+    list
+}
+
+// Apply a vote to the voters of each option, in the same way as Db::set_vote and
+// Db::toggle_vote would
+fn apply_vote(voters: &mut [Vec<UserId>], user_id: UserId, option: usize, toggle: bool) {
+    let previous = voters.iter().position(|v| v.contains(&user_id));
+    for voters in voters.iter_mut() {
+        voters.retain(|&u| u != user_id);
+    }
+    if !(toggle && previous == Some(option)) {
+        if let Some(voters) = voters.get_mut(option) {
+            voters.push(user_id);
+        }
+    }
+}
+
+impl Db {
+    fn button_poll(&self, message_id: MessageId) -> anyhow::Result<Option<ButtonPoll>> {
+        let poll = self
+            .conn
+            .query_row(
+                "SELECT question, options, closes_at, public, menu, closed FROM button_poll
+                 WHERE message_id = ?1",
+                [SqlMessageId(message_id)],
+                |row| {
+                    let options: String = row.get(1)?;
+                    Ok(ButtonPoll {
+                        question: row.get(0)?,
+                        options: options.lines().map(str::to_string).collect(),
+                        closes_at: row.get(2)?,
+                        public: row.get(3)?,
+                        menu: row.get(4)?,
+                        closed: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(poll)
+    }
+
+    // Voters for each option, in the order they voted
+    fn button_poll_voters(
+        &self,
+        message_id: MessageId,
+        options: usize,
+    ) -> anyhow::Result<Vec<Vec<UserId>>> {
+        let mut voters = vec![Vec::new(); options];
+        self.conn
+            .prepare(
+                "SELECT choice, user_id FROM button_poll_vote WHERE message_id = ?1 ORDER BY ts",
+            )?
+            .query([SqlMessageId(message_id)])?
+            .map(|row| Ok((row.get::<_, usize>(0)?, row.get::<_, SqlUserId>(1)?)))
+            .for_each(|(option, SqlUserId(user_id))| {
+                if let Some(voters) = voters.get_mut(option) {
+                    voters.push(user_id);
+                }
+                Ok(())
+            })?;
+        Ok(voters)
+    }
+
+    // Open polls whose deadline passed
+    fn due_button_polls(&self, now: i64) -> anyhow::Result<Vec<(ChannelId, MessageId)>> {
+        let polls = self
+            .conn
+            .prepare(
+                "SELECT channel_id, message_id FROM button_poll
+                 WHERE NOT closed AND closes_at <= ?1",
+            )?
+            .query([now])?
+            .map(|row| {
+                let channel: SqlChannelId = row.get(0)?;
+                let message: SqlMessageId = row.get(1)?;
+                Ok((channel.0, message.0))
+            })
+            .collect()?;
+        Ok(polls)
+    }
+
+    fn set_vote(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        option: usize,
+    ) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT INTO button_poll_vote (message_id, user_id, choice, ts)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(message_id, user_id) DO UPDATE SET choice = ?3, ts = ?4",
+            params![
+                SqlMessageId(message_id),
+                SqlUserId(user_id),
+                option,
+                Utc::now().timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Vote for an option, or withdraw the vote if it was already for that option
+    fn toggle_vote(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        option: usize,
+    ) -> anyhow::Result<()> {
+        let withdrawn = self.conn.execute(
+            "DELETE FROM button_poll_vote WHERE message_id = ?1 AND user_id = ?2 AND choice = ?3",
+            params![SqlMessageId(message_id), SqlUserId(user_id), option],
+        )?;
+        if withdrawn == 0 {
+            self.set_vote(message_id, user_id, option)?;
+        }
+        Ok(())
+    }
+}
+
+fn new_poll<'a>(
+    handler: &Handler,
+    question: &str,
+    options: impl Iterator<Item = &'a str>,
+    closes: Option<&str>,
+) -> anyhow::Result<ButtonPoll> {
+    let now = handler.clock.now();
+    let options = options
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .unique()
+        .collect_vec();
+    if options.len() < 2 {
+        bail!("The poll needs at least 2 different options");
+    }
+    if options.len() > MAX_OPTIONS {
+        bail!("Polls can have at most {MAX_OPTIONS} options");
+    }
+    let closes_at = closes
+        .map(|closes| parse_time(closes, now))
+        .transpose()?
+        .map(|at| at.timestamp());
+    if closes_at.is_some_and(|at| at <= now.timestamp()) {
+        bail!("The poll must close in the future");
+    }
+    Ok(ButtonPoll {
+        question: question.to_string(),
+        options,
+        closes_at,
+        public: false,
+        menu: false,
+        closed: false,
+    })
+}
+
+fn parse_form(handler: &Handler, modal: &ModalInteraction) -> anyhow::Result<ButtonPoll> {
+    let question =
+        input_value(modal, "question").ok_or_else(|| anyhow!("The poll needs a question"))?;
+    let options = input_value(modal, "options").unwrap_or_default().lines();
+    new_poll(handler, question, options, input_value(modal, "closes"))
+}
+
+fn save_poll(
+    db: &Db,
+    guild_id: GuildId,
+    author_id: UserId,
+    message: &Message,
+    poll: &ButtonPoll,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT INTO button_poll (
+            message_id, guild_id, channel_id, author_id, question, options, closes_at, ts,
+            public, menu
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            SqlMessageId(message.id),
+            SqlGuildId(guild_id),
+            SqlChannelId(message.channel_id),
+            SqlUserId(author_id),
+            poll.question,
+            poll.options.join("\n"),
+            poll.closes_at,
+            Utc::now().timestamp(),
+            poll.public,
+            poll.menu
+        ],
+    )?;
+    Ok(())
+}
+
+fn poll_message(poll: &ButtonPoll) -> CreateInteractionResponse {
+    let msg = CreateInteractionResponseMessage::new()
+        .embed(poll.embed(&vec![Vec::new(); poll.options.len()]))
+        .components(poll.components())
+        .allowed_mentions(CreateAllowedMentions::new());
+    CreateInteractionResponse::Message(msg)
+}
+
+async fn create_poll(
+    handler: &Handler,
+    ctx: &Context,
+    modal: &ModalInteraction,
+    guild_id: GuildId,
+) -> anyhow::Result<()> {
+    let poll = match parse_form(handler, modal) {
+        Ok(poll) => poll,
+        Err(e) => {
+            let msg = CreateInteractionResponseMessage::new()
+                .content(e.to_string())
+                .ephemeral(true);
+            modal
+                .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+                .await?;
+            return Ok(());
+        }
+    };
+    modal
+        .create_response(&ctx.http, poll_message(&poll))
+        .await?;
+    let message = modal.get_response(&ctx.http).await?;
+    let db = handler.db.lock().await;
+    save_poll(&db, guild_id, modal.user.id, &message, &poll)
+}
+
+fn handle_form<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    modal: &'a ModalInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if modal.data.custom_id != FORM_ID {
+            return Ok(false);
+        }
+        let guild_id = modal
+            .guild_id
+            .ok_or_else(|| anyhow!("must be run in a guild"))?;
+        create_poll(handler, ctx, modal, guild_id).await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+fn handle_vote<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    component: &'a ComponentInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        // buttons toggle the vote, picking an option in the menu replaces it
+        let (option, toggle) = match &component.data.kind {
+            ComponentInteractionDataKind::StringSelect { values }
+                if component.data.custom_id == VOTE_MENU_ID =>
+            {
+                let option = values.first().ok_or_else(|| anyhow!("No option picked"))?;
+                (option.parse::<usize>()?, false)
+            }
+            _ => match component.data.custom_id.strip_prefix(VOTE_PREFIX) {
+                Some(option) => (option.parse()?, true),
+                None => return Ok(false),
+            },
+        };
+        let message_id = component.message.id;
+        let user_id = component.user.id;
+        let now = handler.clock.now();
+        let updated = handler
+            .db_read(move |db| {
+                Ok(match db.button_poll(message_id)? {
+                    Some(poll) if !poll.is_closed(now) => {
+                        let mut voters = db.button_poll_voters(message_id, poll.options.len())?;
+                        apply_vote(&mut voters, user_id, option, toggle);
+                        Ok(poll.embed(&voters))
+                    }
+                    Some(_) => Err("This poll is closed"),
+                    None => Err("This poll does not exist anymore"),
+                })
+            })
+            .await?;
+        let voted = updated.is_ok();
+        let resp = match updated {
+            // polls created before tallies were embeds had them in the content
+            Ok(embed) => CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content("")
+                    .embed(embed)
+                    .allowed_mentions(CreateAllowedMentions::new()),
+            ),
+            Err(reason) => CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(reason)
+                    .ephemeral(true),
+            ),
+        };
+        component.create_response(&ctx.http, resp).await?;
+        // only saved once the tally is shown, so a failed update does not count the vote
+        if voted {
+            handler
+                .db_call(move |db| {
+                    if toggle {
+                        db.toggle_vote(message_id, user_id, option)
+                    } else {
+                        db.set_vote(message_id, user_id, option)
+                    }
+                })
+                .await?;
+        }
+        Ok(true)
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "poll_form",
+    desc = "Create a poll with several options, voted on with buttons",
+    guild_only
+)]
+pub struct PollForm {}
+
+#[async_trait]
+impl GuildCommand for PollForm {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
+
+    async fn run_in_guild(
+        self,
+        _handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        _guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let form = ModalForm::new(FORM_ID, "New poll")
+            .input(
+                CreateInputText::new(InputTextStyle::Short, "Question", "question").max_length(200),
+            )
+            .paragraph("options", "Options, one per line")
+            .input(
+                CreateInputText::new(
+                    InputTextStyle::Short,
+                    "Closes (e.g. in 2h, 21:30)",
+                    "closes",
+                )
+                .required(false),
+            );
+        command.create_response(&ctx.http, form.response()).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "vote",
+    desc = "Create a poll with up to 10 options, voted on with buttons or a menu",
+    guild_only
+)]
+pub struct Vote {
+    #[cmd(desc = "Question of the poll", max_len = 200)]
+    question: String,
+    #[cmd(desc = "An option of the poll", count = 10, max_len = 80)]
+    option: Vec<String>,
+    #[cmd(desc = "When the poll closes (e.g. in 2h, 21:30)")]
+    closes: Option<String>,
+    #[cmd(desc = "Show who voted for each option (default: anonymous)")]
+    public: Option<bool>,
+    #[cmd(desc = "Vote with a select menu instead of buttons")]
+    menu: Option<bool>,
+}
+
+#[async_trait]
+impl GuildCommand for Vote {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let options = self.option.iter().map(String::as_str);
+        let mut poll = new_poll(handler, &self.question, options, self.closes.as_deref())?;
+        poll.public = self.public.unwrap_or(false);
+        poll.menu = self.menu.unwrap_or(false);
+        command
+            .create_response(&ctx.http, poll_message(&poll))
+            .await?;
+        let message = command.get_response(&ctx.http).await?;
+        let db = handler.db.lock().await;
+        save_poll(&db, guild_id, command.user.id, &message, &poll)?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "poll_results",
+    desc = "Export the votes of a poll created with /poll_form as CSV",
+    guild_only
+)]
+pub struct PollResults {
+    #[cmd(desc = "Link to the poll message")]
+    poll: String,
+}
+
+#[async_trait]
+impl GuildCommand for PollResults {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        // message links end with the message id, ids are accepted as is
+        let message_id = self
+            .poll
+            .trim()
+            .rsplit('/')
+            .next()
+            .and_then(|id| id.parse().ok())
+            .map(MessageId::new)
+            .ok_or_else(|| anyhow!("Invalid poll link"))?;
+        let (poll, votes) = {
+            let db = handler.db.lock().await;
+            let in_guild: Option<SqlGuildId> = db
+                .conn
+                .query_row(
+                    "SELECT guild_id FROM button_poll WHERE message_id = ?1",
+                    [SqlMessageId(message_id)],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let poll = db
+                .button_poll(message_id)?
+                .filter(|_| in_guild.map(|g| g.0) == Some(guild_id))
+                .ok_or_else(|| anyhow!("Poll not found"))?;
+            let votes: Vec<(SqlUserId, usize, i64)> = db
+                .conn
+                .prepare(
+                    "SELECT user_id, choice, ts FROM button_poll_vote
+                     WHERE message_id = ?1 ORDER BY ts",
+                )?
+                .query([SqlMessageId(message_id)])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .collect()?;
+            (poll, votes)
+        };
+        let mut csv = format!("{CSV_HEADER}\n");
+        for (SqlUserId(user_id), option, ts) in &votes {
+            let option = poll.options.get(*option).map_or("", String::as_str);
+            let voted_at = Utc.timestamp_opt(*ts, 0).single().unwrap_or_default();
+            csv.push_str(&format!(
+                "{user_id},{},{}\n",
+                csv_field(option),
+                voted_at.to_rfc3339()
+            ));
+        }
+        let msg = CreateInteractionResponseMessage::new()
+            .content(format!("{} votes on \"{}\"", votes.len(), poll.question))
+            .add_file(CreateAttachment::bytes(
+                csv.into_bytes(),
+                "poll_results.csv",
+            ))
+            .allowed_mentions(CreateAllowedMentions::new())
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+async fn close_poll(
+    handler: &Handler,
+    http: &Http,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> anyhow::Result<()> {
+    // marked closed first, so that a poll whose message was deleted isn't retried
+    let (poll, voters) = {
+        let db = handler.db.lock().await;
+        db.conn.execute(
+            "UPDATE button_poll SET closed = true WHERE message_id = ?1",
+            [SqlMessageId(message_id)],
+        )?;
+        let poll = db
+            .button_poll(message_id)?
+            .ok_or_else(|| anyhow!("Poll not found"))?;
+        let voters = db.button_poll_voters(message_id, poll.options.len())?;
+        (poll, voters)
+    };
+    let edit = EditMessage::new()
+        .content("")
+        .embed(poll.embed(&voters))
+        .components(Vec::new());
+    channel_id.edit_message(http, message_id, edit).await?;
+    let summary = CreateMessage::new()
+        .content(poll.summary(&voters))
+        .reference_message((channel_id, message_id))
+        .allowed_mentions(CreateAllowedMentions::new());
+    channel_id.send_message(http, summary).await?;
+    Ok(())
+}
+
+fn close_due_polls<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = handler.clock.now().timestamp();
+        let due = handler.db_read(move |db| db.due_button_polls(now)).await?;
+        for (channel_id, message_id) in due {
+            if let Err(e) = close_poll(handler, http, channel_id, message_id).await {
+                tracing::warn!(%message_id, "Cannot close poll: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<PollForm>();
+    store.register::<Vote>();
+    store.register::<PollResults>();
+}
+
+pub(crate) fn register_component_handlers(handlers: &mut ComponentStore) {
+    handlers.push(handle_vote);
+}
+
+pub(crate) fn register_modal_handlers(handlers: &mut ModalStore) {
+    handlers.push(handle_form);
+}
+
+pub(crate) fn register_jobs(jobs: &mut JobStore) {
+    jobs.push(Job::new(
+        "close_polls",
+        Schedule::every(Duration::from_secs(60)),
+        close_due_polls,
+    ));
+}
@@ -0,0 +1,261 @@
+// Posts a short "what's new" summary in every guild the first time the bot runs a newer
+// version, on the ready event. The bot's version and changelog are given to `Changelog::new`,
+// and the module is added with `with_module`.
+use anyhow::{anyhow, Context as _};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::{params, OptionalExtension};
+use serenity::builder::{CreateEmbed, CreateEmbedFooter, CreateMessage};
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, Ready};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlChannelId, SqlGuildId};
+use crate::gateway::GatewayHandlers;
+use crate::prelude::*;
+
+// Older releases are summed up as a count, to keep the announcement short
+const MAX_RELEASES: usize = 3;
+
+pub struct Release {
+    pub version: &'static str,
+    pub changes: &'static [&'static str],
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let (major, minor, patch) = version.split('.').map(|n| n.parse().ok()).collect_tuple()?;
+    Some((major?, minor?, patch?))
+}
+
+fn whats_new_embed(version: &str, releases: &[&Release]) -> CreateEmbed {
+    let mut description = releases
+        .iter()
+        .take(MAX_RELEASES)
+        .map(|r| {
+            let changes = r.changes.iter().map(|c| format!("- {c}")).join("\n");
+            format!("**{}**\n{changes}", r.version)
+        })
+        .join("\n\n");
+    if releases.len() > MAX_RELEASES {
+        let older = releases.len() - MAX_RELEASES;
+        description.push_str(&format!("\n\n...and {older} older releases"));
+    }
+    CreateEmbed::new()
+        .title(format!("What's new in version {version}"))
+        .description(description)
+        .footer(CreateEmbedFooter::new(
+            "Use /setchangelog to choose where this is posted, or to turn it off",
+        ))
+}
+
+impl Db {
+    // Record that a guild has been told about the running version.
+    // Returns the version it was last told about, or None if it was already up to date.
+    // Guilds seen for the first time are stamped without announcing anything.
+    fn claim_announcement(
+        &mut self,
+        guild_id: GuildId,
+        version: &str,
+        now: i64,
+    ) -> anyhow::Result<Option<String>> {
+        let tx = self.conn.transaction()?;
+        let previous: Option<String> = tx
+            .query_row(
+                "SELECT version FROM changelog_announced WHERE guild_id = ?1",
+                [SqlGuildId(guild_id)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let newer = match &previous {
+            Some(previous) => parse_version(previous) < parse_version(version),
+            None => true,
+        };
+        if !newer {
+            return Ok(None);
+        }
+        // another instance sharing the database may have claimed it in the meantime
+        let claimed = tx.execute(
+            "INSERT INTO changelog_announced (guild_id, version, ts) VALUES (?1, ?2, ?3)
+             ON CONFLICT(guild_id) DO UPDATE SET version = ?2, ts = ?3 WHERE version IS ?4",
+            params![SqlGuildId(guild_id), version, now, previous],
+        )?;
+        tx.commit()?;
+        Ok(previous.filter(|_| claimed > 0))
+    }
+}
+
+async fn announcement_channel(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+) -> anyhow::Result<Option<ChannelId>> {
+    // guilds without settings yet have the default, announcing in the system channel
+    let (enabled, channel): (Option<bool>, Option<SqlChannelId>) = {
+        let db = handler.db.lock().await;
+        (
+            db.get_guild_field(guild_id, "announce_updates")?,
+            db.get_guild_field(guild_id, "changelog_channel")?,
+        )
+    };
+    if enabled == Some(false) {
+        return Ok(None);
+    }
+    match channel {
+        Some(SqlChannelId(channel_id)) => Ok(Some(channel_id)),
+        None => Ok(guild_id.to_partial_guild(http).await?.system_channel_id),
+    }
+}
+
+async fn announce_in_guild(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+) -> anyhow::Result<()> {
+    let changelog = handler.module::<Changelog>()?;
+    let (version, now) = (changelog.version, handler.clock.now().timestamp());
+    let claimed = handler
+        .db_call(move |db| db.claim_announcement(guild_id, version, now))
+        .await?;
+    let Some(previous) = claimed else {
+        return Ok(());
+    };
+    let releases = changelog.releases_since(&previous);
+    if releases.is_empty() {
+        return Ok(());
+    }
+    let Some(channel_id) = announcement_channel(handler, http, guild_id).await? else {
+        return Ok(());
+    };
+    channel_id
+        .send_message(
+            http,
+            CreateMessage::new().embed(whats_new_embed(version, &releases)),
+        )
+        .await?;
+    Ok(())
+}
+
+// Announce the changes since the last version each guild was told about.
+// Ready is sent again on reconnects, guilds are only told about a version once.
+fn announce_updates<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    ready: &'a Ready,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        for guild in &ready.guilds {
+            if let Err(e) = announce_in_guild(handler, &ctx.http, guild.id).await {
+                tracing::error!("Error announcing updates in {}: {e:?}", guild.id);
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setchangelog",
+    desc = "Post a summary of the bot's new features in this channel when it is updated",
+    guild_only
+)]
+pub struct SetChangelog {
+    enabled: bool,
+}
+
+#[async_trait]
+impl GuildCommand for SetChangelog {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = command.user.id;
+        handler
+            .set_guild_field(guild_id, user_id, "announce_updates", self.enabled)
+            .await
+            .context("updating 'announce_updates' guild field")?;
+        if !self.enabled {
+            return CommandResponse::private("Will not announce updates anymore");
+        }
+        handler
+            .set_guild_field(
+                guild_id,
+                user_id,
+                "changelog_channel",
+                SqlChannelId(command.channel_id),
+            )
+            .await
+            .context("updating 'changelog_channel' guild field")?;
+        CommandResponse::private(format!(
+            "Will announce updates in <#{}>",
+            command.channel_id
+        ))
+    }
+}
+
+pub struct Changelog {
+    version: &'static str,
+    releases: &'static [Release],
+}
+
+impl Changelog {
+    // `version` is the bot's, e.g. `env!("CARGO_PKG_VERSION")` in the bot crate, and
+    // `releases` its changelog, newest first
+    pub fn new(version: &'static str, releases: &'static [Release]) -> Self {
+        Changelog { version, releases }
+    }
+
+    // Releases after `since` up to the running version, newest first
+    fn releases_since(&self, since: &str) -> Vec<&'static Release> {
+        let (Some(since), Some(current)) = (parse_version(since), parse_version(self.version))
+        else {
+            return Vec::new();
+        };
+        self.releases
+            .iter()
+            .filter(|r| parse_version(r.version).is_some_and(|v| v > since && v <= current))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Module for Changelog {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Err(anyhow!(
+            "Must be initialized with Changelog::new and added using with_module"
+        ))
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        // when unset, updates are announced in the guild's system channel
+        db.add_guild_field("changelog_channel", "INTEGER")?;
+        db.add_guild_field("announce_updates", "BOOLEAN NOT NULL DEFAULT(true)")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS changelog_announced (
+                guild_id INTEGER PRIMARY KEY,
+                version STRING NOT NULL,
+                ts INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<SetChangelog>();
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(announce_updates);
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::{
+    CreateAutocompleteResponse, CreateCommandOption, CreateInteractionResponse,
+};
+use serenity::model::application::CommandType;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::get_str_opt_ac;
+use crate::db::{Db, Migration, SqlChannelId, SqlGuildId};
+use crate::modules::Help;
+use crate::prelude::*;
+
+const CONFIG_COMMAND: &str = "command_channels";
+// Never restricted, so that admins can't lock themselves out
+pub(crate) const CONFIG_COMMANDS: &[&str] = &[CONFIG_COMMAND, "command_permissions"];
+
+#[derive(Default)]
+pub(crate) struct Restrictions {
+    pub allowed: Vec<ChannelId>,
+    pub denied: Vec<ChannelId>,
+}
+
+impl Restrictions {
+    // A command with an allow list can only be used in those channels
+    fn permits(&self, channel_id: ChannelId) -> bool {
+        !self.denied.contains(&channel_id)
+            && (self.allowed.is_empty() || self.allowed.contains(&channel_id))
+    }
+}
+
+pub(crate) fn guild_restrictions(
+    db: &Db,
+    guild_id: GuildId,
+    command: Option<&str>,
+) -> anyhow::Result<HashMap<String, Restrictions>> {
+    let rows: Vec<(String, SqlChannelId, bool)> = db
+        .conn
+        .prepare(
+            "SELECT command, channel_id, allow FROM command_channels
+             WHERE guild_id = ?1 AND (?2 IS NULL OR command = ?2)",
+        )?
+        .query(params![SqlGuildId(guild_id), command])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .collect()?;
+    let mut restrictions = HashMap::<String, Restrictions>::new();
+    for (command, SqlChannelId(channel_id), allow) in rows {
+        let entry = restrictions.entry(command).or_default();
+        if allow {
+            entry.allowed.push(channel_id);
+        } else {
+            entry.denied.push(channel_id);
+        }
+    }
+    Ok(restrictions)
+}
+
+// Commands that can't be used in a channel
+pub(crate) fn denied_commands(
+    db: &Db,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> anyhow::Result<HashSet<String>> {
+    let restrictions = guild_restrictions(db, guild_id, None)?;
+    Ok(restrictions
+        .into_iter()
+        .filter(|(_, r)| !r.permits(channel_id))
+        .map(|(command, _)| command)
+        .collect())
+}
+
+// Called before running a command.
+// Returns why the command can't be used: a message pointing to the allowed channels, or to
+// the roles it was restricted to with /command_permissions.
+pub async fn check_restrictions(
+    handler: &Handler,
+    cmd: &CommandInteraction,
+) -> anyhow::Result<Option<String>> {
+    let name = cmd.data.name.as_str();
+    let Some(guild_id) = cmd.guild_id else {
+        return Ok(None);
+    };
+    if CONFIG_COMMANDS.contains(&name) || !handler.modules.contains::<CommandChannels>() {
+        return Ok(None);
+    }
+    if let Some(msg) = check_channel(handler, cmd, guild_id).await? {
+        return Ok(Some(msg));
+    }
+    #[cfg(feature = "command_permissions")]
+    if let Some(msg) = super::command_permissions::check_roles(handler, cmd, guild_id).await? {
+        return Ok(Some(msg));
+    }
+    Ok(None)
+}
+
+async fn check_channel(
+    handler: &Handler,
+    cmd: &CommandInteraction,
+    guild_id: GuildId,
+) -> anyhow::Result<Option<String>> {
+    let name = cmd.data.name.clone();
+    let restrictions = handler
+        .db_read(move |db| guild_restrictions(db, guild_id, Some(&name)))
+        .await?;
+    let name = cmd.data.name.as_str();
+    let Some(restrictions) = restrictions.get(name) else {
+        return Ok(None);
+    };
+    if restrictions.permits(cmd.channel_id) {
+        return Ok(None);
+    }
+    let msg = if restrictions.allowed.is_empty() {
+        handler
+            .tr(cmd, "`/{command}` can't be used in this channel")
+            .replace("{command}", name)
+    } else {
+        let channels = restrictions
+            .allowed
+            .iter()
+            .map(|c| format!("<#{c}>"))
+            .join(", ");
+        handler
+            .tr(cmd, "`/{command}` can only be used in {channels}")
+            .replace("{command}", name)
+            .replace("{channels}", &channels)
+    };
+    Ok(Some(msg))
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "command_channels",
+    desc = "Restrict which channels a command can be used in"
+)]
+pub struct SetCommandChannels {
+    #[cmd(desc = "Name of the command", autocomplete)]
+    command: String,
+    #[cmd(desc = "Allow or deny the command in this channel, or list the current rules")]
+    action: String,
+}
+
+#[async_trait]
+impl BotCommand for SetCommandChannels {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let channel_id = command.channel_id;
+        let name = self.command.trim_start_matches('/');
+        if CONFIG_COMMANDS.contains(&name) {
+            bail!("`/{name}` can't be restricted");
+        }
+        if !handler
+            .commands
+            .read()
+            .await
+            .0
+            .contains_key(&(name, CommandType::ChatInput))
+        {
+            bail!("Unknown command `/{name}`");
+        }
+        let db = handler.db.lock().await;
+        let resp = match self.action.as_str() {
+            "allow" | "deny" => {
+                db.conn.execute(
+                    "INSERT INTO command_channels (guild_id, command, channel_id, allow)
+                     VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(guild_id, command, channel_id) DO UPDATE SET allow = ?4",
+                    params![
+                        SqlGuildId(guild_id),
+                        name,
+                        SqlChannelId(channel_id),
+                        self.action == "allow"
+                    ],
+                )?;
+                let status = if self.action == "allow" {
+                    "allowed"
+                } else {
+                    "denied"
+                };
+                format!("`/{name}` is now {status} in <#{channel_id}>")
+            }
+            "reset" => {
+                db.conn.execute(
+                    "DELETE FROM command_channels
+                     WHERE guild_id = ?1 AND command = ?2 AND channel_id = ?3",
+                    params![SqlGuildId(guild_id), name, SqlChannelId(channel_id)],
+                )?;
+                format!("Removed the rule for `/{name}` in <#{channel_id}>")
+            }
+            "clear" => {
+                db.conn.execute(
+                    "DELETE FROM command_channels WHERE guild_id = ?1 AND command = ?2",
+                    params![SqlGuildId(guild_id), name],
+                )?;
+                format!("`/{name}` can now be used in any channel")
+            }
+            "list" => {
+                let restrictions = guild_restrictions(&db, guild_id, Some(name))?;
+                let Some(r) = restrictions.get(name) else {
+                    return CommandResponse::private(format!(
+                        "`/{name}` can be used in any channel"
+                    ));
+                };
+                let fmt =
+                    |channels: &[ChannelId]| channels.iter().map(|c| format!("<#{c}>")).join(", ");
+                let mut lines = Vec::new();
+                if !r.allowed.is_empty() {
+                    lines.push(format!("Only allowed in {}", fmt(&r.allowed)));
+                }
+                if !r.denied.is_empty() {
+                    lines.push(format!("Denied in {}", fmt(&r.denied)));
+                }
+                format!("`/{name}`: {}", lines.join("\n"))
+            }
+            action => bail!("Unknown action {action}"),
+        };
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        if opt_name == "action" {
+            opt.add_string_choice("allow in this channel", "allow")
+                .add_string_choice("deny in this channel", "deny")
+                .add_string_choice("reset this channel", "reset")
+                .add_string_choice("clear all channels", "clear")
+                .add_string_choice("list", "list")
+        } else {
+            opt
+        }
+    }
+}
+
+// Completes the `command` option of /command_channels and /command_permissions
+fn complete_command_name<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    key: CommandKey<'a>,
+    ac: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if key.1 != CommandType::ChatInput || !CONFIG_COMMANDS.contains(&key.0) {
+            return Ok(false);
+        }
+        let current = get_str_opt_ac(&ac.data.options, "command").unwrap_or("");
+        let commands = handler.commands.read().await;
+        let resp = commands
+            .0
+            .keys()
+            .filter(|(name, kind)| {
+                *kind == CommandType::ChatInput
+                    && !CONFIG_COMMANDS.contains(name)
+                    && name.contains(current)
+            })
+            .map(|(name, _)| *name)
+            .sorted()
+            .take(25)
+            .fold(CreateAutocompleteResponse::new(), |resp, name| {
+                resp.add_string_choice(name, name)
+            });
+        ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+pub struct CommandChannels;
+
+#[async_trait]
+impl Module for CommandChannels {
+    // /help leaves out the commands that can't be used in the channel
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Help>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(CommandChannels)
+    }
+
+    const NAME: &'static str = "CommandChannels";
+    const MIGRATIONS: &'static [Migration] = &[Migration::sql(
+        1,
+        "create command_channels",
+        "CREATE TABLE IF NOT EXISTS command_channels (
+            guild_id INTEGER NOT NULL,
+            command STRING NOT NULL,
+            channel_id INTEGER NOT NULL,
+            allow BOOLEAN NOT NULL,
+            UNIQUE(guild_id, command, channel_id)
+        )",
+    )];
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<SetCommandChannels>();
+        completions.push(complete_command_name);
+    }
+}
@@ -0,0 +1,242 @@
+// Restrict commands to some roles, with /command_permissions. Channel restrictions are stored
+// as /command_channels allow rules, and both are checked by `check_restrictions`.
+use anyhow::bail;
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::CreateCommandOption;
+use serenity::model::application::CommandType;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, RoleId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, Migration, SqlGuildId};
+use crate::modules::command_channels::{guild_restrictions, CONFIG_COMMANDS};
+use crate::modules::CommandChannels;
+use crate::prelude::*;
+
+fn command_roles(db: &Db, guild_id: GuildId, command: &str) -> anyhow::Result<Vec<RoleId>> {
+    let roles = db
+        .conn
+        .prepare(
+            "SELECT target_id FROM command_permissions
+             WHERE guild_id = ?1 AND command = ?2 AND kind = 'role'",
+        )?
+        .query(params![SqlGuildId(guild_id), command])?
+        .map(|row| Ok(RoleId::new(row.get(0)?)))
+        .collect()?;
+    Ok(roles)
+}
+
+fn mention_roles(roles: &[RoleId]) -> String {
+    roles.iter().map(|r| format!("<@&{r}>")).join(", ")
+}
+
+// Returns why the command was refused if the user lacks the roles it is restricted to.
+// Members who can manage the guild bypass role restrictions.
+pub(crate) async fn check_roles(
+    handler: &Handler,
+    cmd: &CommandInteraction,
+    guild_id: GuildId,
+) -> anyhow::Result<Option<String>> {
+    let Some(member) = &cmd.member else {
+        return Ok(None);
+    };
+    if !handler.modules.contains::<CommandPermissions>()
+        || member.permissions.unwrap_or_default().manage_guild()
+    {
+        return Ok(None);
+    }
+    let command = cmd.data.name.clone();
+    let roles = handler
+        .db_read(move |db| command_roles(db, guild_id, &command))
+        .await?;
+    if roles.is_empty() || roles.iter().any(|r| member.roles.contains(r)) {
+        return Ok(None);
+    }
+    let msg = handler
+        .tr(cmd, "`/{command}` requires one of {roles}")
+        .replace("{command}", &cmd.data.name)
+        .replace("{roles}", &mention_roles(&roles));
+    Ok(Some(msg))
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "command_permissions",
+    desc = "Restrict a command to some roles or channels",
+    guild_only
+)]
+pub struct SetCommandPermissions {
+    #[cmd(desc = "Allow or remove a role or channel, or list the current overrides")]
+    action: String,
+    #[cmd(desc = "Name of the command", autocomplete)]
+    command: String,
+    #[cmd(desc = "Role allowed to use the command")]
+    role: Option<RoleId>,
+    #[cmd(desc = "Channel the command can be used in")]
+    channel: Option<ChannelId>,
+}
+
+impl SetCommandPermissions {
+    fn target(&self) -> anyhow::Result<(&'static str, u64, String)> {
+        match (self.role, self.channel) {
+            (Some(role), None) => Ok(("role", role.get(), format!("<@&{role}>"))),
+            (None, Some(channel)) => Ok(("channel", channel.get(), format!("<#{channel}>"))),
+            _ => bail!("Pick either a role or a channel"),
+        }
+    }
+}
+
+#[async_trait]
+impl GuildCommand for SetCommandPermissions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        _command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let name = self.command.trim_start_matches('/');
+        if CONFIG_COMMANDS.contains(&name) {
+            bail!("`/{name}` can't be restricted");
+        }
+        if !handler
+            .commands
+            .read()
+            .await
+            .0
+            .contains_key(&(name, CommandType::ChatInput))
+        {
+            bail!("Unknown command `/{name}`");
+        }
+        let db = handler.db.lock().await;
+        let resp = match self.action.as_str() {
+            "set" => {
+                let (kind, id, target) = self.target()?;
+                match kind {
+                    "role" => db.conn.execute(
+                        "INSERT OR IGNORE INTO command_permissions
+                         (guild_id, command, kind, target_id) VALUES (?1, ?2, ?3, ?4)",
+                        params![SqlGuildId(guild_id), name, kind, id],
+                    )?,
+                    _ => db.conn.execute(
+                        "INSERT INTO command_channels (guild_id, command, channel_id, allow)
+                         VALUES (?1, ?2, ?3, true)
+                         ON CONFLICT(guild_id, command, channel_id) DO UPDATE SET allow = true",
+                        params![SqlGuildId(guild_id), name, id],
+                    )?,
+                };
+                format!("`/{name}` is now allowed for {target}")
+            }
+            "remove" => {
+                let (kind, id, target) = self.target()?;
+                match kind {
+                    "role" => db.conn.execute(
+                        "DELETE FROM command_permissions
+                         WHERE guild_id = ?1 AND command = ?2 AND kind = ?3 AND target_id = ?4",
+                        params![SqlGuildId(guild_id), name, kind, id],
+                    )?,
+                    _ => db.conn.execute(
+                        "DELETE FROM command_channels
+                         WHERE guild_id = ?1 AND command = ?2 AND channel_id = ?3 AND allow",
+                        params![SqlGuildId(guild_id), name, id],
+                    )?,
+                };
+                format!("Removed {target} from the overrides of `/{name}`")
+            }
+            "clear" => {
+                db.conn.execute(
+                    "DELETE FROM command_permissions WHERE guild_id = ?1 AND command = ?2",
+                    params![SqlGuildId(guild_id), name],
+                )?;
+                db.conn.execute(
+                    "DELETE FROM command_channels WHERE guild_id = ?1 AND command = ?2",
+                    params![SqlGuildId(guild_id), name],
+                )?;
+                format!("`/{name}` can now be used by anyone, in any channel")
+            }
+            "list" => {
+                let roles = command_roles(&db, guild_id, name)?;
+                let restrictions = guild_restrictions(&db, guild_id, Some(name))?;
+                let channels = restrictions
+                    .get(name)
+                    .map(|r| r.allowed.as_slice())
+                    .unwrap_or_default();
+                if roles.is_empty() && channels.is_empty() {
+                    return CommandResponse::private(format!("`/{name}` has no overrides"));
+                }
+                let mut lines = Vec::new();
+                if !roles.is_empty() {
+                    lines.push(format!("Roles: {}", mention_roles(&roles)));
+                }
+                if !channels.is_empty() {
+                    let channels = channels.iter().map(|c| format!("<#{c}>")).join(", ");
+                    lines.push(format!("Channels: {channels}"));
+                }
+                format!("`/{name}`\n{}", lines.join("\n"))
+            }
+            action => bail!("Unknown action {action}"),
+        };
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        if opt_name == "action" {
+            opt.add_string_choice("set", "set")
+                .add_string_choice("remove", "remove")
+                .add_string_choice("clear", "clear")
+                .add_string_choice("list", "list")
+        } else {
+            opt
+        }
+    }
+}
+
+pub struct CommandPermissions;
+
+#[async_trait]
+impl Module for CommandPermissions {
+    // channel restrictions are /command_channels rules
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<CommandChannels>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(CommandPermissions)
+    }
+
+    const NAME: &'static str = "CommandPermissions";
+    const MIGRATIONS: &'static [Migration] = &[
+        Migration::sql(
+            1,
+            "create command_permissions",
+            "CREATE TABLE IF NOT EXISTS command_permissions (
+                guild_id INTEGER NOT NULL,
+                command STRING NOT NULL,
+                kind STRING NOT NULL,
+                target_id INTEGER NOT NULL,
+                UNIQUE(guild_id, command, kind, target_id)
+            )",
+        ),
+        Migration::sql(
+            2,
+            "move channel overrides to command_channels",
+            "INSERT INTO command_channels (guild_id, command, channel_id, allow)
+                SELECT guild_id, command, target_id, true FROM command_permissions
+                WHERE kind = 'channel'
+                ON CONFLICT(guild_id, command, channel_id) DO UPDATE SET allow = true;
+            DELETE FROM command_permissions WHERE kind = 'channel';",
+        ),
+    ];
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        // the command option is completed by CommandChannels
+        store.register::<SetCommandPermissions>();
+    }
+}
@@ -0,0 +1,589 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+use anyhow::{anyhow, Context as _};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rusqlite::params;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{
+    CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage,
+};
+use serenity::model::application::{ButtonStyle, CommandInteraction, ComponentInteraction};
+use serenity::model::channel::Attachment;
+use serenity::model::prelude::{ChannelId, GuildId, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use tokio::sync::Mutex;
+
+use crate::command_context::download_attachment;
+use crate::db::{
+    update_guild_field, Db, SettingsConflict, SqlChannelId, SqlGuildId, SECRET_GUILD_FIELDS,
+};
+use crate::modules::{ModAutoreacts, Pinboard};
+use crate::prelude::*;
+
+const CONFIG_VERSION: u32 = 1;
+const IMPORT_PREFIX: &str = "import_config:";
+const MAX_PREVIEW_LEN: usize = 4000;
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+// Interaction tokens are only valid for 15 minutes, the buttons stop working after that
+const PENDING_IMPORT_SECS: i64 = 15 * 60;
+
+// Fields holding a role ID, exported by role name so they can be matched in another guild
+const ROLE_FIELDS: [&str; 1] = ["role_id"];
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GuildConfig {
+    pub version: u32,
+    #[serde(default)]
+    pub fields: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub roles: BTreeMap<String, String>,
+    #[serde(default)]
+    pub autoreacts: Vec<(String, String)>,
+    #[serde(default)]
+    pub pinboard_channels: Vec<String>,
+}
+
+struct FieldChange {
+    name: String,
+    old: Value,
+    new: Value,
+}
+
+impl FieldChange {
+    fn conflicts(&self) -> bool {
+        self.old != Value::Null
+    }
+}
+
+// Changes an import would make to a guild, kept until the admin confirms them
+#[derive(Default)]
+struct ImportPlan {
+    fields: Vec<FieldChange>,
+    autoreacts: Vec<(String, String)>,
+    // triggers that already have other emotes in the target guild
+    autoreact_conflicts: HashSet<String>,
+    pinboard_channels: Vec<ChannelId>,
+    skipped: Vec<String>,
+    // version of the guild's settings the plan was made from
+    version: i64,
+}
+
+impl ImportPlan {
+    fn has_conflicts(&self) -> bool {
+        self.fields.iter().any(FieldChange::conflicts) || !self.autoreact_conflicts.is_empty()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.autoreacts.is_empty() && self.pinboard_channels.is_empty()
+    }
+
+    fn preview(&self) -> String {
+        let mut out = String::new();
+        if !self.fields.is_empty() {
+            out.push_str("**Settings**\n");
+            for change in &self.fields {
+                let conflict = if change.conflicts() { " ⚠️" } else { "" };
+                _ = writeln!(
+                    out,
+                    "`{}`: {} → {}{conflict}",
+                    change.name,
+                    format_value(&change.old),
+                    format_value(&change.new)
+                );
+            }
+        }
+        if !self.autoreacts.is_empty() {
+            out.push_str("**Autoreacts**\n");
+            for (trigger, emote) in &self.autoreacts {
+                let conflict = if self.autoreact_conflicts.contains(trigger) {
+                    " ⚠️ replaces existing"
+                } else {
+                    ""
+                };
+                _ = writeln!(out, "+ `{trigger}` → {emote}{conflict}");
+            }
+        }
+        if !self.pinboard_channels.is_empty() {
+            out.push_str("**Pinboard channels**\n");
+            for channel in &self.pinboard_channels {
+                _ = writeln!(out, "+ <#{channel}>");
+            }
+        }
+        if !self.skipped.is_empty() {
+            out.push_str("**Skipped**\n");
+            for note in &self.skipped {
+                _ = writeln!(out, "- {note}");
+            }
+        }
+        if out.chars().count() > MAX_PREVIEW_LEN {
+            out = out.chars().take(MAX_PREVIEW_LEN).collect();
+            out.push('…');
+        }
+        out
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => "(none)".to_string(),
+        Value::Integer(i) => format!("`{i}`"),
+        Value::Real(r) => format!("`{r}`"),
+        Value::Text(s) => format!("`{s}`"),
+        Value::Blob(_) => "(binary)".to_string(),
+    }
+}
+
+fn value_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Integer(i) => i.into(),
+        Value::Real(r) => r.into(),
+        Value::Text(s) => s.into(),
+        Value::Null | Value::Blob(_) => serde_json::Value::Null,
+    }
+}
+
+fn json_to_value(value: &serde_json::Value) -> Option<Value> {
+    Some(match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64()?),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        _ => return None,
+    })
+}
+
+async fn export_config(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+) -> anyhow::Result<GuildConfig> {
+    let mut config = GuildConfig {
+        version: CONFIG_VERSION,
+        ..Default::default()
+    };
+    let roles = guild_id.roles(&ctx.http).await?;
+    let channels = guild_id.channels(&ctx.http).await?;
+    let db = handler.db.lock().await;
+    for (name, value) in db.get_guild_fields(guild_id)? {
+        if SECRET_GUILD_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        if ROLE_FIELDS.contains(&name.as_str()) {
+            let role = match value {
+                Value::Integer(id) => roles.iter().find(|(r, _)| r.get() as i64 == id),
+                Value::Text(id) => roles.iter().find(|(r, _)| r.to_string() == id),
+                _ => None,
+            };
+            if let Some((_, role)) = role {
+                config.roles.insert(name, role.name.clone());
+            }
+            continue;
+        }
+        config.fields.insert(name, value_to_json(value));
+    }
+    if handler.modules.contains::<ModAutoreacts>() {
+        config.autoreacts = db
+            .conn
+            .prepare(
+                "SELECT trigger, emote FROM autoreact WHERE guild_id = ?1 AND deleted_at IS NULL",
+            )?
+            .query([SqlGuildId(guild_id)])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+    }
+    if handler.modules.contains::<Pinboard>() {
+        let allowed: Vec<SqlChannelId> = db
+            .conn
+            .prepare("SELECT channel_id FROM pinboard_allowed_channels WHERE guild_id = ?1 AND deleted_at IS NULL")?
+            .query([SqlGuildId(guild_id)])?
+            .map(|row| row.get(0))
+            .collect()?;
+        config.pinboard_channels = allowed
+            .into_iter()
+            .filter_map(|SqlChannelId(id)| channels.get(&id).map(|c| c.name.clone()))
+            .collect();
+    }
+    Ok(config)
+}
+
+async fn plan_import(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+    config: GuildConfig,
+) -> anyhow::Result<ImportPlan> {
+    if config.version != CONFIG_VERSION {
+        anyhow::bail!("Unsupported config version {}", config.version);
+    }
+    let mut plan = ImportPlan::default();
+    let roles = guild_id.roles(&ctx.http).await?;
+    let channels = guild_id.channels(&ctx.http).await?;
+    let db = handler.db.lock().await;
+    let current = db.get_guild_fields(guild_id)?;
+    plan.version = db.guild_version(guild_id)?;
+
+    let mut fields = Vec::new();
+    for (name, value) in &config.fields {
+        if SECRET_GUILD_FIELDS.contains(&name.as_str()) || ROLE_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        match json_to_value(value) {
+            Some(value) => fields.push((name.clone(), value)),
+            None => plan
+                .skipped
+                .push(format!("invalid value for setting `{name}`")),
+        }
+    }
+    for (name, role_name) in &config.roles {
+        match roles.values().find(|r| &r.name == role_name) {
+            Some(role) => fields.push((name.clone(), Value::Text(role.id.to_string()))),
+            None => plan
+                .skipped
+                .push(format!("no role named `{role_name}` for `{name}`")),
+        }
+    }
+    for (name, new) in fields {
+        let Some(old) = current.get(&name) else {
+            plan.skipped.push(format!("unknown setting `{name}`"));
+            continue;
+        };
+        if *old != new {
+            plan.fields.push(FieldChange {
+                name,
+                old: old.clone(),
+                new,
+            });
+        }
+    }
+
+    if !config.autoreacts.is_empty() {
+        if handler.modules.contains::<ModAutoreacts>() {
+            let existing: Vec<(String, String)> = db
+                .conn
+                .prepare("SELECT trigger, emote FROM autoreact WHERE guild_id = ?1 AND deleted_at IS NULL")?
+                .query([SqlGuildId(guild_id)])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?)))
+                .collect()?;
+            let triggers: HashSet<_> = existing.iter().map(|(t, _)| t.as_str()).collect();
+            for react in config.autoreacts {
+                if existing.contains(&react) {
+                    continue;
+                }
+                if triggers.contains(react.0.as_str()) {
+                    plan.autoreact_conflicts.insert(react.0.clone());
+                }
+                plan.autoreacts.push(react);
+            }
+        } else {
+            plan.skipped
+                .push("autoreacts (module not loaded)".to_string());
+        }
+    }
+
+    if !config.pinboard_channels.is_empty() {
+        if handler.modules.contains::<Pinboard>() {
+            let allowed: HashSet<ChannelId> = db
+                .conn
+                .prepare("SELECT channel_id FROM pinboard_allowed_channels WHERE guild_id = ?1 AND deleted_at IS NULL")?
+                .query([SqlGuildId(guild_id)])?
+                .map(|row| Ok(row.get::<_, SqlChannelId>(0)?.0))
+                .collect()?;
+            for name in config.pinboard_channels {
+                match channels.values().find(|c| c.name == name) {
+                    Some(c) if allowed.contains(&c.id) => {}
+                    Some(c) => plan.pinboard_channels.push(c.id),
+                    None => plan.skipped.push(format!("no channel named `#{name}`")),
+                }
+            }
+        } else {
+            plan.skipped
+                .push("pinboard channels (module not loaded)".to_string());
+        }
+    }
+    Ok(plan)
+}
+
+// Apply an import in a single transaction, leaving existing values alone on conflicts
+// unless `overwrite` is set. Returns the number of changes made.
+fn apply_import(
+    db: &mut Db,
+    guild_id: GuildId,
+    actor: UserId,
+    plan: ImportPlan,
+    overwrite: bool,
+) -> anyhow::Result<usize> {
+    let mut applied = 0;
+    let tx = db.conn.transaction()?;
+    tx.execute(
+        "INSERT OR IGNORE INTO guild (id) VALUES (?1)",
+        [SqlGuildId(guild_id)],
+    )?;
+    // settings changed since the preview was made are not overwritten
+    let mut version = plan.version;
+    for change in plan.fields {
+        if change.conflicts() && !overwrite {
+            continue;
+        }
+        version = update_guild_field(
+            &tx,
+            guild_id,
+            actor,
+            &change.name,
+            change.new,
+            Some(version),
+        )
+        .with_context(|| format!("updating '{}' guild field", change.name))?;
+        applied += 1;
+    }
+    let mut replaced = HashSet::new();
+    for (trigger, emote) in plan.autoreacts {
+        if plan.autoreact_conflicts.contains(&trigger) {
+            if !overwrite {
+                continue;
+            }
+            if replaced.insert(trigger.clone()) {
+                tx.execute(
+                    "DELETE FROM autoreact WHERE guild_id = ?1 AND trigger = ?2",
+                    params![SqlGuildId(guild_id), &trigger],
+                )?;
+            }
+        }
+        tx.execute(
+            "INSERT INTO autoreact (guild_id, trigger, emote) VALUES (?1, ?2, ?3)",
+            params![SqlGuildId(guild_id), trigger, emote],
+        )?;
+        applied += 1;
+    }
+    for channel_id in plan.pinboard_channels {
+        tx.execute(
+            "INSERT INTO pinboard_allowed_channels (guild_id, channel_id) VALUES (?1, ?2)
+             ON CONFLICT DO UPDATE SET deleted_at = NULL",
+            params![SqlGuildId(guild_id), SqlChannelId(channel_id)],
+        )?;
+        applied += 1;
+    }
+    tx.commit()?;
+    Ok(applied)
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "export_config",
+    desc = "Export this server's bot settings as JSON"
+)]
+pub struct ExportConfig {}
+
+#[async_trait]
+impl BotCommand for ExportConfig {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let config = export_config(handler, ctx, guild_id).await?;
+        let json = serde_json::to_string_pretty(&config)?;
+        let msg = CreateInteractionResponseMessage::new()
+            .content("Use `/import_config` with this file in another server")
+            .add_file(CreateAttachment::bytes(json.into_bytes(), "config.json"))
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "import_config",
+    desc = "Import bot settings exported from another server"
+)]
+pub struct ImportConfig {
+    #[cmd(desc = "File produced by /export_config")]
+    file: Attachment,
+}
+
+#[async_trait]
+impl BotCommand for ImportConfig {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let data = download_attachment(&self.file, MAX_IMPORT_SIZE).await?;
+        let config: GuildConfig = serde_json::from_slice(&data).context("Invalid config JSON")?;
+        let plan = plan_import(handler, ctx, guild_id, config).await?;
+        if plan.is_empty() {
+            let mut resp = "Nothing to import, settings already match".to_string();
+            if !plan.skipped.is_empty() {
+                resp.push_str(&format!("\n{}", plan.preview()));
+            }
+            return CommandResponse::private(resp);
+        }
+        let mut buttons = Vec::new();
+        if plan.has_conflicts() {
+            buttons.push(
+                CreateButton::new(format!("{IMPORT_PREFIX}overwrite"))
+                    .label("Apply and overwrite")
+                    .style(ButtonStyle::Danger),
+            );
+            buttons.push(
+                CreateButton::new(format!("{IMPORT_PREFIX}keep"))
+                    .label("Apply, keep existing")
+                    .style(ButtonStyle::Primary),
+            );
+        } else {
+            buttons.push(
+                CreateButton::new(format!("{IMPORT_PREFIX}overwrite"))
+                    .label("Apply")
+                    .style(ButtonStyle::Primary),
+            );
+        }
+        buttons.push(
+            CreateButton::new(format!("{IMPORT_PREFIX}cancel"))
+                .label("Cancel")
+                .style(ButtonStyle::Secondary),
+        );
+        let embed = CreateEmbed::new()
+            .title("Import preview")
+            .description(plan.preview());
+        let now = handler.clock.now().timestamp();
+        handler
+            .module::<ConfigTransfer>()?
+            .store_plan((guild_id, command.user.id), plan, now)
+            .await;
+        let msg = CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(vec![CreateActionRow::Buttons(buttons)])
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+// Import previews waiting for confirmation, with the time they expire at
+type PendingImports = HashMap<(GuildId, UserId), (i64, ImportPlan)>;
+
+pub struct ConfigTransfer {
+    pending: Mutex<PendingImports>,
+}
+
+impl ConfigTransfer {
+    async fn store_plan(&self, key: (GuildId, UserId), plan: ImportPlan, now: i64) {
+        let mut pending = self.pending.lock().await;
+        // drop previews that were never answered
+        pending.retain(|_, (expires, _)| *expires > now);
+        pending.insert(key, (now + PENDING_IMPORT_SECS, plan));
+    }
+
+    async fn take_plan(&self, key: (GuildId, UserId), now: i64) -> Option<ImportPlan> {
+        let (expires, plan) = self.pending.lock().await.remove(&key)?;
+        (expires > now).then_some(plan)
+    }
+
+    fn confirm_import<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(action) = component.data.custom_id.strip_prefix(IMPORT_PREFIX) else {
+                return Ok(false);
+            };
+            let guild_id = component
+                .guild_id
+                .ok_or_else(|| anyhow!("must be run in a guild"))?;
+            let now = handler.clock.now().timestamp();
+            let plan = handler
+                .module::<ConfigTransfer>()?
+                .take_plan((guild_id, component.user.id), now)
+                .await;
+            let content = match (plan, action) {
+                (None, _) => "This import has expired, run `/import_config` again".to_string(),
+                (Some(_), "cancel") => "Import cancelled".to_string(),
+                (Some(plan), action) => {
+                    let has_reacts = !plan.autoreacts.is_empty();
+                    let mut db = handler.db.lock().await;
+                    let applied = apply_import(
+                        &mut db,
+                        guild_id,
+                        component.user.id,
+                        plan,
+                        action == "overwrite",
+                    );
+                    match applied {
+                        Err(e) if e.downcast_ref::<SettingsConflict>().is_some() => {
+                            "Settings were changed since this preview, nothing was imported. \
+                             Run `/import_config` again to see the current values"
+                                .to_string()
+                        }
+                        Err(e) => return Err(e),
+                        Ok(applied) => {
+                            if has_reacts {
+                                handler
+                                    .module::<ModAutoreacts>()?
+                                    .load_reacts(&mut db)
+                                    .await?;
+                            }
+                            format!("Import done, {applied} changes applied")
+                        }
+                    }
+                }
+            };
+            let msg = CreateInteractionResponseMessage::new()
+                .content(content)
+                .embeds(Vec::new())
+                .components(Vec::new());
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(msg))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for ConfigTransfer {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ConfigTransfer {
+            pending: Default::default(),
+        })
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_guild_tables()
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<ExportConfig>();
+        store.register::<ImportConfig>();
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(ConfigTransfer::confirm_import);
+    }
+}
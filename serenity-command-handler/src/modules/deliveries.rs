@@ -0,0 +1,122 @@
+use anyhow::bail;
+use serenity::model::prelude::CommandInteraction;
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::Paginator;
+use crate::db::Db;
+use crate::prelude::*;
+use crate::retry_queue::{Delivery, REQUEUE_VALIDITY_SECS};
+
+const MAX_ERROR_LEN: usize = 100;
+
+fn format_delivery(delivery: &Delivery, now: i64) -> String {
+    let status = if delivery.expired(now) {
+        "expired".to_string()
+    } else {
+        format!("next attempt <t:{}:R>", delivery.next_attempt)
+    };
+    let mut error = delivery
+        .last_error
+        .chars()
+        .take(MAX_ERROR_LEN)
+        .collect::<String>();
+    if error.len() < delivery.last_error.len() {
+        error.push('…');
+    }
+    format!(
+        "`{}` **{}** `{}`: {} attempt(s), {status}\n> {error}",
+        delivery.id, delivery.kind, delivery.payload, delivery.attempts
+    )
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "failed_deliveries",
+    desc = "Show scheduled posts that failed to send and are being retried"
+)]
+pub struct FailedDeliveries {}
+
+#[async_trait]
+impl BotCommand for FailedDeliveries {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let deliveries = handler.db.lock().await.guild_retries(guild_id)?;
+        if deliveries.is_empty() {
+            return CommandResponse::private("No failed deliveries");
+        }
+        let now = handler.clock.now().timestamp();
+        let lines = deliveries.iter().map(|d| format_delivery(d, now));
+        Paginator::from_lines("Failed deliveries", lines)
+            .ephemeral(true)
+            .respond(handler, ctx, command)
+            .await
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "requeue_delivery",
+    desc = "Retry a failed scheduled post right away"
+)]
+pub struct RequeueDelivery {
+    #[cmd(desc = "The delivery's id, from /failed_deliveries", min = 1)]
+    id: i64,
+}
+
+#[async_trait]
+impl BotCommand for RequeueDelivery {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let now = handler.clock.now().timestamp();
+        let requeued = handler
+            .db
+            .lock()
+            .await
+            .requeue_retry(guild_id, self.id, now)?;
+        if !requeued {
+            bail!("No failed delivery with id {}", self.id);
+        }
+        CommandResponse::private(format!(
+            "Delivery {} will be retried within the hour, for up to {} hours",
+            self.id,
+            REQUEUE_VALIDITY_SECS / 3600
+        ))
+    }
+}
+
+pub struct Deliveries;
+
+#[async_trait]
+impl Module for Deliveries {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Deliveries)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_retry_queue()
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<FailedDeliveries>();
+        store.register::<RequeueDelivery>();
+    }
+}
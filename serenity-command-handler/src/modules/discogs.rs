@@ -0,0 +1,319 @@
+use anyhow::{anyhow, bail, Context as _};
+use chrono::Duration;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Deserialize;
+use serenity::async_trait;
+
+use crate::album::{Album, AlbumProvider, PhysicalRelease};
+use crate::modules::AlbumLookup;
+use crate::{HandlerBuilder, Module, ModuleMap};
+
+const API_URL: &str = "https://api.discogs.com";
+// Discogs rejects requests without a user agent
+const USER_AGENT: &str = "serenity-command-handler +https://github.com/etwyniel/discord_framework";
+const TOKEN_VAR: &str = "DISCOGS_TOKEN";
+
+#[derive(Deserialize)]
+struct SearchResults {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    id: u64,
+    // "Artist - Title"
+    title: String,
+    #[serde(default)]
+    year: Option<String>,
+    #[serde(default)]
+    format: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Master {
+    main_release: u64,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    title: String,
+    uri: String,
+    #[serde(default)]
+    artists: Vec<Artist>,
+    #[serde(default)]
+    released: Option<String>,
+    #[serde(default)]
+    year: Option<u32>,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    styles: Vec<String>,
+    #[serde(default)]
+    formats: Vec<Format>,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    country: Option<String>,
+    #[serde(default)]
+    images: Vec<Image>,
+    #[serde(default)]
+    tracklist: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    name: String,
+    #[serde(default)]
+    descriptions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+    #[serde(default)]
+    catno: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Image {
+    #[serde(rename = "type")]
+    kind: String,
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct Track {
+    #[serde(default)]
+    duration: String,
+}
+
+// Discogs appends a number to disambiguate artists with the same name, e.g. "Low (2)"
+fn artist_name(name: &str) -> &str {
+    match name.rsplit_once(" (") {
+        Some((name, n)) if n.trim_end_matches(')').parse::<u32>().is_ok() => name,
+        _ => name,
+    }
+}
+
+// Track durations are formatted as m:ss or h:mm:ss, and are often missing
+fn parse_duration(s: &str) -> Option<Duration> {
+    let mut secs = 0;
+    for part in s.split(':') {
+        secs = secs * 60 + part.trim().parse::<i64>().ok()?;
+    }
+    Some(Duration::seconds(secs))
+}
+
+impl Release {
+    fn into_album(self) -> Album {
+        let artist = self
+            .artists
+            .iter()
+            .map(|a| artist_name(&a.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // the total duration is only known if every track has one
+        let duration = self
+            .tracklist
+            .iter()
+            .map(|t| parse_duration(&t.duration))
+            .sum::<Option<Duration>>()
+            .filter(|d| d.num_seconds() > 0);
+        let format = self
+            .formats
+            .iter()
+            .map(|f| {
+                std::iter::once(f.name.as_str())
+                    .chain(f.descriptions.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect::<Vec<_>>()
+            .join(" + ");
+        let labels = self
+            .labels
+            .into_iter()
+            .map(|l| {
+                let catno = l.catno.filter(|c| !c.is_empty() && c != "none");
+                (l.name, catno)
+            })
+            .collect();
+        let cover = self
+            .images
+            .iter()
+            .find(|i| i.kind == "primary")
+            .or_else(|| self.images.first())
+            .map(|i| i.uri.clone());
+        Album {
+            name: Some(self.title),
+            artist: (!artist.is_empty()).then_some(artist),
+            // styles are more specific than genres
+            genres: self.styles.into_iter().chain(self.genres).collect(),
+            release_date: self
+                .released
+                .filter(|d| !d.is_empty())
+                .or_else(|| self.year.filter(|&y| y > 0).map(|y| y.to_string())),
+            url: Some(self.uri),
+            duration,
+            cover,
+            physical: Some(PhysicalRelease {
+                format: (!format.is_empty()).then_some(format),
+                labels,
+                country: self.country.filter(|c| !c.is_empty()),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Discogs {
+    client: Client,
+    token: String,
+}
+
+impl Discogs {
+    pub fn new(token: String) -> Self {
+        Discogs {
+            client: Client::new(),
+            token,
+        }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let token = std::env::var(TOKEN_VAR).with_context(|| format!("{TOKEN_VAR} is not set"))?;
+        Ok(Discogs::new(token))
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        self.client
+            .get(url)
+            .header("User-Agent", USER_AGENT)
+            .header("Authorization", format!("Discogs token={}", self.token))
+    }
+
+    async fn get_release(&self, id: u64) -> anyhow::Result<Release> {
+        let url = Url::parse(&format!("{API_URL}/releases/{id}"))?;
+        Ok(self
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    // Masters group the different pressings of an album, their main release is used
+    async fn get_master_release(&self, id: u64) -> anyhow::Result<Release> {
+        let url = Url::parse(&format!("{API_URL}/masters/{id}"))?;
+        let master: Master = self
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.get_release(master.main_release).await
+    }
+
+    async fn search(&self, q: &str) -> anyhow::Result<Vec<SearchResult>> {
+        let mut url = Url::parse(&format!("{API_URL}/database/search"))?;
+        url.query_pairs_mut()
+            .append_pair("q", q)
+            .append_pair("type", "release")
+            .append_pair("per_page", "10");
+        let results: SearchResults = self
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(results.results)
+    }
+}
+
+// Parse the kind and ID of a release page URL, e.g.
+// https://www.discogs.com/release/249504-Rick-Astley-Never-Gonna-Give-You-Up
+fn parse_url(url: &str) -> Option<(&str, u64)> {
+    let path = url.split(['?', '#']).next()?;
+    let (host, path) = path.strip_prefix("https://")?.split_once('/')?;
+    if host != "discogs.com" && !host.ends_with(".discogs.com") {
+        return None;
+    }
+    // the path may start with a language, e.g. /fr/release/...
+    let segments = path.split('/').collect::<Vec<_>>();
+    segments.windows(2).find_map(|w| {
+        if w[0] != "release" && w[0] != "master" {
+            return None;
+        }
+        let id = w[1].split('-').next()?.parse().ok()?;
+        Some((w[0], id))
+    })
+}
+
+#[async_trait]
+impl AlbumProvider for Discogs {
+    fn id(&self) -> &'static str {
+        "discogs"
+    }
+
+    fn url_matches(&self, url: &str) -> bool {
+        parse_url(url).is_some()
+    }
+
+    async fn get_from_url(&self, url: &str) -> anyhow::Result<Album> {
+        let release = match parse_url(url) {
+            Some(("master", id)) => self.get_master_release(id).await?,
+            Some((_, id)) => self.get_release(id).await?,
+            None => bail!("Not a discogs release URL"),
+        };
+        Ok(release.into_album())
+    }
+
+    async fn query_album(&self, q: &str) -> anyhow::Result<Album> {
+        let result = self
+            .search(q)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Not found"))?;
+        Ok(self.get_release(result.id).await?.into_album())
+    }
+
+    async fn query_albums(&self, q: &str) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(self
+            .search(q)
+            .await?
+            .into_iter()
+            .map(|r| {
+                let mut name = r.title;
+                let details = r.year.into_iter().chain(r.format.into_iter().take(2));
+                let details = details.collect::<Vec<_>>().join(", ");
+                if !details.is_empty() {
+                    name = format!("{name} ({details})");
+                }
+                (name, format!("https://www.discogs.com/release/{}", r.id))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Module for Discogs {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<AlbumLookup>().await
+    }
+
+    async fn init(m: &ModuleMap) -> anyhow::Result<Self> {
+        let discogs = Discogs::from_env()?;
+        m.module::<AlbumLookup>()?
+            .register_provider(std::sync::Arc::new(discogs.clone()));
+        Ok(discogs)
+    }
+}
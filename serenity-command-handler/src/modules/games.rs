@@ -0,0 +1,348 @@
+use std::fmt::Write;
+
+use anyhow::bail;
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rand::Rng;
+use rusqlite::{params, OptionalExtension};
+use serenity::builder::CreateEmbed;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlUserId};
+use crate::prelude::*;
+
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+const MAX_TERMS: usize = 20;
+const MAX_CONSTANT: i64 = 1_000_000;
+const LEADERBOARD_SIZE: usize = 10;
+
+// A term of a dice expression, e.g. the "3d6" or "-2" in "3d6-2"
+enum Term {
+    Dice { count: u32, sides: u32 },
+    Constant(i64),
+}
+
+struct Roll {
+    negative: bool,
+    term: Term,
+}
+
+fn parse_term(term: &str) -> anyhow::Result<Term> {
+    let Some((count, sides)) = term.split_once(['d', 'D']) else {
+        let n: i64 = term.parse().unwrap_or(i64::MAX);
+        if n > MAX_CONSTANT {
+            bail!("`{term}` is too large");
+        }
+        return Ok(Term::Constant(n));
+    };
+    let count: u32 = match count {
+        "" => 1,
+        n => n.parse().unwrap_or(u32::MAX),
+    };
+    if sides.is_empty() {
+        bail!("Missing number of sides in `{term}`");
+    }
+    let sides: u32 = sides.parse().unwrap_or(u32::MAX);
+    if count == 0 || count > MAX_DICE {
+        bail!("Can only roll between 1 and {MAX_DICE} dice at once");
+    }
+    if !(2..=MAX_SIDES).contains(&sides) {
+        bail!("Dice must have between 2 and {MAX_SIDES} sides");
+    }
+    Ok(Term::Dice { count, sides })
+}
+
+// Parse dice expressions such as "d20", "3d6+2" or "2d8 - 1d4 + 3".
+// Errors point to the position of the offending character.
+fn parse_expression(expr: &str) -> anyhow::Result<Vec<Roll>> {
+    let mut rolls = Vec::new();
+    let mut negative = false;
+    let mut current = String::new();
+    for (pos, c) in expr.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+        match c {
+            '0'..='9' => current.push(c),
+            'd' | 'D' if !current.contains(['d', 'D']) => current.push(c),
+            // a leading sign applies to the first term
+            '+' | '-' if current.is_empty() && rolls.is_empty() && !negative => {
+                negative = c == '-';
+            }
+            '+' | '-' if current.is_empty() => {
+                bail!("Expected a number or dice before position {}", pos + 1)
+            }
+            '+' | '-' => {
+                rolls.push(Roll {
+                    negative,
+                    term: parse_term(&current)?,
+                });
+                current.clear();
+                negative = c == '-';
+            }
+            _ => bail!("Unexpected `{c}` at position {}", pos + 1),
+        }
+    }
+    if current.is_empty() {
+        bail!("Expected a number or dice at the end of `{expr}`");
+    }
+    rolls.push(Roll {
+        negative,
+        term: parse_term(&current)?,
+    });
+    if rolls.len() > MAX_TERMS {
+        bail!("Expressions are limited to {MAX_TERMS} terms");
+    }
+    Ok(rolls)
+}
+
+// Roll every term, returns the total along with a breakdown of the individual dice
+fn evaluate(rolls: &[Roll]) -> (i64, String) {
+    let mut rng = rand::thread_rng();
+    let mut total = 0;
+    let mut breakdown = String::new();
+    for (i, roll) in rolls.iter().enumerate() {
+        let sign = if roll.negative { -1 } else { 1 };
+        if i > 0 || roll.negative {
+            breakdown.push_str(if roll.negative { " - " } else { " + " });
+        }
+        match roll.term {
+            Term::Dice { count, sides } => {
+                let results = (0..count)
+                    .map(|_| rng.gen_range(1..=sides as i64))
+                    .collect_vec();
+                total += sign * results.iter().sum::<i64>();
+                _ = write!(&mut breakdown, "[{}]", results.iter().join(", "));
+            }
+            Term::Constant(n) => {
+                total += sign * n;
+                _ = write!(&mut breakdown, "{n}");
+            }
+        }
+    }
+    (total, breakdown)
+}
+
+fn history_enabled(db: &Db, channel_id: ChannelId) -> anyhow::Result<bool> {
+    Ok(db
+        .conn
+        .query_row(
+            "SELECT 1 FROM roll_history_channels WHERE channel_id = ?1",
+            [SqlChannelId(channel_id)],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+fn record_roll(
+    db: &Db,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    expression: &str,
+    total: i64,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT INTO roll_history (guild_id, channel_id, user_id, ts, expression, total)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            SqlGuildId(guild_id),
+            SqlChannelId(channel_id),
+            SqlUserId(user_id),
+            chrono::Utc::now().timestamp(),
+            expression,
+            total
+        ],
+    )?;
+    Ok(())
+}
+
+#[derive(Command)]
+#[cmd(name = "roll", desc = "Roll some dice")]
+pub struct RollDice {
+    #[cmd(desc = "Dice to roll, e.g. d20 or 3d6+2")]
+    dice: String,
+}
+
+#[async_trait]
+impl BotCommand for RollDice {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let rolls = parse_expression(&self.dice)?;
+        let (total, breakdown) = evaluate(&rolls);
+        let expression = self.dice.split_whitespace().join("");
+        if let Some(guild_id) = command.guild_id {
+            let db = handler.db.lock().await;
+            if history_enabled(&db, command.channel_id)? {
+                record_roll(
+                    &db,
+                    guild_id,
+                    command.channel_id,
+                    command.user.id,
+                    &expression,
+                    total,
+                )?;
+            }
+        }
+        CommandResponse::public(format!(
+            "<@{}> rolled `{expression}`: {breakdown} = **{total}**",
+            command.user.id
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "coinflip", desc = "Flip a coin")]
+pub struct Coinflip {}
+
+#[async_trait]
+impl BotCommand for Coinflip {
+    type Data = Handler;
+    async fn run(
+        self,
+        _handler: &Handler,
+        _ctx: &Context,
+        _command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let side = if rand::random() { "Heads" } else { "Tails" };
+        CommandResponse::public(format!("🪙 **{side}**"))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "roll_history",
+    desc = "Set whether to keep track of dice rolls in this channel"
+)]
+pub struct SetRollHistory {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetRollHistory {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_CHANNELS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let channel_id = command.channel_id;
+        let db = handler.db.lock().await;
+        let resp = if self.enabled {
+            db.conn.execute(
+                "INSERT OR IGNORE INTO roll_history_channels (guild_id, channel_id)
+                 VALUES (?1, ?2)",
+                params![SqlGuildId(guild_id), SqlChannelId(channel_id)],
+            )?;
+            "Dice rolls in this channel will now be recorded"
+        } else {
+            db.conn.execute(
+                "DELETE FROM roll_history_channels WHERE channel_id = ?1",
+                [SqlChannelId(channel_id)],
+            )?;
+            "Dice rolls in this channel will no longer be recorded"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "roll_leaderboard",
+    desc = "Show who rolls the most (and the best) in this channel"
+)]
+pub struct RollLeaderboard {}
+
+#[async_trait]
+impl BotCommand for RollLeaderboard {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let db = handler.db.lock().await;
+        if !history_enabled(&db, command.channel_id)? {
+            bail!("Dice rolls are not recorded in this channel, see `/roll_history`");
+        }
+        let stats: Vec<(SqlUserId, u64, f64, i64)> = db
+            .conn
+            .prepare(
+                "SELECT user_id, COUNT(*), AVG(total), MAX(total) FROM roll_history
+                 WHERE channel_id = ?1
+                 GROUP BY user_id ORDER BY COUNT(*) DESC LIMIT ?2",
+            )?
+            .query(params![SqlChannelId(command.channel_id), LEADERBOARD_SIZE])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .collect()?;
+        if stats.is_empty() {
+            bail!("Nobody has rolled any dice here yet");
+        }
+        let lines = stats
+            .into_iter()
+            .enumerate()
+            .map(|(i, (SqlUserId(user_id), count, avg, best))| {
+                format!(
+                    "{}. <@{user_id}>: {count} rolls, average {avg:.1}, best {best}",
+                    i + 1
+                )
+            })
+            .join("\n");
+        CommandResponse::public(
+            CreateEmbed::new()
+                .title("Roll leaderboard")
+                .description(lines),
+        )
+    }
+}
+
+pub struct Games;
+
+#[async_trait]
+impl Module for Games {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Games)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS roll_history_channels (
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL UNIQUE
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS roll_history (
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                expression STRING NOT NULL,
+                total INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<RollDice>();
+        store.register::<Coinflip>();
+        store.register::<SetRollHistory>();
+        store.register::<RollLeaderboard>();
+    }
+}
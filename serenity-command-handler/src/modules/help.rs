@@ -0,0 +1,298 @@
+// /help, generated from the commands in the CommandStore.
+// Lists the slash commands that can be used in the channel with their options and required
+// permissions, optionally only the ones of a module, a few commands per page.
+// Commands of other guilds, commands restricted to other channels with /command_channels and
+// commands of degraded modules are left out or marked.
+use std::collections::HashSet;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use serenity::builder::{
+    CreateActionRow, CreateAutocompleteResponse, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::json::{to_value, Value};
+use serenity::model::application::{ButtonStyle, CommandType, ComponentInteraction};
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::get_str_opt_ac;
+use crate::prelude::*;
+
+const HELP_COMMAND: &str = "help";
+const PAGE_PREFIX: &str = "help_page:";
+const PAGE_SIZE: usize = 8;
+
+struct HelpEntry {
+    name: &'static str,
+    description: String,
+    // name, description and whether the option is required
+    options: Vec<(String, String, bool)>,
+    permissions: Vec<&'static str>,
+    unavailable: bool,
+}
+
+impl HelpEntry {
+    fn format(&self) -> String {
+        let mut out = format!("**/{}** {}", self.name, self.description);
+        if !self.permissions.is_empty() {
+            out.push_str(&format!("\n*Requires {}*", self.permissions.join(", ")));
+        }
+        if self.unavailable {
+            out.push_str("\n*Temporarily unavailable*");
+        }
+        for (name, desc, required) in &self.options {
+            let required = if *required { " *(required)*" } else { "" };
+            out.push_str(&format!("\n- `{name}` {desc}{required}"));
+        }
+        out
+    }
+}
+
+// Description and options of a command, from the payload it is registered with
+fn describe(payload: &Value) -> (String, Vec<(String, String, bool)>) {
+    let str_field = |v: &Value, field: &str| {
+        v.get(field)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let options = payload
+        .get("options")
+        .and_then(Value::as_array)
+        .map(|options| {
+            options
+                .iter()
+                .map(|opt| {
+                    let required = opt.get("required").and_then(Value::as_bool);
+                    (
+                        str_field(opt, "name"),
+                        str_field(opt, "description"),
+                        required.unwrap_or(false),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    (str_field(payload, "description"), options)
+}
+
+// Commands restricted out of the channel, the module being optional
+#[cfg_attr(not(feature = "command_channels"), allow(unused_variables))]
+async fn restricted_commands(
+    handler: &Handler,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+) -> anyhow::Result<HashSet<String>> {
+    #[cfg(feature = "command_channels")]
+    if let Some(guild_id) = guild_id {
+        if handler.modules.contains::<super::CommandChannels>() {
+            let db = handler.db.lock().await;
+            return super::command_channels::denied_commands(&db, guild_id, channel_id);
+        }
+    }
+    Ok(HashSet::new())
+}
+
+async fn help_entries(
+    handler: &Handler,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    module: Option<&str>,
+) -> anyhow::Result<Vec<HelpEntry>> {
+    let restricted = restricted_commands(handler, guild_id, channel_id).await?;
+    let commands = handler.commands.read().await;
+    let entries = commands
+        .0
+        .iter()
+        .filter(|((name, kind), runner)| {
+            *kind == CommandType::ChatInput
+                && runner.guild().is_none_or(|g| Some(g) == guild_id)
+                && !(guild_id.is_none() && runner.guild_only())
+                && !restricted.contains(*name)
+        })
+        .filter(|((name, _), _)| {
+            module.is_none_or(|module| {
+                handler
+                    .health
+                    .command_module(name)
+                    .is_some_and(|m| m.eq_ignore_ascii_case(module))
+            })
+        })
+        .sorted_by_key(|((name, _), _)| *name)
+        .map(|((name, _), runner)| {
+            let payload = to_value(runner.register()).unwrap_or_default();
+            let (description, options) = describe(&payload);
+            HelpEntry {
+                name,
+                description,
+                options,
+                permissions: runner.permissions().get_permission_names(),
+                unavailable: handler.health.unavailable_notice(name).is_some(),
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+async fn help_page(
+    handler: &Handler,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    module: Option<&str>,
+    page: usize,
+) -> anyhow::Result<(CreateEmbed, Vec<CreateActionRow>)> {
+    let entries = help_entries(handler, guild_id, channel_id, module).await?;
+    let pages = entries.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let description = entries
+        .iter()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(HelpEntry::format)
+        .join("\n\n");
+    let title = match module {
+        Some(module) => format!("Commands of {module}"),
+        None => "Available commands".to_string(),
+    };
+    let embed = CreateEmbed::new()
+        .title(title)
+        .description(if description.is_empty() {
+            "No commands available here".to_string()
+        } else {
+            description
+        })
+        .footer(CreateEmbedFooter::new(format!("Page {}/{pages}", page + 1)));
+    if pages == 1 {
+        return Ok((embed, vec![]));
+    }
+    let module = module.unwrap_or_default();
+    let nav_button = |label: &str, target: usize, disabled: bool| {
+        CreateButton::new(format!("{PAGE_PREFIX}{target}:{module}"))
+            .label(label)
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled)
+    };
+    let components = vec![CreateActionRow::Buttons(vec![
+        nav_button("Previous", page.saturating_sub(1), page == 0),
+        nav_button("Next", page + 1, page + 1 >= pages),
+    ])];
+    Ok((embed, components))
+}
+
+#[derive(Command)]
+#[cmd(name = "help", desc = "List the commands available in this channel")]
+pub struct ShowHelp {
+    #[cmd(desc = "Only list the commands of this module", autocomplete)]
+    module: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for ShowHelp {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let (embed, components) = help_page(
+            handler,
+            command.guild_id,
+            command.channel_id,
+            self.module.as_deref(),
+            0,
+        )
+        .await?;
+        let msg = CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(components)
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+fn handle_page<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    component: &'a ComponentInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        let Some(target) = component.data.custom_id.strip_prefix(PAGE_PREFIX) else {
+            return Ok(false);
+        };
+        let (page, module) = target.split_once(':').unwrap_or((target, ""));
+        let module = Some(module).filter(|m| !m.is_empty());
+        let (embed, components) = help_page(
+            handler,
+            component.guild_id,
+            component.channel_id,
+            module,
+            page.parse()?,
+        )
+        .await?;
+        let msg = CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(components);
+        component
+            .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(msg))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+fn complete_module<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    key: CommandKey<'a>,
+    ac: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if key != (HELP_COMMAND, CommandType::ChatInput) {
+            return Ok(false);
+        }
+        let current = get_str_opt_ac(&ac.data.options, "module")
+            .unwrap_or("")
+            .to_lowercase();
+        let resp = handler
+            .health
+            .modules()
+            .filter(|name| name.to_lowercase().contains(&current))
+            .sorted()
+            .dedup()
+            .take(25)
+            .fold(CreateAutocompleteResponse::new(), |resp, name| {
+                resp.add_string_choice(name, name)
+            });
+        ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+pub struct Help;
+
+#[async_trait]
+impl Module for Help {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Help)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<ShowHelp>();
+        completions.push(complete_module);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(handle_page);
+    }
+}
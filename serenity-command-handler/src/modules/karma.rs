@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::async_trait;
+use serenity::builder::CreateEmbed;
+use serenity::model::prelude::{CommandInteraction, GuildId, Reaction, ReactionType, UserId};
+use serenity::model::Permissions;
+use serenity::prelude::{Context, Mutex, RwLock};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlGuildId, SqlMessageId, SqlUserId};
+use crate::gateway::{GatewayHandlers, ReactionAdd, ReactionRemove};
+use crate::prelude::*;
+
+const DEFAULT_UP: &str = "⬆️";
+const DEFAULT_DOWN: &str = "⬇️";
+const LEADERBOARD_SIZE: usize = 10;
+
+// Each member can vote at most VOTE_LIMIT times per VOTE_WINDOW
+const VOTE_LIMIT: usize = 10;
+const VOTE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct KarmaConfig {
+    pub up: String,
+    pub down: String,
+    // votes older than this many days no longer count
+    pub decay_days: Option<u32>,
+}
+
+impl Default for KarmaConfig {
+    fn default() -> Self {
+        KarmaConfig {
+            up: DEFAULT_UP.to_string(),
+            down: DEFAULT_DOWN.to_string(),
+            decay_days: None,
+        }
+    }
+}
+
+impl KarmaConfig {
+    fn vote_value(&self, emoji: &ReactionType) -> Option<i64> {
+        match emoji.to_string() {
+            e if e == self.up => Some(1),
+            e if e == self.down => Some(-1),
+            _ => None,
+        }
+    }
+
+    fn since(&self) -> i64 {
+        self.decay_days
+            .map(|days| chrono::Utc::now().timestamp() - days as i64 * 24 * 3600)
+            .unwrap_or(0)
+    }
+}
+
+fn normalize_emote(s: &str) -> anyhow::Result<String> {
+    Ok(ReactionType::from_str(s.trim())?.to_string())
+}
+
+#[derive(Default)]
+pub struct Karma {
+    // only guilds that enabled karma are present
+    config: RwLock<HashMap<GuildId, KarmaConfig>>,
+    recent_votes: Mutex<HashMap<UserId, Vec<Instant>>>,
+}
+
+impl Karma {
+    pub async fn load_config(&self, db: &mut Db) -> anyhow::Result<()> {
+        let config = db
+            .conn
+            .prepare("SELECT guild_id, up_emote, down_emote, decay_days FROM karma_config")?
+            .query([])?
+            .map(|row| {
+                Ok((
+                    row.get::<_, SqlGuildId>(0)?.0,
+                    KarmaConfig {
+                        up: row.get(1)?,
+                        down: row.get(2)?,
+                        decay_days: row.get(3)?,
+                    },
+                ))
+            })
+            .collect()?;
+        *self.config.write().await = config;
+        Ok(())
+    }
+
+    async fn guild_config(&self, guild_id: GuildId) -> Option<KarmaConfig> {
+        self.config.read().await.get(&guild_id).cloned()
+    }
+
+    async fn rate_limited(&self, user_id: UserId) -> bool {
+        let mut recent = self.recent_votes.lock().await;
+        let votes = recent.entry(user_id).or_default();
+        votes.retain(|t| t.elapsed() < VOTE_WINDOW);
+        if votes.len() >= VOTE_LIMIT {
+            return true;
+        }
+        votes.push(Instant::now());
+        false
+    }
+
+    // Returns the sum of votes and the number of up and down votes
+    fn user_karma(
+        db: &Db,
+        guild_id: GuildId,
+        user_id: UserId,
+        since: i64,
+    ) -> anyhow::Result<(i64, u64, u64)> {
+        Ok(db.conn.query_row(
+            "SELECT COALESCE(SUM(value), 0),
+                    COALESCE(SUM(value > 0), 0),
+                    COALESCE(SUM(value < 0), 0)
+             FROM karma_vote WHERE guild_id = ?1 AND target_id = ?2 AND ts >= ?3",
+            params![SqlGuildId(guild_id), SqlUserId(user_id), since],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?)
+    }
+}
+
+// callback for adding a react
+pub async fn handle_reaction_add(
+    handler: &Handler,
+    ctx: &Context,
+    react: &Reaction,
+) -> anyhow::Result<()> {
+    let (Some(guild_id), Some(voter_id)) = (react.guild_id, react.user_id) else {
+        return Ok(());
+    };
+    let karma = handler.module::<Karma>()?;
+    let Some(config) = karma.guild_config(guild_id).await else {
+        return Ok(());
+    };
+    let Some(value) = config.vote_value(&react.emoji) else {
+        return Ok(());
+    };
+    let message = react.message(ctx).await?;
+    let target_id = message.author.id;
+    // no voting for yourself or for bots, checked first so that these don't count towards
+    // the rate limit
+    if target_id == voter_id || message.author.bot {
+        return Ok(());
+    }
+    if karma.rate_limited(voter_id).await {
+        return Ok(());
+    }
+    handler.db.lock().await.conn.execute(
+        "INSERT OR IGNORE INTO karma_vote
+            (guild_id, message_id, voter_id, target_id, value, ts)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            SqlGuildId(guild_id),
+            SqlMessageId(message.id),
+            SqlUserId(voter_id),
+            SqlUserId(target_id),
+            value,
+            chrono::Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+// callback for react removal
+pub async fn handle_reaction_remove(
+    handler: &Handler,
+    _ctx: &Context,
+    react: &Reaction,
+) -> anyhow::Result<()> {
+    let (Some(guild_id), Some(voter_id)) = (react.guild_id, react.user_id) else {
+        return Ok(());
+    };
+    let karma = handler.module::<Karma>()?;
+    let Some(value) = karma
+        .guild_config(guild_id)
+        .await
+        .and_then(|config| config.vote_value(&react.emoji))
+    else {
+        return Ok(());
+    };
+    handler.db.lock().await.conn.execute(
+        "DELETE FROM karma_vote WHERE message_id = ?1 AND voter_id = ?2 AND value = ?3",
+        params![SqlMessageId(react.message_id), SqlUserId(voter_id), value],
+    )?;
+    Ok(())
+}
+
+fn on_reaction_add<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a ReactionAdd,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    handle_reaction_add(handler, ctx, &event.0).boxed()
+}
+
+fn on_reaction_remove<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a ReactionRemove,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    handle_reaction_remove(handler, ctx, &event.0).boxed()
+}
+
+#[derive(Command)]
+#[cmd(name = "karma", desc = "Show a member's karma")]
+pub struct GetKarma {
+    #[cmd(desc = "Member to show the karma of (defaults to you)")]
+    user: Option<UserId>,
+}
+
+#[async_trait]
+impl BotCommand for GetKarma {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let user_id = self.user.unwrap_or(command.user.id);
+        let config = handler
+            .module::<Karma>()?
+            .guild_config(guild_id)
+            .await
+            .ok_or_else(|| anyhow!("Karma is not enabled in this server"))?;
+        let (total, up, down) =
+            Karma::user_karma(&*handler.db.lock().await, guild_id, user_id, config.since())?;
+        CommandResponse::public(format!(
+            "<@{user_id}> has **{total}** karma ({up} {}, {down} {})",
+            config.up, config.down
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "karma_leaderboard",
+    desc = "Show the members with the most karma"
+)]
+pub struct KarmaLeaderboard {}
+
+#[async_trait]
+impl BotCommand for KarmaLeaderboard {
+    type Data = Handler;
+    const SCHEDULABLE: bool = true;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let config = handler
+            .module::<Karma>()?
+            .guild_config(guild_id)
+            .await
+            .ok_or_else(|| anyhow!("Karma is not enabled in this server"))?;
+        let top: Vec<(SqlUserId, i64)> = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .prepare(
+                "SELECT target_id, SUM(value) AS total FROM karma_vote
+                 WHERE guild_id = ?1 AND ts >= ?2
+                 GROUP BY target_id ORDER BY total DESC LIMIT ?3",
+            )?
+            .query(params![
+                SqlGuildId(guild_id),
+                config.since(),
+                LEADERBOARD_SIZE
+            ])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        if top.is_empty() {
+            bail!("Nobody has any karma yet");
+        }
+        let lines = top
+            .into_iter()
+            .enumerate()
+            .map(|(i, (SqlUserId(user_id), total))| format!("{}. <@{user_id}>: {total}", i + 1))
+            .join("\n");
+        CommandResponse::public(
+            CreateEmbed::new()
+                .title("Karma leaderboard")
+                .description(lines),
+        )
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "karma_config",
+    desc = "Enable karma and set the emotes used to vote"
+)]
+pub struct SetKarmaConfig {
+    #[cmd(desc = "Emote to upvote with (default ⬆️)")]
+    up: Option<String>,
+    #[cmd(desc = "Emote to downvote with (default ⬇️)")]
+    down: Option<String>,
+    #[cmd(desc = "Only count votes from the last N days (0 to count all votes)")]
+    decay_days: Option<i64>,
+    #[cmd(desc = "Set to false to disable karma")]
+    enabled: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for SetKarmaConfig {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let karma = handler.module::<Karma>()?;
+        let mut db = handler.db.lock().await;
+        if self.enabled == Some(false) {
+            db.conn.execute(
+                "DELETE FROM karma_config WHERE guild_id = ?1",
+                [SqlGuildId(guild_id)],
+            )?;
+            karma.load_config(&mut db).await?;
+            return CommandResponse::private("Karma disabled");
+        }
+        let mut config = karma.guild_config(guild_id).await.unwrap_or_default();
+        if let Some(up) = &self.up {
+            config.up = normalize_emote(up)?;
+        }
+        if let Some(down) = &self.down {
+            config.down = normalize_emote(down)?;
+        }
+        if config.up == config.down {
+            bail!("The upvote and downvote emotes must be different");
+        }
+        if let Some(days) = self.decay_days {
+            config.decay_days = match days {
+                0 => None,
+                1..=3650 => Some(days as u32),
+                _ => bail!("Invalid number of days"),
+            };
+        }
+        db.conn.execute(
+            "INSERT INTO karma_config (guild_id, up_emote, down_emote, decay_days)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(guild_id) DO UPDATE SET
+                up_emote = ?2, down_emote = ?3, decay_days = ?4",
+            params![
+                SqlGuildId(guild_id),
+                &config.up,
+                &config.down,
+                config.decay_days
+            ],
+        )?;
+        karma.load_config(&mut db).await?;
+        let decay = config
+            .decay_days
+            .map(|days| format!(", counting votes from the last {days} days"))
+            .unwrap_or_default();
+        CommandResponse::private(format!(
+            "Karma enabled: {} to upvote, {} to downvote{decay}",
+            config.up, config.down
+        ))
+    }
+}
+
+#[async_trait]
+impl Module for Karma {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Karma::default())
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS karma_config (
+                guild_id INTEGER PRIMARY KEY,
+                up_emote STRING NOT NULL,
+                down_emote STRING NOT NULL,
+                decay_days INTEGER
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS karma_vote (
+                guild_id INTEGER NOT NULL,
+                message_id INTEGER NOT NULL,
+                voter_id INTEGER NOT NULL,
+                target_id INTEGER NOT NULL,
+                value INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                UNIQUE(message_id, voter_id, value)
+            )",
+            [],
+        )?;
+        self.load_config(db).await
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<GetKarma>();
+        store.register::<KarmaLeaderboard>();
+        store.register::<SetKarmaConfig>();
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(on_reaction_add);
+        handlers.add(on_reaction_remove);
+    }
+}
@@ -5,21 +5,23 @@ use futures::future::BoxFuture;
 use futures::{Future, FutureExt, Stream, StreamExt, TryStreamExt};
 use image::imageops::FilterType;
 use image::io::Reader;
-use image::{DynamicImage, GenericImage, ImageOutputFormat, RgbaImage};
+use image::{DynamicImage, GenericImage, ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
 use itertools::Itertools;
 use regex::Regex;
-use reqwest::{Client, Method, StatusCode, Url};
+use reqwest::{Method, StatusCode, Url};
 use rspotify::ClientError;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
+use rusttype::{Font, Scale};
 use serde::Deserialize;
 use serenity::async_trait;
 use serenity::builder::{
-    CreateAttachment, CreateAutocompleteResponse, CreateEmbed, CreateInteractionResponse,
-    CreateInteractionResponseFollowup, EditInteractionResponse,
+    CreateAttachment, CreateAutocompleteResponse, CreateEmbed, CreateEmbedAuthor,
+    CreateInteractionResponse, CreateInteractionResponseFollowup, EditInteractionResponse,
 };
 use serenity::json::JsonMap;
-use serenity::model::prelude::CommandInteraction;
 use serenity::model::prelude::CommandType;
+use serenity::model::prelude::{CommandInteraction, GuildId, UserId};
 use serenity::prelude::{Context, Mutex};
 use serenity_command::{BotCommand, CommandKey, CommandResponse};
 
@@ -30,24 +32,36 @@ use std::fmt::Write;
 use std::io::Cursor;
 use std::iter::IntoIterator;
 use std::ops::RangeInclusive;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 use crate::command_context::{get_focused_option, get_str_opt_ac};
-use crate::db::Db;
+use crate::date_format::{Timestamp, TimestampStyle};
+use crate::db::{Db, Migration, SqlGuildId, SqlUserId};
+use crate::http_util::RateLimitedClient;
+use crate::metrics::metrics;
+use crate::modules::server_chart::{self, ChartCache};
+use crate::modules::taste;
 use crate::modules::Spotify;
+use crate::normalize::{album_key, artist_key};
 use crate::prelude::*;
-use serenity_command_derive::Command;
+use serenity_command_derive::{Command, CommandChoice};
 
 const API_ENDPOINT: &str = "http://ws.audioscrobbler.com/2.0/";
 
 const CHART_SQUARE_SIZE: u32 = 300;
+// font of the labels on charts, see assets/DejaVuSans-LICENSE.txt
+const LABEL_FONT_DATA: &[u8] = include_bytes!("../../assets/DejaVuSans-Bold.ttf");
+const LABEL_SCALE: f32 = 20.0;
+const LABEL_LINE_HEIGHT: u32 = 24;
+const LABEL_PADDING: u32 = 8;
 
 const TTL_DAYS: i64 = 30;
 
 pub struct Lastfm {
-    client: Client,
+    client: RateLimitedClient,
     api_key: String,
+    pub(crate) server_charts: ChartCache,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -207,6 +221,25 @@ pub struct TopTracksResponse {
     pub toptracks: TopTracks,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopArtist {
+    pub name: String,
+    pub url: String,
+    pub playcount: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopArtists {
+    pub artist: Vec<TopArtist>,
+    #[serde(rename = "@attr")]
+    pub attr: TopAlbumsAttr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopArtistsResp {
+    pub topartists: TopArtists,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AlbumShort {
     pub artist: String,
@@ -236,15 +269,277 @@ pub struct MbReleaseInfo {
     pub date: String,
 }
 
+// Last.fm account linked with /lastfm_link
+pub fn linked_username(db: &Db, user_id: UserId) -> anyhow::Result<Option<String>> {
+    let username = db
+        .conn
+        .query_row(
+            "SELECT username FROM lastfm_users WHERE user_id = ?1",
+            [SqlUserId(user_id)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(username)
+}
+
+// Suggest a linked account in a guild's autocompletes and charts
+fn add_user_guild(db: &Db, guild_id: GuildId, user_id: UserId) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT OR IGNORE INTO lastfm_user_guild (guild_id, user_id) VALUES (?1, ?2)",
+        params![SqlGuildId(guild_id), SqlUserId(user_id)],
+    )?;
+    Ok(())
+}
+
+// The given username, or the caller's linked account.
+// Callers with a linked account are added to the guild the command is run in, so that
+// accounts linked in another guild show up there too.
+async fn username_or_linked(
+    handler: &Handler,
+    username: Option<String>,
+    opts: &CommandInteraction,
+) -> anyhow::Result<String> {
+    let (user_id, guild_id) = (opts.user.id, opts.guild_id);
+    let linked = handler
+        .db_call(move |db| {
+            let linked = linked_username(db, user_id)?;
+            if let (Some(_), Some(guild_id)) = (&linked, guild_id) {
+                add_user_guild(db, guild_id, user_id)?;
+            }
+            Ok(linked)
+        })
+        .await?;
+    username
+        .or(linked)
+        .context("Give a username, or link your account with /lastfm_link")
+}
+
 #[derive(Command, Debug)]
-#[cmd(name = "aoty", desc = "Get your albums of the year")]
-pub struct GetAotys {
+#[cmd(name = "lastfm_link", desc = "Link your Last.fm account")]
+pub struct LinkLastfm {
     #[cmd(desc = "Last.fm username")]
     pub username: String,
+}
+
+#[async_trait]
+impl BotCommand for LinkLastfm {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let lastfm = handler.module::<Lastfm>()?;
+        let user: anyhow::Result<JsonMap> = lastfm
+            .query("user.getInfo", [("user", self.username.as_str())])
+            .await;
+        if let Err(e) = user {
+            tracing::debug!("Last.fm user lookup failed: {e:?}");
+            bail!("Last.fm user {} not found", self.username);
+        }
+        let (user_id, guild_id, username) = (opts.user.id, opts.guild_id, self.username.clone());
+        handler
+            .db_call(move |db| {
+                db.conn.execute(
+                    "INSERT OR REPLACE INTO lastfm_users (user_id, username, ts) VALUES (?1, ?2, ?3)",
+                    params![SqlUserId(user_id), username, Utc::now().timestamp()],
+                )?;
+                if let Some(guild_id) = guild_id {
+                    add_user_guild(db, guild_id, user_id)?;
+                }
+                Ok(())
+            })
+            .await?;
+        CommandResponse::private(format!("Linked your Last.fm account {}", self.username))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lastfm_unlink", desc = "Unlink your Last.fm account")]
+pub struct UnlinkLastfm;
+
+#[async_trait]
+impl BotCommand for UnlinkLastfm {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user = SqlUserId(opts.user.id);
+        let removed = handler
+            .db_call(move |db| {
+                let removed = db
+                    .conn
+                    .execute("DELETE FROM lastfm_users WHERE user_id = ?1", [&user])?;
+                db.conn
+                    .execute("DELETE FROM lastfm_user_guild WHERE user_id = ?1", [&user])?;
+                Ok(removed)
+            })
+            .await?;
+        if removed == 0 {
+            bail!("No Last.fm account linked");
+        }
+        CommandResponse::private("Unlinked your Last.fm account")
+    }
+}
+
+fn linked_usernames(db: &Db, guild_id: GuildId, query: &str) -> anyhow::Result<Vec<String>> {
+    let usernames = db
+        .conn
+        .prepare(
+            "SELECT u.username FROM lastfm_users u
+             JOIN lastfm_user_guild g ON g.user_id = u.user_id
+             WHERE g.guild_id = ?1 AND u.username LIKE '%' || ?2 || '%'
+             ORDER BY u.username LIMIT 25",
+        )?
+        .query(params![SqlGuildId(guild_id), query])?
+        .map(|row| row.get(0))
+        .collect()?;
+    Ok(usernames)
+}
+
+// Suggest the accounts linked in the guild
+fn complete_username<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    key: CommandKey<'a>,
+    ac: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if !matches!(key, ("aoty" | "soty" | "chart", CommandType::ChatInput)) {
+            return Ok(false);
+        }
+        let options = &ac.data.options;
+        if get_focused_option(options) != Some("username") {
+            return Ok(false);
+        }
+        let Some(guild_id) = ac.guild_id else {
+            return Ok(false);
+        };
+        let query = get_str_opt_ac(options, "username")
+            .unwrap_or_default()
+            .to_string();
+        let usernames = handler
+            .db_read(move |db| linked_usernames(db, guild_id, &query))
+            .await?;
+        let complete = usernames
+            .iter()
+            .fold(CreateAutocompleteResponse::new(), |complete, name| {
+                complete.add_string_choice(name, name)
+            });
+        ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(complete))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "np", desc = "Show what you or another member are listening to")]
+pub struct NowPlaying {
+    #[cmd(desc = "Member with a linked Last.fm account (defaults to you)")]
+    pub user: Option<UserId>,
+}
+
+// Link to the album on the best album provider, e.g. Spotify
+#[cfg_attr(not(feature = "album_lookup"), allow(unused_variables))]
+async fn album_link(handler: &Handler, artist: &str, album: &str) -> Option<String> {
+    #[cfg(feature = "album_lookup")]
+    if let Some(lookup) = handler.try_module::<crate::modules::AlbumLookup>() {
+        let query = format!("{artist} - {album}");
+        match lookup.lookup_album(&query, None).await {
+            Ok(info) => return info.and_then(|info| info.url),
+            Err(e) => tracing::debug!("Could not look up {query}: {e:?}"),
+        }
+    }
+    None
+}
+
+#[async_trait]
+impl BotCommand for NowPlaying {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = self.user.unwrap_or(opts.user.id);
+        let Some(username) = handler
+            .db_read(move |db| linked_username(db, user_id))
+            .await?
+        else {
+            bail!("<@{user_id}> has not linked a Last.fm account, see /lastfm_link");
+        };
+        let lastfm = handler.module::<Lastfm>()?;
+        let recent = lastfm
+            .get_recent_tracks(&username, None, None, Some(1), None)
+            .await?;
+        let Some(track) = recent.track.into_iter().next() else {
+            bail!("{username} has not scrobbled anything yet");
+        };
+        let playing = track
+            .attr
+            .as_ref()
+            .is_some_and(|attr| attr.nowplaying == "true");
+        let (artist, album) = (&track.artist.text, &track.album.text);
+        let mut description = format!("by **{artist}**");
+        if !album.is_empty() {
+            _ = write!(&mut description, " on *{album}*");
+        }
+        if let Some(date) = track.date.as_ref().filter(|_| !playing) {
+            if let Ok(uts) = date.uts.parse() {
+                let played = Timestamp(uts, TimestampStyle::Relative);
+                _ = write!(&mut description, "\nScrobbled {played}");
+            }
+        }
+        let title = if playing {
+            "Now playing"
+        } else {
+            "Last played"
+        };
+        let mut embed = CreateEmbed::new()
+            .author(CreateEmbedAuthor::new(format!("{title} for {username}")))
+            .title(&track.name)
+            .url(&track.url)
+            .description(description);
+        // images are listed from smallest to largest, some are empty
+        if let Some(image) = track.image.iter().rev().find(|img| !img.url.is_empty()) {
+            embed = embed.thumbnail(&image.url);
+        }
+        let tags = lastfm.artist_top_tags(artist).await.unwrap_or_default();
+        if !tags.is_empty() {
+            embed = embed.field("Tags", tags.join(", "), true);
+        }
+        if !album.is_empty() {
+            if let Some(link) = album_link(handler, artist, album).await {
+                embed = embed.field("Listen", link, true);
+            }
+        }
+        CommandResponse::public(embed)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "aoty", desc = "Get your albums of the year")]
+pub struct GetAotys {
+    #[cmd(
+        desc = "Last.fm username (defaults to your linked account)",
+        autocomplete
+    )]
+    pub username: Option<String>,
     pub year: Option<i64>,
     pub year_range: Option<String>,
     #[cmd(desc = "Skip albums without album art")]
     pub skip: Option<bool>,
+    #[cmd(desc = "Style of the chart (defaults to a clean grid)", choice)]
+    pub style: Option<ChartStyle>,
 }
 
 #[async_trait]
@@ -263,7 +558,7 @@ impl BotCommand for GetAotys {
         )
         .await?;
         if let Err(e) = self.get_aotys(handler, ctx, opts).await {
-            eprintln!("get aotys failed: {:?}", &e);
+            tracing::error!("get aotys failed: {:?}", &e);
             opts.create_followup(
                 &ctx.http,
                 CreateInteractionResponseFollowup::new().content(e.to_string()),
@@ -281,6 +576,7 @@ impl GetAotys {
         ctx: &Context,
         opts: &CommandInteraction,
     ) -> anyhow::Result<()> {
+        let username = username_or_linked(handler, self.username.clone(), opts).await?;
         let lastfm: Arc<Lastfm> = handler.module_arc()?;
         let spotify: Arc<Spotify> = handler.module_arc()?;
         let db = Arc::clone(&handler.db);
@@ -309,7 +605,7 @@ impl GetAotys {
             format!("{start}-{end}")
         };
         let mut aotys = lastfm
-            .get_albums_of_the_year(db, spotify, &self.username, &year_range)
+            .get_albums_of_the_year(db, spotify, &username, &year_range)
             .await?;
         let http = &ctx.http;
         if aotys.is_empty() {
@@ -317,15 +613,18 @@ impl GetAotys {
                 http,
                 CreateInteractionResponseFollowup::new().content(format!(
                     "No {} albums found for user {}",
-                    &year_fmt, &self.username
+                    &year_fmt, &username
                 )),
             )
             .await?;
             return Ok(());
         }
         aotys.truncate(25);
-        let image = create_aoty_chart(&aotys, self.skip.unwrap_or(false)).await?;
-        let mut content = format!("**Top albums of {} for {}**", &year_fmt, &self.username);
+        let chart = ChartBuilder::new()
+            .skip_missing(self.skip.unwrap_or(false))
+            .labels(self.style == Some(ChartStyle::Labeled));
+        let image = create_aoty_chart(&aotys, chart).await?;
+        let mut content = format!("**Top albums of {} for {}**", &year_fmt, &username);
         aotys
             .iter()
             .map(|ab| &ab.album)
@@ -345,7 +644,7 @@ impl GetAotys {
                 .content(content)
                 .add_file(CreateAttachment::bytes(
                     Cow::Owned(image),
-                    format!("{}_aoty_{}.png", &self.username, &year_fmt),
+                    format!("{}_aoty_{}.png", &username, &year_fmt),
                 )),
         )
         .await?;
@@ -358,6 +657,40 @@ pub struct AlbumWithImage {
     image: Option<DynamicImage>,
 }
 
+impl AlbumWithImage {
+    pub async fn fetch(album: TopAlbum) -> anyhow::Result<Self> {
+        let image = album.get_image().await?;
+        Ok(AlbumWithImage { album, image })
+    }
+
+    fn tile(&self) -> ChartTile<'_> {
+        ChartTile {
+            image: self.image.as_ref(),
+            label: vec![
+                self.album.artist.name.clone(),
+                self.album.name.clone(),
+                format!("{} plays", self.album.playcount),
+            ],
+        }
+    }
+}
+
+// Download an album cover, resized to fit in a chart square
+pub async fn fetch_cover(image_url: &str) -> anyhow::Result<Option<DynamicImage>> {
+    let reader = match reqwest::get(image_url).await {
+        Ok(resp) => Reader::new(Cursor::new(
+            resp.bytes().await.context("Error getting album cover")?,
+        )),
+        Err(_) => return Ok(None),
+    };
+    let img = reader.with_guessed_format()?.decode()?.resize(
+        CHART_SQUARE_SIZE,
+        CHART_SQUARE_SIZE,
+        FilterType::Triangle,
+    );
+    Ok(Some(img))
+}
+
 impl TopAlbum {
     fn get_image(&self) -> impl 'static + Future<Output = anyhow::Result<Option<DynamicImage>>> {
         let image = self.image.iter().last().map(|img| img.url.clone());
@@ -366,56 +699,363 @@ impl TopAlbum {
             let Some(image_url) = image else {
                 return Ok(None);
             };
-            let reader = match reqwest::get(&image_url).await {
-                Ok(resp) => Reader::new(Cursor::new(
-                    resp.bytes().await.context("Error getting album cover")?,
-                )),
-                Err(_) => return Ok(None),
-            };
-            let img = reader.with_guessed_format()?.decode()?.resize(
-                CHART_SQUARE_SIZE,
-                CHART_SQUARE_SIZE,
-                FilterType::Triangle,
-            );
-            Ok(Some(img))
+            fetch_cover(&image_url).await
         }
         .boxed()
     }
 }
 
-pub async fn create_aoty_chart(albums: &[AlbumWithImage], skip: bool) -> anyhow::Result<Vec<u8>> {
-    let n = (albums.len() as f32).sqrt().ceil() as u32;
-    eprintln!("Creating {n}x{n} chart");
-    let len = n * CHART_SQUARE_SIZE;
-    let mut height = n;
-    while (height - 1) * n >= albums.len() as u32 {
-        height -= 1;
+pub async fn create_aoty_chart(
+    albums: &[AlbumWithImage],
+    chart: ChartBuilder,
+) -> anyhow::Result<Vec<u8>> {
+    let tiles = albums.iter().map(AlbumWithImage::tile).collect_vec();
+    chart.render(&tiles)
+}
+
+// Arrange images in a square grid, missing images are left blank unless `skip` is set
+pub fn create_chart(images: &[Option<&DynamicImage>], skip: bool) -> anyhow::Result<Vec<u8>> {
+    let n = (images.len() as f32).sqrt().ceil() as u32;
+    create_grid_chart(images, n, skip)
+}
+
+// Arrange images in rows of `n`, the last rows are dropped if there aren't enough images
+pub fn create_grid_chart(
+    images: &[Option<&DynamicImage>],
+    n: u32,
+    skip: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let tiles = images
+        .iter()
+        .map(|&image| ChartTile::new(image))
+        .collect_vec();
+    ChartBuilder::new()
+        .columns(n)
+        .skip_missing(skip)
+        .render(&tiles)
+}
+
+pub struct ChartTile<'a> {
+    pub image: Option<&'a DynamicImage>,
+    // lines of text drawn at the bottom of the tile in labeled charts
+    pub label: Vec<String>,
+}
+
+impl<'a> ChartTile<'a> {
+    pub fn new(image: Option<&'a DynamicImage>) -> Self {
+        ChartTile {
+            image,
+            label: Vec::new(),
+        }
     }
-    let mut out = RgbaImage::new(len, height * CHART_SQUARE_SIZE);
-    let mut offset = 0;
-    for (mut i, ab) in albums.iter().enumerate() {
-        let Some(img) = ab.image.as_ref() else {
-            offset += 1;
-            continue;
-        };
-        if skip {
-            i -= offset;
+}
+
+// Layout and style of a chart of album covers, e.g. a labeled top 10 in 2 rows:
+//
+//     ChartBuilder::new().rows(2).labels(true).render(&tiles)?
+//
+// Without columns or rows, the grid is fitted to the number of tiles.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChartBuilder {
+    columns: Option<u32>,
+    rows: Option<u32>,
+    skip: bool,
+    labels: bool,
+}
+
+impl ChartBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn columns(mut self, n: u32) -> Self {
+        self.columns = Some(n.max(1));
+        self
+    }
+
+    // Tiles that don't fit in the rows are left out
+    pub fn rows(mut self, n: u32) -> Self {
+        self.rows = Some(n.max(1));
+        self
+    }
+
+    // Leave out tiles without an image instead of leaving them blank
+    pub fn skip_missing(mut self, skip: bool) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    // Draw each tile's label over its image
+    pub fn labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    fn layout(&self, count: u32) -> (u32, u32) {
+        match (self.columns, self.rows) {
+            (Some(columns), Some(rows)) => (columns, rows),
+            (Some(columns), None) => (columns, count.div_ceil(columns).max(1)),
+            (None, Some(rows)) => (count.div_ceil(rows).max(1), rows),
+            (None, None) => fit_layout(count),
+        }
+    }
+
+    pub fn render(&self, tiles: &[ChartTile]) -> anyhow::Result<Vec<u8>> {
+        let tiles = tiles
+            .iter()
+            .filter(|tile| !self.skip || tile.image.is_some())
+            .collect_vec();
+        let (columns, rows) = self.layout(tiles.len() as u32);
+        tracing::debug!("Creating chart of {} images, {columns}x{rows}", tiles.len());
+        let mut out = RgbaImage::new(columns * CHART_SQUARE_SIZE, rows * CHART_SQUARE_SIZE);
+        for (i, tile) in tiles.iter().take((columns * rows) as usize).enumerate() {
+            let x = (i as u32 % columns) * CHART_SQUARE_SIZE;
+            let y = (i as u32 / columns) * CHART_SQUARE_SIZE;
+            if let Some(img) = tile.image {
+                out.copy_from(img, x, y)?;
+            }
+            if self.labels && !tile.label.is_empty() {
+                draw_label(&mut out, x, y, &tile.label);
+            }
+        }
+        let buf = Vec::new();
+        let mut writer = Cursor::new(buf);
+        out.write_to(&mut writer, ImageOutputFormat::Png)?;
+        Ok(writer.into_inner())
+    }
+}
+
+fn label_font() -> &'static Font<'static> {
+    static FONT: OnceLock<Font<'static>> = OnceLock::new();
+    FONT.get_or_init(|| Font::try_from_bytes(LABEL_FONT_DATA).expect("invalid chart font"))
+}
+
+// Most compact grid for `count` tiles: fewest blank tiles, then closest to a square, with at
+// most twice as many columns as rows (a top 10 is 5x2, a top 12 4x3)
+fn fit_layout(count: u32) -> (u32, u32) {
+    let count = count.max(1);
+    (1..=count)
+        .map(|rows| (count.div_ceil(rows), rows))
+        .filter(|&(columns, rows)| columns >= rows && columns <= 2 * rows)
+        .min_by_key(|&(columns, rows)| (columns * rows - count, columns - rows))
+        .unwrap_or((count, 1))
+}
+
+// Text cut short with an ellipsis to fit in `max_width` pixels
+fn fit_text(font: &Font, scale: Scale, text: &str, max_width: u32) -> String {
+    let fits = |text: &str| text_size(scale, font, text).0 <= max_width as i32;
+    if fits(text) {
+        return text.to_string();
+    }
+    let mut cut = text.to_string();
+    while cut.pop().is_some() {
+        let shortened = format!("{}…", cut.trim_end());
+        if fits(&shortened) {
+            return shortened;
+        }
+    }
+    String::new()
+}
+
+// Draw the lines over a darkened band at the bottom of the tile at (x, y)
+fn draw_label(out: &mut RgbaImage, x: u32, y: u32, lines: &[String]) {
+    let font = label_font();
+    let scale = Scale::uniform(LABEL_SCALE);
+    let height =
+        (lines.len() as u32 * LABEL_LINE_HEIGHT + 2 * LABEL_PADDING).min(CHART_SQUARE_SIZE);
+    let top = y + CHART_SQUARE_SIZE - height;
+    for py in top..y + CHART_SQUARE_SIZE {
+        for px in x..x + CHART_SQUARE_SIZE {
+            let Rgba([r, g, b, _]) = *out.get_pixel(px, py);
+            out.put_pixel(px, py, Rgba([r / 3, g / 3, b / 3, 255]));
+        }
+    }
+    let max_width = CHART_SQUARE_SIZE - 2 * LABEL_PADDING;
+    for (i, line) in lines.iter().enumerate() {
+        let text = fit_text(font, scale, line, max_width);
+        let line_y = top + LABEL_PADDING + i as u32 * LABEL_LINE_HEIGHT;
+        let (text_x, text_y) = ((x + LABEL_PADDING) as i32, line_y as i32);
+        draw_text_mut(out, Rgba([255; 4]), text_x, text_y, scale, font, &text);
+    }
+}
+
+#[derive(CommandChoice, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChartStyle {
+    #[cmd(name = "clean grid")]
+    Grid,
+    // artist, album and plays over each cover
+    #[cmd(name = "labeled grid")]
+    Labeled,
+}
+
+// Periods Last.fm computes top albums for
+#[derive(CommandChoice, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Period {
+    #[cmd(name = "7 days")]
+    Week,
+    #[cmd(name = "1 month")]
+    Month,
+    #[cmd(name = "3 months")]
+    Quarter,
+    #[cmd(name = "6 months")]
+    HalfYear,
+    #[cmd(name = "12 months")]
+    Year,
+    #[cmd(name = "all time")]
+    Overall,
+}
+
+impl Period {
+    fn api_value(self) -> &'static str {
+        match self {
+            Period::Week => "7day",
+            Period::Month => "1month",
+            Period::Quarter => "3month",
+            Period::HalfYear => "6month",
+            Period::Year => "12month",
+            Period::Overall => "overall",
+        }
+    }
+
+    pub(crate) fn describe(self) -> &'static str {
+        match self {
+            Period::Week => "the last 7 days",
+            Period::Month => "the last month",
+            Period::Quarter => "the last 3 months",
+            Period::HalfYear => "the last 6 months",
+            Period::Year => "the last 12 months",
+            Period::Overall => "all time",
+        }
+    }
+}
+
+#[derive(CommandChoice, Clone, Copy, Debug)]
+pub enum ChartSize {
+    #[cmd(name = "3x3")]
+    Small,
+    #[cmd(name = "4x4")]
+    Medium,
+    #[cmd(name = "5x5")]
+    Large,
+}
+
+impl ChartSize {
+    fn columns(self) -> u32 {
+        match self {
+            ChartSize::Small => 3,
+            ChartSize::Medium => 4,
+            ChartSize::Large => 5,
         }
-        let y = (i as u32 / n) * CHART_SQUARE_SIZE;
-        let x = (i as u32 % n) * CHART_SQUARE_SIZE;
-        out.copy_from(img, x, y)?;
     }
-    let buf = Vec::new();
-    let mut writer = Cursor::new(buf);
-    out.write_to(&mut writer, ImageOutputFormat::Png)?;
-    Ok(writer.into_inner())
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "chart", desc = "Chart of your top albums over a period")]
+pub struct GetChart {
+    #[cmd(desc = "Period of the chart (defaults to 7 days)", choice)]
+    pub period: Option<Period>,
+    #[cmd(desc = "Size of the grid (defaults to 3x3)", choice)]
+    pub size: Option<ChartSize>,
+    #[cmd(
+        desc = "Last.fm username (defaults to your linked account)",
+        autocomplete
+    )]
+    pub username: Option<String>,
+    #[cmd(desc = "List the albums and their plays with the chart")]
+    pub captions: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for GetChart {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        opts.create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(Default::default()),
+        )
+        .await?;
+        if let Err(e) = self.chart(handler, ctx, opts).await {
+            tracing::error!("chart failed: {:?}", &e);
+            opts.create_followup(
+                &ctx.http,
+                CreateInteractionResponseFollowup::new().content(e.to_string()),
+            )
+            .await?;
+        }
+        Ok(CommandResponse::None)
+    }
+}
+
+impl GetChart {
+    async fn chart(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<()> {
+        let username = username_or_linked(handler, self.username, opts).await?;
+        let period = self.period.unwrap_or(Period::Week);
+        let columns = self.size.unwrap_or(ChartSize::Small).columns();
+        let lastfm: Arc<Lastfm> = handler.module_arc()?;
+        let top = lastfm
+            .get_top_albums(username.clone(), None, Some(period))
+            .await?;
+        let albums = top
+            .album
+            .into_iter()
+            .take((columns * columns) as usize)
+            .collect_vec();
+        if albums.is_empty() {
+            bail!("No albums found for user {username}");
+        }
+        let with_images = futures::future::join_all(albums.into_iter().map(AlbumWithImage::fetch))
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let images = with_images.iter().map(|ab| ab.image.as_ref()).collect_vec();
+        let image = create_grid_chart(&images, columns, false)?;
+        // charts have no text, albums are listed in the message instead
+        let mut content = format!("**Top albums of {} for {username}**", period.describe());
+        if self.captions == Some(true) {
+            for (i, ab) in with_images.iter().map(|ab| &ab.album).enumerate() {
+                _ = write!(
+                    &mut content,
+                    "\n{}. {} - {} ({} plays)",
+                    i + 1,
+                    &ab.artist.name,
+                    &ab.name,
+                    &ab.playcount
+                );
+            }
+        }
+        opts.create_followup(
+            &ctx.http,
+            CreateInteractionResponseFollowup::new()
+                .content(content)
+                .add_file(CreateAttachment::bytes(
+                    Cow::Owned(image),
+                    format!("{username}_chart.png"),
+                )),
+        )
+        .await?;
+        Ok(())
+    }
 }
 
 #[derive(Command, Debug)]
 #[cmd(name = "soty", desc = "Get your songs of the year")]
 pub struct GetSotys {
-    #[cmd(desc = "Last.fm username")]
-    pub username: String,
+    #[cmd(
+        desc = "Last.fm username (defaults to your linked account)",
+        autocomplete
+    )]
+    pub username: Option<String>,
     pub year: Option<i64>,
     #[cmd(desc = "Skip albums without album art")]
     pub skip: Option<bool>,
@@ -452,15 +1092,11 @@ impl GetSotys {
             .year
             .map(|yr| yr as u64)
             .unwrap_or_else(|| Utc::now().year() as u64);
+        let username = username_or_linked(handler, self.username, opts).await?;
         let lastfm: Arc<Lastfm> = handler.module_arc()?;
         let spotify: Arc<Spotify> = handler.module_arc()?;
         let mut songs = lastfm
-            .get_songs_of_the_year(
-                Arc::clone(&handler.db),
-                spotify,
-                self.username.clone(),
-                year,
-            )
+            .get_songs_of_the_year(Arc::clone(&handler.db), spotify, username.clone(), year)
             .await?;
         songs.truncate(25);
         let content = songs
@@ -474,21 +1110,22 @@ impl GetSotys {
             .join("\n");
         let embed = CreateEmbed::default()
             .description(content)
-            .title(format!("Top songs of {year} for {}", &self.username));
+            .title(format!("Top songs of {year} for {}", &username));
         opts.edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
             .await?;
         Ok(())
     }
 }
 
-async fn retrieve_release_year(url: &str) -> anyhow::Result<Option<u64>> {
-    let client = reqwest::Client::new();
-    let resp = client
+async fn retrieve_release_year(
+    client: &RateLimitedClient,
+    url: &str,
+) -> anyhow::Result<Option<u64>> {
+    let req = client
         .request(Method::GET, url)
         .header("accept", "text/html")
-        .header("user-agent", "lpbot (0.1.0)")
-        .send()
-        .await?;
+        .header("user-agent", "lpbot (0.1.0)");
+    let resp = client.send(req).await?;
     let status = resp.status();
     if !status.is_success() {
         bail!("{}", status.canonical_reason().unwrap_or_default());
@@ -513,8 +1150,12 @@ async fn retrieve_release_year(url: &str) -> anyhow::Result<Option<u64>> {
 impl Lastfm {
     pub fn new() -> Self {
         let api_key = env::var("LFM_API_KEY").unwrap();
-        let client = Client::new();
-        Lastfm { client, api_key }
+        let client = RateLimitedClient::new("lastfm");
+        Lastfm {
+            client,
+            api_key,
+            server_charts: Default::default(),
+        }
     }
 
     async fn query<'a, T, I: IntoIterator<Item = (&'static str, &'a str)>>(
@@ -525,6 +1166,7 @@ impl Lastfm {
     where
         T: serde::de::DeserializeOwned,
     {
+        metrics().increment("lastfm_api_calls", method);
         let mut url = Url::parse(API_ENDPOINT)?;
         {
             let mut pairs = url.query_pairs_mut();
@@ -536,7 +1178,7 @@ impl Lastfm {
                 .into_iter()
                 .fold(&mut pairs, |pairs, (k, v)| pairs.append_pair(k, v));
         }
-        let resp = self.client.get(url).send().await?;
+        let resp = self.client.get(url).await?;
         if resp.status() != StatusCode::OK {
             let map: JsonMap = resp.json().await?;
             bail!("Error getting top albums: {:?}", map);
@@ -599,7 +1241,7 @@ impl Lastfm {
         self: Arc<Self>,
         user: String,
         page: Option<u64>,
-        current_year: bool,
+        period: Option<Period>,
     ) -> anyhow::Result<TopAlbums> {
         // using a limit of 500 because somewhere above that number lastfm stops including
         // image links. this limit seems to vary somehow?
@@ -610,8 +1252,8 @@ impl Lastfm {
             params.push(("page", page));
         }
 
-        if current_year {
-            params.push(("period", "12month"))
+        if let Some(period) = period {
+            params.push(("period", period.api_value()))
         }
 
         let top_albums: TopAlbumsResp = self.query("user.gettopalbums", params).await?;
@@ -630,6 +1272,23 @@ impl Lastfm {
         Ok(top_tracks.toptracks)
     }
 
+    pub async fn get_top_artists(
+        &self,
+        user: &str,
+        limit: u64,
+        period: Option<Period>,
+    ) -> anyhow::Result<TopArtists> {
+        let limit_s = limit.to_string();
+        let mut params: Vec<(&'static str, &str)> = vec![("user", user), ("limit", &limit_s)];
+
+        if let Some(period) = period {
+            params.push(("period", period.api_value()))
+        }
+
+        let top_artists: TopArtistsResp = self.query("user.gettopartists", params).await?;
+        Ok(top_artists.topartists)
+    }
+
     pub fn top_albums_stream_inner(
         self: Arc<Self>,
         user: String,
@@ -638,8 +1297,8 @@ impl Lastfm {
         tokio_stream::iter(1..).map(move |i| {
             let user = user.clone();
             let lfm = Arc::clone(&self);
-            eprintln!("querying page {i}");
-            lfm.get_top_albums(user, Some(i), current_year)
+            tracing::debug!("querying page {i}");
+            lfm.get_top_albums(user, Some(i), current_year.then_some(Period::Year))
         })
     }
 
@@ -679,7 +1338,7 @@ impl Lastfm {
             })
             .boxed();
         while let Some(res) = stream.next().await {
-            eprintln!("Retrieved page");
+            tracing::debug!("Retrieved page");
             let top_albums = res?;
             let tuples = top_albums
                 .album
@@ -687,7 +1346,7 @@ impl Lastfm {
                 .enumerate()
                 .map(|(i, ab)| (ab.artist.name.as_str(), ab.name.as_str(), i));
             let res = get_release_years(&db, tuples).await?;
-            eprintln!(
+            tracing::debug!(
                 "Found {}/{} release years in db",
                 res.len(),
                 top_albums.album.len()
@@ -705,11 +1364,13 @@ impl Lastfm {
                     .map(|(i, ab, last_checked)| {
                         tokio::spawn({
                             let year_fut = get_release_year(
+                                Arc::clone(&self),
                                 Arc::clone(&db),
                                 Arc::clone(&spotify),
                                 ab.artist.name.clone(),
                                 ab.name.clone(),
                                 ab.url,
+                                true,
                             );
                             async move {
                                 let last_checked = Utc
@@ -754,7 +1415,7 @@ impl Lastfm {
             }
         }
         let mut out = Vec::with_capacity(aotys.len());
-        for (album, fut) in aotys.into_iter().zip(img_futures.into_iter()) {
+        for (album, fut) in aotys.into_iter().zip(img_futures) {
             let image = fut.await?.ok().flatten();
             out.push(AlbumWithImage { album, image })
         }
@@ -777,7 +1438,7 @@ impl Lastfm {
             async move { lastfm.get_top_tracks(&user, Some(page)).await }
         }));
         loop {
-            eprintln!("Querying page {page}");
+            tracing::debug!("Querying page {page}");
             let top_songs = match top_songs_fut.take() {
                 Some(fut) => fut.await?.context("Error getting top albums")?,
                 None => break,
@@ -818,11 +1479,14 @@ impl Lastfm {
                             None
                         } else {
                             get_release_year(
+                                Arc::clone(&self),
                                 Arc::clone(&db),
                                 Arc::clone(&spotify),
                                 album.artist,
                                 album.title,
                                 album.url,
+                                // songs can come from singles
+                                false,
                             )
                             .await?
                         }
@@ -866,24 +1530,29 @@ fn err_is_status_code(e: &anyhow::Error, expected: u16) -> bool {
 }
 
 async fn get_release_year(
+    lastfm: Arc<Lastfm>,
     db: Arc<Mutex<Db>>,
     spotify: Arc<Spotify>,
     artist: String,
     album: String,
     url: String,
+    albums_only: bool,
 ) -> anyhow::Result<Option<u64>> {
-    let lastfm_release_year = retrieve_release_year(&url).await;
+    let lastfm_release_year = retrieve_release_year(&lastfm.client, &url).await;
     match lastfm_release_year {
         Ok(Some(year)) => {
             set_release_year(&db, &artist, &album, year).await?;
             return Ok(Some(year));
         }
-        Err(e) => eprintln!("Error getting release year from lastfm: {e}"),
+        Err(e) => tracing::warn!("Error getting release year from lastfm: {e}"),
         _ => (),
     }
     // Backoff loop
     loop {
-        match spotify.get_album(&artist, &album).await {
+        match spotify
+            .get_album_with_type(&artist, &album, albums_only)
+            .await
+        {
             Ok(Some(crate::album::Album {
                 release_date: Some(date),
                 ..
@@ -893,7 +1562,7 @@ async fn get_release_year(
                 break Ok(Some(year));
             }
             Ok(_) => {
-                eprintln!("No release year found for {}", &url);
+                tracing::debug!("No release year found for {}", &url);
                 set_last_checked(&db, &artist, &album).await?;
                 break Ok(None);
             }
@@ -904,7 +1573,7 @@ async fn get_release_year(
                     break Ok(None);
                 }
                 if !retry {
-                    eprintln!("query {} {} failed: {:?}", &artist, &album, &e);
+                    tracing::error!("query {} {} failed: {:?}", &artist, &album, &e);
                     set_last_checked(&db, &artist, &album).await?;
                     // discard error, best effort
                     break Ok(None);
@@ -927,9 +1596,9 @@ pub async fn get_release_years<'a, I: IntoIterator<Item = (&'a str, &'a str, usi
         }
         write!(
             &mut query,
-            "(lower('{}'), lower('{}'), {})",
-            crate::db::escape_str(ab.0),
-            crate::db::escape_str(ab.1),
+            "('{}', '{}', {})",
+            crate::db::escape_str(&artist_key(ab.0)),
+            crate::db::escape_str(&album_key(ab.1)),
             ab.2
         )
         .unwrap();
@@ -962,15 +1631,15 @@ async fn set_release_year(
     year: u64,
 ) -> anyhow::Result<()> {
     let db = db.lock().await;
-    db.conn.execute("INSERT INTO album_cache (artist, album, year) VALUES (lower(?1), lower(?2), ?3) ON CONFLICT(artist, album) DO NOTHING",
-    params![artist, album, year])?;
+    db.conn.execute("INSERT INTO album_cache (artist, album, year) VALUES (?1, ?2, ?3) ON CONFLICT(artist, album) DO NOTHING",
+    params![artist_key(artist), album_key(album), year])?;
     Ok(())
 }
 
 async fn set_last_checked(db: &Mutex<Db>, artist: &str, album: &str) -> anyhow::Result<()> {
     let db = db.lock().await;
     db.conn.execute("INSERT INTO album_cache (artist, album, last_checked) VALUES (?1, ?2, ?3) ON CONFLICT(artist, album) DO UPDATE SET last_checked = ?3",
-    params![artist.to_lowercase(), album.to_lowercase(), Utc::now().timestamp()])?;
+    params![artist_key(artist), album_key(album), Utc::now().timestamp()])?;
     Ok(())
 }
 
@@ -979,7 +1648,7 @@ fn get_release_year_db(db: &Db, artist: &str, album: &str) -> Result<u64, u64> {
         .conn
         .query_row(
             "SELECT year, last_checked FROM album_cache WHERE artist = ?1 AND album = ?2",
-            [artist.to_lowercase(), album.to_lowercase()],
+            [artist_key(artist), album_key(album)],
             |row| Ok((row.get(0)?, row.get(1)?)),
         )
         .unwrap_or((None, None));
@@ -1013,21 +1682,24 @@ impl BotCommand for FixReleaseYear {
         _ctx: &Context,
         _opts: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let db = handler.db.lock().await;
-        let current_value = match get_release_year_db(&db, &self.artist, &self.album) {
-            Ok(year) if year == self.year as u64 => bail!("Release year is already {year}"),
-            Ok(year) => Some(year),
-            Err(0) => bail!("Album not found in database, check spelling?"),
-            _ => None,
-        };
-        db.conn.execute(
-            "UPDATE album_cache SET year = ?3, last_checked = 0 WHERE artist = ?1 AND album = ?2",
-            params![
-                self.artist.to_lowercase(),
-                self.album.to_lowercase(),
-                self.year
-            ],
-        )?;
+        let (artist, album, year) = (self.artist.clone(), self.album.clone(), self.year);
+        let current_value = handler
+            .db_call(move |db| {
+                let current_value = match get_release_year_db(db, &artist, &album) {
+                    Ok(current) if current == year as u64 => {
+                        bail!("Release year is already {current}")
+                    }
+                    Ok(current) => Some(current),
+                    Err(0) => bail!("Album not found in database, check spelling?"),
+                    _ => None,
+                };
+                db.conn.execute(
+                    "UPDATE album_cache SET year = ?3, last_checked = 0 WHERE artist = ?1 AND album = ?2",
+                    params![artist_key(&artist), album_key(&album), year],
+                )?;
+                Ok(current_value)
+            })
+            .await?;
         let mut resp = format!(
             "Updated release year of {} - {} to {}",
             &self.artist, &self.album, self.year
@@ -1070,16 +1742,16 @@ fn complete_album<'a>(
                           LIMIT 15"
         );
 
-        let values: Vec<String> = {
-            let db = handler.db.lock().await;
-            let mut stmt = db.conn.prepare(&qry)?;
-            let values = stmt
-                .query_map([artist.to_lowercase(), album.to_lowercase()], |row| {
-                    row.get(0)
-                })?
-                .collect::<Result<_, _>>()?;
-            values
-        };
+        let params = [artist_key(artist), album_key(album)];
+        let values: Vec<String> = handler
+            .db_read(move |db| {
+                let mut stmt = db.conn.prepare(&qry)?;
+                let values = stmt
+                    .query_map(params, |row| row.get(0))?
+                    .collect::<Result<_, _>>()?;
+                Ok(values)
+            })
+            .await?;
 
         let complete = values
             .iter()
@@ -1093,6 +1765,46 @@ fn complete_album<'a>(
     .boxed()
 }
 
+// Keys used to be lowercased names, merge rows that now have the same key.
+// This used to be a named one-off migration, skip it if it already ran that way.
+fn normalize_album_cache_keys(tx: &rusqlite::Transaction) -> anyhow::Result<()> {
+    let legacy_done: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'migration')",
+        [],
+        |row| row.get(0),
+    )? && tx.query_row(
+        "SELECT COUNT(*) FROM migration WHERE name = 'album_cache_normalized_keys'",
+        [],
+        |row| row.get(0),
+    )?;
+    tx.execute("DROP TABLE IF EXISTS migration", [])?;
+    if legacy_done {
+        return Ok(());
+    }
+    let rows: Vec<(String, String, Option<u64>, Option<u64>)> = tx
+        .prepare("SELECT artist, album, year, last_checked FROM album_cache")?
+        .query([])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+        .collect()?;
+    tx.execute("DELETE FROM album_cache", [])?;
+    let mut insert = tx.prepare(
+        "INSERT INTO album_cache (artist, album, year, last_checked)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(artist, album) DO UPDATE
+         SET year = coalesce(year, excluded.year),
+             last_checked = max(coalesce(last_checked, 0), coalesce(excluded.last_checked, 0))",
+    )?;
+    for (artist, album, year, last_checked) in rows {
+        insert.execute(params![
+            artist_key(&artist),
+            album_key(&album),
+            year,
+            last_checked
+        ])?;
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl Module for Lastfm {
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
@@ -1103,23 +1815,59 @@ impl Module for Lastfm {
         builder.module::<Spotify>().await
     }
 
-    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
-        db.conn.execute(
+    const NAME: &'static str = "Lastfm";
+    const MIGRATIONS: &'static [Migration] = &[
+        Migration::sql(
+            1,
+            "create lastfm_users",
+            "CREATE TABLE IF NOT EXISTS lastfm_users (
+                user_id INTEGER PRIMARY KEY,
+                username STRING NOT NULL,
+                ts INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS lastfm_user_guild (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                UNIQUE(guild_id, user_id)
+            );",
+        ),
+        Migration::sql(
+            2,
+            "create lastfm_taste",
+            "CREATE TABLE IF NOT EXISTS lastfm_taste (
+                user_a STRING NOT NULL,
+                user_b STRING NOT NULL,
+                score REAL NOT NULL,
+                shared INTEGER NOT NULL,
+                favorites STRING NOT NULL,
+                ts INTEGER NOT NULL,
+                UNIQUE(user_a, user_b)
+            );",
+        ),
+        Migration::sql(
+            3,
+            "create album_cache",
             "CREATE TABLE IF NOT EXISTS album_cache (
-            artist STRING NOT NULL,
-            album STRING NOT NULL,
-            year INTEGER,
-            last_checked INTEGER,
-            UNIQUE(artist, album)
-        )",
-            [],
-        )?;
-        Ok(())
-    }
+                artist STRING NOT NULL,
+                album STRING NOT NULL,
+                year INTEGER,
+                last_checked INTEGER,
+                UNIQUE(artist, album)
+            );",
+        ),
+        Migration::run(4, "album_cache normalized keys", normalize_album_cache_keys),
+    ];
 
     fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
         store.register::<GetAotys>();
         store.register::<FixReleaseYear>();
+        store.register::<LinkLastfm>();
+        store.register::<UnlinkLastfm>();
+        store.register::<NowPlaying>();
+        store.register::<GetChart>();
+        server_chart::register_commands(store);
+        taste::register_commands(store);
         completions.push(complete_album);
+        completions.push(complete_username);
     }
 }
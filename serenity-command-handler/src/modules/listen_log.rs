@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, bail};
+use chrono::{Datelike, TimeZone, Utc};
+use fallible_iterator::FallibleIterator;
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::{
+    CreateActionRow, CreateAttachment, CreateAutocompleteResponse, CreateButton, CreateEmbed,
+    CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse,
+};
+use serenity::model::application::{ButtonStyle, CommandType, ComponentInteraction};
+use serenity::model::prelude::{CommandInteraction, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::album::Album;
+use crate::command_context::get_str_opt_ac;
+use crate::db::{Db, SqlUserId};
+use crate::modules::lastfm::{create_chart, fetch_cover};
+use crate::modules::{AlbumLookup, Lastfm};
+use crate::prelude::*;
+
+const PAGE_SIZE: usize = 10;
+const MAX_COLLAGE: usize = 25;
+const COLLAGE_NAME: &str = "listen_log.png";
+const PAGE_PREFIX: &str = "listen_log:";
+
+struct LogEntry {
+    ts: i64,
+    artist: Option<String>,
+    album: String,
+    url: Option<String>,
+    rating: Option<i64>,
+    note: Option<String>,
+}
+
+impl LogEntry {
+    fn format(&self) -> String {
+        let name = match &self.artist {
+            Some(artist) => format!("{artist} - {}", self.album),
+            None => self.album.clone(),
+        };
+        let mut line = match &self.url {
+            Some(url) => format!("<t:{}:d> [{name}]({url})", self.ts),
+            None => format!("<t:{}:d> {name}", self.ts),
+        };
+        if let Some(rating) = self.rating {
+            line.push_str(&format!(" **{rating}/10**"));
+        }
+        if let Some(note) = &self.note {
+            line.push_str(&format!(" — {note}"));
+        }
+        line
+    }
+}
+
+// Add an album to a user's listen log.
+// Meant to be usable by other modules as well, e.g. to log albums rated after an LP.
+pub fn log_album(
+    db: &Db,
+    user_id: UserId,
+    info: &Album,
+    rating: Option<i64>,
+    note: Option<&str>,
+) -> anyhow::Result<()> {
+    let Some(album) = &info.name else {
+        bail!("Missing album name");
+    };
+    db.conn.execute(
+        "INSERT INTO listen_log (user_id, ts, artist, album, url, cover, rating, note)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            SqlUserId(user_id),
+            Utc::now().timestamp(),
+            info.artist,
+            album,
+            info.url,
+            info.cover,
+            rating,
+            note
+        ],
+    )?;
+    Ok(())
+}
+
+fn log_page(
+    db: &Db,
+    user_id: UserId,
+    page: usize,
+) -> anyhow::Result<(CreateEmbed, Vec<CreateActionRow>)> {
+    let total: usize = db.conn.query_row(
+        "SELECT COUNT(*) FROM listen_log WHERE user_id = ?1",
+        [SqlUserId(user_id)],
+        |row| row.get(0),
+    )?;
+    if total == 0 {
+        bail!("<@{user_id}> hasn't logged any albums yet");
+    }
+    let pages = total.div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let entries: Vec<LogEntry> = db
+        .conn
+        .prepare(
+            "SELECT ts, artist, album, url, rating, note FROM listen_log
+             WHERE user_id = ?1 ORDER BY ts DESC LIMIT ?2 OFFSET ?3",
+        )?
+        .query(params![SqlUserId(user_id), PAGE_SIZE, page * PAGE_SIZE])?
+        .map(|row| {
+            Ok(LogEntry {
+                ts: row.get(0)?,
+                artist: row.get(1)?,
+                album: row.get(2)?,
+                url: row.get(3)?,
+                rating: row.get(4)?,
+                note: row.get(5)?,
+            })
+        })
+        .collect()?;
+    let embed = CreateEmbed::new()
+        .title("Listen log")
+        .description(format!(
+            "<@{user_id}>\n{}",
+            entries.iter().map(LogEntry::format).join("\n")
+        ))
+        .footer(CreateEmbedFooter::new(format!("Page {}/{pages}", page + 1)));
+    let button = |label: &str, target: usize, disabled: bool| {
+        CreateButton::new(format!("{PAGE_PREFIX}{user_id}:{target}"))
+            .label(label)
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled)
+    };
+    let buttons = vec![
+        button("Previous", page.saturating_sub(1), page == 0),
+        button("Next", page + 1, page + 1 >= pages),
+    ];
+    Ok((embed, vec![CreateActionRow::Buttons(buttons)]))
+}
+
+// Collage of the covers of the albums logged this month
+async fn monthly_collage(handler: &Handler, user_id: UserId) -> anyhow::Result<Option<Vec<u8>>> {
+    let now = Utc::now();
+    let month_start = Utc
+        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .unwrap()
+        .timestamp();
+    let covers: Vec<String> = handler
+        .db
+        .lock()
+        .await
+        .conn
+        .prepare(
+            "SELECT cover FROM listen_log
+             WHERE user_id = ?1 AND ts >= ?2 AND cover IS NOT NULL
+             ORDER BY ts ASC LIMIT ?3",
+        )?
+        .query(params![SqlUserId(user_id), month_start, MAX_COLLAGE])?
+        .map(|row| row.get(0))
+        .collect()?;
+    if covers.is_empty() {
+        return Ok(None);
+    }
+    let images = join_all(covers.iter().map(|url| fetch_cover(url)))
+        .await
+        .into_iter()
+        .map(|res| {
+            res.unwrap_or_else(|e| {
+                tracing::warn!("could not fetch cover: {e:?}");
+                None
+            })
+        })
+        .collect_vec();
+    if images.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    Ok(Some(create_chart(
+        &images.iter().map(Option::as_ref).collect_vec(),
+        true,
+    )?))
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "log_album",
+    desc = "Add an album you've finished to your listen log"
+)]
+pub struct LogAlbum {
+    #[cmd(desc = "The album you listened to", autocomplete)]
+    album: String,
+    #[cmd(desc = "Your rating, out of 10")]
+    rating: Option<i64>,
+    #[cmd(desc = "A few words about it")]
+    note: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for LogAlbum {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        if let Some(rating) = self.rating {
+            if !(0..=10).contains(&rating) {
+                bail!("Rating must be between 0 and 10");
+            }
+        }
+        let lookup = handler.module::<AlbumLookup>()?;
+        let info = if self.album.starts_with("https://") {
+            lookup.get_album_info(&self.album).await?
+        } else {
+            lookup.lookup_album(&self.album, None).await?
+        };
+        let info = info.unwrap_or_else(|| Album {
+            name: Some(self.album.clone()),
+            ..Default::default()
+        });
+        log_album(
+            &*handler.db.lock().await,
+            command.user.id,
+            &info,
+            self.rating,
+            self.note.as_deref(),
+        )?;
+        CommandResponse::private(format!("Logged {}", info.as_link(None)))
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "listen_log", desc = "Show the albums a member has logged")]
+pub struct ShowListenLog {
+    #[cmd(desc = "Member whose log to show (defaults to you)")]
+    user: Option<UserId>,
+}
+
+#[async_trait]
+impl BotCommand for ShowListenLog {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = self.user.unwrap_or(command.user.id);
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let page = log_page(&*handler.db.lock().await, user_id, 0);
+        let mut resp = match page {
+            Ok((embed, components)) => EditInteractionResponse::new()
+                .embed(embed)
+                .components(components),
+            Err(e) => EditInteractionResponse::new().content(e.to_string()),
+        };
+        match monthly_collage(handler, user_id).await {
+            Ok(Some(collage)) => {
+                resp =
+                    resp.new_attachment(CreateAttachment::bytes(Cow::Owned(collage), COLLAGE_NAME))
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("could not create listen log collage: {e:?}"),
+        }
+        command.edit_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+pub struct ListenLog;
+
+impl ListenLog {
+    fn change_page<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(ids) = component.data.custom_id.strip_prefix(PAGE_PREFIX) else {
+                return Ok(false);
+            };
+            let (user_id, page) = ids
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid listen log page id: {ids}"))?;
+            let user_id = UserId::new(user_id.parse()?);
+            let (embed, components) = log_page(&*handler.db.lock().await, user_id, page.parse()?)?;
+            let msg = CreateInteractionResponseMessage::new()
+                .embed(embed)
+                .components(components);
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(msg))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn complete_album<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if key != ("log_album", CommandType::ChatInput) {
+                return Ok(false);
+            }
+            let query = get_str_opt_ac(&ac.data.options, "album").unwrap_or("");
+            let choices = if query.len() >= 3 {
+                handler
+                    .module::<AlbumLookup>()?
+                    .query_albums(query, None)
+                    .await?
+            } else {
+                Vec::new()
+            };
+            let resp = choices
+                .into_iter()
+                .filter(|(_, value)| value.len() < 100)
+                .fold(CreateAutocompleteResponse::new(), |resp, (name, value)| {
+                    resp.add_string_choice(name, value)
+                });
+            ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for ListenLog {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Lastfm>()
+            .await?
+            .module::<AlbumLookup>()
+            .await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ListenLog)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS listen_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                artist STRING,
+                album STRING NOT NULL,
+                url STRING,
+                cover STRING,
+                rating INTEGER,
+                note STRING
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<LogAlbum>();
+        store.register::<ShowListenLog>();
+        completions.push(ListenLog::complete_album);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(ListenLog::change_page);
+    }
+}
@@ -1,55 +1,92 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt::Write;
 use std::ops::Add;
 
-use crate::{db::Db, CommandStore, HandlerBuilder, Module};
+use crate::{
+    db::{Db, Migration, SqlChannelId, SqlGuildId, SqlMessageId, SqlUserId},
+    CommandStore, HandlerBuilder, Module,
+};
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context as _;
 use chrono::{prelude::*, Duration};
+use fallible_iterator::FallibleIterator;
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use itertools::Itertools;
 use regex::Regex;
 use reqwest::Url;
+use rusqlite::{params, OptionalExtension};
+use serde::de::{self, Deserializer, Visitor};
 use serde::Deserialize;
 use serde::Serialize;
 use serenity::all::AutoArchiveDuration;
 use serenity::all::Message;
 use serenity::all::RoleId;
 use serenity::async_trait;
-use serenity::builder::CreateAllowedMentions;
 use serenity::builder::CreateAutocompleteResponse;
-use serenity::builder::CreateCommandOption;
+use serenity::builder::CreateEmbed;
+use serenity::builder::CreateForumPost;
 use serenity::builder::CreateInteractionResponse;
+use serenity::builder::CreateMessage;
+use serenity::builder::CreateScheduledEvent;
+use serenity::builder::CreateStageInstance;
 use serenity::builder::CreateThread;
 use serenity::builder::EditMessage;
+use serenity::builder::EditStageInstance;
 use serenity::builder::EditThread;
-use serenity::builder::ExecuteWebhook;
+use serenity::builder::EditWebhookMessage;
 use serenity::builder::GetMessages;
 use serenity::client::Context;
+use serenity::gateway::ActivityData;
+use serenity::http::Http;
 use serenity::model::application::CommandDataOption;
 use serenity::model::application::CommandType;
 use serenity::model::channel::ChannelType;
-use serenity::model::id::GuildId;
-use serenity::model::prelude::CommandInteraction;
+use serenity::model::guild::ScheduledEventType;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, MessageId, UserId};
 use serenity::model::Permissions;
 use serenity_command_derive::Command;
 
 use crate::album::Album;
-use crate::command_context::{get_focused_option, get_str_opt_ac, Responder};
-use crate::modules::{Bandcamp, Lastfm, Spotify};
+use crate::command_context::{get_focused_option, get_str_opt_ac, Responder, ThreadExt};
+use crate::date_format::{Timestamp, TimestampStyle};
+use crate::gateway::GatewayHandlers;
+use crate::modules::{lp_queue, lp_stats, lp_tracks, Bandcamp, Lastfm, Spotify};
+use crate::normalize::fold;
 use crate::prelude::*;
+use crate::presence::{PresenceEntry, PRIORITY_EVENT};
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::time_parse::parse_time;
 use serenity_command::CommandResponse;
-use serenity_command::{BotCommand, CommandKey};
+use serenity_command::{BotCommand, CommandError, CommandKey, GuildCommand};
 
+use super::album_lookup::Provider;
 use super::AlbumLookup;
 
 const SEPARATOR: char = '\u{200B}';
 const LP_URI: &str = "http://lp";
+// Version of the data saved for LP messages and embedded in them. Bump it when changing
+// ResolvedLp or Lp in a way that data from older messages can't be read, and keep decoding
+// older versions in `ResolvedLp::decode` so /edit_lp still works on LPs that were already
+// posted.
+const LP_DATA_VERSION: u32 = 1;
+
+// Each thread member add is a separate request, so cap and space them out
+const MAX_THREAD_INVITES: usize = 100;
+const THREAD_INVITE_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+// How long the album is shown in the bot's presence when its duration is unknown
+const DEFAULT_LP_MINUTES: i64 = 60;
+// Shown instead of the album in blind LPs, until they start
+const MYSTERY_ALBUM: &str = "Mystery album 🎭";
+const DEFAULT_LP_NAME: &str = "Listening party";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResolvedLp {
+    // missing from messages posted before the data was versioned, i.e. version 0
+    #[serde(rename = "v", default)]
+    pub version: u32,
     #[serde(rename = "rtitle")]
     pub resolved_title: Option<String>,
     #[serde(rename = "rlink")]
@@ -60,6 +97,57 @@ pub struct ResolvedLp {
     pub params: Lp,
 }
 
+impl ResolvedLp {
+    // Parse data saved by `save_lp_data`, encoded like the embedded data
+    fn from_data(data: &str) -> anyhow::Result<Self> {
+        let mut url = Url::parse(LP_URI)?;
+        url.set_query(Some(data));
+        Self::decode(&url)
+    }
+
+    // Data of an LP message, from the database if it was saved there, otherwise from the
+    // data embedded in the message, which is lost if the message is edited or truncated
+    async fn for_message(handler: &Handler, msg: &Message) -> anyhow::Result<Self> {
+        let message_id = msg.id;
+        let saved = handler
+            .db_read(move |db| saved_lp_data(db, message_id))
+            .await?;
+        match saved {
+            Some(data) => Self::from_data(&data),
+            None => Self::from_message(&msg.content),
+        }
+    }
+
+    // Parse the data embedded in an LP message by `build_message_contents`
+    fn from_message(content: &str) -> anyhow::Result<Self> {
+        let Some(pos) = content.find(LP_URI) else {
+            bail!("no embedded data");
+        };
+        let url: Url = content[pos..]
+            .trim_end_matches(')')
+            .parse()
+            .context("invalid embedded URL")?;
+        Self::decode(&url)
+    }
+
+    // Decode embedded data of any version, the result is upgraded to the current version
+    fn decode(url: &Url) -> anyhow::Result<Self> {
+        let version: u32 = match url.query_pairs().find(|(key, _)| key == "v") {
+            Some((_, v)) => v.parse().context("invalid embedded data version")?,
+            None => 0,
+        };
+        let query = url.query().unwrap_or_default();
+        let mut lp: ResolvedLp = match version {
+            // version 0 only lacks the version tag
+            0 | LP_DATA_VERSION => serde_urlencoded::de::from_str(query)
+                .context("failed to deserialize embedded data")?,
+            v => bail!("unsupported embedded data version {v}"),
+        };
+        lp.version = LP_DATA_VERSION;
+        Ok(lp)
+    }
+}
+
 #[derive(Command, Serialize, Deserialize, Debug)]
 #[cmd(name = "lp", desc = "run a listening party")]
 pub struct Lp {
@@ -73,12 +161,51 @@ pub struct Lp {
         autocomplete
     )]
     link: Option<String>,
-    #[cmd(desc = "Time at which the LP will take place (e.g. XX:20, +5)")]
+    #[cmd(desc = "Time at which the LP will take place (e.g. XX:20, +5, in 1h15, 21:30 CET)")]
     time: Option<String>,
-    #[cmd(desc = "Where to look for album info (defaults to spotify)")]
-    provider: Option<String>,
+    #[cmd(desc = "Where to look for album info (defaults to spotify)", choice)]
+    provider: Option<Provider>,
     #[cmd(desc = "Use a specific role instead of the default (admin-only)")]
     role: Option<RoleId>,
+    #[cmd(desc = "Hide the album until the listening party starts")]
+    #[serde(default, deserialize_with = "deserialize_opt_bool")]
+    blind: Option<bool>,
+}
+
+// Lp is flattened in ResolvedLp, so its fields are buffered before being deserialized and
+// urlencoded booleans arrive as strings
+fn deserialize_opt_bool<'de, D: Deserializer<'de>>(de: D) -> Result<Option<bool>, D::Error> {
+    struct OptBool;
+
+    impl<'de> Visitor<'de> for OptBool {
+        type Value = Option<bool>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a boolean")
+        }
+
+        fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+            Ok(Some(v))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            v.parse().map(Some).map_err(E::custom)
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, de: D) -> Result<Self::Value, D::Error> {
+            de.deserialize_any(OptBool)
+        }
+    }
+
+    de.deserialize_option(OptBool)
 }
 
 fn format_end(start: DateTime<Utc>, duration: Option<Duration>) -> String {
@@ -86,59 +213,49 @@ fn format_end(start: DateTime<Utc>, duration: Option<Duration>) -> String {
         return String::new();
     };
     let end = start.add(duration);
-    format!(", ends at <t:{}:t>", end.timestamp())
+    format!(
+        ", ends at {}",
+        Timestamp::new(end, TimestampStyle::ShortTime)
+    )
+}
+
+// timestamp and relative time
+fn format_start(start: DateTime<Utc>, end_str: &str) -> String {
+    format!(
+        "at {} ({}{end_str})",
+        Timestamp::new(start, TimestampStyle::ShortTime),
+        Timestamp::new(start, TimestampStyle::Relative)
+    )
 }
 
 fn convert_lp_time(
     time: Option<&str>,
     duration: Option<Duration>,
     resolved_start: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
 ) -> anyhow::Result<(String, Option<DateTime<Utc>>)> {
     if let (Some(start), None) = (resolved_start, time) {
         let end_str = format_end(start, duration);
-        let formatted = format!("at <t:{0:}:t> (<t:{0:}:R>{end_str})", start.timestamp());
-        return Ok((formatted, Some(start)));
+        return Ok((format_start(start, &end_str), Some(start)));
     }
-    let mut lp_time = Utc::now().add(Duration::seconds(10));
+    let mut lp_time = now.add(Duration::seconds(10));
     let time = match time {
         Some("now") | None => {
             let end_str = format_end(lp_time, duration);
-            let formatted = format!("now (<t:{}:R>{end_str})", lp_time.timestamp());
+            let relative = Timestamp::new(lp_time, TimestampStyle::Relative);
+            let formatted = format!("now ({relative}{end_str})");
             return Ok((formatted, Some(lp_time)));
         }
         Some(t) => t,
     };
-    let xx_re = Regex::new("(?i)^(XX:?)?([0-5][0-9])$")?; // e.g. XX:15, xx15 or 15
-    let plus_re = Regex::new(r"\+?(([0-5])?[0-9])m?")?; // e.g. +25
-    if let Some(cap) = xx_re.captures(time) {
-        let min: i64 = cap.get(2).unwrap().as_str().parse()?;
-        if !(0..60).contains(&min) {
-            bail!("Invalid time");
-        }
-        let cur_min = lp_time.minute() as i64;
-        let to_add = if cur_min <= min {
-            min - cur_min
-        } else {
-            (60 - cur_min) + min
-        };
-        lp_time = lp_time.add(Duration::minutes(to_add));
-    } else if let Some(cap) = plus_re.captures(time) {
-        let extra_mins: i64 = cap.get(1).unwrap().as_str().parse()?;
-        lp_time = lp_time.add(Duration::minutes(extra_mins));
-    } else {
-        return Ok((time.to_string(), None));
-    }
+    lp_time = parse_time(time, lp_time)?;
 
     let end_str = format_end(lp_time, duration);
-    // timestamp and relative time
-    Ok((
-        format!("at <t:{0:}:t> (<t:{0:}:R>{end_str})", lp_time.timestamp()),
-        Some(lp_time),
-    ))
+    Ok((format_start(lp_time, &end_str), Some(lp_time)))
 }
 
 async fn get_lastfm_genres(handler: &Handler, info: &Album) -> Option<Vec<String>> {
-    if info.is_playlist || !info.genres.is_empty() {
+    if info.is_playlist || info.is_episode || !info.genres.is_empty() {
         return None;
     }
     // No genres, try to get some from last.fm
@@ -151,7 +268,7 @@ async fn get_lastfm_genres(handler: &Handler, info: &Album) -> Option<Vec<String
         Ok(genres) => Some(genres),
         Err(err) => {
             // Log error but carry on
-            eprintln!("Couldn't retrieve genres from lastfm: {err}");
+            tracing::warn!("Couldn't retrieve genres from lastfm: {err}");
             None
         }
     }
@@ -163,10 +280,22 @@ async fn build_message_contents(
     info: &Album,
     role_id: Option<u64>,
     resolved_start: Option<DateTime<Utc>>,
-) -> anyhow::Result<String> {
+    now: DateTime<Utc>,
+) -> anyhow::Result<(String, String)> {
     let (when, resolved_start) =
-        convert_lp_time(lp.time.as_deref(), info.duration, resolved_start)?;
-    let hyperlinked = info.as_link(lp_name);
+        convert_lp_time(lp.time.as_deref(), info.duration, resolved_start, now)?;
+    let resolved_link = info.url.clone();
+    // blind LPs only show the duration, the album is kept in the embedded data
+    let hidden;
+    let (hyperlinked, info) = if lp.blind == Some(true) {
+        hidden = Album {
+            duration: info.duration,
+            ..Default::default()
+        };
+        (MYSTERY_ALBUM.to_string(), &hidden)
+    } else {
+        (info.as_link(lp_name), info)
+    };
     let mut resp_content = format!(
         "{} {SEPARATOR}{hyperlinked}{SEPARATOR} {}\n",
         role_id // mention role if set
@@ -174,29 +303,32 @@ async fn build_message_contents(
             .unwrap_or_else(|| "Listening party: ".to_string()),
         when
     );
+    let mut details = Vec::new();
     if let Some(duration) = info.duration {
+        let mut formatted = String::new();
         if duration.num_hours() > 0 {
-            _ = write!(&mut resp_content, "{}h", duration.num_hours());
+            _ = write!(&mut formatted, "{}h", duration.num_hours());
         }
         let minutes = duration.num_minutes() % 60;
         if minutes > 0 {
-            _ = write!(&mut resp_content, "{minutes:02}m");
+            _ = write!(&mut formatted, "{minutes:02}m");
         }
         let seconds = duration.num_seconds();
         if seconds < 60 {
-            _ = write!(&mut resp_content, "{seconds}s");
-        }
-    }
-    if let Some(genres) = info.format_genres() {
-        if info.duration.is_some() {
-            resp_content.push_str(" | ");
+            _ = write!(&mut formatted, "{seconds}s");
         }
-        _ = write!(&mut resp_content, "{}", &genres);
+        details.push(formatted);
     }
+    details.extend(info.format_genres());
+    details.extend(info.format_episodes());
+    details.extend(info.rating.clone());
+    details.extend(info.format_physical());
+    resp_content.push_str(&details.join(" | "));
     let resolved = ResolvedLp {
+        version: LP_DATA_VERSION,
         resolved_start,
         resolved_title: lp_name.map(|s| s.to_string()),
-        resolved_link: info.url.clone(),
+        resolved_link,
         params: lp,
     };
     let encoded_data = serde_urlencoded::ser::to_string(resolved).unwrap();
@@ -204,7 +336,28 @@ async fn build_message_contents(
     encoded_data_url.set_query(Some(&encoded_data));
     let data: String = encoded_data_url.into();
     _ = write!(&mut resp_content, "[̣]({data})");
-    Ok(resp_content)
+    Ok((resp_content, encoded_data))
+}
+
+// Save the data of an LP message, to edit it later without relying on the embedded data
+fn save_lp_data(db: &Db, message_id: MessageId, data: &str) -> anyhow::Result<()> {
+    db.conn.execute(
+        "UPDATE lp_message SET data = ?2 WHERE message_id = ?1",
+        params![SqlMessageId(message_id), data],
+    )?;
+    Ok(())
+}
+
+fn saved_lp_data(db: &Db, message_id: MessageId) -> anyhow::Result<Option<String>> {
+    let data = db
+        .conn
+        .query_row(
+            "SELECT data FROM lp_message WHERE message_id = ?1",
+            [SqlMessageId(message_id)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(data.flatten())
 }
 
 async fn find_album<'a>(
@@ -242,13 +395,21 @@ async fn find_album<'a>(
     Ok((lp_name, info))
 }
 
+// Contents of an LP message, with the data to save for /edit_lp
+struct LpContents {
+    text: String,
+    role_id: Option<u64>,
+    info: Album,
+    data: String,
+}
+
 impl Lp {
     async fn build_contents(
         self,
         handler: &Handler,
-        command: &CommandInteraction,
+        guild_id: GuildId,
         resolved_start: Option<DateTime<Utc>>,
-    ) -> anyhow::Result<(String, Option<u64>, Album)> {
+    ) -> anyhow::Result<LpContents> {
         let Lp {
             album,
             link,
@@ -257,23 +418,476 @@ impl Lp {
             ..
         } = &self;
         let (lp_name, mut info) =
-            find_album(handler, album, link.as_deref(), provider.as_deref()).await?;
+            find_album(handler, album, link.as_deref(), provider.map(Provider::id)).await?;
         let lp_name = lp_name.map(|s| s.to_string());
         // get genres if needed
         if let Some(genres) = get_lastfm_genres(handler, &info).await {
             info.genres = genres
         }
-        let guild_id = command.guild_id()?.get();
+        #[cfg(feature = "ratings")]
+        crate::modules::ratings::add_rating(handler, Some(guild_id), &mut info).await;
         let mut role_id = handler
             .get_guild_field(guild_id, "role_id")
             .await
             .context("error retrieving LP role")?;
         role_id = role.map(|r| r.get()).or(role_id);
-        let resp_content =
-            build_message_contents(self, lp_name.as_deref(), &info, role_id, resolved_start)
+        let now = handler.clock.now();
+        let (text, data) = build_message_contents(
+            self,
+            lp_name.as_deref(),
+            &info,
+            role_id,
+            resolved_start,
+            now,
+        )
+        .await?;
+        Ok(LpContents {
+            text,
+            role_id,
+            info,
+            data,
+        })
+    }
+}
+
+// Names that could be used to pass webhook messages off as coming from the bot or staff.
+// Discord also rejects webhook names containing "clyde" or "discord".
+const DENIED_WEBHOOK_NAMES: &[&str] = &["clyde", "discord", "admin", "moderator", "system"];
+const MAX_WEBHOOK_NAME_LEN: usize = 80;
+const FALLBACK_WEBHOOK_NAME: &str = "Listening party";
+const WEBHOOK_LOG_SIZE: usize = 15;
+
+// Strip invisible and control characters (zero-width spaces, bidi overrides...) and
+// collapse whitespace, as they can be used to make names look like others
+fn sanitize_name(name: &str) -> String {
+    let invisible = |c: &char| {
+        matches!(
+            c,
+            '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{206F}' | '\u{FEFF}'
+        )
+    };
+    name.chars()
+        .filter(|c| !c.is_control() && !invisible(c))
+        .collect::<String>()
+        .split_whitespace()
+        .join(" ")
+        .chars()
+        .take(MAX_WEBHOOK_NAME_LEN)
+        .collect()
+}
+
+// Fold and ignore separators, so that e.g. "A.d m-i_n" or "Ådmîn" is caught too
+fn normalize_name(name: &str) -> String {
+    fold(name).replace(' ', "")
+}
+
+fn is_denied_name(name: &str, bot_name: &str) -> bool {
+    let normalized = normalize_name(name);
+    let bot_name = normalize_name(bot_name);
+    normalized.is_empty()
+        || (!bot_name.is_empty() && normalized.contains(&bot_name))
+        || DENIED_WEBHOOK_NAMES
+            .iter()
+            .any(|denied| normalized.contains(denied))
+}
+
+// Name to send LP webhooks under: the member's nickname if it is safe, otherwise their
+// username, otherwise a generic name
+fn webhook_username(nick: &str, username: &str, bot_name: &str) -> String {
+    [nick, username]
+        .into_iter()
+        .map(sanitize_name)
+        .find(|name| !is_denied_name(name, bot_name))
+        .unwrap_or_else(|| FALLBACK_WEBHOOK_NAME.to_string())
+}
+
+fn log_webhook_send(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    message: &Message,
+    ts: i64,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT INTO lp_webhook_log (guild_id, user_id, username, channel_id, message_id, ts)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            SqlGuildId(guild_id),
+            SqlUserId(user_id),
+            message.author.name,
+            SqlChannelId(message.channel_id),
+            SqlMessageId(message.id),
+            ts
+        ],
+    )?;
+    Ok(())
+}
+
+fn record_lp(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    info: &Album,
+    ts: i64,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT INTO lp_history (guild_id, user_id, ts, name) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            SqlGuildId(guild_id),
+            SqlUserId(user_id),
+            ts,
+            info.format_name()
+        ],
+    )?;
+    Ok(())
+}
+
+// Show the album as the bot's activity while the LP is running
+fn show_lp_presence(
+    handler: &Handler,
+    guild_id: GuildId,
+    info: &Album,
+    start: DateTime<Utc>,
+    blind: bool,
+) {
+    let duration = info
+        .duration
+        .unwrap_or_else(|| Duration::minutes(DEFAULT_LP_MINUTES));
+    let end = start.add(duration);
+    let name = if blind {
+        MYSTERY_ALBUM.to_string()
+    } else {
+        info.format_name()
+    };
+    let activity = ActivityData::listening(name);
+    handler.presence.set(
+        format!("lp:{guild_id}"),
+        PresenceEntry::new(activity, PRIORITY_EVENT).between(start, end),
+    );
+}
+
+// Where an LP takes place, besides its message
+#[derive(Clone, Copy)]
+enum Venue {
+    // a thread on the message, if the guild enabled /setcreatethreads
+    Thread,
+    // a post in the guild's LP forum, starting with the LP message
+    Forum(ChannelId),
+    // the stage the command was run in, with the album as its topic
+    Stage(ChannelId),
+}
+
+impl Venue {
+    async fn for_command(handler: &Handler, command: &CommandInteraction) -> anyhow::Result<Self> {
+        if let Some(ChannelType::Stage) = command.channel.as_ref().map(|c| c.kind) {
+            return Ok(Venue::Stage(command.channel_id));
+        }
+        let forum: Option<SqlChannelId> = handler
+            .get_guild_field(command.guild_id()?, "lp_forum")
+            .await?;
+        Ok(forum.map_or(Venue::Thread, |SqlChannelId(forum)| Venue::Forum(forum)))
+    }
+}
+
+// Open the stage for an LP, or schedule it if the LP starts later
+async fn open_stage(
+    http: &Http,
+    guild_id: GuildId,
+    stage: ChannelId,
+    topic: &str,
+    start: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    match start.filter(|start| *start > now) {
+        Some(start) => {
+            let start = serenity::model::Timestamp::from_unix_timestamp(start.timestamp())?;
+            let event = CreateScheduledEvent::new(ScheduledEventType::StageInstance, topic, start)
+                .channel_id(stage);
+            guild_id.create_scheduled_event(http, event).await?;
+        }
+        None => {
+            stage
+                .create_stage_instance(http, CreateStageInstance::new(topic))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Rename the thread, forum post or stage of an LP to a new album name. Threads of LPs
+// started in the user's own thread are left as they are.
+async fn rename_venue(http: &Http, msg: &Message, name: &str) -> anyhow::Result<()> {
+    let Some(channel) = msg.channel(http).await?.guild() else {
+        return Ok(());
+    };
+    match channel.kind {
+        ChannelType::Stage => {
+            // nothing to rename if the stage isn't live
+            let edit = EditStageInstance::new().topic(name);
+            if let Err(e) = channel.id.edit_stage_instance(http, edit).await {
+                tracing::warn!("could not rename stage {}: {e}", channel.id);
+            }
+        }
+        // forum posts have the same id as their first message
+        ChannelType::PublicThread if channel.id.get() == msg.id.get() => {
+            channel
+                .id
+                .edit_thread(http, EditThread::new().name(name))
+                .await?;
+        }
+        // as do threads created from a message
+        _ if msg.thread.is_some() => {
+            ChannelId::new(msg.id.get())
+                .edit_thread(http, EditThread::new().name(name))
+                .await?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// A blind LP message, to be edited to show the album when the LP starts
+struct PendingReveal {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    // thread created or renamed for the LP
+    thread_id: Option<ChannelId>,
+    // text before the LP contents, e.g. a mention of the user who started it
+    prefix: String,
+    // whether the message was sent through the guild's webhook
+    webhook: bool,
+}
+
+impl PendingReveal {
+    fn schedule(&self, db: &Db, at: DateTime<Utc>) -> anyhow::Result<()> {
+        db.conn.execute(
+            "INSERT INTO lp_reveal (
+                guild_id, channel_id, message_id, thread_id, prefix, webhook, reveal_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                SqlGuildId(self.guild_id),
+                SqlChannelId(self.channel_id),
+                SqlMessageId(self.message_id),
+                self.thread_id.map(SqlChannelId),
+                self.prefix,
+                self.webhook,
+                at.timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn due(db: &Db, now: i64) -> anyhow::Result<Vec<Self>> {
+        let mut stmt = db.conn.prepare(
+            "SELECT guild_id, channel_id, message_id, thread_id, prefix, webhook
+             FROM lp_reveal WHERE reveal_at <= ?1",
+        )?;
+        let res = stmt
+            .query([now])?
+            .map(|row| {
+                Ok(PendingReveal {
+                    guild_id: row.get::<_, SqlGuildId>(0)?.0,
+                    channel_id: row.get::<_, SqlChannelId>(1)?.0,
+                    message_id: row.get::<_, SqlMessageId>(2)?.0,
+                    thread_id: row.get::<_, Option<SqlChannelId>>(3)?.map(|c| c.0),
+                    prefix: row.get(4)?,
+                    webhook: row.get(5)?,
+                })
+            })
+            .collect()?;
+        Ok(res)
+    }
+
+    async fn reveal(&self, handler: &Handler, http: &Http) -> anyhow::Result<()> {
+        let msg = self.channel_id.message(http, self.message_id).await?;
+        if msg.content.starts_with("~~") {
+            // canceled with /edit_lp
+            return Ok(());
+        }
+        let mut lp = ResolvedLp::for_message(handler, &msg).await?;
+        lp.params.blind = None;
+        lp.params.time = None;
+        // the album was already looked up when the LP was created
+        if let Some(link) = lp.resolved_link.take() {
+            lp.params.link = Some(link);
+        }
+        let LpContents {
+            text,
+            role_id,
+            info,
+            data,
+        } = lp
+            .params
+            .build_contents(handler, self.guild_id, lp.resolved_start)
+            .await?;
+        let contents = format!("{}{text}", self.prefix);
+        let policy = handler.mention_policy(Some(self.guild_id), "lp").await;
+        let mentions = policy.allowed_mentions(role_id.map(RoleId::new), []);
+        if self.webhook {
+            let url: String = handler
+                .get_guild_field::<Option<String>>(self.guild_id, "webhook")
+                .await?
+                .ok_or_else(|| anyhow!("the LP webhook was removed"))?;
+            let wh = http.get_webhook_from_url(&url).await?;
+            let edit = EditWebhookMessage::new()
+                .content(contents)
+                .allowed_mentions(mentions);
+            wh.edit_message(http, self.message_id, edit).await?;
+        } else {
+            let edit = EditMessage::new()
+                .content(contents)
+                .allowed_mentions(mentions);
+            self.channel_id
+                .edit_message(http, self.message_id, edit)
                 .await?;
-        Ok((resp_content, role_id, info))
+        }
+        let name = info.name.as_deref().unwrap_or(DEFAULT_LP_NAME);
+        if let Some(thread_id) = self.thread_id {
+            thread_id
+                .edit_thread(http, EditThread::new().name(name))
+                .await?;
+        } else {
+            // LPs in a stage have the mystery album as their topic
+            rename_venue(http, &msg, name).await?;
+        }
+        let message_id = self.message_id;
+        handler
+            .db_call(move |db| save_lp_data(db, message_id, &data))
+            .await?;
+        show_lp_presence(handler, self.guild_id, &info, handler.clock.now(), false);
+        if let Some(start) = lp.resolved_start {
+            lp_tracks::start_track_timer(
+                handler,
+                self.guild_id,
+                self.message_id,
+                self.thread_id,
+                start,
+                &info,
+            )
+            .await;
+        }
+        Ok(())
+    }
+}
+
+// Show the album of blind LPs once they start
+fn reveal_blind_lps<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = handler.clock.now().timestamp();
+        let due = handler
+            .db_read(move |db| PendingReveal::due(db, now))
+            .await?;
+        for reveal in due {
+            if let Err(e) = reveal.reveal(handler, http).await {
+                tracing::error!("Error revealing blind LP {}: {e:?}", reveal.message_id);
+            }
+            let message_id = SqlMessageId(reveal.message_id);
+            let res = handler
+                .db_call(move |db| {
+                    db.conn
+                        .execute("DELETE FROM lp_reveal WHERE message_id = ?1", [message_id])?;
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = res {
+                tracing::error!("Error removing blind LP {}: {e:?}", reveal.message_id);
+            }
+        }
+        Ok(())
     }
+    .boxed()
+}
+
+// Start an LP right away in a channel on behalf of a user, for LPs that are not created
+// with /lp (e.g. from a button). Webhooks and threads are not used.
+pub async fn post_lp(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    album: &str,
+) -> anyhow::Result<Message> {
+    let lp = Lp {
+        album: album.to_string(),
+        link: None,
+        time: None,
+        provider: None,
+        role: None,
+        blind: None,
+    };
+    send_lp(handler, http, guild_id, channel_id, user_id, lp).await
+}
+
+// Post an LP queued with /lp_schedule once its time has come
+pub(crate) async fn post_scheduled_lp(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    album: &str,
+    link: Option<&str>,
+) -> anyhow::Result<Message> {
+    let lp = Lp {
+        album: album.to_string(),
+        link: link.map(str::to_string),
+        time: None,
+        provider: None,
+        role: None,
+        blind: None,
+    };
+    send_lp(handler, http, guild_id, channel_id, user_id, lp).await
+}
+
+async fn send_lp(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    lp: Lp,
+) -> anyhow::Result<Message> {
+    let time = lp.time.clone();
+    let LpContents {
+        text,
+        role_id,
+        info,
+        data,
+    } = lp.build_contents(handler, guild_id, None).await?;
+    let policy = handler.mention_policy(Some(guild_id), "lp").await;
+    let message = channel_id
+        .respond(
+            http,
+            CommandResponse::Public(format!("<@{user_id}>: {text}").into()),
+            role_id,
+            policy,
+        )
+        .await?
+        .unwrap(); // public responses always create a message
+    let now = handler.clock.now().timestamp();
+    let (channel_id, message_id) = (message.channel_id, message.id);
+    let album = info.clone();
+    handler
+        .db_call(move |db| {
+            record_lp(db, guild_id, user_id, &album, now)?;
+            lp_stats::record_lp_message(
+                db, guild_id, user_id, channel_id, message_id, None, &album,
+            )?;
+            save_lp_data(db, message_id, &data)
+        })
+        .await?;
+    if let (_, Some(start)) =
+        convert_lp_time(time.as_deref(), info.duration, None, handler.clock.now())?
+    {
+        show_lp_presence(handler, guild_id, &info, start, false);
+    }
+    Ok(message)
 }
 
 #[async_trait]
@@ -287,66 +901,169 @@ impl BotCommand for Lp {
     ) -> anyhow::Result<CommandResponse> {
         if let (Some(_), Some(member)) = (self.role, &command.member) {
             if !member.permissions.unwrap_or_default().mention_everyone() {
-                bail!("Only admins are allowed to specify a role to ping.");
+                return Err(CommandError::PermissionDenied(
+                    "Only admins are allowed to specify a role to ping.".into(),
+                )
+                .into());
             }
         }
         let http = &ctx.http;
-        let (resp_content, role_id, info) = self.build_contents(handler, command, None).await?;
-        let guild_id = command.guild_id()?.get();
-        let webhook: Option<String> = handler.get_guild_field(guild_id, "webhook").await?;
+        let guild_id = command.guild_id()?;
+        let time = self.time.clone();
+        let blind = self.blind == Some(true);
+        let LpContents {
+            text: resp_content,
+            role_id,
+            info,
+            data,
+        } = self.build_contents(handler, guild_id, None).await?;
+        let policy = handler.mention_policy(Some(guild_id), "lp").await;
+        let venue = Venue::for_command(handler, command).await?;
+        let create_threads: bool = handler.get_guild_field(guild_id, "create_threads").await?;
+        // LPs started in someone else's thread are posted in the parent channel so they can
+        // get a thread of their own, threads started by the user are renamed instead
+        let parent_channel = match command.thread() {
+            Some(thread) if thread.locked => bail!("This thread is locked"),
+            Some(thread) if create_threads => {
+                let owner = thread
+                    .id
+                    .to_channel(http)
+                    .await?
+                    .guild()
+                    .and_then(|c| c.owner_id);
+                (owner != Some(command.user.id)).then_some(thread.parent_id)
+            }
+            _ => None,
+        };
+        let thread_name = match blind {
+            true => MYSTERY_ALBUM,
+            false => info.name.as_deref().unwrap_or(DEFAULT_LP_NAME),
+        };
+        // forum posts are created by the bot with the LP message, not through the webhook
+        let webhook: Option<String> = match venue {
+            Venue::Forum(_) => None,
+            _ => handler.get_guild_field(guild_id, "webhook").await?,
+        };
         let wh = match webhook.as_deref().map(|url| http.get_webhook_from_url(url)) {
             Some(fut) => Some(fut.await?),
             None => None,
         };
-        let message = if let Some(wh) = &wh {
-            // Send LP message through webhook
-            // This lets us impersonate the user who sent the command
+        // whether the message starts with a mention of the user, see PendingReveal
+        let mut prefixed = true;
+        let message = if let Venue::Forum(forum) = venue {
+            let resp = format!("<@{}>: {resp_content}", command.user.id);
+            let mentions = policy.allowed_mentions(role_id.map(RoleId::new), []);
+            let post = CreateForumPost::new(
+                thread_name,
+                CreateMessage::new()
+                    .content(resp)
+                    .allowed_mentions(mentions),
+            );
+            let post = forum.create_forum_post(http, post).await?;
+            post.id.message(http, MessageId::new(post.id.get())).await?
+        } else if let Some(wh) = &wh {
             let user = &command.user;
-            let avatar_url = GuildId::new(guild_id)
-                .member(http, user)
-                .await?
-                .avatar_url()
-                .or_else(|| user.avatar_url());
-            let nick = user // try to get the user's nickname
-                .nick_in(http, guild_id)
-                .await
-                .map(Cow::Owned)
-                .unwrap_or_else(|| Cow::Borrowed(&user.name));
-            wh.execute(http, true, {
-                let mut webhook = ExecuteWebhook::new()
-                    .content(&resp_content)
-                    .allowed_mentions(CreateAllowedMentions::new().roles(role_id))
-                    .username(nick.as_str());
+            let impersonate: bool = handler
+                .get_guild_field(guild_id, "webhook_impersonation")
+                .await?;
+            prefixed = !impersonate;
+            let mut webhook = policy.execute_webhook(role_id.map(RoleId::new), []);
+            if impersonate {
+                // Send LP message through webhook
+                // This lets us impersonate the user who sent the command
+                let avatar_url = guild_id
+                    .member(http, user)
+                    .await?
+                    .avatar_url()
+                    .or_else(|| user.avatar_url());
+                let nick = user // try to get the user's nickname
+                    .nick_in(http, guild_id)
+                    .await
+                    .map(Cow::Owned)
+                    .unwrap_or_else(|| Cow::Borrowed(&user.name));
+                let bot_name = ctx.cache.current_user().name.clone();
+                let username = webhook_username(&nick, &user.name, &bot_name);
+                webhook = webhook.content(&resp_content).username(username);
                 if let Some(url) = avatar_url.as_ref() {
                     webhook = webhook.avatar_url(url);
                 }
-                webhook
-            })
-            .await?
-            .unwrap() // Message is present because we set wait to true in execute
+            } else {
+                // keep the webhook's own name and avatar, and mention the pinger instead
+                webhook = webhook.content(format!("<@{}>: {resp_content}", user.id));
+            }
+            let message = wh.execute(http, true, webhook).await?.unwrap(); // Message is present because we set wait to true in execute
+            let now = handler.clock.now().timestamp();
+            let (user_id, sent) = (user.id, message.clone());
+            handler
+                .db_call(move |db| log_webhook_send(db, guild_id, user_id, &sent, now))
+                .await?;
+            message
+        } else if let Some(parent) = parent_channel {
+            let resp = format!("<@{}>: {resp_content}", command.user.id);
+            parent
+                .respond(http, CommandResponse::Public(resp.into()), role_id, policy)
+                .await?
+                .unwrap()
         } else {
             // prefix response with pinger mention
             let resp = format!("<@{}>: {resp_content}", command.user.id.get());
             // Create interaction response
             command
-                .respond(&ctx.http, CommandResponse::Public(resp.into()), role_id)
+                .respond(
+                    &ctx.http,
+                    CommandResponse::Public(resp.into()),
+                    role_id,
+                    policy,
+                )
                 .await?
                 .unwrap()
         };
+        let posted_in = message.channel_id;
         let mut response = format!(
             "LP created: {}",
             message.id.link(message.channel_id, command.guild_id)
         );
-        if handler.get_guild_field(guild_id, "create_threads").await? {
+        let now = handler.clock.now().timestamp();
+        let (user_id, album) = (command.user.id, info.clone());
+        handler
+            .db_call(move |db| record_lp(db, guild_id, user_id, &album, now))
+            .await?;
+        let (_, start) =
+            convert_lp_time(time.as_deref(), info.duration, None, handler.clock.now())?;
+        if let Some(start) = start {
+            show_lp_presence(handler, guild_id, &info, start, blind);
+        }
+        let message_id = message.id;
+        let mut thread_id = None;
+        if let Venue::Forum(_) = venue {
+            response = format!("LP created: <#{posted_in}>");
+            thread_id = Some(posted_in);
+        } else if let Venue::Stage(stage) = venue {
+            // the LP is still posted if the bot can't manage the stage
+            if let Err(e) = open_stage(
+                http,
+                guild_id,
+                stage,
+                thread_name,
+                start,
+                handler.clock.now(),
+            )
+            .await
+            {
+                tracing::warn!("could not open stage for LP: {e:?}");
+            }
+        } else if create_threads {
             // Create a thread from the response message for the LP to take place in
             let chan = message.channel(http).await?;
-            let thread_name = info.name.as_deref().unwrap_or("Listening party");
             let mut guild_chan = chan.guild().map(|c| (c.kind, c));
-            if let (None, Some((ChannelType::PublicThread, c))) = (&webhook, &mut guild_chan) {
-                // If we're already in a thread, just rename it
+            if let (None, Some((ChannelType::PublicThread | ChannelType::PrivateThread, c))) =
+                (&webhook, &mut guild_chan)
+            {
+                // If we're already in the user's own thread, just rename it
                 // unless we are using a webhook, in which case we can create a new thread
                 c.edit_thread(http, EditThread::new().name(thread_name))
                     .await?;
+                thread_id = Some(c.id);
             } else if let Some((ChannelType::Text, c)) = &guild_chan {
                 // Create thread from response message
                 let thread = c
@@ -359,28 +1076,68 @@ impl BotCommand for Lp {
                     )
                     .await?;
                 response = format!("LP created: <#{}>", thread.id.get());
+                thread_id = Some(thread.id);
+                if let Some(role_id) = role_id {
+                    if handler.get_guild_field(guild_id, "thread_invite").await? {
+                        let muted = handler
+                            .db_read(move |db| db.muted_users(guild_id, "lp"))
+                            .await?;
+                        let http = ctx.http.clone();
+                        tokio::spawn(async move {
+                            let role = RoleId::new(role_id);
+                            if let Err(e) =
+                                invite_role_members(&http, guild_id, thread.id, role, muted).await
+                            {
+                                tracing::error!("error inviting LP role members to thread: {e:?}");
+                            }
+                        });
+                    }
+                }
             }
         }
-        if let Some(wh) = wh {
-            // If we used a webhook, we still need to create the interaction response
-            let response = if wh.channel_id == Some(command.channel_id) {
+        let album = info.clone();
+        handler
+            .db_call(move |db| {
+                lp_stats::record_lp_message(
+                    db, guild_id, user_id, posted_in, message_id, thread_id, &album,
+                )?;
+                save_lp_data(db, message_id, &data)
+            })
+            .await?;
+        // blind LPs start their timer once revealed
+        if let (false, Some(start)) = (blind, start) {
+            lp_tracks::start_track_timer(handler, guild_id, message_id, thread_id, start, &info)
+                .await;
+        }
+        if let (true, Some(start)) = (blind, start) {
+            let reveal = PendingReveal {
+                guild_id,
+                channel_id: posted_in,
+                message_id,
+                thread_id,
+                // impersonating webhook messages are not prefixed with a mention
+                prefix: match prefixed {
+                    true => format!("<@{}>: ", command.user.id),
+                    false => String::new(),
+                },
+                webhook: wh.is_some(),
+            };
+            handler
+                .db_call(move |db| reveal.schedule(db, start))
+                .await?;
+        }
+        let forum = matches!(venue, Venue::Forum(_));
+        if wh.is_some() || parent_channel.is_some() || forum {
+            // If the LP was not posted as the interaction response, we still need to create it
+            let response = if posted_in == command.channel_id {
                 CommandResponse::Private(response.into())
             } else {
                 CommandResponse::Public(response.into())
             };
-            command.respond(&ctx.http, response, None).await?;
+            command.respond(&ctx.http, response, None, policy).await?;
         }
         Ok(CommandResponse::None)
     }
-
-    fn setup_options(opt_name: &str, opt: CreateCommandOption) -> CreateCommandOption {
-        if opt_name == "provider" {
-            opt.add_string_choice("spotify", "spotify")
-                .add_string_choice("bandcamp", "bandcamp")
-        } else {
-            opt
-        }
-    }
 }
 
 #[derive(Command)]
@@ -402,9 +1159,15 @@ impl BotCommand for SetCreateThreads {
         _ctx: &Context,
         command: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = command.guild_id()?.get();
-        let mut db = handler.db.lock().await;
-        db.set_guild_field(guild_id, "create_threads", self.create_threads)
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(
+                guild_id,
+                command.user.id,
+                "create_threads",
+                self.create_threads,
+            )
+            .await
             .context("updating 'create_threads' guild field")?;
         let resp = if self.create_threads {
             "Will create threads when setting up listening parties"
@@ -415,6 +1178,121 @@ impl BotCommand for SetCreateThreads {
     }
 }
 
+// Add members holding the LP role to the thread so it shows up in their sidebar, except
+// those who turned LP notifications off
+async fn invite_role_members(
+    http: &Http,
+    guild_id: GuildId,
+    thread_id: ChannelId,
+    role_id: RoleId,
+    muted: HashSet<UserId>,
+) -> anyhow::Result<()> {
+    let mut members = Vec::new();
+    let mut after = None;
+    loop {
+        let page = guild_id.members(http, Some(1000), after).await?;
+        after = page.last().map(|m| m.user.id);
+        members.extend(
+            page.iter()
+                .filter(|m| !m.user.bot && m.roles.contains(&role_id))
+                .filter(|m| !muted.contains(&m.user.id))
+                .map(|m| m.user.id),
+        );
+        if page.len() < 1000 || members.len() >= MAX_THREAD_INVITES {
+            break;
+        }
+    }
+    for user_id in members.into_iter().take(MAX_THREAD_INVITES) {
+        if let Err(e) = thread_id.add_thread_member(http, user_id).await {
+            tracing::warn!("could not add {user_id} to thread: {e}");
+        }
+        tokio::time::sleep(THREAD_INVITE_DELAY).await;
+    }
+    Ok(())
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setthreadinvite",
+    desc = "set whether to add LP role members to listening party threads"
+)]
+pub struct SetThreadInvite {
+    thread_invite: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetThreadInvite {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_THREADS;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(
+                guild_id,
+                command.user.id,
+                "thread_invite",
+                self.thread_invite,
+            )
+            .await
+            .context("updating 'thread_invite' guild field")?;
+        let resp = if self.thread_invite {
+            "Will add LP role members to listening party threads"
+        } else {
+            "Will not add LP role members to listening party threads"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setlpforum",
+    desc = "set whether to create listening parties as posts in this forum",
+    guild_only
+)]
+pub struct SetLpForum {
+    enabled: bool,
+}
+
+#[async_trait]
+impl GuildCommand for SetLpForum {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let forum = if self.enabled {
+            // commands can't be run in the forum itself, only in its posts
+            let parent = command.parent_channel_id();
+            let kind = parent.to_channel(&ctx.http).await?.guild().map(|c| c.kind);
+            if kind != Some(ChannelType::Forum) {
+                bail!("Run this command in a post of the forum to use");
+            }
+            Some(SqlChannelId(parent))
+        } else {
+            None
+        };
+        handler
+            .set_guild_field(guild_id, command.user.id, "lp_forum", forum)
+            .await
+            .context("updating 'lp_forum' guild field")?;
+        let resp = match forum {
+            Some(SqlChannelId(forum)) => format!("Will create listening parties in <#{forum}>"),
+            None => "Will not create listening parties in a forum".to_string(),
+        };
+        CommandResponse::private(resp)
+    }
+}
+
 #[derive(Command)]
 #[cmd(name = "setrole", desc = "set the role to ping for listening parties")]
 pub struct SetRole {
@@ -431,10 +1309,11 @@ impl BotCommand for SetRole {
         _ctx: &Context,
         command: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = command.guild_id()?.get();
+        let guild_id = command.guild_id()?;
         let role = self.role.as_ref().map(|r| r.get().to_string());
-        let mut db = handler.db.lock().await;
-        db.set_guild_field(guild_id, "role_id", &role)
+        handler
+            .set_guild_field(guild_id, command.user.id, "role_id", &role)
+            .await
             .context("updating 'role_id' guild field")?;
         let resp = if let Some(role_id) = role {
             format!("Set listening party role to <@&{role_id}>.")
@@ -464,9 +1343,10 @@ impl BotCommand for SetWebhook {
         _ctx: &Context,
         command: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = command.guild_id()?.get();
-        let mut db = handler.db.lock().await;
-        db.set_guild_field(guild_id, "webhook", self.webhook.as_ref())
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(guild_id, command.user.id, "webhook", self.webhook.as_ref())
+            .await
             .context("updating 'webhook' guild field")?;
         let resp = if self.webhook.is_some() {
             "Listening parties will be created using a webhook."
@@ -477,6 +1357,112 @@ impl BotCommand for SetWebhook {
     }
 }
 
+#[derive(Command)]
+#[cmd(
+    name = "setwebhookimpersonation",
+    desc = "set whether LP webhooks use the name and avatar of the member who ran /lp"
+)]
+pub struct SetWebhookImpersonation {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetWebhookImpersonation {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_WEBHOOKS;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(
+                guild_id,
+                command.user.id,
+                "webhook_impersonation",
+                self.enabled,
+            )
+            .await
+            .context("updating 'webhook_impersonation' guild field")?;
+        let resp = if self.enabled {
+            "Listening party webhooks will use the name and avatar of the member who ran /lp."
+        } else {
+            "Listening party webhooks will keep their own name and avatar."
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "webhook_log",
+    desc = "Show recent listening parties sent through the webhook"
+)]
+pub struct WebhookLog {
+    #[cmd(desc = "Only show LPs created by this user")]
+    user: Option<UserId>,
+}
+
+#[async_trait]
+impl BotCommand for WebhookLog {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_WEBHOOKS;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let entries: Vec<(SqlUserId, String, SqlChannelId, SqlMessageId, i64)> = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .prepare(
+                "SELECT user_id, username, channel_id, message_id, ts FROM lp_webhook_log
+                 WHERE guild_id = ?1 AND (?2 IS NULL OR user_id = ?2)
+                 ORDER BY ts DESC LIMIT ?3",
+            )?
+            .query(params![
+                SqlGuildId(guild_id),
+                self.user.map(SqlUserId),
+                WEBHOOK_LOG_SIZE
+            ])?
+            .map(|row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .collect()?;
+        if entries.is_empty() {
+            return CommandResponse::private("No listening parties sent through the webhook");
+        }
+        let lines = entries
+            .into_iter()
+            .map(|(user_id, username, channel_id, message_id, ts)| {
+                format!(
+                    "{} <@{}> as **{username}**: {}",
+                    Timestamp(ts, TimestampStyle::ShortDateTime),
+                    user_id.0,
+                    message_id.0.link(channel_id.0, Some(guild_id))
+                )
+            })
+            .join("\n");
+        CommandResponse::private(
+            CreateEmbed::new()
+                .title("LP webhook log")
+                .description(lines),
+        )
+    }
+}
+
 #[derive(Command)]
 #[cmd(name = "edit_lp", desc = "Edit the last LP you created")]
 pub struct EditLp {
@@ -487,22 +1473,74 @@ pub struct EditLp {
 }
 
 impl EditLp {
-    async fn edit_from_embedded_data(
+    // The user's last saved LP in this channel or in its thread
+    async fn saved_lp_message(
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<Option<Message>> {
+        let (channel_id, user_id) = (command.channel_id, command.user.id);
+        let saved = handler
+            .db_read(move |db| {
+                let lp = db
+                    .conn
+                    .query_row(
+                        "SELECT channel_id, message_id FROM lp_message
+                         WHERE user_id = ?1 AND (channel_id = ?2 OR thread_id = ?2)
+                         ORDER BY ts DESC LIMIT 1",
+                        params![SqlUserId(user_id), SqlChannelId(channel_id)],
+                        |row| {
+                            let channel: SqlChannelId = row.get(0)?;
+                            let message: SqlMessageId = row.get(1)?;
+                            Ok((channel.0, message.0))
+                        },
+                    )
+                    .optional()?;
+                Ok(lp)
+            })
+            .await?;
+        let Some((channel_id, message_id)) = saved else {
+            return Ok(None);
+        };
+        let msg = channel_id.message(&ctx.http, message_id).await?;
+        // messages sent through the webhook can't be edited by the bot
+        Ok(Some(msg).filter(|msg| handler.self_id.get() == Some(&msg.author.id)))
+    }
+
+    async fn recent_lp_message(
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<Message> {
+        let messages = command
+            .channel_id
+            .messages(&ctx.http, GetMessages::new().limit(100))
+            .await
+            .context("couldn't retrieve messages")?;
+        let self_id = *handler.self_id.get().unwrap();
+        let author_id = command.user.id.get();
+        let author_id_str = author_id.to_string();
+        messages
+            .into_iter()
+            .filter(|msg| msg.author.id == self_id)
+            .find(|msg| {
+                if let Some(interation) = &msg.interaction {
+                    interation.user.id == author_id && interation.name == "lp"
+                } else {
+                    msg.content.contains(&author_id_str)
+                }
+            })
+            .ok_or_else(|| anyhow!("No recent listening party to edit."))
+    }
+
+    async fn edit_from_saved_data(
         &self,
         handler: &Handler,
         ctx: &Context,
         msg: &mut Message,
         command: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let Some(pos) = msg.content.find(LP_URI) else {
-            bail!("no embedded data");
-        };
-        let url: Url = msg.content[pos..]
-            .trim_end_matches(')')
-            .parse()
-            .context("invalid embedded URL")?;
-        let mut lp: ResolvedLp = serde_urlencoded::de::from_str(url.query().unwrap_or_default())
-            .context("failed to deserialize embedded data")?;
+        let mut lp = ResolvedLp::for_message(handler, msg).await?;
         let mut changed = false;
         if let Some(album) = &self.album {
             lp.params.album = album.clone();
@@ -519,27 +1557,66 @@ impl EditLp {
         if !changed {
             bail!("Nothing to change");
         }
-        let (contents, role_id, info) = lp
+        let blind = lp.params.blind == Some(true);
+        let LpContents {
+            text: contents,
+            role_id,
+            info,
+            data,
+        } = lp
             .params
-            .build_contents(handler, command, lp.resolved_start)
+            .build_contents(handler, command.guild_id()?, lp.resolved_start)
             .await?;
+        let policy = handler
+            .mention_policy(command.guild_id, &command.data.name)
+            .await;
         // prefix response with pinger mention
         let contents = format!("<@{}>: {contents}", command.user.id.get());
         msg.edit(
             &ctx.http,
             EditMessage::new()
                 .content(contents)
-                .allowed_mentions(CreateAllowedMentions::new().roles(role_id)),
+                .allowed_mentions(policy.allowed_mentions(role_id.map(RoleId::new), [])),
         )
         .await?;
+        let (message_id, saved) = (msg.id, data.clone());
+        handler
+            .db_call(move |db| save_lp_data(db, message_id, &saved))
+            .await?;
+        // the album or start time changed, restart the timer with the new tracklist
+        if let (false, Some(start)) = (blind, ResolvedLp::from_data(&data)?.resolved_start) {
+            let guild_id = command.guild_id()?;
+            lp_tracks::start_track_timer(handler, guild_id, msg.id, None, start, &info).await;
+        }
         // build response to indicate what was updated
         let mut resp = String::new();
-        if self.album.is_some() {
+        if self.album.is_some() && blind {
+            _ = writeln!(&mut resp, "Updated the mystery album");
+        } else if self.album.is_some() {
             _ = writeln!(&mut resp, "Updated album to {}", info.as_link(None));
+            let name = info.name.as_deref().unwrap_or(DEFAULT_LP_NAME);
+            rename_venue(&ctx.http, msg, name).await?;
         }
         if self.time.is_some() {
-            let (when, _) = convert_lp_time(self.time.as_deref(), info.duration, None)?;
+            let (when, start) = convert_lp_time(
+                self.time.as_deref(),
+                info.duration,
+                None,
+                handler.clock.now(),
+            )?;
             _ = writeln!(&mut resp, "Listening party will start {when}");
+            if let (true, Some(start)) = (blind, start) {
+                let message_id = msg.id;
+                handler
+                    .db_call(move |db| {
+                        db.conn.execute(
+                            "UPDATE lp_reveal SET reveal_at = ?2 WHERE message_id = ?1",
+                            params![SqlMessageId(message_id), start.timestamp()],
+                        )?;
+                        Ok(())
+                    })
+                    .await?;
+            }
         }
         CommandResponse::public(resp)
     }
@@ -554,39 +1631,36 @@ impl BotCommand for EditLp {
         ctx: &Context,
         command: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let messages = command
-            .channel_id
-            .messages(&ctx.http, GetMessages::new().limit(100))
-            .await
-            .context("couldn't retrieve messages")?;
-        let self_id = *handler.self_id.get().unwrap();
-        let author_id = command.user.id.get();
-        let author_id_str = author_id.to_string();
-        let mut msg = messages
-            .into_iter()
-            .filter(|msg| msg.author.id == self_id)
-            .find(|msg| {
-                if let Some(interation) = &msg.interaction {
-                    interation.user.id == author_id && interation.name == "lp"
-                } else {
-                    msg.content.contains(&author_id_str)
-                }
-            })
-            .ok_or_else(|| anyhow!("No recent listening party to edit."))?;
+        let mut msg = match Self::saved_lp_message(handler, ctx, command).await? {
+            Some(msg) => msg,
+            // LPs posted before they were saved
+            None => Self::recent_lp_message(handler, ctx, command).await?,
+        };
         if self.cancel == Some(true) {
             msg.edit(
                 &ctx.http,
                 EditMessage::new().content(format!("~~{}~~", &msg.content)),
             )
             .await?;
+            let message_id = msg.id;
+            handler
+                .db_call(move |db| {
+                    db.conn.execute(
+                        "DELETE FROM lp_reveal WHERE message_id = ?1",
+                        [SqlMessageId(message_id)],
+                    )?;
+                    Ok(())
+                })
+                .await?;
+            lp_tracks::stop_track_timer(handler, msg.id)?;
             return CommandResponse::public("Canceled listening party");
         }
         match self
-            .edit_from_embedded_data(handler, ctx, &mut msg, command)
+            .edit_from_saved_data(handler, ctx, &mut msg, command)
             .await
         {
             Ok(resp) => return Ok(resp),
-            Err(e) => eprintln!("Could not edit LP from embedded data: {e:?}"),
+            Err(e) => tracing::warn!("Could not edit LP from saved data: {e:?}"),
         }
         let mut new_content = Cow::<'_, str>::Borrowed(&msg.content);
         let mut resp = String::new();
@@ -603,7 +1677,7 @@ impl BotCommand for EditLp {
             _ = writeln!(&mut resp, "Listening party album updated to {hyperlinked}");
         }
         if let Some(time) = self.time.as_ref() {
-            let (formatted, _) = convert_lp_time(Some(time), None, None)?;
+            let (formatted, _) = convert_lp_time(Some(time), None, None, handler.clock.now())?;
             let re = Regex::new(r"(now|at <t:\d+:t>) \(.*\)").unwrap();
             new_content = Cow::Owned(re.replace(&new_content, &formatted).to_string());
             _ = writeln!(&mut resp, "Listening party will start {formatted}");
@@ -617,7 +1691,10 @@ impl BotCommand for EditLp {
     }
 }
 
-pub struct ModLp;
+#[derive(Default)]
+pub struct ModLp {
+    pub(crate) track_timers: lp_tracks::TrackTimers,
+}
 
 impl ModLp {
     async fn autocomplete_lp(
@@ -661,7 +1738,7 @@ impl ModLp {
         ac: &'a CommandInteraction,
     ) -> BoxFuture<'a, anyhow::Result<bool>> {
         async move {
-            let ("lp" | "edit_lp", CommandType::ChatInput) = key else {
+            let ("lp" | "edit_lp" | "lp_schedule", CommandType::ChatInput) = key else {
                 return Ok(false);
             };
             let choices = Self::autocomplete_lp(handler, &ac.data.options).await?;
@@ -694,22 +1771,199 @@ impl Module for ModLp {
     }
 
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
-        Ok(ModLp)
+        Ok(ModLp::default())
     }
 
     async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
         db.add_guild_field("create_threads", "BOOLEAN NOT NULL DEFAULT(false)")?;
         db.add_guild_field("webhook", "STRING")?;
         db.add_guild_field("role_id", "STRING")?;
+        db.add_guild_field("thread_invite", "BOOLEAN NOT NULL DEFAULT(false)")?;
+        db.add_guild_field("webhook_impersonation", "BOOLEAN NOT NULL DEFAULT(true)")?;
+        db.add_guild_field("lp_forum", "INTEGER")?;
+        db.add_guild_field("lp_track_timer", "BOOLEAN NOT NULL DEFAULT(false)")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_history (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                name STRING NOT NULL
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_reveal (
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                message_id INTEGER NOT NULL,
+                thread_id INTEGER,
+                prefix STRING NOT NULL,
+                webhook BOOLEAN NOT NULL,
+                reveal_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_webhook_log (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                username STRING NOT NULL,
+                channel_id INTEGER NOT NULL,
+                message_id INTEGER NOT NULL,
+                ts INTEGER NOT NULL
+            )",
+            [],
+        )?;
         Ok(())
     }
 
+    const NAME: &'static str = "ModLp";
+    const MIGRATIONS: &'static [Migration] = &[
+        Migration::sql(
+            1,
+            "create lp_queue",
+            "CREATE TABLE IF NOT EXISTS lp_queue (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                album STRING NOT NULL,
+                link STRING,
+                start_at INTEGER NOT NULL,
+                remind_at INTEGER,
+                ts INTEGER NOT NULL
+            )",
+        ),
+        Migration::sql(
+            2,
+            "create lp_message and lp_attendance",
+            "CREATE TABLE IF NOT EXISTS lp_message (
+                message_id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                thread_id INTEGER,
+                user_id INTEGER NOT NULL,
+                name STRING,
+                artist STRING,
+                ts INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS lp_message_thread ON lp_message (thread_id);
+            CREATE TABLE IF NOT EXISTS lp_attendance (
+                message_id INTEGER NOT NULL,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                UNIQUE(message_id, user_id)
+            );",
+        ),
+        Migration::sql(
+            3,
+            "add data to lp_message",
+            "ALTER TABLE lp_message ADD COLUMN data STRING",
+        ),
+    ];
+
     fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
         store.register::<Lp>();
         store.register::<SetRole>();
         store.register::<SetCreateThreads>();
+        store.register::<SetThreadInvite>();
+        store.register::<SetLpForum>();
         store.register::<SetWebhook>();
+        store.register::<SetWebhookImpersonation>();
+        store.register::<WebhookLog>();
         store.register::<EditLp>();
+        lp_queue::register_commands(store);
+        lp_stats::register_commands(store);
+        lp_tracks::register_commands(store);
         completions.push(ModLp::complete_lp);
     }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "lp_reveal",
+            Schedule::every(std::time::Duration::from_secs(15)),
+            reveal_blind_lps,
+        ));
+        lp_queue::register_jobs(jobs);
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        lp_stats::register_gateway_handlers(handlers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved_lp() -> ResolvedLp {
+        ResolvedLp {
+            version: LP_DATA_VERSION,
+            resolved_title: Some("Artist - Album".to_string()),
+            resolved_link: Some("https://artist.bandcamp.com/album/album".to_string()),
+            resolved_start: Some("2024-05-01T20:30:00Z".parse().unwrap()),
+            params: Lp {
+                album: "artist album & more".to_string(),
+                link: None,
+                time: Some("+5".to_string()),
+                provider: Some(Provider::Bandcamp),
+                role: Some(RoleId::new(1234)),
+                blind: Some(true),
+            },
+        }
+    }
+
+    #[test]
+    fn round_trip_current_version() {
+        let encoded = serde_urlencoded::ser::to_string(resolved_lp()).unwrap();
+        let mut url = Url::parse(LP_URI).unwrap();
+        url.set_query(Some(&encoded));
+        let message = format!("LP of Artist - Album [̣]({url})");
+
+        for decoded in [
+            ResolvedLp::from_data(&encoded).unwrap(),
+            ResolvedLp::from_message(&message).unwrap(),
+        ] {
+            let expected = resolved_lp();
+            assert_eq!(decoded.version, LP_DATA_VERSION);
+            assert_eq!(decoded.resolved_title, expected.resolved_title);
+            assert_eq!(decoded.resolved_link, expected.resolved_link);
+            assert_eq!(decoded.resolved_start, expected.resolved_start);
+            assert_eq!(decoded.params.album, expected.params.album);
+            assert_eq!(decoded.params.link, None);
+            assert_eq!(decoded.params.time, expected.params.time);
+            assert!(matches!(decoded.params.provider, Some(Provider::Bandcamp)));
+            assert_eq!(decoded.params.role, expected.params.role);
+            assert_eq!(decoded.params.blind, Some(true));
+        }
+    }
+
+    #[test]
+    fn decode_legacy_untagged_data() {
+        // posted before the data was versioned, without a `v` field
+        let data = "album=Artist+-+Album&link=https%3A%2F%2Fexample.com%2Falbum\
+                    &rtitle=Artist+-+Album&rstart=2023-01-02T18%3A00%3A00Z";
+        let decoded = ResolvedLp::from_data(data).unwrap();
+        assert_eq!(decoded.version, LP_DATA_VERSION);
+        assert_eq!(decoded.resolved_title.as_deref(), Some("Artist - Album"));
+        assert_eq!(decoded.resolved_link, None);
+        assert_eq!(
+            decoded.resolved_start,
+            Some("2023-01-02T18:00:00Z".parse().unwrap())
+        );
+        assert_eq!(decoded.params.album, "Artist - Album");
+        assert_eq!(
+            decoded.params.link.as_deref(),
+            Some("https://example.com/album")
+        );
+        assert_eq!(decoded.params.blind, None);
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let data = format!("v={}&album=Artist+-+Album", LP_DATA_VERSION + 1);
+        let err = ResolvedLp::from_data(&data).unwrap_err().to_string();
+        assert!(err.contains("unsupported embedded data version"), "{err}");
+    }
 }
@@ -0,0 +1,272 @@
+// Listening parties scheduled ahead of time with /lp_schedule and listed with /lp_queue.
+// A job pings the LP role some minutes before each one starts, then posts it like /lp at
+// the scheduled time, on behalf of the user who scheduled it.
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::Utc;
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
+use rusqlite::params;
+use serenity::builder::CreateMessage;
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, RoleId, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::date_format::{Timestamp, TimestampStyle};
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlUserId};
+use crate::modules::lp::post_scheduled_lp;
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::time_parse::parse_time;
+
+const MAX_QUEUED_LPS: usize = 25;
+const DEFAULT_REMINDER_MINUTES: i64 = 15;
+
+struct QueuedLp {
+    id: i64,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    album: String,
+    link: Option<String>,
+    start_at: i64,
+}
+
+impl QueuedLp {
+    const COLUMNS: &'static str = "id, guild_id, channel_id, user_id, album, link, start_at";
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(QueuedLp {
+            id: row.get(0)?,
+            guild_id: row.get::<_, SqlGuildId>(1)?.0,
+            channel_id: row.get::<_, SqlChannelId>(2)?.0,
+            user_id: row.get::<_, SqlUserId>(3)?.0,
+            album: row.get(4)?,
+            link: row.get(5)?,
+            start_at: row.get(6)?,
+        })
+    }
+
+    fn describe(&self) -> String {
+        let start = Timestamp(self.start_at, TimestampStyle::ShortDateTime);
+        let relative = Timestamp(self.start_at, TimestampStyle::Relative);
+        format!(
+            "`{}` **{}** in <#{}> by <@{}>, {start} ({relative})",
+            self.id, self.album, self.channel_id, self.user_id
+        )
+    }
+}
+
+fn guild_queue(db: &Db, guild_id: GuildId) -> anyhow::Result<Vec<QueuedLp>> {
+    let queue = db
+        .conn
+        .prepare(&format!(
+            "SELECT {} FROM lp_queue WHERE guild_id = ?1 ORDER BY start_at",
+            QueuedLp::COLUMNS
+        ))?
+        .query([SqlGuildId(guild_id)])?
+        .map(QueuedLp::from_row)
+        .collect()?;
+    Ok(queue)
+}
+
+// LPs whose reminder or start time has passed
+fn due_lps(db: &Db, now: i64) -> anyhow::Result<Vec<QueuedLp>> {
+    let due = db
+        .conn
+        .prepare(&format!(
+            "SELECT {} FROM lp_queue WHERE start_at <= ?1 OR remind_at <= ?1 ORDER BY start_at",
+            QueuedLp::COLUMNS
+        ))?
+        .query([now])?
+        .map(QueuedLp::from_row)
+        .collect()?;
+    Ok(due)
+}
+
+#[derive(Command)]
+#[cmd(name = "lp_schedule", desc = "Schedule a listening party", guild_only)]
+pub struct LpSchedule {
+    #[cmd(
+        desc = "What you will be listening to (e.g. band - album, spotify/bandcamp link)",
+        autocomplete
+    )]
+    album: String,
+    #[cmd(desc = "When the LP will take place (e.g. 21:30 CET, in 2h, tomorrow 20:00)")]
+    time: String,
+    #[cmd(
+        desc = "(Optional) Link to the album/playlist (Spotify, Youtube, Bandcamp...)",
+        autocomplete
+    )]
+    link: Option<String>,
+    #[cmd(
+        desc = "Minutes before the LP to ping the LP role (default: 15, 0 to disable)",
+        min = 0,
+        max = 1440
+    )]
+    reminder: Option<i64>,
+}
+
+#[async_trait]
+impl GuildCommand for LpSchedule {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let now = handler.clock.now();
+        let start_at = parse_time(&self.time, now)?;
+        if start_at <= now {
+            bail!("The listening party must be in the future");
+        }
+        let start_at = start_at.timestamp();
+        let reminder = self.reminder.unwrap_or(DEFAULT_REMINDER_MINUTES);
+        // a reminder that would already be due is pointless
+        let remind_at =
+            Some(start_at - reminder * 60).filter(|at| reminder > 0 && *at > now.timestamp());
+        let db = handler.db.lock().await;
+        if guild_queue(&db, guild_id)?.len() >= MAX_QUEUED_LPS {
+            bail!("The queue is full ({MAX_QUEUED_LPS} listening parties)");
+        }
+        db.conn.execute(
+            "INSERT INTO lp_queue (
+                guild_id, channel_id, user_id, album, link, start_at, remind_at, ts
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                SqlGuildId(guild_id),
+                SqlChannelId(command.channel_id),
+                SqlUserId(command.user.id),
+                self.album,
+                self.link,
+                start_at,
+                remind_at,
+                Utc::now().timestamp()
+            ],
+        )?;
+        let start = Timestamp(start_at, TimestampStyle::ShortDateTime);
+        let relative = Timestamp(start_at, TimestampStyle::Relative);
+        CommandResponse::public(format!(
+            "Scheduled **{}** for {start} ({relative})",
+            self.album
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "lp_queue",
+    desc = "List the scheduled listening parties",
+    guild_only
+)]
+pub struct LpQueue;
+
+#[async_trait]
+impl GuildCommand for LpQueue {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        _command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let queue = handler.db_read(move |db| guild_queue(db, guild_id)).await?;
+        if queue.is_empty() {
+            return CommandResponse::private("No listening party scheduled");
+        }
+        let lines: Vec<String> = queue.iter().map(QueuedLp::describe).collect();
+        CommandResponse::private(lines.join("\n"))
+    }
+}
+
+async fn send_reminder(handler: &Handler, http: &Http, lp: &QueuedLp) -> anyhow::Result<()> {
+    let role_id: Option<u64> = handler.get_guild_field(lp.guild_id, "role_id").await?;
+    let mention = role_id
+        .map(|role| format!("<@&{role}> "))
+        .unwrap_or_default();
+    let relative = Timestamp(lp.start_at, TimestampStyle::Relative);
+    let content = format!(
+        "{mention}Listening party of **{}** starting {relative}, scheduled by <@{}>",
+        lp.album, lp.user_id
+    );
+    let policy = handler.mention_policy(Some(lp.guild_id), "lp").await;
+    let mentions = policy.allowed_mentions(role_id.map(RoleId::new), []);
+    let msg = CreateMessage::new()
+        .content(content)
+        .allowed_mentions(mentions);
+    lp.channel_id.send_message(http, msg).await?;
+    Ok(())
+}
+
+async fn run_queued_lp(
+    handler: &Handler,
+    http: &Http,
+    lp: &QueuedLp,
+    now: i64,
+) -> anyhow::Result<()> {
+    if lp.start_at > now {
+        // only the reminder is due, cleared first so that it isn't sent twice
+        handler.db.lock().await.conn.execute(
+            "UPDATE lp_queue SET remind_at = NULL WHERE id = ?1",
+            [lp.id],
+        )?;
+        return send_reminder(handler, http, lp).await;
+    }
+    handler
+        .db
+        .lock()
+        .await
+        .conn
+        .execute("DELETE FROM lp_queue WHERE id = ?1", [lp.id])?;
+    let link = lp.link.as_deref();
+    post_scheduled_lp(
+        handler,
+        http,
+        lp.guild_id,
+        lp.channel_id,
+        lp.user_id,
+        &lp.album,
+        link,
+    )
+    .await?;
+    Ok(())
+}
+
+fn run_lp_queue<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = handler.clock.now().timestamp();
+        let due = handler.db_read(move |db| due_lps(db, now)).await?;
+        for lp in &due {
+            if let Err(e) = run_queued_lp(handler, http, lp, now).await {
+                tracing::warn!(id = lp.id, "Error running scheduled LP: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<LpSchedule>();
+    store.register::<LpQueue>();
+}
+
+pub(crate) fn register_jobs(jobs: &mut JobStore) {
+    jobs.push(Job::new(
+        "lp_queue",
+        Schedule::every(Duration::from_secs(60)),
+        run_lp_queue,
+    ));
+}
@@ -0,0 +1,490 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Datelike, Local, Weekday};
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
+use rusqlite::params;
+use serenity::builder::{CreateCommandOption, CreateMessage};
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, RoleId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlChannelId, SqlGuildId};
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+const WEEK_SECS: i64 = 7 * 24 * 3600;
+
+// Next time the given weekday and time of day occur strictly after `after`
+fn next_occurrence(
+    after: DateTime<Local>,
+    weekday: Weekday,
+    hour: u32,
+    minute: u32,
+) -> Option<DateTime<Local>> {
+    let days_ahead = (7 + weekday.num_days_from_monday() as i64
+        - after.weekday().num_days_from_monday() as i64)
+        % 7;
+    let date = after.date_naive() + chrono::Duration::days(days_ahead);
+    let mut next = date
+        .and_hms_opt(hour, minute, 0)?
+        .and_local_timezone(Local)
+        .earliest()?;
+    if next <= after {
+        next += chrono::Duration::weeks(1);
+    }
+    Some(next)
+}
+
+pub struct Series {
+    pub id: u64,
+    pub channel_id: ChannelId,
+    pub role_id: Option<u64>,
+    pub name: String,
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub every_weeks: u8,
+    pub paused: bool,
+    pub next_run: i64,
+}
+
+impl Series {
+    fn describe(&self) -> String {
+        let every = match self.every_weeks {
+            1 => "Every".to_string(),
+            n => format!("Every {n} weeks on"),
+        };
+        let role = self
+            .role_id
+            .map(|role| format!(", pinging <@&{role}>"))
+            .unwrap_or_default();
+        let status = if self.paused {
+            " (paused)".to_string()
+        } else {
+            format!(", next on <t:{}:f>", self.next_run)
+        };
+        format!(
+            "`{}` **{}**: {every} {} at {:02}:{:02} in <#{}>{role}{status}",
+            self.id,
+            self.name,
+            WEEKDAYS[self.weekday as usize % 7],
+            self.hour,
+            self.minute,
+            self.channel_id,
+        )
+    }
+
+    fn next_run_after(&self, after: DateTime<Local>) -> anyhow::Result<i64> {
+        let weekday = Weekday::try_from(self.weekday).map_err(|_| anyhow!("Invalid weekday"))?;
+        let next = next_occurrence(after, weekday, self.hour as u32, self.minute as u32)
+            .ok_or_else(|| anyhow!("Invalid time"))?;
+        Ok(next.timestamp() + (self.every_weeks.max(1) as i64 - 1) * WEEK_SECS)
+    }
+}
+
+fn get_series(db: &Db, guild_id: GuildId) -> anyhow::Result<Vec<Series>> {
+    let res = db
+        .conn
+        .prepare(
+            "SELECT id, channel_id, role_id, name, weekday, hour, minute, every_weeks, paused,
+                    next_run
+             FROM lp_series WHERE guild_id = ?1 ORDER BY id",
+        )?
+        .query([SqlGuildId(guild_id)])?
+        .map(|row| {
+            Ok(Series {
+                id: row.get(0)?,
+                channel_id: row.get::<_, SqlChannelId>(1)?.0,
+                role_id: row.get(2)?,
+                name: row.get(3)?,
+                weekday: row.get(4)?,
+                hour: row.get(5)?,
+                minute: row.get(6)?,
+                every_weeks: row.get(7)?,
+                paused: row.get(8)?,
+                next_run: row.get(9)?,
+            })
+        })
+        .collect()?;
+    Ok(res)
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "lp_series_create",
+    desc = "Create a recurring listening party in this channel"
+)]
+pub struct CreateLpSeries {
+    #[cmd(desc = "Name of the listening party series")]
+    name: String,
+    #[cmd(desc = "Day of the week")]
+    weekday: i64,
+    #[cmd(desc = "Hour (server time)", min = 0, max = 23)]
+    hour: i64,
+    #[cmd(desc = "Minute", min = 0, max = 59)]
+    minute: Option<i64>,
+    #[cmd(
+        desc = "Number of weeks between listening parties (default: 1)",
+        min = 1,
+        max = 8
+    )]
+    every_weeks: Option<i64>,
+    #[cmd(desc = "Role to ping (defaults to the listening party role)")]
+    role: Option<RoleId>,
+}
+
+#[async_trait]
+impl BotCommand for CreateLpSeries {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let role_id = match self.role {
+            Some(role) => Some(role.get()),
+            None => handler
+                .get_guild_field::<Option<String>>(guild_id, "role_id")
+                .await?
+                .and_then(|role| role.parse().ok()),
+        };
+        let mut series = Series {
+            id: 0,
+            channel_id: command.channel_id,
+            role_id,
+            name: self.name,
+            weekday: self.weekday as u8,
+            hour: self.hour as u8,
+            minute: self.minute.unwrap_or(0) as u8,
+            every_weeks: self.every_weeks.unwrap_or(1) as u8,
+            paused: false,
+            next_run: 0,
+        };
+        series.next_run = series.next_run_after(handler.clock.local_now())?;
+        let db = handler.db.lock().await;
+        db.conn.execute(
+            "INSERT INTO lp_series (
+                guild_id, channel_id, role_id, name, weekday, hour, minute, every_weeks, paused,
+                next_run
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, false, ?9)",
+            params![
+                SqlGuildId(guild_id),
+                SqlChannelId(series.channel_id),
+                series.role_id,
+                &series.name,
+                series.weekday,
+                series.hour,
+                series.minute,
+                series.every_weeks,
+                series.next_run
+            ],
+        )?;
+        series.id = db.conn.last_insert_rowid() as u64;
+        CommandResponse::private(format!(
+            "Listening party series created\n{}",
+            series.describe()
+        ))
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "weekday" => WEEKDAYS
+                .iter()
+                .enumerate()
+                .fold(opt, |opt, (n, &day)| opt.add_int_choice(day, n as i32)),
+            _ => opt,
+        }
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "lp_series", desc = "Manage recurring listening parties")]
+pub struct ManageLpSeries {
+    #[cmd(desc = "What to do")]
+    action: String,
+    #[cmd(desc = "ID of the series (see list)", min = 1)]
+    id: Option<i64>,
+}
+
+#[async_trait]
+impl BotCommand for ManageLpSeries {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let db = handler.db.lock().await;
+        let mut series = get_series(&db, guild_id)?;
+        if self.action == "list" {
+            if series.is_empty() {
+                return CommandResponse::private("No listening party series");
+            }
+            let list = series.iter().map(Series::describe).collect::<Vec<_>>();
+            return CommandResponse::private(list.join("\n"));
+        }
+        let id = self
+            .id
+            .ok_or_else(|| anyhow!("Please specify a series ID"))? as u64;
+        let Some(s) = series.iter_mut().find(|s| s.id == id) else {
+            bail!("No such series");
+        };
+        let resp = match self.action.as_str() {
+            "pause" => {
+                db.conn
+                    .execute("UPDATE lp_series SET paused = true WHERE id = ?1", [id])?;
+                "Series paused"
+            }
+            "resume" => {
+                // skip occurrences that happened while the series was paused
+                let now = handler.clock.local_now();
+                if s.next_run <= now.timestamp() {
+                    s.next_run = s.next_run_after(now)?;
+                }
+                db.conn.execute(
+                    "UPDATE lp_series SET paused = false, next_run = ?2 WHERE id = ?1",
+                    params![id, s.next_run],
+                )?;
+                "Series resumed"
+            }
+            "cancel" => {
+                db.conn
+                    .execute("DELETE FROM lp_series WHERE id = ?1", [id])?;
+                "Series cancelled"
+            }
+            other => bail!("Unknown action {other}"),
+        };
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "action" => ["list", "pause", "resume", "cancel"]
+                .into_iter()
+                .fold(opt, |opt, action| opt.add_string_choice(action, action)),
+            _ => opt,
+        }
+    }
+}
+
+async fn post_reminder(
+    handler: &Handler,
+    http: &Http,
+    guild_id: GuildId,
+    series: &Series,
+) -> anyhow::Result<()> {
+    let mention = series
+        .role_id
+        .map(|role| format!("<@&{role}> "))
+        .unwrap_or_default();
+    let policy = handler.mention_policy(Some(guild_id), "lp").await;
+    let allowed_mentions = policy.allowed_mentions(series.role_id.map(RoleId::new), []);
+    series
+        .channel_id
+        .send_message(
+            http,
+            CreateMessage::new()
+                .content(format!(
+                    "{mention}**{}** listening party starts now! Use `/lp` to set it up.",
+                    series.name
+                ))
+                .allowed_mentions(allowed_mentions),
+        )
+        .await?;
+    Ok(())
+}
+
+// Series that are due, with the guild they belong to
+fn due_series(db: &Db, now: i64) -> anyhow::Result<Vec<(GuildId, Series)>> {
+    let due = db
+        .conn
+        .prepare(
+            "SELECT id, channel_id, role_id, name, weekday, hour, minute, every_weeks, guild_id
+             FROM lp_series WHERE NOT paused AND next_run <= ?1",
+        )?
+        .query([now])?
+        .map(|row| {
+            let guild_id = row.get::<_, SqlGuildId>(8)?.0;
+            let series = Series {
+                id: row.get(0)?,
+                channel_id: row.get::<_, SqlChannelId>(1)?.0,
+                role_id: row.get(2)?,
+                name: row.get(3)?,
+                weekday: row.get(4)?,
+                hour: row.get(5)?,
+                minute: row.get(6)?,
+                every_weeks: row.get(7)?,
+                paused: false,
+                next_run: 0,
+            };
+            Ok((guild_id, series))
+        })
+        .collect()?;
+    Ok(due)
+}
+
+// Post reminders for listening party series that are due
+fn post_due_reminders<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = handler.clock.local_now();
+        let ts = now.timestamp();
+        let due = handler.db_read(move |db| due_series(db, ts)).await?;
+        for (guild_id, series) in due {
+            if let Err(e) = post_reminder(handler, http, guild_id, &series).await {
+                tracing::error!("Error posting listening party reminder: {e:?}");
+            }
+            let next_run = match series.next_run_after(now) {
+                Ok(next_run) => next_run,
+                Err(e) => {
+                    tracing::error!(
+                        "Error scheduling listening party series {}: {e:?}",
+                        series.id
+                    );
+                    continue;
+                }
+            };
+            let id = series.id;
+            let updated = handler
+                .db_call(move |db| {
+                    db.conn.execute(
+                        "UPDATE lp_series SET next_run = ?2 WHERE id = ?1",
+                        params![id, next_run],
+                    )?;
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = updated {
+                tracing::error!("Error updating listening party series: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+pub struct ModLpSeries;
+
+#[async_trait]
+impl Module for ModLpSeries {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<super::ModLp>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ModLpSeries)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_series (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                role_id INTEGER,
+                name STRING NOT NULL,
+                weekday INTEGER NOT NULL,
+                hour INTEGER NOT NULL,
+                minute INTEGER NOT NULL,
+                every_weeks INTEGER NOT NULL DEFAULT(1),
+                paused BOOLEAN NOT NULL DEFAULT(false),
+                next_run INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<CreateLpSeries>();
+        store.register::<ManageLpSeries>();
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "lp_series",
+            Schedule::every(Duration::from_secs(60)),
+            post_due_reminders,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    fn local(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 1, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn series(weekday: Weekday, every_weeks: u8) -> Series {
+        Series {
+            id: 1,
+            channel_id: ChannelId::new(1),
+            role_id: None,
+            name: "Weekly LP".to_string(),
+            weekday: weekday.num_days_from_monday() as u8,
+            hour: 19,
+            minute: 0,
+            every_weeks,
+            paused: false,
+            next_run: 0,
+        }
+    }
+
+    #[test]
+    fn next_run_across_week_boundary() {
+        let monday = series(Weekday::Mon, 1);
+        // Sunday 2024-01-07, the next run is the following day
+        let clock = MockClock::new(local(7, 20, 0).with_timezone(&Utc));
+        let next = monday.next_run_after(clock.local_now()).unwrap();
+        assert_eq!(next, local(8, 19, 0).timestamp());
+        // right when it runs, the next one is a week later
+        clock.set(local(8, 19, 0).with_timezone(&Utc));
+        let next = monday.next_run_after(clock.local_now()).unwrap();
+        assert_eq!(next, local(15, 19, 0).timestamp());
+    }
+
+    #[test]
+    fn next_run_every_other_week() {
+        let sunday = series(Weekday::Sun, 2);
+        let clock = MockClock::new(local(6, 12, 0).with_timezone(&Utc));
+        let next = sunday.next_run_after(clock.local_now()).unwrap();
+        assert_eq!(next, local(14, 19, 0).timestamp());
+        // past this week's time, the next run is two weeks later
+        clock.advance(chrono::Duration::hours(32));
+        let next = sunday.next_run_after(clock.local_now()).unwrap();
+        assert_eq!(next, local(21, 19, 0).timestamp());
+    }
+}
@@ -0,0 +1,241 @@
+// Attendance of listening parties and /lp_stats.
+// LP messages are saved with their album when posted. Their author, users reacting to them
+// and users talking in the LP's thread are counted as attending it.
+use std::fmt::Write;
+
+use chrono::Utc;
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
+use rusqlite::{params, OptionalExtension};
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, MessageId, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::album::Album;
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlMessageId, SqlUserId};
+use crate::gateway::{GatewayHandlers, MessageCreate, ReactionAdd};
+use crate::prelude::*;
+
+const TOP_ARTISTS: usize = 5;
+const LEADERBOARD_SIZE: usize = 10;
+
+pub(crate) fn record_lp_message(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    thread_id: Option<ChannelId>,
+    info: &Album,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT OR REPLACE INTO lp_message (
+            message_id, guild_id, channel_id, thread_id, user_id, name, artist, ts
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            SqlMessageId(message_id),
+            SqlGuildId(guild_id),
+            SqlChannelId(channel_id),
+            thread_id.map(SqlChannelId),
+            SqlUserId(user_id),
+            info.name,
+            info.artist,
+            Utc::now().timestamp()
+        ],
+    )?;
+    record_attendance(db, guild_id, message_id, user_id)
+}
+
+fn record_attendance(
+    db: &Db,
+    guild_id: GuildId,
+    message_id: MessageId,
+    user_id: UserId,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT OR IGNORE INTO lp_attendance (message_id, guild_id, user_id, ts)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            SqlMessageId(message_id),
+            SqlGuildId(guild_id),
+            SqlUserId(user_id),
+            Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+// LP message whose thread is `thread_id`
+fn lp_in_thread(db: &Db, thread_id: ChannelId) -> anyhow::Result<Option<(MessageId, GuildId)>> {
+    let lp = db
+        .conn
+        .query_row(
+            "SELECT message_id, guild_id FROM lp_message WHERE thread_id = ?1",
+            [SqlChannelId(thread_id)],
+            |row| {
+                let message: SqlMessageId = row.get(0)?;
+                let guild: SqlGuildId = row.get(1)?;
+                Ok((message.0, guild.0))
+            },
+        )
+        .optional()?;
+    Ok(lp)
+}
+
+fn on_message<'a>(
+    handler: &'a Handler,
+    _ctx: &'a Context,
+    event: &'a MessageCreate,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let msg = &event.0;
+        if msg.author.bot || msg.guild_id.is_none() {
+            return Ok(());
+        }
+        let (thread_id, user_id) = (msg.channel_id, msg.author.id);
+        // most messages are not in an LP thread, only lock the database for writing if it is
+        let Some((message_id, guild_id)) = handler
+            .db_read(move |db| lp_in_thread(db, thread_id))
+            .await?
+        else {
+            return Ok(());
+        };
+        handler
+            .db_call(move |db| record_attendance(db, guild_id, message_id, user_id))
+            .await
+    }
+    .boxed()
+}
+
+fn on_reaction_add<'a>(
+    handler: &'a Handler,
+    _ctx: &'a Context,
+    event: &'a ReactionAdd,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let react = &event.0;
+        let (Some(guild_id), Some(user_id)) = (react.guild_id, react.user_id) else {
+            return Ok(());
+        };
+        let is_bot = react.member.as_ref().is_some_and(|m| m.user.bot);
+        if is_bot || handler.self_id.get() == Some(&user_id) {
+            return Ok(());
+        }
+        let message_id = react.message_id;
+        handler
+            .db_call(move |db| {
+                let is_lp = db
+                    .conn
+                    .query_row(
+                        "SELECT 1 FROM lp_message WHERE message_id = ?1",
+                        [SqlMessageId(message_id)],
+                        |_| Ok(()),
+                    )
+                    .optional()?
+                    .is_some();
+                if is_lp {
+                    record_attendance(db, guild_id, message_id, user_id)?;
+                }
+                Ok(())
+            })
+            .await
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(name = "lp_stats", desc = "Listening party attendance", guild_only)]
+pub struct LpStats {
+    #[cmd(desc = "Member to show the attendance of (defaults to you)")]
+    user: Option<UserId>,
+}
+
+struct Stats {
+    attended: u64,
+    hosted: u64,
+    artists: Vec<(String, u64)>,
+    leaderboard: Vec<(UserId, u64)>,
+}
+
+fn lp_stats(db: &Db, guild_id: GuildId, user_id: UserId) -> anyhow::Result<Stats> {
+    let count = |sql: &str| -> rusqlite::Result<u64> {
+        db.conn.query_row(
+            sql,
+            params![SqlGuildId(guild_id), SqlUserId(user_id)],
+            |row| row.get(0),
+        )
+    };
+    let attended =
+        count("SELECT COUNT(*) FROM lp_attendance WHERE guild_id = ?1 AND user_id = ?2")?;
+    let hosted = count("SELECT COUNT(*) FROM lp_message WHERE guild_id = ?1 AND user_id = ?2")?;
+    let artists = db
+        .conn
+        .prepare(
+            "SELECT artist, COUNT(*) AS n FROM lp_message
+             WHERE guild_id = ?1 AND artist IS NOT NULL
+             GROUP BY artist ORDER BY n DESC LIMIT ?2",
+        )?
+        .query(params![SqlGuildId(guild_id), TOP_ARTISTS])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    let leaderboard = db
+        .conn
+        .prepare(
+            "SELECT user_id, COUNT(*) AS n FROM lp_attendance WHERE guild_id = ?1
+             GROUP BY user_id ORDER BY n DESC LIMIT ?2",
+        )?
+        .query(params![SqlGuildId(guild_id), LEADERBOARD_SIZE])?
+        .map(|row| Ok((row.get::<_, SqlUserId>(0)?.0, row.get(1)?)))
+        .collect()?;
+    Ok(Stats {
+        attended,
+        hosted,
+        artists,
+        leaderboard,
+    })
+}
+
+#[async_trait]
+impl GuildCommand for LpStats {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = self.user.unwrap_or(command.user.id);
+        let stats = handler
+            .db_read(move |db| lp_stats(db, guild_id, user_id))
+            .await?;
+        let mut resp = format!(
+            "<@{user_id}> attended **{}** listening parties and hosted **{}**",
+            stats.attended, stats.hosted
+        );
+        if !stats.artists.is_empty() {
+            resp.push_str("\n\n**Most LP'd artists**");
+            for (artist, n) in &stats.artists {
+                _ = write!(resp, "\n{artist}: {n}");
+            }
+        }
+        if !stats.leaderboard.is_empty() {
+            resp.push_str("\n\n**Leaderboard**");
+            for (i, (user, n)) in stats.leaderboard.iter().enumerate() {
+                _ = write!(resp, "\n{}. <@{user}>: {n}", i + 1);
+            }
+        }
+        CommandResponse::public(resp)
+    }
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<LpStats>();
+}
+
+pub(crate) fn register_gateway_handlers(handlers: &mut GatewayHandlers) {
+    handlers.add(on_message);
+    handlers.add(on_reaction_add);
+}
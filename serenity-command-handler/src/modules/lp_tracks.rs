@@ -0,0 +1,185 @@
+// Posts the track being played in LP threads, for albums with a known tracklist.
+// A task is started per LP once its start time is known, and restarted when the LP is edited.
+// Enabled per guild with /lp_tracktimer.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, MessageId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::album::{Album, Track};
+use crate::clock::Clock;
+use crate::db::{SqlChannelId, SqlMessageId};
+use crate::modules::lp::ModLp;
+use crate::prelude::*;
+
+// Running timers by LP message, cancelled when the LP is edited or canceled
+#[derive(Default)]
+pub(crate) struct TrackTimers(Mutex<HashMap<MessageId, CancellationToken>>);
+
+impl TrackTimers {
+    // Stop the LP's timer if there is one, and return a token for its new timer
+    fn replace(&self, message_id: MessageId) -> CancellationToken {
+        let mut timers = self.0.lock().unwrap();
+        if let Some(token) = timers.remove(&message_id) {
+            token.cancel();
+        }
+        // finished timers cancel their own token
+        timers.retain(|_, token| !token.is_cancelled());
+        let token = CancellationToken::new();
+        timers.insert(message_id, token.clone());
+        token
+    }
+
+    fn stop(&self, message_id: MessageId) {
+        if let Some(token) = self.0.lock().unwrap().remove(&message_id) {
+            token.cancel();
+        }
+    }
+}
+
+// Start posting the tracks of `info` in the LP's thread, looked up if `thread_id` is None.
+// Errors are only logged, the LP itself was posted fine.
+pub(crate) async fn start_track_timer(
+    handler: &Handler,
+    guild_id: GuildId,
+    message_id: MessageId,
+    thread_id: Option<ChannelId>,
+    start: DateTime<Utc>,
+    info: &Album,
+) {
+    if info.tracks.is_empty() {
+        return;
+    }
+    let res = async {
+        if !handler
+            .get_guild_field::<bool>(guild_id, "lp_track_timer")
+            .await?
+        {
+            return Ok(());
+        }
+        let thread_id = match thread_id {
+            Some(thread_id) => Some(thread_id),
+            None => {
+                handler
+                    .db_read(move |db| {
+                        let thread = db
+                            .conn
+                            .query_row(
+                                "SELECT thread_id FROM lp_message WHERE message_id = ?1",
+                                [SqlMessageId(message_id)],
+                                |row| row.get::<_, Option<SqlChannelId>>(0),
+                            )
+                            .optional()?;
+                        Ok(thread.flatten().map(|c| c.0))
+                    })
+                    .await?
+            }
+        };
+        // LPs without a thread have nowhere to post the tracks
+        let Some(thread_id) = thread_id else {
+            return Ok(());
+        };
+        let http = Arc::clone(handler.http.get().context("not connected yet")?);
+        let token = handler.module::<ModLp>()?.track_timers.replace(message_id);
+        let clock = Arc::clone(&handler.clock);
+        let tracks = info.tracks.clone();
+        handler.spawn_task("lp_track_timer", async move {
+            tokio::select! {
+                res = run_track_timer(&http, &*clock, thread_id, start, &tracks) => {
+                    if let Err(e) = res {
+                        tracing::warn!("Error posting LP tracks: {e:?}");
+                    }
+                }
+                _ = token.cancelled() => {}
+            }
+            token.cancel();
+        });
+        anyhow::Ok(())
+    };
+    if let Err(e) = res.await {
+        tracing::warn!("Could not start LP track timer: {e:?}");
+    }
+}
+
+pub(crate) fn stop_track_timer(handler: &Handler, message_id: MessageId) -> anyhow::Result<()> {
+    handler.module::<ModLp>()?.track_timers.stop(message_id);
+    Ok(())
+}
+
+async fn run_track_timer(
+    http: &Http,
+    clock: &dyn Clock,
+    thread_id: ChannelId,
+    start: DateTime<Utc>,
+    tracks: &[Track],
+) -> anyhow::Result<()> {
+    let mut track_start = start;
+    for (i, track) in tracks.iter().enumerate() {
+        let track_end = track_start + track.duration;
+        // tracks that are already over when the timer is (re)started are skipped
+        if track_end > clock.now() {
+            let wait = (track_start - clock.now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            let content = format!(
+                "Now playing: track {}/{} – **{}**",
+                i + 1,
+                tracks.len(),
+                track.name
+            );
+            let msg = CreateMessage::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new());
+            thread_id.send_message(http, msg).await?;
+        }
+        track_start = track_end;
+    }
+    Ok(())
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "lp_tracktimer",
+    desc = "set whether to post the track being played in listening party threads"
+)]
+pub struct SetTrackTimer {
+    #[cmd(desc = "Post each track of the album in the LP's thread when it starts")]
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetTrackTimer {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_THREADS;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(guild_id, command.user.id, "lp_track_timer", self.enabled)
+            .await
+            .context("updating 'lp_track_timer' guild field")?;
+        let resp = if self.enabled {
+            "Will post the track being played in listening party threads"
+        } else {
+            "Will not post the track being played in listening party threads"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<SetTrackTimer>();
+}
@@ -1,32 +1,193 @@
+#[cfg(feature = "spotify")]
 pub mod spotify;
+#[cfg(feature = "spotify")]
 use rspotify::ClientCredsSpotify;
+#[cfg(feature = "spotify")]
 pub type Spotify = spotify::Spotify<ClientCredsSpotify>;
+#[cfg(feature = "spotify")]
 pub use spotify::SpotifyOAuth;
 
+#[cfg(feature = "bandcamp")]
 pub mod bandcamp;
+#[cfg(feature = "bandcamp")]
 pub use bandcamp::Bandcamp;
 
+#[cfg(feature = "discogs")]
+pub mod discogs;
+#[cfg(feature = "discogs")]
+pub use discogs::Discogs;
+
+#[cfg(feature = "lastfm")]
 pub mod lastfm;
+#[cfg(feature = "lastfm")]
+pub mod server_chart;
+#[cfg(feature = "lastfm")]
+pub mod taste;
+#[cfg(feature = "lastfm")]
 pub use lastfm::Lastfm;
 
+#[cfg(feature = "polls")]
+pub mod button_polls;
+#[cfg(feature = "polls")]
 pub mod polls;
+#[cfg(feature = "polls")]
 pub use polls::ModPoll;
 
+#[cfg(feature = "autoreact")]
 pub mod autoreact;
+#[cfg(feature = "autoreact")]
 pub use autoreact::ModAutoreacts;
 
+#[cfg(feature = "quotes")]
 pub mod quotes;
+#[cfg(feature = "quotes")]
 pub use quotes::Quotes;
 
+#[cfg(feature = "quote_stats")]
+pub mod quote_stats;
+#[cfg(feature = "quote_stats")]
+pub use quote_stats::QuoteStats;
+
+#[cfg(feature = "pinboard")]
 pub mod pinboard;
+#[cfg(feature = "pinboard")]
 pub use pinboard::Pinboard;
 
+#[cfg(feature = "lp")]
 pub mod lp;
+#[cfg(feature = "lp")]
+pub mod lp_queue;
+#[cfg(feature = "lp")]
+pub mod lp_stats;
+#[cfg(feature = "lp")]
+pub mod lp_tracks;
+#[cfg(feature = "lp")]
 pub use lp::ModLp;
 
+#[cfg(feature = "lp_series")]
+pub mod lp_series;
+#[cfg(feature = "lp_series")]
+pub use lp_series::ModLpSeries;
+
+#[cfg(feature = "album_lookup")]
+pub mod album_cache;
+#[cfg(feature = "album_lookup")]
 pub mod album_lookup;
+#[cfg(feature = "album_lookup")]
 pub use album_lookup::AlbumLookup;
 
+#[cfg(feature = "games")]
+pub mod games;
+#[cfg(feature = "games")]
+pub use games::Games;
+
+#[cfg(feature = "karma")]
+pub mod karma;
+#[cfg(feature = "karma")]
+pub use karma::Karma;
+
+#[cfg(feature = "listen_log")]
+pub mod listen_log;
+#[cfg(feature = "listen_log")]
+pub use listen_log::ListenLog;
+
+#[cfg(feature = "notifications")]
+pub mod notifications;
+#[cfg(feature = "notifications")]
+pub use notifications::Notifications;
+
+#[cfg(feature = "on_this_day")]
+pub mod on_this_day;
+#[cfg(feature = "on_this_day")]
+pub use on_this_day::OnThisDay;
+
+#[cfg(feature = "quiz")]
+pub mod quiz;
+#[cfg(feature = "quiz")]
+pub use quiz::Quiz;
+
+#[cfg(feature = "ratings")]
+pub mod ratings;
+#[cfg(feature = "ratings")]
+pub use ratings::Ratings;
+
+#[cfg(feature = "release_pings")]
+pub mod release_pings;
+#[cfg(feature = "release_pings")]
+pub use release_pings::ReleasePings;
+
+#[cfg(feature = "releases")]
+pub mod releases;
+#[cfg(feature = "releases")]
+pub use releases::Releases;
+
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+
+#[cfg(feature = "to_listen")]
+pub mod to_listen;
+#[cfg(feature = "to_listen")]
+pub use to_listen::ToListen;
+
+#[cfg(feature = "translate")]
+pub mod translate;
+#[cfg(feature = "translate")]
+pub use translate::Translate;
+
+#[cfg(feature = "year_in_review")]
+pub mod year_review;
+#[cfg(feature = "year_in_review")]
+pub use year_review::YearReview;
+
+#[cfg(feature = "bdays")]
 pub mod bdays;
 
+#[cfg(feature = "bot_management")]
+pub mod bot_management;
+#[cfg(feature = "bot_management")]
+pub use bot_management::BotManagement;
+
+#[cfg(feature = "changelog")]
+pub mod changelog;
+#[cfg(feature = "changelog")]
+pub use changelog::Changelog;
+
+#[cfg(feature = "command_channels")]
+pub mod command_channels;
+#[cfg(feature = "command_channels")]
+pub use command_channels::CommandChannels;
+
+#[cfg(feature = "command_permissions")]
+pub mod command_permissions;
+#[cfg(feature = "command_permissions")]
+pub use command_permissions::CommandPermissions;
+
+#[cfg(feature = "help")]
+pub mod help;
+#[cfg(feature = "help")]
+pub use help::Help;
+
+#[cfg(feature = "config_transfer")]
+pub mod config_transfer;
+#[cfg(feature = "config_transfer")]
+pub use config_transfer::ConfigTransfer;
+
+#[cfg(feature = "deliveries")]
+pub mod deliveries;
+#[cfg(feature = "deliveries")]
+pub use deliveries::Deliveries;
+
+#[cfg(feature = "scheduled_commands")]
+pub mod scheduled_commands;
+#[cfg(feature = "scheduled_commands")]
+pub use scheduled_commands::ScheduledCommands;
+
+#[cfg(feature = "settings")]
+pub mod settings;
+#[cfg(feature = "settings")]
+pub use settings::Settings;
+
+#[cfg(feature = "sql")]
 pub mod sql;
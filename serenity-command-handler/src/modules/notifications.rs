@@ -0,0 +1,121 @@
+use std::fmt::Write;
+
+use anyhow::bail;
+use rusqlite::params;
+use serenity::async_trait;
+use serenity::builder::CreateCommandOption;
+use serenity::model::prelude::{CommandInteraction, GuildId, UserId};
+use serenity::prelude::Context;
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::prelude::*;
+
+// Notification categories users can turn off, with a description for /notify.
+// Role pings reach everyone holding the role, so these only cover notifications sent to
+// users individually.
+pub const CATEGORIES: [(&str, &str); 3] = [
+    ("lp", "being added to listening party threads"),
+    ("releases", "release day pings"),
+    ("bdays", "birthday wishes mentioning you"),
+];
+
+fn state(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    category: &str,
+) -> anyhow::Result<&'static str> {
+    match db.muted_users(guild_id, category)?.contains(&user_id) {
+        true => Ok("off"),
+        false => Ok("on"),
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "notify",
+    desc = "Choose which notifications you get in this server"
+)]
+pub struct Notify {
+    #[cmd(desc = "Kind of notifications, shows your preferences if not set")]
+    category: Option<String>,
+    #[cmd(desc = "Whether to get these notifications")]
+    enabled: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for Notify {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let user_id = command.user.id;
+        let db = handler.db.lock().await;
+        let Some(category) = self.category else {
+            let mut resp = String::new();
+            for (category, desc) in CATEGORIES {
+                let state = state(&db, guild_id, user_id, category)?;
+                _ = writeln!(resp, "`{category}` ({desc}): **{state}**");
+            }
+            return CommandResponse::private(resp);
+        };
+        let Some((category, desc)) = CATEGORIES.into_iter().find(|(c, _)| *c == category) else {
+            bail!("Unknown notification category `{category}`");
+        };
+        let Some(enabled) = self.enabled else {
+            let state = state(&db, guild_id, user_id, category)?;
+            return CommandResponse::private(format!("Notifications for {desc} are {state}"));
+        };
+        db.conn.execute(
+            "INSERT INTO notification_pref (guild_id, user_id, category, enabled)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(guild_id, user_id, category) DO UPDATE SET enabled = ?4",
+            params![SqlGuildId(guild_id), SqlUserId(user_id), category, enabled],
+        )?;
+        let state = if enabled { "on" } else { "off" };
+        CommandResponse::private(format!("Turned {state} notifications for {desc}"))
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "category" => CATEGORIES.into_iter().fold(opt, |opt, (category, _)| {
+                opt.add_string_choice(category, category)
+            }),
+            _ => opt,
+        }
+    }
+}
+
+pub struct Notifications;
+
+#[async_trait]
+impl Module for Notifications {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Notifications)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS notification_pref (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                category STRING NOT NULL,
+                enabled BOOLEAN NOT NULL,
+                UNIQUE(guild_id, user_id, category)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<Notify>();
+    }
+}
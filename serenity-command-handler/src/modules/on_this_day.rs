@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rusqlite::params;
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId};
+use serenity::model::Permissions;
+use serenity::prelude::Mutex;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse, NotInGuild};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlUserId};
+use crate::modules::{ModLp, Quotes};
+use crate::prelude::*;
+use crate::retry_queue::{end_of_day, retry_due};
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+
+// How many years to look back
+const MAX_YEARS: i32 = 20;
+const MAX_QUOTES: usize = 5;
+const MAX_LPS: usize = 5;
+const EXCERPT_LEN: usize = 100;
+// Discord's limit for embed field values
+const FIELD_LEN: usize = 1024;
+const POST_HOUR: u32 = 10;
+const RETRY_KIND: &str = "on_this_day";
+
+// Start and end timestamps of the same day in previous years, most recent first.
+// Years in which the date doesn't exist (Feb 29) are skipped.
+fn previous_years(today: NaiveDate) -> Vec<(i32, i64, i64)> {
+    (1..=MAX_YEARS)
+        .filter_map(|ago| {
+            let year = today.year() - ago;
+            let day = NaiveDate::from_ymd_opt(year, today.month(), today.day())?;
+            let start = Local
+                .from_local_datetime(&day.and_hms_opt(0, 0, 0)?)
+                .earliest()?;
+            Some((year, start.timestamp(), start.timestamp() + 24 * 3600))
+        })
+        .collect()
+}
+
+fn quote_lines(db: &Db, guild_id: GuildId, today: NaiveDate) -> anyhow::Result<Vec<String>> {
+    let mut stmt = db.conn.prepare(
+        "SELECT quote_number, author_id, contents FROM quote
+         WHERE guild_id = ?1 AND ts >= ?2 AND ts < ?3 AND deleted_at IS NULL
+         ORDER BY ts",
+    )?;
+    let mut lines = Vec::new();
+    for (year, start, end) in previous_years(today) {
+        let quotes: Vec<(u64, SqlUserId, String)> = stmt
+            .query(params![SqlGuildId(guild_id), start, end])?
+            .map(|row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    crate::db::column_as_string(row.get_ref(2)?)?,
+                ))
+            })
+            .collect()?;
+        lines.extend(
+            quotes
+                .into_iter()
+                .map(|(number, SqlUserId(author), contents)| {
+                    let excerpt = contents.chars().take(EXCERPT_LEN).collect::<String>();
+                    format!("**{year}** #{number} by <@{author}>: {excerpt}")
+                }),
+        );
+        if lines.len() >= MAX_QUOTES {
+            lines.truncate(MAX_QUOTES);
+            break;
+        }
+    }
+    Ok(lines)
+}
+
+fn lp_lines(db: &Db, guild_id: GuildId, today: NaiveDate) -> anyhow::Result<Vec<String>> {
+    let mut stmt = db.conn.prepare(
+        "SELECT user_id, name FROM lp_history
+         WHERE guild_id = ?1 AND ts >= ?2 AND ts < ?3
+         ORDER BY ts",
+    )?;
+    let mut lines = Vec::new();
+    for (year, start, end) in previous_years(today) {
+        let lps: Vec<(SqlUserId, String)> = stmt
+            .query(params![SqlGuildId(guild_id), start, end])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        lines.extend(
+            lps.into_iter()
+                .map(|(SqlUserId(user), name)| format!("**{year}** <@{user}> hosted {name}")),
+        );
+        if lines.len() >= MAX_LPS {
+            lines.truncate(MAX_LPS);
+            break;
+        }
+    }
+    Ok(lines)
+}
+
+fn field_value(lines: &[String]) -> String {
+    let mut value = String::new();
+    for line in lines {
+        if value.len() + line.len() + 1 > FIELD_LEN {
+            break;
+        }
+        value.push_str(line);
+        value.push('\n');
+    }
+    value
+}
+
+// Quotes and LPs from the same day in previous years, None if there are none
+fn on_this_day_embed(
+    db: &Db,
+    guild_id: GuildId,
+    today: NaiveDate,
+) -> anyhow::Result<Option<CreateEmbed>> {
+    let quotes = quote_lines(db, guild_id, today)?;
+    let lps = lp_lines(db, guild_id, today)?;
+    if quotes.is_empty() && lps.is_empty() {
+        return Ok(None);
+    }
+    let mut embed = CreateEmbed::new().title(format!("On this day, {}", today.format("%B %-d")));
+    if !quotes.is_empty() {
+        embed = embed.field("Quotes", field_value(&quotes), false);
+    }
+    if !lps.is_empty() {
+        embed = embed.field("Listening parties", field_value(&lps), false);
+    }
+    Ok(Some(embed))
+}
+
+// Post in the guild's channel, at POST_HOUR in its timezone
+fn post_today<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let guild_id = run.guild_id.ok_or(NotInGuild)?;
+        let today = run.due.date_naive();
+        if let Err(e) = post_on_day(&handler.db, http, guild_id, today).await {
+            tracing::error!("Error posting on this day: {e:?}");
+            let payload = today.to_string();
+            let queued = handler.db.lock().await.queue_retry(
+                guild_id,
+                RETRY_KIND,
+                &payload,
+                end_of_day(run.due),
+                &e,
+                handler.clock.now().timestamp(),
+            );
+            if let Err(e) = queued {
+                tracing::error!("Error queuing on this day retry: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+fn retry_posts<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        retry_due(handler, RETRY_KIND, |guild_id, payload| async move {
+            post_on_day(&handler.db, http, guild_id, payload.parse()?).await
+        })
+        .await;
+        Ok(())
+    }
+    .boxed()
+}
+
+// Post for a day in the guild's current channel, if it still has one
+async fn post_on_day(
+    db: &Mutex<Db>,
+    http: &Http,
+    guild_id: GuildId,
+    day: NaiveDate,
+) -> anyhow::Result<()> {
+    let post_to = {
+        let db = db.lock().await;
+        let channel: Option<SqlChannelId> = db.get_guild_field(guild_id, "on_this_day_channel")?;
+        match channel {
+            Some(SqlChannelId(channel_id)) => {
+                on_this_day_embed(&db, guild_id, day)?.map(|embed| (channel_id, embed))
+            }
+            // not enabled, or disabled since then
+            None => None,
+        }
+    };
+    match post_to {
+        Some((channel_id, embed)) => post(http, channel_id, embed).await,
+        None => Ok(()),
+    }
+}
+
+async fn post(http: &Http, channel_id: ChannelId, embed: CreateEmbed) -> anyhow::Result<()> {
+    channel_id
+        .send_message(http, CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setonthisday",
+    desc = "Post quotes and listening parties from this day in previous years in this channel"
+)]
+pub struct SetOnThisDay {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetOnThisDay {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let channel = self.enabled.then_some(SqlChannelId(command.channel_id));
+        handler
+            .set_guild_field(guild_id, command.user.id, "on_this_day_channel", channel)
+            .await
+            .context("updating 'on_this_day_channel' guild field")?;
+        let resp = if self.enabled {
+            format!(
+                "Will post \"On this day\" every day in <#{}>",
+                command.channel_id
+            )
+        } else {
+            "Will not post \"On this day\" anymore".to_string()
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "on_this_day",
+    desc = "Show quotes and listening parties from this day in previous years"
+)]
+pub struct ShowOnThisDay {}
+
+#[async_trait]
+impl BotCommand for ShowOnThisDay {
+    type Data = Handler;
+    const SCHEDULABLE: bool = true;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let today = handler.clock.local_now().date_naive();
+        match on_this_day_embed(&*handler.db.lock().await, guild_id, today)? {
+            Some(embed) => CommandResponse::public(embed),
+            None => CommandResponse::private("Nothing happened on this day in previous years"),
+        }
+    }
+}
+
+pub struct OnThisDay;
+
+#[async_trait]
+impl Module for OnThisDay {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Quotes>().await?.module::<ModLp>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(OnThisDay)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_retry_queue()?;
+        db.add_guild_field("on_this_day_channel", "INTEGER")?;
+        db.conn.execute(
+            "CREATE INDEX IF NOT EXISTS quote_guild_ts ON quote (guild_id, ts)",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE INDEX IF NOT EXISTS lp_history_guild_ts ON lp_history (guild_id, ts)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<SetOnThisDay>();
+        store.register::<ShowOnThisDay>();
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        let post_at = Schedule::daily_at(POST_HOUR, 0);
+        jobs.push(
+            Job::new("on_this_day", post_at, post_today)
+                .per_guild("SELECT id FROM guild WHERE on_this_day_channel IS NOT NULL"),
+        );
+        let hourly = Schedule::every(Duration::from_secs(3600));
+        jobs.push(Job::new("on_this_day_retries", hourly, retry_posts));
+    }
+}
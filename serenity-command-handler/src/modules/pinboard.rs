@@ -1,25 +1,44 @@
 use anyhow::{anyhow, bail, Context as _};
 use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
 use itertools::Itertools;
-use serenity::builder::{CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, ExecuteWebhook};
+use rusqlite::params;
+use serenity::builder::{
+    CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse,
+};
 use serenity::model::prelude::Member;
 use serenity::model::user::User;
+use serenity::model::webhook::Webhook;
 use serenity::{
     async_trait,
     model::{
-        prelude::{ChannelId, CommandInteraction, Embed, GuildId, Message},
+        prelude::{ChannelId, CommandInteraction, ComponentInteraction, Embed, GuildId, Message},
         Permissions,
     },
     prelude::Context,
 };
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{BotCommand, CommandResponse, GuildCommand};
 use serenity_command_derive::Command;
+use std::collections::VecDeque;
 use std::fmt::Write;
+use tokio::sync::RwLock;
 
+use crate::command_context::{thread_parent, ThreadExt};
+use crate::db::{SqlChannelId, SqlGuildId};
+use crate::gateway::{ChannelPinsUpdate, GatewayHandlers};
+use crate::mentions::MentionPolicy;
 use crate::prelude::*;
+use crate::soft_delete::{handle_undo, undo_response};
 
 const MAX_EMBEDS: usize = 10;
 
+const MAX_CACHED_WEBHOOKS: usize = 32;
+
+// Pins updates are also sent for unpins, e.g. by the pinboard itself, with the time of the
+// latest remaining pin. Only updates with a pin newer than this are moved to the pinboard.
+const RECENT_PIN_SECS: i64 = 60;
+
 pub fn copy_embed(em: &Embed) -> CreateEmbed {
     let mut out = CreateEmbed::new();
     if let Some(title) = &em.title {
@@ -94,7 +113,8 @@ impl<'a> From<&'a Message> for SimpleMessage<'a> {
 #[derive(Command)]
 #[cmd(
     name = "setpinboardwebhook",
-    desc = "Set (or unset) a webhook for the pinboard channel"
+    desc = "Set (or unset) a webhook for the pinboard channel",
+    guild_only
 )]
 pub struct SetPinboardWebhook {
     #[cmd(desc = "The webhook URL for the pinboard channel (leave empty to remove)")]
@@ -102,23 +122,25 @@ pub struct SetPinboardWebhook {
 }
 
 #[async_trait]
-impl BotCommand for SetPinboardWebhook {
+impl GuildCommand for SetPinboardWebhook {
     type Data = Handler;
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
         _ctx: &Context,
         opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
         handler.db.lock().await.set_guild_field(
             guild_id,
+            opts.user.id,
             "pinboard_webhook",
             self.webhook.as_deref(),
         )?;
+        handler
+            .module::<Pinboard>()?
+            .invalidate_webhook(guild_id)
+            .await;
         CommandResponse::private(if self.webhook.is_some() {
             "Pinboard webhook set"
         } else {
@@ -134,12 +156,13 @@ async fn load_allowed_channels(
     guild_id: GuildId,
 ) -> anyhow::Result<Vec<ChannelId>> {
     let db = handler.db.lock().await;
-    let mut stmt = db
-        .conn
-        .prepare("SELECT channel_id FROM pinboard_allowed_channels WHERE guild_id = ?1")?;
+    let mut stmt = db.conn.prepare(
+        "SELECT channel_id FROM pinboard_allowed_channels
+             WHERE guild_id = ?1 AND deleted_at IS NULL",
+    )?;
     let channels: Vec<_> = stmt
-        .query([guild_id.get()])?
-        .map(|row| Ok(ChannelId::new(row.get(0)?)))
+        .query([SqlGuildId(guild_id)])?
+        .map(|row| Ok(row.get::<_, SqlChannelId>(0)?.0))
         .collect()?;
     Ok(channels)
 }
@@ -152,26 +175,121 @@ fn user_avatar(user: &User, member: Option<&Member>) -> Option<String> {
         .filter(|av| av.starts_with("http"))
 }
 
-pub struct Pinboard;
+// webhooks are cached along with the URL they were retrieved from
+type WebhookCache = VecDeque<(GuildId, String, Webhook)>;
+
+#[derive(Default)]
+pub struct Pinboard {
+    webhooks: RwLock<WebhookCache>,
+}
 
 impl Pinboard {
-    // Posts a newly-pinned message to a pinboard channel via webhook and unpins it.
-    pub async fn move_pin_to_pinboard(
-        handler: &Handler,
+    fn undo_unregister_channel<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let handled = handle_undo(handler, ctx, component, "pinboard_allowed_channels").await?;
+            Ok(handled.is_some())
+        }
+        .boxed()
+    }
+
+    async fn get_webhook(
+        &self,
         ctx: &Context,
-        channel: ChannelId,
         guild_id: GuildId,
-    ) -> anyhow::Result<()> {
-        let pinboard_webhook = handler
+        url: &str,
+    ) -> anyhow::Result<Webhook> {
+        let cached = self
+            .webhooks
+            .read()
+            .await
+            .iter()
+            .find(|(id, cached_url, _)| *id == guild_id && cached_url == url)
+            .map(|(_, _, wh)| wh.clone());
+        if let Some(wh) = cached {
+            return Ok(wh);
+        }
+        let wh = ctx
+            .http
+            .get_webhook_from_url(url)
+            .await
+            .context("error getting webhook")?;
+        let mut webhooks = self.webhooks.write().await;
+        webhooks.retain(|(id, _, _)| *id != guild_id);
+        while webhooks.len() >= MAX_CACHED_WEBHOOKS {
+            webhooks.pop_back();
+        }
+        webhooks.push_front((guild_id, url.to_string(), wh.clone()));
+        Ok(wh)
+    }
+
+    pub async fn invalidate_webhook(&self, guild_id: GuildId) {
+        self.webhooks
+            .write()
+            .await
+            .retain(|(id, _, _)| *id != guild_id);
+    }
+
+    async fn pinboard_webhook(handler: &Handler, guild_id: GuildId) -> anyhow::Result<String> {
+        handler
             .db
             .lock()
             .await
-            .get_guild_field(guild_id.get(), "pinboard_webhook")
+            .get_guild_field(guild_id, "pinboard_webhook")
             .ok()
             .filter(|s: &String| !s.is_empty())
-            .ok_or_else(|| anyhow!("No webhook configured"))?;
+            .ok_or_else(|| anyhow!("No webhook configured"))
+    }
+
+    // Threads are allowed if their parent channel is
+    async fn channel_allowed(
+        handler: &Handler,
+        ctx: &Context,
+        guild_id: GuildId,
+        channel: ChannelId,
+    ) -> anyhow::Result<bool> {
         let allowed_channels = load_allowed_channels(handler, guild_id).await?;
-        if !(allowed_channels.is_empty() || allowed_channels.contains(&channel)) {
+        if allowed_channels.is_empty() || allowed_channels.contains(&channel) {
+            return Ok(true);
+        }
+        let parent = channel
+            .to_channel(ctx)
+            .await?
+            .guild()
+            .and_then(|ch| thread_parent(&ch));
+        Ok(parent.is_some_and(|parent| allowed_channels.contains(&parent)))
+    }
+
+    fn on_pins_update<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        event: &'a ChannelPinsUpdate,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let (Some(guild_id), Some(last_pin)) = (event.0.guild_id, event.0.last_pin_timestamp)
+            else {
+                return Ok(());
+            };
+            if chrono::Utc::now().timestamp() - last_pin.unix_timestamp() > RECENT_PIN_SECS {
+                return Ok(());
+            }
+            Self::move_pin_to_pinboard(handler, ctx, event.0.channel_id, guild_id).await
+        }
+        .boxed()
+    }
+
+    // Posts a newly-pinned message to a pinboard channel via webhook and unpins it.
+    pub async fn move_pin_to_pinboard(
+        handler: &Handler,
+        ctx: &Context,
+        channel: ChannelId,
+        guild_id: GuildId,
+    ) -> anyhow::Result<()> {
+        let pinboard_webhook = Self::pinboard_webhook(handler, guild_id).await?;
+        if !Self::channel_allowed(handler, ctx, guild_id, channel).await? {
             return Ok(());
         }
         let pins = channel
@@ -183,14 +301,41 @@ impl Pinboard {
             _ => return Ok(()),
         };
         let message: SimpleMessage = last_pin.into();
-        dbg!(message);
-        let author = &last_pin.author;
+        tracing::debug!(?message, "Posting the last pin to the pinboard");
+        Self::post_to_pinboard(
+            handler,
+            ctx,
+            guild_id,
+            &pinboard_webhook,
+            last_pin,
+            "Pinned",
+        )
+        .await?;
+        last_pin
+            .unpin(&ctx.http)
+            .await
+            .context("error deleting pinned message")?;
+        Ok(())
+    }
+
+    // Posts a message to a pinboard channel via webhook.
+    // Returns the first message created in the pinboard channel.
+    async fn post_to_pinboard(
+        handler: &Handler,
+        ctx: &Context,
+        guild_id: GuildId,
+        pinboard_webhook: &str,
+        pin: &Message,
+        action: &str,
+    ) -> anyhow::Result<Option<Message>> {
+        let channel = pin.channel_id;
+        let author = &pin.author;
         // retrieve user as guild member in order to get nickname and guild avatar
         let member = match guild_id.member(&ctx.http, author).await {
             Ok(m) => Some(m),
             Err(e) => {
                 // log error but carry on
-                eprintln!("Error getting member: {e:#}");
+                tracing::error!("Error getting member: {e:#}");
                 None
             }
         };
@@ -206,16 +351,16 @@ impl Pinboard {
             .map(|ch| ch.name().to_string())
             .unwrap_or_else(|| "unknown-channel".to_string());
         // Filter attachments to find images
-        let mut images = last_pin
+        let mut images = pin
             .attachments
             .iter()
             .filter(|at| at.height.is_some())
             .map(|at| at.url.as_str());
         let self_name = handler.self_id.get().unwrap().to_user(&ctx).await?.name;
-        let mut embeds = Vec::with_capacity(last_pin.embeds.len() + 1);
-        let footer_str = format!("Pinned from #{channel_name} using {self_name}");
+        let mut embeds = Vec::with_capacity(pin.embeds.len() + 1);
+        let footer_str = format!("{action} from #{channel_name} using {self_name}");
         // retrieve actual message in order to get potential reply
-        let msg = last_pin.channel_id.message(&ctx.http, last_pin.id).await?;
+        let msg = pin.channel_id.message(&ctx.http, pin.id).await?;
         if let Some(reply) = &msg.referenced_message {
             let author = &reply.author;
             // retrieve user as guild member in order to get nickname and guild avatar
@@ -223,7 +368,7 @@ impl Pinboard {
                 Ok(m) => Some(m),
                 Err(e) => {
                     // log error but carry on
-                    eprintln!("Error getting member: {e:#}");
+                    tracing::error!("Error getting member: {e:#}");
                     None
                 }
             };
@@ -260,19 +405,19 @@ impl Pinboard {
         }
         // put first image with the embed for message text
         let image = images.next();
-        if !last_pin.content.is_empty() || image.is_some() {
+        if !pin.content.is_empty() || image.is_some() {
             embeds.push({
-                let mut content = last_pin.content.clone();
+                let mut content = pin.content.clone();
                 if !content.is_empty() {
                     content.push_str("\n\n");
                 }
-                _ = write!(&mut content, "[(Source)]({})", last_pin.link());
+                _ = write!(&mut content, "[(Source)]({})", pin.link());
                 let mut em = CreateEmbed::new()
                     .description(content)
                     .footer(CreateEmbedFooter::new(&footer_str))
-                    .timestamp(last_pin.timestamp)
+                    .timestamp(pin.timestamp)
                     .author({
-                        let mut at = CreateEmbedAuthor::new(name).url(last_pin.link());
+                        let mut at = CreateEmbedAuthor::new(name).url(pin.link());
                         if let Some(url) = avatar.as_ref() {
                             at = at.icon_url(url);
                         }
@@ -289,115 +434,186 @@ impl Pinboard {
             CreateEmbed::new()
                 .image(img)
                 .footer(CreateEmbedFooter::new(&footer_str))
-                .timestamp(last_pin.timestamp)
+                .timestamp(pin.timestamp)
         }));
         embeds.extend(
-            last_pin
-                .embeds
+            pin.embeds
                 .iter()
                 .filter(|em| em.kind.as_deref() == Some("rich"))
                 .map(copy_embed),
         );
+        let module = handler.module::<Pinboard>()?;
+        let webhook = module.get_webhook(ctx, guild_id, pinboard_webhook).await?;
+        let mut posted = None;
         for embeds in embeds.chunks(MAX_EMBEDS).map(Vec::from) {
-            ctx.http
-                .get_webhook_from_url(&pinboard_webhook)
-                .await
-                .context("error getting webhook")?
+            let res = webhook
                 .execute(&ctx.http, true, {
-                    let mut wh = ExecuteWebhook::new().embeds(embeds).username(name);
+                    // pins are copied as embeds, nothing in them should ping
+                    let mut wh = MentionPolicy::default()
+                        .execute_webhook([], [])
+                        .embeds(embeds)
+                        .username(name);
                     if let Some(url) = avatar.as_ref() {
                         wh = wh.avatar_url(url);
                     }
                     wh
                 })
-                .await
-                .context("error calling pinboard webhook")?;
+                .await;
+            match res {
+                Err(e) => {
+                    // webhook might have been deleted, retrieve it again next time
+                    module.invalidate_webhook(guild_id).await;
+                    return Err(e).context("error calling pinboard webhook");
+                }
+                Ok(msg) => {
+                    posted = posted.or(msg);
+                }
+            }
         }
-        last_pin
-            .unpin(&ctx.http)
-            .await
-            .context("error deleting pinned message")?;
-        Ok(())
+        Ok(posted)
     }
 }
 
 #[derive(Command)]
-#[cmd(name = "register_channel_to_pinboard")]
-struct RegisterChannel;
+#[cmd(name = "Send to pinboard", message)]
+struct SendToPinboard(Message);
+
+impl SendToPinboard {
+    async fn send(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> anyhow::Result<String> {
+        let pinboard_webhook = Pinboard::pinboard_webhook(handler, guild_id).await?;
+        if !Pinboard::channel_allowed(handler, ctx, guild_id, self.0.channel_id).await? {
+            bail!("This channel is not registered to the pinboard");
+        }
+        let posted =
+            Pinboard::post_to_pinboard(handler, ctx, guild_id, &pinboard_webhook, &self.0, "Sent")
+                .await?;
+        Ok(match posted {
+            Some(msg) => format!("Sent to pinboard: {}", msg.link()),
+            None => "Sent to pinboard".to_string(),
+        })
+    }
+}
 
 #[async_trait]
-impl BotCommand for RegisterChannel {
+impl BotCommand for SendToPinboard {
     type Data = Handler;
     const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
 
     async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction.guild_id()?;
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let resp = match self.send(handler, ctx, guild_id).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::error!("send to pinboard failed: {e:?}");
+                e.to_string()
+            }
+        };
+        interaction
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(resp))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "register_channel_to_pinboard", guild_only)]
+struct RegisterChannel;
+
+#[async_trait]
+impl GuildCommand for RegisterChannel {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
+
+    async fn run_in_guild(
         self,
         data: &Handler,
         _: &Context,
         interaction: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let Some(guild_id) = interaction.guild_id else {
-            bail!("Must be run in a guild")
-        };
+        // threads follow their parent channel's registration
+        let channel_id = interaction.parent_channel_id();
         let db = data.db.lock().await;
         db.conn.execute(
-            "INSERT INTO pinboard_allowed_channels (guild_id, channel_id) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
-            [guild_id.get(), interaction.channel_id.get()])?;
-        CommandResponse::private(format!(
-            "Registered <#{}> to pinboard",
-            interaction.channel_id.get()
-        ))
+            "INSERT INTO pinboard_allowed_channels (guild_id, channel_id) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET deleted_at = NULL",
+            params![SqlGuildId(guild_id), SqlChannelId(channel_id)])?;
+        CommandResponse::private(format!("Registered <#{}> to pinboard", channel_id.get()))
     }
 }
 
 #[derive(Command)]
-#[cmd(name = "unregister_channel_from_pinboard")]
+#[cmd(name = "unregister_channel_from_pinboard", guild_only)]
 struct UnregisterChannel;
 
 #[async_trait]
-impl BotCommand for UnregisterChannel {
+impl GuildCommand for UnregisterChannel {
     type Data = Handler;
     const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
 
-    async fn run(
+    async fn run_in_guild(
         self,
         data: &Handler,
-        _: &Context,
+        ctx: &Context,
         interaction: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let Some(guild_id) = interaction.guild_id else {
-            bail!("Must be run in a guild")
-        };
-        let db = data.db.lock().await;
-        db.conn.execute(
-            "DELETE FROM pinboard_allowed_channels WHERE guild_id = ?1 AND channel_id = ?2",
-            [guild_id.get(), interaction.channel_id.get()],
+        let channel_id = interaction.parent_channel_id();
+        let token = data.db.lock().await.soft_delete(
+            "pinboard_allowed_channels",
+            "guild_id = ?1 AND channel_id = ?2",
+            params![SqlGuildId(guild_id), SqlChannelId(channel_id)],
         )?;
-        CommandResponse::private(format!(
-            "Unregistered <#{}> from pinboard",
-            interaction.channel_id.get()
-        ))
+        let Some(token) = token else {
+            return CommandResponse::private(format!(
+                "<#{}> is not registered to pinboard",
+                channel_id.get()
+            ));
+        };
+        let resp = undo_response(
+            "pinboard_allowed_channels",
+            guild_id,
+            token,
+            format!("Unregistered <#{}> from pinboard", channel_id.get()),
+        );
+        interaction.create_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
     }
 }
 
 #[derive(Command)]
-#[cmd(name = "list_pinboard_channels")]
+#[cmd(name = "list_pinboard_channels", guild_only)]
 struct ListChannels;
 
 #[async_trait]
-impl BotCommand for ListChannels {
+impl GuildCommand for ListChannels {
     type Data = Handler;
     const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
 
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
         _: &Context,
-        interaction: &CommandInteraction,
+        _interaction: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let Some(guild_id) = interaction.guild_id else {
-            bail!("Must be run in a guild")
-        };
         let channels = load_allowed_channels(handler, guild_id).await?;
         let resp = match channels.as_slice() {
             [] => "No channels configured, pins from every channel will be sent to pinboard"
@@ -417,7 +633,7 @@ impl BotCommand for ListChannels {
 #[async_trait]
 impl Module for Pinboard {
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
-        Ok(Pinboard)
+        Ok(Pinboard::default())
     }
 
     async fn setup(&mut self, db: &mut crate::db::Db) -> anyhow::Result<()> {
@@ -431,6 +647,7 @@ impl Module for Pinboard {
             )",
             [],
         )?;
+        db.add_soft_delete("pinboard_allowed_channels")?;
         Ok(())
     }
 
@@ -443,5 +660,14 @@ impl Module for Pinboard {
         store.register::<RegisterChannel>();
         store.register::<UnregisterChannel>();
         store.register::<ListChannels>();
+        store.register::<SendToPinboard>();
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(Pinboard::undo_unregister_channel);
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(Pinboard::on_pins_update);
     }
 }
@@ -4,23 +4,36 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context as _};
+use chrono::Utc;
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
 use itertools::Itertools;
+use rusqlite::params;
 use serenity::builder::{
     CreateAllowedMentions, CreateInteractionResponse, CreateInteractionResponseMessage,
     EditInteractionResponse, EditMessage,
 };
 use serenity::http::Http;
 use serenity::model::id::MessageId;
+use serenity::model::application::CommandType;
 use serenity::model::prelude::CommandInteraction;
-use serenity::model::prelude::{ChannelId, Message, Reaction, ReactionType, UserId};
+use serenity::model::prelude::{ChannelId, Message, Reaction, ReactionType, Ready, UserId};
 use serenity::{async_trait, prelude::Context};
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
 use serenity_command_derive::Command;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tokio::time::timeout;
 
-use crate::{CommandStore, CompletionStore, Handler, Module, ModuleMap, events};
+use crate::command_context::{get_focused_option, get_str_opt_ac};
+use crate::emotes::{complete_emotes, validate_emote};
+use crate::db::{Db, Migration, SqlChannelId, SqlMessageId, SqlUserId};
+use crate::gateway::GatewayHandlers;
+use crate::modules::button_polls;
+use crate::scheduler::JobStore;
+use crate::{
+    CommandStore, CompletionStore, ComponentStore, Handler, ModalStore, Module, ModuleMap, events,
+};
 
 const YES: &str = "<:FeelsGoodCrab:988509541069127780>";
 const NO: &str = "<:FeelsBadCrab:988508541499342918>";
@@ -41,6 +54,9 @@ pub enum PollType {
 pub struct PendingPoll {
     msg: Message,
     typ: PollType,
+    // votes cast before the task started, for polls restored after a restart
+    users_yes: Vec<UserId>,
+    users_no: Vec<UserId>,
 }
 
 #[derive(Clone, Copy)]
@@ -65,9 +81,9 @@ pub type PendingPolls = VecDeque<PendingPoll>;
 #[derive(Command, Debug)]
 #[cmd(name = "ready_poll", desc = "Poll to start a listening party")]
 pub struct ReadyPoll {
-    #[cmd(desc = "Count emote")]
+    #[cmd(desc = "Count emote", autocomplete)]
     pub count_emote: Option<String>,
-    #[cmd(desc = "Emote Go")]
+    #[cmd(desc = "Emote Go", autocomplete)]
     pub go_emote: Option<String>,
 }
 
@@ -98,51 +114,232 @@ async fn create_poll(
 
     // retrieve handle to interaction response so we can edit it later
     let resp = interaction.get_response(http).await?;
+    let author_id = interaction.user.id;
+    save_poll(handler, &resp, author_id, &poll_type).await?;
+    let pending_poll = PendingPoll {
+        msg: resp.clone(),
+        typ: poll_type,
+        users_yes: Vec::new(),
+        users_no: Vec::new(),
+    };
+    let is_ready = matches!(pending_poll.typ, PollType::Ready { .. });
+    start_poll(
+        handler,
+        module,
+        Arc::clone(&ctx.http),
+        pending_poll,
+        author_id,
+        event_handlers,
+    )
+    .await?;
+
+    // add reacts to interaction response
+    resp.react(http, ReactionType::from_str(&module.yes)?)
+        .await
+        .context(format!("error adding yes react: {}", &module.yes))?;
+    resp.react(http, ReactionType::from_str(&module.no)?)
+        .await
+        .context("error adding no react")?;
+    if is_ready {
+        resp.react(http, ReactionType::from_str(&module.start)?)
+            .await
+            .context("error adding go react")?;
+    }
+    Ok(())
+}
+
+// add a poll to the list and spawn the task handling its reactions
+async fn start_poll(
+    handler: &Handler,
+    module: &ModPoll,
+    http: Arc<Http>,
+    poll: PendingPoll,
+    author_id: UserId,
+    event_handlers: Arc<events::EventHandlers>,
+) -> anyhow::Result<()> {
     // create async channel in order to process reactions asynchronously
     let (sender, receiver) = channel(32);
-
     {
-        // add this poll to the list
         // using a sub-scope to ensure write lock gets dropped ASAP
         let mut polls = module.ready_polls.write().await;
         while polls.len() >= MAX_POLLS {
+            // dropping the sender stops the task, which forgets the poll
             polls.pop_back();
         }
         let handle = PollHandle {
             sender,
-            user_id: interaction.user.id,
+            user_id: author_id,
         };
-        polls.push_front((resp.id, handle));
+        polls.push_front((poll.msg.id, handle));
     }
+    handler.spawn_task(
+        "poll",
+        poll_task(
+            handler.module_arc()?,
+            http,
+            Arc::clone(&handler.db),
+            poll,
+            receiver,
+            event_handlers,
+        ),
+    );
+    Ok(())
+}
 
-    // add reacts to interaction response
-    resp.react(http, ReactionType::from_str(&module.yes)?)
-        .await
-        .context(format!("error adding yes react: {}", &module.yes))?;
-    resp.react(http, ReactionType::from_str(&module.no)?)
+// Polls are stored until their task stops, so that they can be restored after a restart
+async fn save_poll(
+    handler: &Handler,
+    msg: &Message,
+    author_id: UserId,
+    typ: &PollType,
+) -> anyhow::Result<()> {
+    let (question, count_emote, go_emote) = match typ {
+        PollType::Question(q) => (Some(q.clone()), None, None),
+        PollType::Ready {
+            count_emote,
+            go_emote,
+        } => (None, count_emote.clone(), go_emote.clone()),
+    };
+    let (message_id, channel_id) = (msg.id, msg.channel_id);
+    handler
+        .db_call(move |db| {
+            db.conn.execute(
+                "INSERT OR REPLACE INTO ready_poll (
+                    message_id, channel_id, author_id, question, count_emote, go_emote, ts
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    SqlMessageId(message_id),
+                    SqlChannelId(channel_id),
+                    SqlUserId(author_id),
+                    question,
+                    count_emote,
+                    go_emote,
+                    Utc::now().timestamp()
+                ],
+            )?;
+            Ok(())
+        })
         .await
-        .context("error adding no react")?;
-    if let PollType::Ready { .. } = &poll_type {
-        resp.react(http, ReactionType::from_str(&module.start)?)
-            .await
-            .context("error adding go react")?;
-    }
+}
 
-    // spawn task to handle reactions
-    let http_arc = Arc::clone(&ctx.http);
-    let pending_poll = PendingPoll {
-        msg: resp,
-        typ: poll_type,
+async fn forget_poll(db: &Mutex<Db>, message_id: MessageId) -> anyhow::Result<()> {
+    db.lock().await.conn.execute(
+        "DELETE FROM ready_poll WHERE message_id = ?1",
+        [SqlMessageId(message_id)],
+    )?;
+    Ok(())
+}
+
+struct SavedPoll {
+    message_id: MessageId,
+    channel_id: ChannelId,
+    author_id: UserId,
+    typ: PollType,
+}
+
+fn saved_polls(db: &Db) -> anyhow::Result<Vec<SavedPoll>> {
+    let polls = db
+        .conn
+        .prepare(
+            "SELECT message_id, channel_id, author_id, question, count_emote, go_emote
+             FROM ready_poll ORDER BY ts DESC LIMIT ?1",
+        )?
+        .query([MAX_POLLS])?
+        .map(|row| {
+            let question: Option<String> = row.get(3)?;
+            let typ = match question {
+                Some(q) => PollType::Question(q),
+                None => PollType::Ready {
+                    count_emote: row.get(4)?,
+                    go_emote: row.get(5)?,
+                },
+            };
+            Ok(SavedPoll {
+                message_id: row.get::<_, SqlMessageId>(0)?.0,
+                channel_id: row.get::<_, SqlChannelId>(1)?.0,
+                author_id: row.get::<_, SqlUserId>(2)?.0,
+                typ,
+            })
+        })
+        .collect()?;
+    Ok(polls)
+}
+
+// users who reacted with an emote, besides the bot
+async fn reacted(
+    handler: &Handler,
+    http: &Http,
+    msg: &Message,
+    emote: &str,
+) -> anyhow::Result<Vec<UserId>> {
+    let users = msg
+        .reaction_users(http, ReactionType::from_str(emote)?, Some(100), None)
+        .await?;
+    Ok(users
+        .into_iter()
+        .map(|u| u.id)
+        .filter(|id| handler.self_id.get() != Some(id))
+        .collect())
+}
+
+async fn restore_poll(handler: &Handler, ctx: &Context, saved: SavedPoll) -> anyhow::Result<()> {
+    let module: &ModPoll = handler.module()?;
+    let msg = match saved.channel_id.message(&ctx.http, saved.message_id).await {
+        Ok(msg) => msg,
+        Err(e) => {
+            // the message was deleted, or the bot can't see it anymore
+            forget_poll(&handler.db, saved.message_id).await?;
+            return Err(e.into());
+        }
     };
-    tokio::spawn(poll_task(
-        handler.module_arc().unwrap(),
-        http_arc,
-        // resp,
-        pending_poll,
-        receiver,
+    // reacts added while the bot was down are picked up from the message
+    let poll = PendingPoll {
+        users_yes: reacted(handler, &ctx.http, &msg, &module.yes).await?,
+        users_no: reacted(handler, &ctx.http, &msg, &module.no).await?,
+        msg,
+        typ: saved.typ,
+    };
+    let event_handlers = Arc::clone(&handler.event_handlers);
+    start_poll(
+        handler,
+        module,
+        Arc::clone(&ctx.http),
+        poll,
+        saved.author_id,
         event_handlers,
-    ));
-    Ok(())
+    )
+    .await
+}
+
+// Poll tasks don't survive restarts, start them again for the polls that were active
+fn restore_polls<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    _ready: &'a Ready,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let module: &ModPoll = handler.module()?;
+        let saved = handler.db_read(saved_polls).await?;
+        // oldest first, so that the most recent polls are the last to be evicted
+        for saved in saved.into_iter().rev() {
+            // ready is sent again on reconnects, polls may already be running
+            let running = module
+                .ready_polls
+                .read()
+                .await
+                .iter()
+                .any(|(id, _)| *id == saved.message_id);
+            if running {
+                continue;
+            }
+            let message_id = saved.message_id;
+            if let Err(e) = restore_poll(handler, ctx, saved).await {
+                tracing::warn!(%message_id, "Cannot restore poll: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
 }
 
 impl ReadyPoll {
@@ -192,10 +389,14 @@ impl BotCommand for ReadyPoll {
         ctx: &Context,
         interaction: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
+        // check the emotes before responding, so errors are shown to the user
+        for emote in [&self.count_emote, &self.go_emote].into_iter().flatten() {
+            validate_emote(ctx, interaction.guild_id, emote).await?;
+        }
         // create ready poll message
         let resp = match self.create_poll(handler, ctx, interaction).await {
             Err(e) => {
-                dbg!(&e);
+                tracing::error!("Error creating poll: {e:?}");
                 Some(e.to_string())
             }
             _ => None,
@@ -228,7 +429,7 @@ impl BotCommand for Poll {
         // create ready poll message
         let resp = match self.create_poll(handler, ctx, interaction).await {
             Err(e) => {
-                dbg!(&e);
+                tracing::error!("Error creating poll: {e:?}");
                 Some(e.to_string())
             }
             _ => None,
@@ -287,31 +488,36 @@ fn build_message(typ: &PollType, users_yes: &[UserId], users_no: &[UserId]) -> S
 }
 
 // task responsible for handling reactions to a poll
+#[tracing::instrument(name = "poll", skip_all, fields(message_id = %poll.msg.id))]
 async fn poll_task(
     module: Arc<ModPoll>,
     http: Arc<Http>,
-    poll: PendingPoll,
+    db: Arc<Mutex<Db>>,
+    mut poll: PendingPoll,
     mut r: Receiver<PollEvent>,
     event_handlers: Arc<events::EventHandlers>
 ) {
     // poll state
-    let mut users_yes = Vec::new(); // list of users who have clicked the YES react
-    let mut users_no = Vec::new(); // list of users who have clicked the NO react
-    let mut changed = false; // whether the message needs to be edited
+    // lists of users who have clicked the YES and NO reacts, restored polls start with the
+    // reacts already on the message
+    let mut users_yes = std::mem::take(&mut poll.users_yes);
+    let mut users_no = std::mem::take(&mut poll.users_no);
+    // whether the message needs to be edited, restored polls may have missed reacts
+    let mut changed = !users_yes.is_empty() || !users_no.is_empty();
     let mut started = false; // whether the poll's author has clicked the GO react
     let mut last_event = Instant::now();
 
-    loop {
+    'poll: loop {
         if last_event.elapsed() >= Duration::from_secs(900) {
             // too long since last event, stop this task
-            return;
+            break;
         }
 
         // poll for new events
         while let Ok(evt) = timeout(Duration::from_millis(150), r.recv()).await {
             let Some(evt) = evt else {
                 // channel closed
-                return;
+                break 'poll;
             };
             last_event = Instant::now();
             match evt {
@@ -350,7 +556,7 @@ async fn poll_task(
                     )
                     .await;
                     if let Err(e) = res {
-                        eprintln!("error executing crabdown: {e}");
+                        tracing::error!("error executing crabdown: {e}");
                     }
                     continue;
                 }
@@ -377,12 +583,16 @@ async fn poll_task(
                     )
                     .await;
                 if let Err(e) = res {
-                    eprintln!("failed to edit ready message: {e}");
+                    tracing::error!("failed to edit ready message: {e}");
                 }
             }
         });
         changed = false;
     }
+    // the task is only cancelled when the bot shuts down, the poll is then restored on startup
+    if let Err(e) = forget_poll(&db, poll.msg.id).await {
+        tracing::error!("failed to forget poll: {e:?}");
+    }
 }
 
 #[derive(Debug)]
@@ -412,7 +622,7 @@ pub async fn crabdown(
     let go_emote = go_emote.unwrap_or(&module.go);
     for i in 0..3 {
         // repeat count emote 3 - i times
-        let contents = std::iter::repeat(count_emote).take(3 - i).join(" ");
+        let contents = std::iter::repeat_n(count_emote, 3 - i).join(" ");
         channel.say(http, contents).await?;
         interval.tick().await;
     }
@@ -531,6 +741,28 @@ impl ModPoll {
     }
 }
 
+impl ModPoll {
+    fn complete_poll_emotes<'a>(
+        _handler: &'a Handler,
+        ctx: &'a Context,
+        key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if key != ("ready_poll", CommandType::ChatInput) {
+                return Ok(false);
+            }
+            let options = &ac.data.options;
+            let partial = get_focused_option(options)
+                .and_then(|focused| get_str_opt_ac(options, focused))
+                .unwrap_or("");
+            complete_emotes(ctx, ac, partial).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
 impl Default for ModPoll {
     fn default() -> Self {
         Self::new(None, None, None, None, None)
@@ -543,8 +775,71 @@ impl Module for ModPoll {
         Ok(Default::default())
     }
 
-    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+    const NAME: &'static str = "ModPoll";
+    const MIGRATIONS: &'static [Migration] = &[
+        Migration::sql(
+            1,
+            "create button polls",
+            "CREATE TABLE IF NOT EXISTS button_poll (
+                message_id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                author_id INTEGER NOT NULL,
+                question STRING NOT NULL,
+                options STRING NOT NULL,
+                closes_at INTEGER,
+                ts INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS button_poll_vote (
+                message_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                choice INTEGER NOT NULL,
+                ts INTEGER NOT NULL,
+                UNIQUE(message_id, user_id)
+            );",
+        ),
+        Migration::sql(
+            2,
+            "add public, menu and closed to button polls",
+            "ALTER TABLE button_poll ADD COLUMN public BOOLEAN NOT NULL DEFAULT(false);
+            ALTER TABLE button_poll ADD COLUMN menu BOOLEAN NOT NULL DEFAULT(false);
+            ALTER TABLE button_poll ADD COLUMN closed BOOLEAN NOT NULL DEFAULT(false);",
+        ),
+        Migration::sql(
+            3,
+            "create ready_poll",
+            "CREATE TABLE IF NOT EXISTS ready_poll (
+                message_id INTEGER PRIMARY KEY,
+                channel_id INTEGER NOT NULL,
+                author_id INTEGER NOT NULL,
+                question STRING,
+                count_emote STRING,
+                go_emote STRING,
+                ts INTEGER NOT NULL
+            )",
+        ),
+    ];
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
         store.register::<ReadyPoll>();
         store.register::<Poll>();
+        button_polls::register_commands(store);
+        completions.push(ModPoll::complete_poll_emotes);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        button_polls::register_component_handlers(handlers);
+    }
+
+    fn register_modal_handlers(&self, handlers: &mut ModalStore) {
+        button_polls::register_modal_handlers(handlers);
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        button_polls::register_jobs(jobs);
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(restore_polls);
     }
 }
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use image::ImageOutputFormat;
+use itertools::Itertools;
+use rand::seq::SliceRandom;
+use rusqlite::params;
+use serenity::builder::{
+    CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditInteractionResponse,
+};
+use serenity::http::Http;
+use serenity::model::application::{ButtonStyle, ComponentInteraction};
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, UserId};
+use serenity::prelude::Mutex;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::modules::lastfm::fetch_cover;
+use crate::modules::{AlbumLookup, ModLp};
+use crate::prelude::*;
+
+const CHOICES: usize = 4;
+const DEFAULT_ROUNDS: usize = 5;
+const MAX_ROUNDS: usize = 10;
+const ROUND_DURATION: Duration = Duration::from_secs(20);
+const BLUR_SIGMA: f32 = 12.0;
+const COVER_NAME: &str = "quiz.png";
+const ANSWER_PREFIX: &str = "quiz:";
+const LEADERBOARD_SIZE: usize = 10;
+
+struct Question {
+    choices: Vec<String>,
+    answer: usize,
+    cover: Option<Vec<u8>>,
+}
+
+impl Question {
+    // Only keep the first letter of each word, e.g. "Artist - Album" -> "A***** - A****"
+    fn hint(&self) -> String {
+        self.choices[self.answer]
+            .split(' ')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) if first.is_alphanumeric() => {
+                        std::iter::once(first).chain(chars.map(|_| '*')).collect()
+                    }
+                    _ => word.to_string(),
+                }
+            })
+            .join(" ")
+    }
+}
+
+// A quiz runs in a channel, one question at a time
+struct Game {
+    guild_id: GuildId,
+    questions: Vec<Question>,
+    round: usize,
+    answers: HashMap<UserId, usize>,
+    scores: HashMap<UserId, u32>,
+}
+
+#[derive(Default)]
+pub struct Quiz {
+    games: Mutex<HashMap<ChannelId, Game>>,
+}
+
+async fn blurred_cover(lookup: &AlbumLookup, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(url) = lookup.lookup_album(name, None).await?.and_then(|a| a.cover) else {
+        return Ok(None);
+    };
+    let Some(img) = fetch_cover(&url).await? else {
+        return Ok(None);
+    };
+    let mut out = Cursor::new(Vec::new());
+    img.blur(BLUR_SIGMA)
+        .write_to(&mut out, ImageOutputFormat::Png)?;
+    Ok(Some(out.into_inner()))
+}
+
+async fn build_questions(
+    handler: &Handler,
+    guild_id: GuildId,
+    rounds: usize,
+) -> anyhow::Result<Vec<Question>> {
+    let names: Vec<String> = handler
+        .db
+        .lock()
+        .await
+        .conn
+        .prepare("SELECT DISTINCT name FROM lp_history WHERE guild_id = ?1 AND name != 'this'")?
+        .query([SqlGuildId(guild_id)])?
+        .map(|row| row.get(0))
+        .collect()?;
+    if names.len() < CHOICES {
+        bail!("Not enough listening parties in this server's history to run a quiz");
+    }
+    let lookup = handler.module::<AlbumLookup>()?;
+    let answers = names
+        .choose_multiple(&mut rand::thread_rng(), rounds.min(names.len()))
+        .cloned()
+        .collect_vec();
+    let mut questions = Vec::new();
+    for answer in answers {
+        let mut choices = names
+            .iter()
+            .filter(|name| **name != answer)
+            .cloned()
+            .collect_vec()
+            .choose_multiple(&mut rand::thread_rng(), CHOICES - 1)
+            .cloned()
+            .collect_vec();
+        choices.push(answer.clone());
+        choices.shuffle(&mut rand::thread_rng());
+        let cover = blurred_cover(lookup, &answer).await.unwrap_or_else(|e| {
+            tracing::warn!("could not get quiz cover for {answer}: {e:?}");
+            None
+        });
+        questions.push(Question {
+            answer: choices.iter().position(|c| *c == answer).unwrap(),
+            choices,
+            cover,
+        });
+    }
+    Ok(questions)
+}
+
+fn question_message(question: &Question, round: usize, rounds: usize) -> CreateMessage {
+    let buttons = question
+        .choices
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| {
+            CreateButton::new(format!("{ANSWER_PREFIX}{round}:{i}"))
+                .label(choice.chars().take(80).collect::<String>())
+                .style(ButtonStyle::Secondary)
+        })
+        .collect_vec();
+    let mut embed = CreateEmbed::new()
+        .title(format!("Question {}/{rounds}", round + 1))
+        .description(format!(
+            "Which album is this?\nHint: `{}`\nAnswers close <t:{}:R>",
+            question.hint(),
+            chrono::Utc::now().timestamp() + ROUND_DURATION.as_secs() as i64
+        ));
+    let mut msg = CreateMessage::new();
+    if let Some(cover) = &question.cover {
+        embed = embed.image(format!("attachment://{COVER_NAME}"));
+        msg = msg.add_file(CreateAttachment::bytes(cover.clone(), COVER_NAME));
+    }
+    msg.embed(embed)
+        .components(vec![CreateActionRow::Buttons(buttons)])
+}
+
+fn scoreboard(scores: &HashMap<UserId, u32>) -> String {
+    if scores.is_empty() {
+        return "Nobody scored any points".to_string();
+    }
+    scores
+        .iter()
+        .sorted_by_key(|(_, score)| std::cmp::Reverse(**score))
+        .enumerate()
+        .map(|(i, (user_id, score))| format!("{}. <@{user_id}>: {score}", i + 1))
+        .join("\n")
+}
+
+fn save_scores(db: &Db, guild_id: GuildId, scores: &HashMap<UserId, u32>) -> anyhow::Result<()> {
+    for (user_id, score) in scores {
+        db.conn.execute(
+            "INSERT INTO quiz_score (guild_id, user_id, points, games) VALUES (?1, ?2, ?3, 1)
+             ON CONFLICT(guild_id, user_id) DO UPDATE SET
+                points = points + ?3, games = games + 1",
+            params![SqlGuildId(guild_id), SqlUserId(*user_id), score],
+        )?;
+    }
+    Ok(())
+}
+
+// Close the current question after a delay, then ask the next one or end the game
+async fn run_game(quiz: Arc<Quiz>, db: Arc<Mutex<Db>>, http: Arc<Http>, channel_id: ChannelId) {
+    loop {
+        tokio::time::sleep(ROUND_DURATION).await;
+        let (reveal, next) = {
+            let mut games = quiz.games.lock().await;
+            let Some(game) = games.get_mut(&channel_id) else {
+                return;
+            };
+            let question = &game.questions[game.round];
+            let correct = game
+                .answers
+                .drain()
+                .filter(|(_, choice)| *choice == question.answer)
+                .map(|(user_id, _)| user_id)
+                .collect_vec();
+            for user_id in &correct {
+                *game.scores.entry(*user_id).or_default() += 1;
+            }
+            let mut reveal = format!("The answer was **{}**", question.choices[question.answer]);
+            if !correct.is_empty() {
+                let winners = correct.iter().map(|u| format!("<@{u}>")).join(", ");
+                reveal.push_str(&format!(", well done {winners}"));
+            }
+            game.round += 1;
+            if game.round < game.questions.len() {
+                let msg = question_message(
+                    &game.questions[game.round],
+                    game.round,
+                    game.questions.len(),
+                );
+                (reveal, Some(msg))
+            } else {
+                let game = games.remove(&channel_id).unwrap();
+                if let Err(e) = save_scores(&*db.lock().await, game.guild_id, &game.scores) {
+                    tracing::error!("Error saving quiz scores: {e:?}");
+                }
+                reveal.push_str(&format!(
+                    "\n\n**Final scores**\n{}",
+                    scoreboard(&game.scores)
+                ));
+                (reveal, None)
+            }
+        };
+        if let Err(e) = channel_id.say(&http, reveal).await {
+            tracing::error!("Error revealing quiz answer: {e:?}");
+        }
+        let Some(next) = next else {
+            return;
+        };
+        if let Err(e) = channel_id.send_message(&http, next).await {
+            tracing::error!("Error posting quiz question: {e:?}");
+            quiz.games.lock().await.remove(&channel_id);
+            return;
+        }
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "quiz",
+    desc = "Start a quiz on the albums of this server's listening parties"
+)]
+pub struct StartQuiz {
+    #[cmd(desc = "Number of questions (default 5)")]
+    rounds: Option<i64>,
+}
+
+impl StartQuiz {
+    async fn start(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<String> {
+        let guild_id = command.guild_id()?;
+        let channel_id = command.channel_id;
+        let quiz = handler.module_arc::<Quiz>()?;
+        if quiz.games.lock().await.contains_key(&channel_id) {
+            bail!("A quiz is already running in this channel");
+        }
+        let rounds = self
+            .rounds
+            .map(|n| n.clamp(1, MAX_ROUNDS as i64) as usize)
+            .unwrap_or(DEFAULT_ROUNDS);
+        let questions = build_questions(handler, guild_id, rounds).await?;
+        let first = question_message(&questions[0], 0, questions.len());
+        {
+            let mut games = quiz.games.lock().await;
+            if games.contains_key(&channel_id) {
+                bail!("A quiz is already running in this channel");
+            }
+            games.insert(
+                channel_id,
+                Game {
+                    guild_id,
+                    round: 0,
+                    answers: HashMap::new(),
+                    scores: HashMap::new(),
+                    questions,
+                },
+            );
+        }
+        if let Err(e) = channel_id.send_message(&ctx.http, first).await {
+            quiz.games.lock().await.remove(&channel_id);
+            return Err(e.into());
+        }
+        handler.spawn_task(
+            "quiz",
+            run_game(
+                quiz,
+                Arc::clone(&handler.db),
+                Arc::clone(&ctx.http),
+                channel_id,
+            ),
+        );
+        Ok("Quiz started!".to_string())
+    }
+}
+
+#[async_trait]
+impl BotCommand for StartQuiz {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let content = match self.start(handler, ctx, command).await {
+            Ok(content) => content,
+            Err(e) => e.to_string(),
+        };
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "quiz_leaderboard", desc = "Show the best quiz players")]
+pub struct QuizLeaderboard {}
+
+#[async_trait]
+impl BotCommand for QuizLeaderboard {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let top: Vec<(SqlUserId, u64, u64)> = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .prepare(
+                "SELECT user_id, points, games FROM quiz_score WHERE guild_id = ?1
+                 ORDER BY points DESC LIMIT ?2",
+            )?
+            .query(params![SqlGuildId(guild_id), LEADERBOARD_SIZE])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .collect()?;
+        if top.is_empty() {
+            bail!("Nobody has played a quiz yet");
+        }
+        let lines = top
+            .into_iter()
+            .enumerate()
+            .map(|(i, (SqlUserId(user_id), points, games))| {
+                format!("{}. <@{user_id}>: {points} points in {games} games", i + 1)
+            })
+            .join("\n");
+        CommandResponse::public(
+            CreateEmbed::new()
+                .title("Quiz leaderboard")
+                .description(lines),
+        )
+    }
+}
+
+impl Quiz {
+    fn answer<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(ids) = component.data.custom_id.strip_prefix(ANSWER_PREFIX) else {
+                return Ok(false);
+            };
+            let (round, choice) = ids
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid quiz answer id: {ids}"))?;
+            let (round, choice): (usize, usize) = (round.parse()?, choice.parse()?);
+            let content = {
+                let quiz = handler.module::<Quiz>()?;
+                let mut games = quiz.games.lock().await;
+                match games.get_mut(&component.channel_id) {
+                    Some(game) if game.round == round => {
+                        let question = &game.questions[round];
+                        let choice_name = question
+                            .choices
+                            .get(choice)
+                            .ok_or_else(|| anyhow!("invalid quiz choice {choice}"))?;
+                        let previous = game.answers.insert(component.user.id, choice);
+                        if previous.is_some() {
+                            format!("Answer changed to {choice_name}")
+                        } else {
+                            format!("Answered {choice_name}")
+                        }
+                    }
+                    _ => "This question is over".to_string(),
+                }
+            };
+            let msg = CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true);
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for Quiz {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<ModLp>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Quiz::default())
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS quiz_score (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                points INTEGER NOT NULL,
+                games INTEGER NOT NULL,
+                UNIQUE(guild_id, user_id)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<StartQuiz>();
+        store.register::<QuizLeaderboard>();
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(Quiz::answer);
+    }
+}
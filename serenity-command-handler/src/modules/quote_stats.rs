@@ -0,0 +1,266 @@
+// Ranks quotes by the reactions their original message got.
+// Reaction counts are snapshotted by the `quote_reactions` job. Only quotes younger than the
+// module's `max_age` are refreshed, older ones keep their last counts; add the module with
+// `with_module(QuoteStats::new(max_age))` to change it.
+use std::time::Duration;
+
+use fallible_iterator::FallibleIterator;
+use futures::{future::BoxFuture, FutureExt};
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::CreateEmbed;
+use serenity::http::Http;
+use serenity::model::channel::ReactionType;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, MessageId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+use tokio::time::sleep;
+
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlMessageId};
+use crate::modules::quotes::QUOTE_EMOJI;
+use crate::modules::Quotes;
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::schema::quote_reactions;
+
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+// How long a snapshot is kept before the counts are fetched again
+const REFRESH_AFTER_SECS: i64 = 6 * 3600;
+// Messages fetched per tick, with a pause between each to stay clear of rate limits
+const BATCH_SIZE: usize = 30;
+const FETCH_DELAY: Duration = Duration::from_secs(2);
+const RANKED: usize = 10;
+const EXCERPT_LEN: usize = 100;
+
+struct DueQuote {
+    guild_id: GuildId,
+    quote_number: u64,
+    channel_id: ChannelId,
+    message_id: MessageId,
+}
+
+impl Db {
+    // Recent quotes whose counts were never fetched or are stale, never fetched first
+    fn quotes_to_refresh(&self, max_age: Duration, now: i64) -> anyhow::Result<Vec<DueQuote>> {
+        let quoted_since = now - max_age.as_secs() as i64;
+        let due = self
+            .conn
+            .prepare(
+                "SELECT q.guild_id, q.quote_number, q.channel_id, q.message_id FROM quote q
+                 LEFT JOIN quote_reactions r
+                    ON r.guild_id = q.guild_id AND r.quote_number = q.quote_number
+                 WHERE q.deleted_at IS NULL AND q.ts >= ?1
+                    AND (r.refreshed_at IS NULL OR r.refreshed_at < ?2)
+                 ORDER BY r.refreshed_at IS NOT NULL, r.refreshed_at
+                 LIMIT ?3",
+            )?
+            .query(params![quoted_since, now - REFRESH_AFTER_SECS, BATCH_SIZE])?
+            .map(|row| {
+                Ok(DueQuote {
+                    guild_id: row.get::<_, SqlGuildId>(0)?.0,
+                    quote_number: row.get(1)?,
+                    channel_id: row.get::<_, SqlChannelId>(2)?.0,
+                    message_id: row.get::<_, SqlMessageId>(3)?.0,
+                })
+            })
+            .collect()?;
+        Ok(due)
+    }
+
+    // Store the counts of a quote, or only mark it as refreshed if they could not be
+    // fetched so that it doesn't get retried on every tick
+    fn save_reactions(
+        &self,
+        quote: &DueQuote,
+        counts: Option<(u64, Option<String>)>,
+        now: i64,
+    ) -> anyhow::Result<()> {
+        let (guild_id, number) = (SqlGuildId(quote.guild_id), quote.quote_number);
+        match counts {
+            Some((total, top_emoji)) => self.conn.execute(
+                "INSERT INTO quote_reactions (guild_id, quote_number, total, top_emoji, refreshed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(guild_id, quote_number)
+                 DO UPDATE SET total = ?3, top_emoji = ?4, refreshed_at = ?5",
+                params![guild_id, number, total, top_emoji, now],
+            )?,
+            None => self.conn.execute(
+                "INSERT INTO quote_reactions (guild_id, quote_number, refreshed_at)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT(guild_id, quote_number) DO UPDATE SET refreshed_at = ?3",
+                params![guild_id, number, now],
+            )?,
+        };
+        Ok(())
+    }
+}
+
+// Total reactions on a quoted message and its most used emoji. The quote emoji is left out,
+// it is how messages get quoted.
+async fn fetch_reactions(http: &Http, quote: &DueQuote) -> anyhow::Result<(u64, Option<String>)> {
+    let msg = quote.channel_id.message(http, quote.message_id).await?;
+    let quote_emoji = ReactionType::Unicode(QUOTE_EMOJI.to_string());
+    let reactions = msg
+        .reactions
+        .iter()
+        .filter(|r| r.reaction_type != quote_emoji)
+        .collect_vec();
+    let total = reactions.iter().map(|r| r.count).sum();
+    let top_emoji = reactions
+        .iter()
+        .max_by_key(|r| r.count)
+        .map(|r| r.reaction_type.to_string());
+    Ok((total, top_emoji))
+}
+
+fn refresh_reactions<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let max_age = handler.module::<QuoteStats>()?.max_age;
+        let now = handler.clock.now().timestamp();
+        let due = handler
+            .db_read(move |db| db.quotes_to_refresh(max_age, now))
+            .await?;
+        for quote in due {
+            let counts = match fetch_reactions(http, &quote).await {
+                Ok(counts) => Some(counts),
+                Err(e) => {
+                    tracing::error!(
+                        "Error fetching reactions of quote #{} in {}: {e:?}",
+                        quote.quote_number,
+                        quote.guild_id
+                    );
+                    None
+                }
+            };
+            let now = handler.clock.now().timestamp();
+            let saved = handler
+                .db_call(move |db| db.save_reactions(&quote, counts, now))
+                .await;
+            if let Err(e) = saved {
+                tracing::error!("Error saving quote reactions: {e:?}");
+            }
+            sleep(FETCH_DELAY).await;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+// Quote number, total reactions, most used emoji and contents
+type RankedQuote = (u64, u64, Option<String>, String);
+
+fn most_reacted(db: &Db, guild_id: GuildId) -> anyhow::Result<Vec<RankedQuote>> {
+    let quotes = db
+        .conn
+        .prepare(
+            "SELECT r.quote_number, r.total, r.top_emoji, q.contents FROM quote_reactions r
+             JOIN quote q ON q.guild_id = r.guild_id AND q.quote_number = r.quote_number
+             WHERE r.guild_id = ?1 AND r.total > 0 AND q.deleted_at IS NULL
+             ORDER BY r.total DESC, r.quote_number
+             LIMIT ?2",
+        )?
+        .query(params![SqlGuildId(guild_id), RANKED])?
+        .map(|row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                crate::db::column_as_string(row.get_ref(3)?)?,
+            ))
+        })
+        .collect()?;
+    Ok(quotes)
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "quote_stats",
+    desc = "Show the quotes whose messages got the most reactions",
+    guild_only
+)]
+pub struct ShowQuoteStats {}
+
+#[async_trait]
+impl GuildCommand for ShowQuoteStats {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        _command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let quotes = most_reacted(&*handler.db.lock().await, guild_id)?;
+        if quotes.is_empty() {
+            return CommandResponse::private("No reactions counted on quotes yet");
+        }
+        let description = quotes
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (number, total, top_emoji, contents))| {
+                let excerpt = contents
+                    .chars()
+                    .take(EXCERPT_LEN)
+                    .collect::<String>()
+                    .replace('\n', " ");
+                let top = top_emoji
+                    .map(|e| format!(", mostly {e}"))
+                    .unwrap_or_default();
+                format!(
+                    "{}. **#{number}**: {total} reactions{top}\n> {excerpt}",
+                    rank + 1
+                )
+            })
+            .join("\n");
+        CommandResponse::public(
+            CreateEmbed::new()
+                .title("Most reacted quotes")
+                .description(description),
+        )
+    }
+}
+
+pub struct QuoteStats {
+    max_age: Duration,
+}
+
+impl QuoteStats {
+    pub fn new(max_age: Duration) -> Self {
+        QuoteStats { max_age }
+    }
+}
+
+#[async_trait]
+impl Module for QuoteStats {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Quotes>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(QuoteStats::new(DEFAULT_MAX_AGE))
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_table(&quote_reactions::TABLE)?;
+        db.check_schema(&quote_reactions::TABLE)?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<ShowQuoteStats>();
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "quote_reactions",
+            Schedule::every(Duration::from_secs(600)),
+            refresh_reactions,
+        ));
+    }
+}
@@ -14,26 +14,42 @@ use itertools::Itertools;
 use rand::random;
 use regex::Regex;
 use rusqlite::{params, Error::SqliteFailure, ErrorCode};
+use serde::Deserialize;
 use serenity::{
     async_trait,
     builder::{
-        CreateAutocompleteResponse, CreateCommandOption, CreateEmbed, CreateEmbedAuthor,
-        CreateEmbedFooter, CreateInteractionResponse, GetMessages,
+        CreateActionRow, CreateAutocompleteResponse, CreateButton, CreateEmbed, CreateEmbedAuthor,
+        CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage,
+        CreateMessage, EditInteractionResponse, GetMessages,
     },
     model::{
         self,
-        application::{CommandInteraction, CommandType},
-        channel::Message,
+        application::{ButtonStyle, CommandInteraction, CommandType, ComponentInteraction},
+        channel::{Attachment, Message, Reaction},
         id::MessageId,
         prelude::{ChannelId, GuildId, ReactionType, UserId},
+        Permissions,
     },
     prelude::Context,
 };
 
-use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command::{BotCommand, CommandKey, CommandResponse, GuildCommand};
 use serenity_command_derive::Command;
 
-use crate::{command_context::get_str_opt_ac, prelude::*};
+use crate::{
+    command_context::{download_attachment, get_str_opt_ac, thread_parent},
+    db::{Db, SqlChannelId, SqlGuildId, SqlMessageId, SqlUserId},
+    gateway::{GatewayHandlers, ReactionAdd},
+    prelude::*,
+    schema::{self, quote, Select},
+    soft_delete::{handle_undo, undo_response},
+};
+
+pub(crate) const QUOTE_EMOJI: &str = "🗨️";
+
+const SAVE_QUOTE_PREFIX: &str = "save_quote:";
+const DISMISS_QUOTE_ID: &str = "save_quote:dismiss";
+const MAX_IMPORT_SIZE: u32 = 4 * 1024 * 1024;
 
 pub async fn message_to_quote_contents(
     _handler: &Handler,
@@ -43,7 +59,7 @@ pub async fn message_to_quote_contents(
     let quote_ndx = message
         .reactions
         .iter()
-        .find_position(|r| r.reaction_type == ReactionType::Unicode("🗨️".to_string()))
+        .find_position(|r| r.reaction_type == ReactionType::Unicode(QUOTE_EMOJI.to_string()))
         .map(|(ndx, _)| ndx)
         .unwrap_or(message.reactions.len());
     let prev_react = message
@@ -95,11 +111,11 @@ pub async fn message_to_quote_contents(
 
 pub struct Quote {
     pub quote_number: u64,
-    pub guild_id: u64,
-    pub channel_id: u64,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
     pub message_id: MessageId,
     pub ts: DateTime<Utc>,
-    pub author_id: u64,
+    pub author_id: UserId,
     pub author_name: String,
     pub contents: String,
     pub image: Option<String>,
@@ -107,30 +123,46 @@ pub struct Quote {
 
 pub async fn fetch_quote(
     handler: &Handler,
-    guild_id: u64,
+    guild_id: GuildId,
     quote_number: u64,
 ) -> anyhow::Result<Option<Quote>> {
-    let db = handler.db.lock().await;
-    let res = db.conn.query_row(
-            "SELECT guild_id, channel_id, message_id, ts, author_id, author_name, contents, image FROM quote
-     WHERE guild_id = ?1 AND quote_number = ?2",
-            [guild_id, quote_number],
-            |row| {
-                let dt = NaiveDateTime::from_timestamp_opt(row.get(3)?, 0)
-                    .unwrap_or_default(); // yes this was quoted in 1970, what of it?
-                Ok(Quote {
-                    quote_number,
-                    guild_id: row.get(0)?,
-                    channel_id: row.get(1)?,
-                    message_id: MessageId::new(row.get(2)?),
-                    ts: DateTime::<Utc>::from_utc(dt, Utc),
-                    author_id: row.get(4)?,
-                    author_name: row.get(5)?,
-                    contents: crate::db::column_as_string(row.get_ref(6)?)?,
-                    image: row.get(7)?,
-                })
-            },
-        );
+    handler
+        .db_read(move |db| quote_by_number(db, guild_id, quote_number))
+        .await
+}
+
+fn quote_by_number(db: &Db, guild_id: GuildId, quote_number: u64) -> anyhow::Result<Option<Quote>> {
+    let sql = Select::new(&quote::TABLE)
+        .columns([
+            quote::guild_id,
+            quote::channel_id,
+            quote::message_id,
+            quote::ts,
+            quote::author_id,
+            quote::author_name,
+            quote::contents,
+            quote::image,
+        ])
+        .eq(quote::guild_id, 1)
+        .eq(quote::quote_number, 2)
+        .is_null(quote::deleted_at)
+        .sql();
+    let res = db
+        .conn
+        .query_row(&sql, params![SqlGuildId(guild_id), quote_number], |row| {
+            let dt = NaiveDateTime::from_timestamp_opt(row.get(3)?, 0).unwrap_or_default(); // yes this was quoted in 1970, what of it?
+            Ok(Quote {
+                quote_number,
+                guild_id: row.get::<_, SqlGuildId>(0)?.0,
+                channel_id: row.get::<_, SqlChannelId>(1)?.0,
+                message_id: row.get::<_, SqlMessageId>(2)?.0,
+                ts: DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc),
+                author_id: row.get::<_, SqlUserId>(4)?.0,
+                author_name: row.get(5)?,
+                contents: crate::db::column_as_string(row.get_ref(6)?)?,
+                image: row.get(7)?,
+            })
+        });
     match res {
         Ok(q) => Ok(Some(q)),
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -141,40 +173,62 @@ pub async fn fetch_quote(
 pub async fn add_quote(
     handler: &Handler,
     ctx: &Context,
-    guild_id: u64,
+    guild_id: GuildId,
     message: &Message,
 ) -> anyhow::Result<Option<u64>> {
     let contents = message_to_quote_contents(handler, ctx, message).await?;
-    let mut db = handler.db.lock().await;
+    let message = message.clone();
+    handler
+        .db_call(move |db| insert_quote(db, guild_id, &message, &contents))
+        .await
+}
+
+fn insert_quote(
+    db: &mut Db,
+    guild_id: GuildId,
+    message: &Message,
+    contents: &str,
+) -> anyhow::Result<Option<u64>> {
     let tx = db.conn.transaction()?;
+    // deleted quotes are included so their numbers don't get reused while they can be restored
+    let last_number = Select::new(&quote::TABLE)
+        .columns([quote::quote_number])
+        .eq(quote::guild_id, 1)
+        .order_by(quote::quote_number, true)
+        .sql();
     let last_quote: u64 = tx
-        .query_row(
-            "SELECT quote_number FROM quote WHERE guild_id = ?1 ORDER BY quote_number DESC",
-            [guild_id],
-            |row| row.get(0),
-        )
+        .query_row(&last_number, [SqlGuildId(guild_id)], |row| row.get(0))
         .unwrap_or(0);
-    let channel_id = message.channel_id.get();
     let ts = message.timestamp;
-    let author_id = message.author.id.get();
     let author_name = &message.author.name;
     let image = message
         .attachments
         .iter()
         .find(|att| att.height.is_some())
         .map(|att| att.url.clone());
+    let insert = schema::insert(
+        &quote::TABLE,
+        &[
+            quote::guild_id,
+            quote::channel_id,
+            quote::message_id,
+            quote::ts,
+            quote::quote_number,
+            quote::author_id,
+            quote::author_name,
+            quote::contents,
+            quote::image,
+        ],
+    );
     match tx.execute(
-        r"INSERT INTO quote (
-    guild_id, channel_id, message_id, ts, quote_number,
-    author_id, author_name, contents, image
-) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        &insert,
         params![
-            guild_id,
-            channel_id,
-            message.id.get(),
+            SqlGuildId(guild_id),
+            SqlChannelId(message.channel_id),
+            SqlMessageId(message.id),
             ts.unix_timestamp(),
             last_quote + 1,
-            author_id,
+            SqlUserId(message.author.id),
             author_name,
             contents.trim(),
             image
@@ -192,23 +246,28 @@ pub async fn add_quote(
 
 pub async fn get_random_quote(
     handler: &Handler,
-    guild_id: u64,
-    user: Option<u64>,
+    guild_id: GuildId,
+    user: Option<UserId>,
 ) -> anyhow::Result<Option<Quote>> {
-    let number = {
-        let db = handler.db.lock().await;
-        let mut stmt = db.conn.prepare(
-            "SELECT quote_number FROM quote WHERE guild_id = ?1 AND (?2 IS NULL OR author_id = ?2)",
-        )?;
-        let numbers: Vec<_> = stmt
-            .query(params![guild_id, user])?
-            .map(|row| row.get(0))
-            .collect()?;
-        if numbers.is_empty() {
-            bail!("No quotes saved");
-        }
-        numbers[rand::random::<usize>() % numbers.len()]
-    };
+    let number = handler
+        .db_read(move |db| {
+            let sql = Select::new(&quote::TABLE)
+                .columns([quote::quote_number])
+                .eq(quote::guild_id, 1)
+                .eq_opt(quote::author_id, 2)
+                .is_null(quote::deleted_at)
+                .sql();
+            let mut stmt = db.conn.prepare(&sql)?;
+            let numbers: Vec<_> = stmt
+                .query(params![SqlGuildId(guild_id), user.map(SqlUserId)])?
+                .map(|row| row.get(0))
+                .collect()?;
+            if numbers.is_empty() {
+                bail!("No quotes saved");
+            }
+            Ok(numbers[rand::random::<usize>() % numbers.len()])
+        })
+        .await?;
     fetch_quote(handler, guild_id, number).await
 }
 
@@ -240,20 +299,37 @@ impl Eq for CaseInsensitiveString<'_> {}
 
 pub async fn quotes_markov_chain(
     handler: &Handler,
-    guild_id: u64,
-    user: Option<u64>,
+    guild_id: GuildId,
+    user: Option<UserId>,
     order: Option<usize>,
 ) -> anyhow::Result<(
-    markov::Chain<CaseInsensitiveString>,
-    HashSet<CaseInsensitiveString>,
+    markov::Chain<CaseInsensitiveString<'static>>,
+    HashSet<CaseInsensitiveString<'static>>,
 )> {
-    let db = handler.db.lock().await;
-    let mut stmt = db.conn.prepare(
-        "SELECT contents FROM quote WHERE guild_id = ?1 AND (?2 IS NULL or author_id = ?2)",
-    )?;
+    handler
+        .db_read(move |db| quotes_chain(db, guild_id, user, order))
+        .await
+}
+
+fn quotes_chain(
+    db: &Db,
+    guild_id: GuildId,
+    user: Option<UserId>,
+    order: Option<usize>,
+) -> anyhow::Result<(
+    markov::Chain<CaseInsensitiveString<'static>>,
+    HashSet<CaseInsensitiveString<'static>>,
+)> {
+    let sql = Select::new(&quote::TABLE)
+        .columns([quote::contents])
+        .eq(quote::guild_id, 1)
+        .eq_opt(quote::author_id, 2)
+        .is_null(quote::deleted_at)
+        .sql();
+    let mut stmt = db.conn.prepare(&sql)?;
     let mut chain = markov::Chain::of_order(order.unwrap_or(1));
     let mut quotes = HashSet::new();
-    stmt.query(params![guild_id, user])?
+    stmt.query(params![SqlGuildId(guild_id), user.map(SqlUserId)])?
         .map(|row| crate::db::column_as_string(row.get_ref(0)?))
         .for_each(|quote: String| {
             let parts = quote.split("- <@").collect_vec();
@@ -270,7 +346,7 @@ pub async fn quotes_markov_chain(
                         .get(i + 1)
                         .and_then(|next| next.split_once('>'))
                         .and_then(|(id, _)| id.parse::<u64>().ok());
-                    if author_id.is_some_and(|id| id != user_id) {
+                    if author_id.is_some_and(|id| id != user_id.get()) {
                         return;
                     }
                 }
@@ -288,23 +364,95 @@ pub async fn quotes_markov_chain(
 
 pub async fn list_quotes(
     handler: &Handler,
-    guild_id: u64,
+    guild_id: GuildId,
     like: &str,
 ) -> anyhow::Result<Vec<(u64, String)>> {
-    let db = handler.db.lock().await;
-    let res = db.conn.prepare(
-            "SELECT quote_number, contents FROM quote WHERE guild_id = ?1 AND contents LIKE '%'||?2||'%' LIMIT 15",
-        )?
-            .query(params![guild_id, like])?
-            .map(|row| Ok((row.get(0)?, row.get(1)?)))
-            .collect()?;
+    let like = like.to_string();
+    handler
+        .db_read(move |db| quotes_like(db, guild_id, &like))
+        .await
+}
+
+fn quotes_like(db: &Db, guild_id: GuildId, like: &str) -> anyhow::Result<Vec<(u64, String)>> {
+    let sql = Select::new(&quote::TABLE)
+        .columns([quote::quote_number, quote::contents])
+        .eq(quote::guild_id, 1)
+        .contains(quote::contents, 2)
+        .is_null(quote::deleted_at)
+        .limit(15)
+        .sql();
+    let res = db
+        .conn
+        .prepare(&sql)?
+        .query(params![SqlGuildId(guild_id), like])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    Ok(res)
+}
+
+// Quote number, date, author name and contents
+pub type BrowsedQuote = (u64, DateTime<Utc>, String, String);
+
+// Page of quotes containing `like`, most recent first
+pub async fn browse_quotes(
+    handler: &Handler,
+    guild_id: GuildId,
+    like: &str,
+    limit: usize,
+    offset: usize,
+) -> anyhow::Result<Vec<BrowsedQuote>> {
+    let like = like.to_string();
+    handler
+        .db_read(move |db| quotes_page(db, guild_id, &like, limit, offset))
+        .await
+}
+
+fn quotes_page(
+    db: &Db,
+    guild_id: GuildId,
+    like: &str,
+    limit: usize,
+    offset: usize,
+) -> anyhow::Result<Vec<BrowsedQuote>> {
+    let sql = Select::new(&quote::TABLE)
+        .columns([
+            quote::quote_number,
+            quote::ts,
+            quote::author_name,
+            quote::contents,
+        ])
+        .eq(quote::guild_id, 1)
+        .contains(quote::contents, 2)
+        .is_null(quote::deleted_at)
+        .order_by(quote::quote_number, true)
+        .limit(limit)
+        .offset(offset)
+        .sql();
+    let res = db
+        .conn
+        .prepare(&sql)?
+        .query(params![SqlGuildId(guild_id), like])?
+        .map(|row| {
+            let dt = NaiveDateTime::from_timestamp_opt(row.get(1)?, 0).unwrap_or_default();
+            Ok((
+                row.get(0)?,
+                DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc),
+                row.get(2)?,
+                crate::db::column_as_string(row.get_ref(3)?)?,
+            ))
+        })
+        .collect()?;
     Ok(res)
 }
 
 #[derive(Command)]
-#[cmd(name = "quote", desc = "Retrieve a quote")]
+#[cmd(name = "quote", desc = "Retrieve a quote", guild_only)]
 pub struct GetQuote {
-    #[cmd(desc = "Number the quote was saved as (optional)", autocomplete)]
+    #[cmd(
+        desc = "Number the quote was saved as (optional)",
+        autocomplete,
+        min = 1
+    )]
     pub number: Option<i64>,
     #[cmd(desc = "Get a random quote from a specific user")]
     pub user: Option<UserId>,
@@ -313,28 +461,19 @@ pub struct GetQuote {
 }
 
 #[async_trait]
-impl BotCommand for GetQuote {
+impl GuildCommand for GetQuote {
     type Data = Handler;
-    async fn run(
+    const SCHEDULABLE: bool = true;
+
+    async fn run_in_guild(
         self,
         handler: &Handler,
         ctx: &Context,
-        opts: &CommandInteraction,
+        _opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
         self.get_quote(handler, ctx, guild_id).await
     }
-
-    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
-        if opt_name == "number" {
-            opt.min_int_value(1)
-        } else {
-            opt
-        }
-    }
 }
 
 impl GetQuote {
@@ -342,26 +481,35 @@ impl GetQuote {
         self,
         handler: &Handler,
         ctx: &Context,
-        guild_id: u64,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
         let quote = if let Some(quote_number) = self.number {
             fetch_quote(handler, guild_id, quote_number as u64).await?
         } else {
-            get_random_quote(handler, guild_id, self.user.map(|u| u.get())).await?
+            get_random_quote(handler, guild_id, self.user).await?
         }
         .ok_or_else(|| anyhow!("No such quote"))?;
-        let message_url = format!(
-            "https://discord.com/channels/{}/{}/{}",
-            quote.guild_id, quote.channel_id, quote.message_id
-        );
-        let channel = ChannelId::new(quote.channel_id)
+        let message_url = quote
+            .message_id
+            .link(quote.channel_id, Some(quote.guild_id));
+        // deleted channels and archived private threads cannot be retrieved
+        let channel = quote
+            .channel_id
             .to_channel(&ctx.http)
-            .await?
-            .guild();
-        let channel_name = channel
-            .as_ref()
-            .map(|c| c.name())
-            .unwrap_or("unknown-channel");
+            .await
+            .ok()
+            .and_then(|c| c.guild());
+        let channel_name = match &channel {
+            // quotes from threads also show the channel they're in
+            Some(c) => match thread_parent(c) {
+                Some(parent) => match parent.name(ctx).await {
+                    Ok(parent_name) => format!("{parent_name} › {}", c.name()),
+                    Err(_) => c.name().to_string(),
+                },
+                None => c.name().to_string(),
+            },
+            None => "unknown-channel".to_string(),
+        };
         let hide_author = self.hide_author == Some(true);
         let mut contents = format!(
             "{}\n- <@{}> [(Source)]({})",
@@ -370,7 +518,8 @@ impl GetQuote {
         let author_avatar = if hide_author {
             None
         } else {
-            UserId::new(quote.author_id)
+            quote
+                .author_id
                 .to_user(&ctx.http)
                 .await?
                 .avatar_url()
@@ -408,27 +557,21 @@ impl GetQuote {
 }
 
 #[derive(Command)]
-#[cmd(name = "quote", message)]
+#[cmd(name = "quote", message, guild_only)]
 pub struct SaveQuote(Message);
 
 #[async_trait]
-impl BotCommand for SaveQuote {
+impl GuildCommand for SaveQuote {
     type Data = Handler;
-    async fn run(
+    async fn run_in_guild(
         self,
         handler: &Handler,
         ctx: &Context,
-        opts: &CommandInteraction,
+        _opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let guild_id = opts
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?
-            .get();
         let quote_number = add_quote(handler, ctx, guild_id, &self.0).await?;
-        let link = self
-            .0
-            .id
-            .link(self.0.channel_id, Some(GuildId::new(guild_id)));
+        let link = self.0.id.link(self.0.channel_id, Some(guild_id));
         let resp_text = match quote_number {
             Some(n) => format!("Quote saved as #{n}: {link}"),
             None => "Quote already added".to_string(),
@@ -438,31 +581,35 @@ impl BotCommand for SaveQuote {
 }
 
 #[derive(Command)]
-#[cmd(name = "fake_quote", desc = "Get a procedurally generated quote")]
+#[cmd(
+    name = "fake_quote",
+    desc = "Get a procedurally generated quote",
+    guild_only
+)]
 pub struct FakeQuote {
     user: Option<UserId>,
     start: Option<String>,
+    #[cmd(
+        desc = "Markov chain order. Higher = closer to real quotes but more coherent",
+        min = 1,
+        max = 4
+    )]
     order: Option<usize>,
 }
 
 #[async_trait]
-impl BotCommand for FakeQuote {
+impl GuildCommand for FakeQuote {
     type Data = Handler;
-    async fn run(
+    const SCHEDULABLE: bool = true;
+
+    async fn run_in_guild(
         self,
         handler: &Handler,
         _ctx: &Context,
-        opts: &CommandInteraction,
+        _opts: &CommandInteraction,
+        guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let (chain, quotes) = quotes_markov_chain(
-            handler,
-            opts.guild_id
-                .ok_or_else(|| anyhow!("must be run in a guild"))?
-                .get(),
-            self.user.map(|u| u.get()),
-            self.order,
-        )
-        .await?;
+        let (chain, quotes) = quotes_markov_chain(handler, guild_id, self.user, self.order).await?;
         let mut resp = String::new();
         for _ in 0..100 {
             resp = if let Some(start) = &self.start {
@@ -477,7 +624,7 @@ impl BotCommand for FakeQuote {
             if !quotes.contains(&CaseInsensitiveString(resp.as_str().into())) {
                 break;
             }
-            eprintln!("generated a real quote, trying again");
+            tracing::debug!("generated a real quote, trying again");
         }
         if resp.is_empty() {
             resp = "Failed to generate quote".to_string();
@@ -486,21 +633,306 @@ impl BotCommand for FakeQuote {
         }
         CommandResponse::public(resp)
     }
+}
 
-    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
-        if opt_name == "order" {
-            opt.min_int_value(1)
-                .max_int_value(4)
-                .description("Markov chain order. Higher = closer to real quotes but more coherent")
+#[derive(Command)]
+#[cmd(
+    name = "quote_suggestions",
+    desc = "Suggest saving messages as quotes when they get a 🗨️ reaction"
+)]
+pub struct SetQuoteSuggestions {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetQuoteSuggestions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        opts: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = opts.guild_id()?;
+        handler
+            .set_guild_field(guild_id, opts.user.id, "quote_suggestions", self.enabled)
+            .await
+            .context("updating 'quote_suggestions' guild field")?;
+        CommandResponse::private(if self.enabled {
+            "Will suggest saving messages reacted to with 🗨️ as quotes"
         } else {
-            opt
+            "Will not suggest saving quotes"
+        })
+    }
+}
+
+async fn is_quoted(
+    handler: &Handler,
+    guild_id: GuildId,
+    message_id: MessageId,
+) -> anyhow::Result<bool> {
+    let sql = Select::new(&quote::TABLE)
+        .count()
+        .eq(quote::guild_id, 1)
+        .eq(quote::message_id, 2)
+        .sql();
+    let count: usize = handler
+        .db_read(move |db| {
+            let count = db.conn.query_row(
+                &sql,
+                params![SqlGuildId(guild_id), SqlMessageId(message_id)],
+                |row| row.get(0),
+            )?;
+            Ok(count)
+        })
+        .await?;
+    Ok(count > 0)
+}
+
+// Called when a reaction is added to a message.
+// If quote suggestions are enabled in the guild and the reaction is the quote emoji, offer
+// the user who reacted to save the message as a quote.
+pub async fn suggest_quote(
+    handler: &Handler,
+    ctx: &Context,
+    react: &Reaction,
+) -> anyhow::Result<()> {
+    let (Some(guild_id), Some(user_id)) = (react.guild_id, react.user_id) else {
+        return Ok(());
+    };
+    if react.emoji != ReactionType::Unicode(QUOTE_EMOJI.to_string())
+        || Some(&user_id) == handler.self_id.get()
+    {
+        return Ok(());
+    }
+    if !handler
+        .get_guild_field::<bool>(guild_id, "quote_suggestions")
+        .await?
+        || is_quoted(handler, guild_id, react.message_id).await?
+    {
+        return Ok(());
+    }
+    // sent as a DM so that only the reactor sees it, a reaction has no interaction to
+    // reply to ephemerally
+    let (channel_id, message_id) = (react.channel_id, react.message_id);
+    let save = CreateButton::new(format!(
+        "{SAVE_QUOTE_PREFIX}{guild_id}:{channel_id}:{message_id}"
+    ))
+    .label("Save as quote")
+    .style(ButtonStyle::Primary);
+    let dismiss = CreateButton::new(DISMISS_QUOTE_ID)
+        .label("Dismiss")
+        .style(ButtonStyle::Secondary);
+    let prompt = CreateMessage::new()
+        .content(format!(
+            "Save {} as a quote?",
+            message_id.link(channel_id, Some(guild_id))
+        ))
+        .components(vec![CreateActionRow::Buttons(vec![save, dismiss])]);
+    let dm = user_id.create_dm_channel(&ctx.http).await?;
+    if let Err(e) = dm.send_message(&ctx.http, prompt).await {
+        // users can turn DMs from server members off
+        tracing::debug!(%user_id, "Could not suggest saving a quote: {e:?}");
+    }
+    Ok(())
+}
+
+fn on_reaction_add<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a ReactionAdd,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    suggest_quote(handler, ctx, &event.0).boxed()
+}
+
+#[derive(Command)]
+#[cmd(name = "quote_delete", desc = "Delete a quote", guild_only)]
+pub struct DeleteQuote {
+    #[cmd(desc = "Number of the quote to delete", autocomplete, min = 1)]
+    pub number: i64,
+}
+
+#[async_trait]
+impl GuildCommand for DeleteQuote {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_MESSAGES;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        opts: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let number = self.number;
+        let token = handler
+            .db_call(move |db| {
+                db.soft_delete(
+                    quote::TABLE.name,
+                    &format!("{} = ?1 AND {} = ?2", quote::guild_id, quote::quote_number),
+                    params![SqlGuildId(guild_id), number],
+                )
+            })
+            .await?;
+        let Some(token) = token else {
+            return CommandResponse::private(format!("No quote #{}", self.number));
+        };
+        let resp = undo_response(
+            quote::TABLE.name,
+            guild_id,
+            token,
+            format!("Deleted quote #{}", self.number),
+        );
+        opts.create_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+// A quote in an /import_quotes file, e.g. from another bot.
+// Ids can be numbers or strings, `ts` is a unix timestamp.
+#[derive(Deserialize)]
+struct ImportedQuote {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    ts: i64,
+    author_id: UserId,
+    author_name: String,
+    contents: String,
+    #[serde(default)]
+    image: Option<String>,
+}
+
+// Save imported quotes after the guild's last one, in the order of the file.
+// Returns how many were imported, quotes of messages that were already saved are skipped.
+fn import_quotes(
+    db: &mut crate::db::Db,
+    guild_id: GuildId,
+    quotes: Vec<ImportedQuote>,
+) -> anyhow::Result<usize> {
+    let tx = db.conn.transaction()?;
+    let last_number = Select::new(&quote::TABLE)
+        .columns([quote::quote_number])
+        .eq(quote::guild_id, 1)
+        .order_by(quote::quote_number, true)
+        .sql();
+    let mut number: u64 = tx
+        .query_row(&last_number, [SqlGuildId(guild_id)], |row| row.get(0))
+        .unwrap_or(0);
+    let insert = schema::insert(
+        &quote::TABLE,
+        &[
+            quote::guild_id,
+            quote::channel_id,
+            quote::message_id,
+            quote::ts,
+            quote::quote_number,
+            quote::author_id,
+            quote::author_name,
+            quote::contents,
+            quote::image,
+        ],
+    );
+    let mut imported = 0;
+    for q in quotes {
+        let inserted = tx.execute(
+            &insert,
+            params![
+                SqlGuildId(guild_id),
+                SqlChannelId(q.channel_id),
+                SqlMessageId(q.message_id),
+                q.ts,
+                number + 1,
+                SqlUserId(q.author_id),
+                q.author_name,
+                q.contents.trim(),
+                q.image
+            ],
+        );
+        match inserted {
+            Err(SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => continue,
+            res => res?,
+        };
+        number += 1;
+        imported += 1;
+    }
+    tx.commit()?;
+    Ok(imported)
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "import_quotes",
+    desc = "Import quotes from a JSON file",
+    guild_only
+)]
+pub struct ImportQuotes {
+    #[cmd(desc = "JSON list of quotes with their channel, message, author, contents and date")]
+    file: Attachment,
+}
+
+impl ImportQuotes {
+    async fn import(&self, handler: &Handler, guild_id: GuildId) -> anyhow::Result<String> {
+        let data = download_attachment(&self.file, MAX_IMPORT_SIZE).await?;
+        let quotes: Vec<ImportedQuote> =
+            serenity::json::from_slice(&data).context("Invalid quotes file")?;
+        let total = quotes.len();
+        let imported = handler
+            .db_call(move |db| import_quotes(db, guild_id, quotes))
+            .await?;
+        let mut report = format!("Imported {imported} quotes");
+        if imported < total {
+            write!(&mut report, ", skipped {} already saved", total - imported).unwrap();
         }
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl GuildCommand for ImportQuotes {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        opts: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        opts.create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(true),
+            ),
+        )
+        .await?;
+        let content = match self.import(handler, guild_id).await {
+            Ok(report) => report,
+            Err(e) => format!("{e:#}"),
+        };
+        opts.edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+            .await?;
+        Ok(CommandResponse::None)
     }
 }
 
 pub struct Quotes;
 
 impl Quotes {
+    fn undo_delete_quote<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let handled = handle_undo(handler, ctx, component, "quote").await?;
+            Ok(handled.is_some())
+        }
+        .boxed()
+    }
+
     fn complete_quotes<'a>(
         handler: &'a Handler,
         ctx: &'a Context,
@@ -508,13 +940,14 @@ impl Quotes {
         ac: &'a CommandInteraction,
     ) -> BoxFuture<'a, anyhow::Result<bool>> {
         async move {
-            if key != ("quote", CommandType::ChatInput) {
+            if key != ("quote", CommandType::ChatInput)
+                && key != ("quote_delete", CommandType::ChatInput)
+            {
                 return Ok(false);
             }
             let guild_id = ac
                 .guild_id
-                .ok_or_else(|| anyhow!("must be run in a guild"))?
-                .get();
+                .ok_or_else(|| anyhow!("must be run in a guild"))?;
             let options = &ac.data.options;
             let val = get_str_opt_ac(options, "number");
             let Some(v) = val else {
@@ -534,6 +967,44 @@ impl Quotes {
         }
         .boxed()
     }
+
+    fn save_suggested_quote<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let custom_id = component.data.custom_id.as_str();
+            if custom_id == DISMISS_QUOTE_ID {
+                component.message.delete(&ctx.http).await?;
+                return Ok(true);
+            }
+            let Some(ids) = custom_id.strip_prefix(SAVE_QUOTE_PREFIX) else {
+                return Ok(false);
+            };
+            let (guild_id, channel_id, message_id) = ids
+                .split(':')
+                .map(str::parse::<u64>)
+                .collect_tuple()
+                .ok_or_else(|| anyhow!("invalid quote suggestion id: {ids}"))?;
+            let guild_id = GuildId::new(guild_id?);
+            let message = ChannelId::new(channel_id?)
+                .message(&ctx.http, MessageId::new(message_id?))
+                .await?;
+            let content = match add_quote(handler, ctx, guild_id, &message).await? {
+                Some(n) => format!("Quote saved as #{n}: {}", message.link()),
+                None => "Quote already added".to_string(),
+            };
+            let msg = CreateInteractionResponseMessage::new().content(content);
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+                .await?;
+            // the prompt was answered
+            component.message.delete(&ctx.http).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
 }
 
 #[async_trait]
@@ -543,22 +1014,10 @@ impl Module for Quotes {
     }
 
     async fn setup(&mut self, db: &mut crate::db::Db) -> anyhow::Result<()> {
-        db.conn.execute(
-            "CREATE TABLE IF NOT EXISTS quote (
-                guild_id INTEGER,
-                channel_id INTEGER,
-                message_id INTEGER,
-                ts INTEGER,
-                quote_number INTEGER,
-                author_id INTEGER,
-                author_name STRING,
-                contents STRING,
-                image STRING,
-                UNIQUE(guild_id, quote_number),
-                UNIQUE(guild_id, message_id)
-            )",
-            [],
-        )?;
+        db.create_table(&quote::TABLE)?;
+        db.add_soft_delete(quote::TABLE.name)?;
+        db.check_schema(&quote::TABLE)?;
+        db.add_guild_field("quote_suggestions", "BOOLEAN NOT NULL DEFAULT(false)")?;
         Ok(())
     }
 
@@ -566,6 +1025,18 @@ impl Module for Quotes {
         store.register::<GetQuote>();
         store.register::<SaveQuote>();
         store.register::<FakeQuote>();
+        store.register::<SetQuoteSuggestions>();
+        store.register::<DeleteQuote>();
+        store.register::<ImportQuotes>();
         completions.push(Quotes::complete_quotes);
     }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(Quotes::save_suggested_quote);
+        handlers.push(Quotes::undo_delete_quote);
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(on_reaction_add);
+    }
 }
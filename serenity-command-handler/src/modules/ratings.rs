@@ -0,0 +1,248 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context as _};
+use reqwest::{Client, Url};
+use rusqlite::{params, OptionalExtension};
+use scraper::{Html, Selector};
+use serenity::model::prelude::{CommandInteraction, GuildId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use tokio::sync::Mutex;
+
+use crate::album::Album;
+use crate::db::Db;
+use crate::prelude::*;
+
+const BASE_URL: &str = "https://www.albumoftheyear.org";
+const USER_AGENT: &str = "discord_framework ratings lookup";
+
+// Keep a reasonable pace and reuse results to avoid hammering the site
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const CACHE_TTL_DAYS: i64 = 7;
+
+pub struct Rating {
+    pub critic_score: Option<String>,
+    pub user_score: Option<String>,
+    pub url: String,
+}
+
+impl Rating {
+    pub fn format(&self) -> Option<String> {
+        let scores = [
+            self.critic_score.as_ref().map(|s| format!("critics {s}")),
+            self.user_score.as_ref().map(|s| format!("users {s}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if scores.is_empty() {
+            return None;
+        }
+        Some(format!("[AOTY](<{}>): {}", self.url, scores.join(", ")))
+    }
+}
+
+fn text(html: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    let text = html
+        .select(&selector)
+        .next()?
+        .text()
+        .collect::<String>()
+        .trim()
+        .to_string();
+    // unrated albums show "NR" or a placeholder dash
+    (!text.is_empty() && text != "NR" && text != "-").then_some(text)
+}
+
+pub struct Ratings {
+    client: Client,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl Ratings {
+    async fn get_page(&self, url: Url) -> anyhow::Result<Html> {
+        {
+            let mut last = self.last_request.lock().await;
+            if let Some(elapsed) = last.map(|t| t.elapsed()) {
+                if elapsed < MIN_REQUEST_INTERVAL {
+                    tokio::time::sleep(MIN_REQUEST_INTERVAL - elapsed).await;
+                }
+            }
+            *last = Some(Instant::now());
+        }
+        let page = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(Html::parse_document(&page))
+    }
+
+    async fn scrape(&self, artist: &str, name: &str) -> anyhow::Result<Option<Rating>> {
+        let mut search = Url::parse(BASE_URL)?.join("/search/albums/")?;
+        search
+            .query_pairs_mut()
+            .append_pair("q", &format!("{artist} {name}"));
+        let album_url = {
+            let html = self.get_page(search).await?;
+            let selector = Selector::parse(".albumBlock a[href^=\"/album/\"]").unwrap();
+            let Some(href) = html
+                .select(&selector)
+                .next()
+                .and_then(|a| a.value().attr("href"))
+            else {
+                return Ok(None);
+            };
+            Url::parse(BASE_URL)?.join(href)?
+        };
+        let html = self.get_page(album_url.clone()).await?;
+        Ok(Some(Rating {
+            critic_score: text(&html, ".albumCriticScore"),
+            user_score: text(&html, ".albumUserScore"),
+            url: album_url.to_string(),
+        }))
+    }
+
+    // Look up the ratings of an album, going through the cache first.
+    // Albums that could not be found are cached too, so they aren't searched repeatedly.
+    pub async fn get_rating(
+        &self,
+        db: &Mutex<Db>,
+        artist: &str,
+        name: &str,
+    ) -> anyhow::Result<Option<Rating>> {
+        let now = chrono::Utc::now().timestamp();
+        let cached = db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT critic_score, user_score, url FROM rating_cache
+                 WHERE artist = ?1 AND album = ?2 AND fetched_at > ?3",
+                params![artist, name, now - CACHE_TTL_DAYS * 24 * 3600],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, Option<String>>(2)?)),
+            )
+            .optional()?;
+        if let Some((critic_score, user_score, url)) = cached {
+            return Ok(url.map(|url| Rating {
+                critic_score,
+                user_score,
+                url,
+            }));
+        }
+        let rating = tokio::time::timeout(REQUEST_TIMEOUT, self.scrape(artist, name))
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+        db.lock().await.conn.execute(
+            "INSERT INTO rating_cache (artist, album, critic_score, user_score, url, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(artist, album) DO UPDATE SET
+                critic_score = ?3, user_score = ?4, url = ?5, fetched_at = ?6",
+            params![
+                artist,
+                name,
+                rating.as_ref().and_then(|r| r.critic_score.as_deref()),
+                rating.as_ref().and_then(|r| r.user_score.as_deref()),
+                rating.as_ref().map(|r| r.url.as_str()),
+                now
+            ],
+        )?;
+        Ok(rating)
+    }
+}
+
+// Set the ratings of an album if they are enabled in the guild.
+// Lookup failures are logged and otherwise ignored.
+pub async fn add_rating(handler: &Handler, guild_id: Option<GuildId>, info: &mut Album) {
+    let (Some(guild_id), Some(artist), Some(name)) = (guild_id, &info.artist, &info.name) else {
+        return;
+    };
+    let Ok(ratings) = handler.module::<Ratings>() else {
+        return;
+    };
+    match handler.get_guild_field(guild_id, "show_ratings").await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::warn!("could not check ratings setting: {e:?}");
+            return;
+        }
+    }
+    match ratings.get_rating(&handler.db, artist, name).await {
+        Ok(rating) => info.rating = rating.and_then(|r| r.format()),
+        Err(e) => tracing::warn!("could not get ratings for {artist} - {name}: {e:?}"),
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setshowratings",
+    desc = "set whether to show AOTY ratings in album info and listening parties"
+)]
+pub struct SetShowRatings {
+    show_ratings: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetShowRatings {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(guild_id, command.user.id, "show_ratings", self.show_ratings)
+            .await
+            .context("updating 'show_ratings' guild field")?;
+        let resp = if self.show_ratings {
+            "Will show album ratings"
+        } else {
+            "Will not show album ratings"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[async_trait]
+impl Module for Ratings {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        let client = Client::builder().user_agent(USER_AGENT).build()?;
+        Ok(Ratings {
+            client,
+            last_request: Mutex::new(None),
+        })
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.add_guild_field("show_ratings", "BOOLEAN NOT NULL DEFAULT(false)")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS rating_cache (
+                artist STRING NOT NULL,
+                album STRING NOT NULL,
+                critic_score STRING,
+                user_score STRING,
+                url STRING,
+                fetched_at INTEGER NOT NULL,
+                UNIQUE(artist, album)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<SetShowRatings>();
+    }
+}
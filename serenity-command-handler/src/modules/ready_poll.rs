@@ -111,12 +111,15 @@ impl ReadyPoll {
             count_emote: self.count_emote,
             go_emote: self.go_emote,
         };
-        tokio::spawn(poll_task(
-            handler.module_arc().unwrap(),
-            http_arc,
-            pending_poll,
-            receiver,
-        ));
+        handler.spawn_task(
+            "ready_poll",
+            poll_task(
+                handler.module_arc().unwrap(),
+                http_arc,
+                pending_poll,
+                receiver,
+            ),
+        );
         Ok(())
     }
 }
@@ -152,7 +155,7 @@ impl BotCommand for ReadyPoll {
         // create ready poll message
         let resp = match self.create_poll(handler, ctx, interaction).await {
             Err(e) => {
-                dbg!(&e);
+                tracing::error!("Error creating poll: {e:?}");
                 Some(e.to_string())
             }
             _ => None,
@@ -170,6 +173,7 @@ impl BotCommand for ReadyPoll {
 }
 
 // task responsible for handling reactions to a poll
+#[tracing::instrument(name = "ready_poll", skip_all, fields(message_id = %poll.msg.id))]
 async fn poll_task(
     module: Arc<ModPoll>,
     http: Arc<Http>,
@@ -209,7 +213,7 @@ async fn poll_task(
                     )
                     .await;
                     if let Err(e) = res {
-                        eprintln!("error executing crabdown: {e}");
+                        tracing::error!("error executing crabdown: {e}");
                     }
                     continue;
                 }
@@ -233,7 +237,7 @@ async fn poll_task(
                     })
                     .await;
                 if let Err(e) = res {
-                    eprintln!("failed to edit ready message: {e}");
+                    tracing::error!("failed to edit ready message: {e}");
                 }
             }
         });
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context as _};
+use chrono::NaiveDate;
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::{params, OptionalExtension};
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateAutocompleteResponse, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse,
+};
+use serenity::http::Http;
+use serenity::model::application::{ButtonStyle, CommandType, ComponentInteraction};
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::get_str_opt_ac;
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlUserId};
+use crate::modules::lp::post_lp;
+use crate::modules::{AlbumLookup, ModLp};
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+
+const LP_PREFIX: &str = "release_lp:";
+const POST_HOUR: u32 = 10;
+
+struct Release {
+    id: i64,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    name: String,
+    url: String,
+    subscribers: Vec<UserId>,
+    // subscriptions to mark as notified once the ping is posted
+    ping_ids: Vec<i64>,
+}
+
+fn mark_notified(db: &Db, ping_ids: &[i64]) -> anyhow::Result<()> {
+    let mut stmt = db
+        .conn
+        .prepare("UPDATE release_ping SET notified = true WHERE id = ?1")?;
+    for id in ping_ids {
+        stmt.execute([id])?;
+    }
+    Ok(())
+}
+
+// Albums released by today in guilds with a release ping channel, with their subscribers.
+// Subscriptions are only marked as notified once the ping is posted, so that failed posts
+// are retried on the next run.
+fn due_releases(db: &Db, today: NaiveDate) -> anyhow::Result<Vec<Release>> {
+    let rows: Vec<(i64, SqlGuildId, SqlChannelId, String, String, SqlUserId)> = db
+        .conn
+        .prepare(
+            "SELECT r.id, r.guild_id, g.release_ping_channel, r.name, r.url, r.user_id
+             FROM release_ping r JOIN guild g ON g.id = r.guild_id
+             WHERE r.release_date <= ?1 AND NOT r.notified
+               AND g.release_ping_channel IS NOT NULL
+             ORDER BY r.id",
+        )?
+        .query([today.to_string()])?
+        .map(|row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })
+        .collect()?;
+    let mut releases: BTreeMap<(u64, String), Release> = BTreeMap::new();
+    for (id, guild_id, channel_id, name, url, user_id) in rows {
+        let release = releases
+            .entry((guild_id.0.get(), url.clone()))
+            .or_insert_with(|| Release {
+                id,
+                guild_id: guild_id.0,
+                channel_id: channel_id.0,
+                name,
+                url,
+                subscribers: Vec::new(),
+                ping_ids: Vec::new(),
+            });
+        release.subscribers.push(user_id.0);
+        release.ping_ids.push(id);
+    }
+    let mut releases: Vec<Release> = releases.into_values().collect();
+    // subscribers who turned release pings off are not pinged, but still marked as notified
+    for release in &mut releases {
+        let muted = db.muted_users(release.guild_id, "releases")?;
+        release
+            .subscribers
+            .retain(|user_id| !muted.contains(user_id));
+    }
+    for release in releases.iter().filter(|r| r.subscribers.is_empty()) {
+        mark_notified(db, &release.ping_ids)?;
+    }
+    releases.retain(|release| !release.subscribers.is_empty());
+    Ok(releases)
+}
+
+async fn post_release(http: &Http, release: &Release) -> anyhow::Result<()> {
+    let mentions = release
+        .subscribers
+        .iter()
+        .map(|user_id| format!("<@{user_id}>"))
+        .join(" ");
+    let button = CreateButton::new(format!("{LP_PREFIX}{}", release.id))
+        .label("Start a listening party")
+        .style(ButtonStyle::Primary);
+    release
+        .channel_id
+        .send_message(
+            http,
+            CreateMessage::new()
+                .content(format!(
+                    "{mentions} [**{}**]({}) is out today!",
+                    release.name, release.url
+                ))
+                .allowed_mentions(CreateAllowedMentions::new().users(&release.subscribers))
+                .components(vec![CreateActionRow::Buttons(vec![button])]),
+        )
+        .await?;
+    Ok(())
+}
+
+// Ping subscribers of the albums released today, at POST_HOUR in the bot's timezone
+fn post_releases<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let today = run.due.date_naive();
+        let releases = handler.db_call(move |db| due_releases(db, today)).await?;
+        for release in releases {
+            if let Err(e) = post_release(http, &release).await {
+                tracing::error!("Error posting release ping in {}: {e:?}", release.guild_id);
+                continue;
+            }
+            let ping_ids = release.ping_ids;
+            handler
+                .db_call(move |db| mark_notified(db, &ping_ids))
+                .await?;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "release_ping",
+    desc = "Get pinged when an upcoming album comes out (run again to unsubscribe)"
+)]
+pub struct ReleasePing {
+    #[cmd(desc = "The upcoming album", autocomplete)]
+    album: String,
+}
+
+#[async_trait]
+impl BotCommand for ReleasePing {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let lookup = handler.module::<AlbumLookup>()?;
+        let info = if self.album.starts_with("https://") {
+            lookup.get_album_info(&self.album).await?
+        } else {
+            lookup.lookup_album(&self.album, None).await?
+        };
+        let Some(info) = info else {
+            bail!("Album not found");
+        };
+        let Some(url) = &info.url else {
+            bail!("No link found for {}", info.format_name());
+        };
+        let db = handler.db.lock().await;
+        let removed = db.conn.execute(
+            "DELETE FROM release_ping
+             WHERE guild_id = ?1 AND user_id = ?2 AND url = ?3 AND NOT notified",
+            params![SqlGuildId(guild_id), SqlUserId(command.user.id), url],
+        )?;
+        if removed > 0 {
+            return CommandResponse::private(format!(
+                "You will no longer be pinged for {}",
+                info.as_link(None)
+            ));
+        }
+        // providers may only give a year or month for some releases
+        let release_date = info
+            .release_date
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .ok_or_else(|| anyhow!("No release date found for {}", info.format_name()))?;
+        if release_date <= handler.clock.local_now().date_naive() {
+            bail!("{} is already out", info.format_name());
+        }
+        db.conn.execute(
+            "INSERT INTO release_ping (guild_id, user_id, release_date, name, url)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                SqlGuildId(guild_id),
+                SqlUserId(command.user.id),
+                release_date.to_string(),
+                info.format_name(),
+                url
+            ],
+        )?;
+        CommandResponse::private(format!(
+            "You will be pinged when {} comes out on {}",
+            info.as_link(None),
+            release_date.format("%B %-d, %Y")
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "setreleasepings",
+    desc = "Post release day pings for upcoming albums in this channel"
+)]
+pub struct SetReleasePings {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetReleasePings {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let channel = self.enabled.then_some(SqlChannelId(command.channel_id));
+        handler
+            .set_guild_field(guild_id, command.user.id, "release_ping_channel", channel)
+            .await
+            .context("updating 'release_ping_channel' guild field")?;
+        let resp = if self.enabled {
+            format!("Will post release day pings in <#{}>", command.channel_id)
+        } else {
+            "Will not post release day pings anymore".to_string()
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+pub struct ReleasePings;
+
+impl ReleasePings {
+    fn start_lp<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(id) = component.data.custom_id.strip_prefix(LP_PREFIX) else {
+                return Ok(false);
+            };
+            let guild_id = component
+                .guild_id
+                .ok_or_else(|| anyhow!("release pings are only posted in servers"))?;
+            component
+                .create_response(
+                    &ctx.http,
+                    CreateInteractionResponse::Defer(
+                        CreateInteractionResponseMessage::new().ephemeral(true),
+                    ),
+                )
+                .await?;
+            let url: Option<String> = handler
+                .db
+                .lock()
+                .await
+                .conn
+                .query_row(
+                    "SELECT url FROM release_ping WHERE id = ?1",
+                    [id.parse::<i64>()?],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let resp = match url {
+                Some(url) => post_lp(
+                    handler,
+                    &ctx.http,
+                    guild_id,
+                    component.channel_id,
+                    component.user.id,
+                    &url,
+                )
+                .await
+                .map(|msg| format!("LP created: {}", msg.link()))
+                .unwrap_or_else(|e| e.to_string()),
+                None => "This release is no longer available".to_string(),
+            };
+            component
+                .edit_response(&ctx.http, EditInteractionResponse::new().content(resp))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn complete_album<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if key != ("release_ping", CommandType::ChatInput) {
+                return Ok(false);
+            }
+            let query = get_str_opt_ac(&ac.data.options, "album").unwrap_or("");
+            let choices = if query.len() >= 3 {
+                handler
+                    .module::<AlbumLookup>()?
+                    .query_albums(query, None)
+                    .await?
+            } else {
+                Vec::new()
+            };
+            let resp = choices
+                .into_iter()
+                .filter(|(_, value)| value.len() < 100)
+                .fold(CreateAutocompleteResponse::new(), |resp, (name, value)| {
+                    resp.add_string_choice(name, value)
+                });
+            ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for ReleasePings {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<ModLp>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ReleasePings)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.add_guild_field("release_ping_channel", "INTEGER")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS release_ping (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                release_date STRING NOT NULL,
+                name STRING NOT NULL,
+                url STRING NOT NULL,
+                notified BOOLEAN NOT NULL DEFAULT(false),
+                UNIQUE(guild_id, user_id, url)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<ReleasePing>();
+        store.register::<SetReleasePings>();
+        completions.push(ReleasePings::complete_album);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(ReleasePings::start_lp);
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "release_pings",
+            Schedule::daily_at(POST_HOUR, 0),
+            post_releases,
+        ));
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::HashSet;
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use itertools::Itertools;
+use rspotify::model::{Id, SimplifiedAlbum};
+use rusqlite::params;
+use serenity::builder::{
+    CreateAutocompleteResponse, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::model::prelude::{CommandInteraction, CommandType, GuildId, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::get_str_opt_ac;
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::modules::Spotify;
+use crate::prelude::*;
+
+// Limits the number of Spotify requests made for a single calendar
+const MAX_ARTISTS: usize = 30;
+const RECENT_DAYS: i64 = 30;
+const MAX_LISTED: usize = 25;
+const CONCURRENT_REQUESTS: usize = 4;
+
+pub struct FollowedArtist {
+    pub artist_id: String,
+    pub artist_name: String,
+}
+
+pub struct Release {
+    pub date: NaiveDate,
+    pub artist: String,
+    pub name: String,
+    pub url: Option<String>,
+}
+
+fn get_follows(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: Option<UserId>,
+    genre: Option<&str>,
+) -> anyhow::Result<Vec<FollowedArtist>> {
+    let mut stmt = db.conn.prepare(
+        "SELECT DISTINCT artist_id, artist_name FROM artist_follow
+         WHERE guild_id = ?1
+           AND (?2 IS NULL OR user_id = ?2)
+           AND (?3 IS NULL OR genres LIKE '%' || ?3 || '%')
+         ORDER BY artist_name",
+    )?;
+    let follows = stmt
+        .query(params![
+            SqlGuildId(guild_id),
+            user_id.map(SqlUserId),
+            genre.map(str::to_lowercase)
+        ])?
+        .map(|row| {
+            Ok(FollowedArtist {
+                artist_id: row.get(0)?,
+                artist_name: row.get(1)?,
+            })
+        })
+        .collect()?;
+    Ok(follows)
+}
+
+// Draw the days of the month as a grid, marking days with releases
+fn month_grid(first: NaiveDate, release_days: &HashSet<u32>) -> String {
+    let mut grid = String::from(" Mo  Tu  We  Th  Fr  Sa  Su\n");
+    let offset = first.weekday().num_days_from_monday() as usize;
+    grid.push_str(&"    ".repeat(offset));
+    let mut date = first;
+    while date.month() == first.month() {
+        let mark = if release_days.contains(&date.day()) {
+            '*'
+        } else {
+            ' '
+        };
+        grid.push_str(&format!("{:>3}{mark}", date.day()));
+        if date.weekday() == Weekday::Sun {
+            grid.push('\n');
+        }
+        date += Duration::days(1);
+    }
+    grid.trim_end().to_string()
+}
+
+async fn artist_releases<'a>(
+    spotify: &Spotify,
+    artist: &'a FollowedArtist,
+) -> (&'a FollowedArtist, anyhow::Result<Vec<SimplifiedAlbum>>) {
+    (artist, spotify.artist_releases(&artist.artist_id).await)
+}
+
+async fn fetch_releases(spotify: &Spotify, artists: &[FollowedArtist]) -> Vec<Release> {
+    let requests = artists
+        .iter()
+        .map(|artist| artist_releases(spotify, artist))
+        .collect_vec();
+    let mut results = futures::stream::iter(requests).buffer_unordered(CONCURRENT_REQUESTS);
+    let mut seen = HashSet::new();
+    let mut releases = Vec::new();
+    while let Some((artist, res)) = results.next().await {
+        let albums = match res {
+            Ok(albums) => albums,
+            Err(e) => {
+                tracing::warn!("could not get releases for {}: {e}", artist.artist_name);
+                continue;
+            }
+        };
+        for album in albums {
+            let Some(date) = album
+                .release_date
+                .as_deref()
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+            else {
+                continue;
+            };
+            // collaborations between followed artists are only listed once
+            if let Some(id) = &album.id {
+                if !seen.insert(id.clone()) {
+                    continue;
+                }
+            }
+            releases.push(Release {
+                date,
+                artist: Spotify::artists_to_string(&album.artists),
+                name: album.name,
+                url: album.id.map(|id| id.url()),
+            });
+        }
+    }
+    releases
+}
+
+#[derive(Command)]
+#[cmd(name = "follow_artist", desc = "Follow an artist's new releases")]
+pub struct FollowArtist {
+    #[cmd(desc = "Name of the artist")]
+    artist: String,
+}
+
+#[async_trait]
+impl BotCommand for FollowArtist {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let spotify = handler.module::<Spotify>()?;
+        let Some(artist) = spotify.search_artist(&self.artist).await? else {
+            return CommandResponse::private(format!("No artist found for `{}`", self.artist));
+        };
+        let genres = artist.genres.join(",").to_lowercase();
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO artist_follow (guild_id, user_id, artist_id, artist_name, genres)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(guild_id, user_id, artist_id)
+             DO UPDATE SET artist_name = ?4, genres = ?5",
+            params![
+                SqlGuildId(guild_id),
+                SqlUserId(command.user.id),
+                artist.id.id(),
+                &artist.name,
+                genres
+            ],
+        )?;
+        CommandResponse::private(format!("Now following {}", artist.name))
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "unfollow_artist", desc = "Stop following an artist")]
+pub struct UnfollowArtist {
+    #[cmd(desc = "Name of the artist", autocomplete)]
+    artist: String,
+}
+
+#[async_trait]
+impl BotCommand for UnfollowArtist {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let removed = handler.db.lock().await.conn.execute(
+            "DELETE FROM artist_follow
+             WHERE guild_id = ?1 AND user_id = ?2 AND artist_name = ?3 COLLATE NOCASE",
+            params![
+                SqlGuildId(guild_id),
+                SqlUserId(command.user.id),
+                &self.artist
+            ],
+        )?;
+        if removed == 0 {
+            return CommandResponse::private(format!("You are not following {}", self.artist));
+        }
+        CommandResponse::private(format!("No longer following {}", self.artist))
+    }
+}
+
+fn complete_followed<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    key: CommandKey<'a>,
+    ac: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if key != ("unfollow_artist", CommandType::ChatInput) {
+            return Ok(false);
+        }
+        let guild_id = ac.guild_id()?;
+        let current = get_str_opt_ac(&ac.data.options, "artist")
+            .unwrap_or("")
+            .to_lowercase();
+        let follows = get_follows(&*handler.db.lock().await, guild_id, Some(ac.user.id), None)?;
+        let resp = follows
+            .into_iter()
+            .filter(|f| f.artist_name.to_lowercase().contains(&current))
+            .take(25)
+            .fold(CreateAutocompleteResponse::new(), |resp, f| {
+                resp.add_string_choice(f.artist_name.clone(), f.artist_name)
+            });
+        ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "release_calendar",
+    desc = "Show recent and upcoming releases from followed artists"
+)]
+pub struct ReleaseCalendar {
+    #[cmd(desc = "Only show artists with this genre")]
+    genre: Option<String>,
+    #[cmd(desc = "Only show artists you follow")]
+    mine: Option<bool>,
+}
+
+impl ReleaseCalendar {
+    async fn calendar(
+        &self,
+        handler: &Handler,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CreateEmbed> {
+        let guild_id = command.guild_id()?;
+        let user_id = self.mine.unwrap_or(false).then_some(command.user.id);
+        let follows = get_follows(
+            &*handler.db.lock().await,
+            guild_id,
+            user_id,
+            self.genre.as_deref(),
+        )?;
+        if follows.is_empty() {
+            anyhow::bail!("No followed artists, use /follow_artist to add some");
+        }
+        let truncated = follows.len() > MAX_ARTISTS;
+        let follows = &follows[..follows.len().min(MAX_ARTISTS)];
+
+        let today = handler.clock.local_now().date_naive();
+        let first = today.with_day(1).unwrap_or(today);
+        let since = today - Duration::days(RECENT_DAYS);
+        let spotify = handler.module::<Spotify>()?;
+        let releases = fetch_releases(spotify, follows)
+            .await
+            .into_iter()
+            .filter(|r| r.date >= since.min(first))
+            .sorted_by_key(|r| r.date)
+            .collect_vec();
+
+        let release_days = releases
+            .iter()
+            .filter(|r| r.date.year() == first.year() && r.date.month() == first.month())
+            .map(|r| r.date.day())
+            .collect();
+        let listed = releases
+            .iter()
+            .take(MAX_LISTED)
+            .map(|r| {
+                let name = match &r.url {
+                    Some(url) => format!("[{}]({url})", r.name),
+                    None => r.name.clone(),
+                };
+                let upcoming = if r.date > today { " (upcoming)" } else { "" };
+                format!(
+                    "`{}` {} - {name}{upcoming}",
+                    r.date.format("%m-%d"),
+                    r.artist
+                )
+            })
+            .join("\n");
+        let mut description = format!("```\n{}\n```\n", month_grid(first, &release_days));
+        if listed.is_empty() {
+            description.push_str("No recent releases");
+        } else {
+            description.push_str(&listed);
+        }
+        let mut footer = format!("{} artists", follows.len());
+        if let Some(genre) = &self.genre {
+            footer.push_str(&format!(" • genre: {genre}"));
+        }
+        if truncated {
+            footer.push_str(&format!(" • only the first {MAX_ARTISTS} are shown"));
+        }
+        Ok(CreateEmbed::new()
+            .title(format!("Releases for {}", first.format("%B %Y")))
+            .description(description)
+            .footer(CreateEmbedFooter::new(footer)))
+    }
+}
+
+#[async_trait]
+impl BotCommand for ReleaseCalendar {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let edit = match self.calendar(handler, command).await {
+            Ok(embed) => EditInteractionResponse::new().embed(embed),
+            Err(e) => EditInteractionResponse::new().content(e.to_string()),
+        };
+        command.edit_response(&ctx.http, edit).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+pub struct Releases;
+
+#[async_trait]
+impl Module for Releases {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Spotify>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Releases)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS artist_follow (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                artist_id STRING NOT NULL,
+                artist_name STRING NOT NULL,
+                genres STRING NOT NULL DEFAULT '',
+                UNIQUE(guild_id, user_id, artist_id)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<FollowArtist>();
+        store.register::<UnfollowArtist>();
+        store.register::<ReleaseCalendar>();
+        completions.push(complete_followed);
+    }
+}
@@ -0,0 +1,577 @@
+// Run commands later, once or on a schedule, e.g. posting /bdays every Monday.
+// The interaction that scheduled the command is stored with the scheduled command's name
+// and options, and replayed in the same channel on behalf of the same user. Only commands
+// that opt in with `BotCommand::SCHEDULABLE` can be scheduled, as commands responding to the
+// interaction themselves cannot run without a user.
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use regex::Regex;
+use rusqlite::params;
+use serenity::builder::{
+    CreateAutocompleteResponse, CreateCommandOption, CreateInteractionResponse,
+};
+use serenity::http::Http;
+use serenity::json::{self, Value};
+use serenity::model::application::CommandType;
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, Ready, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::{get_str_opt_ac, Paginator, Responder};
+use crate::db::{Db, SqlChannelId, SqlGuildId, SqlUserId};
+use crate::gateway::GatewayHandlers;
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+use crate::time_parse::parse_time;
+
+const DAY_SECS: i64 = 24 * 3600;
+const REPEATS: &[(&str, i64)] = &[("once", 0), ("daily", DAY_SECS), ("weekly", 7 * DAY_SECS)];
+// Permission needed to schedule commands, checked again on every run
+const SCHEDULE_PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+static ID_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+").unwrap());
+static OPTION_NAME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|\s)(\w+):").unwrap());
+
+pub struct ScheduledCommand {
+    pub id: i64,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub command: String,
+    pub options: String,
+    // the interaction replayed when the command runs, as JSON
+    pub interaction: String,
+    pub next_run: i64,
+    pub every_secs: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+const SELECT_SCHEDULED: &str = "SELECT id, guild_id, channel_id, user_id, command, options,
+    interaction, next_run, every_secs, last_error FROM scheduled_command";
+
+fn scheduled_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ScheduledCommand> {
+    Ok(ScheduledCommand {
+        id: row.get(0)?,
+        guild_id: row.get::<_, SqlGuildId>(1)?.0,
+        channel_id: row.get::<_, SqlChannelId>(2)?.0,
+        user_id: row.get::<_, SqlUserId>(3)?.0,
+        command: row.get(4)?,
+        options: row.get(5)?,
+        interaction: row.get(6)?,
+        next_run: row.get(7)?,
+        every_secs: row.get(8)?,
+        last_error: row.get(9)?,
+    })
+}
+
+// One-off commands that failed are kept so that the error shows in /scheduled_commands, but
+// are not run again
+fn due_commands(db: &Db, now: i64) -> anyhow::Result<Vec<ScheduledCommand>> {
+    let mut stmt = db.conn.prepare(&format!(
+        "{SELECT_SCHEDULED} WHERE next_run <= ?1
+         AND (every_secs IS NOT NULL OR last_error IS NULL)"
+    ))?;
+    let res = stmt.query([now])?.map(scheduled_from_row).collect()?;
+    Ok(res)
+}
+
+fn guild_commands(db: &Db, guild_id: GuildId) -> anyhow::Result<Vec<ScheduledCommand>> {
+    let mut stmt = db.conn.prepare(&format!(
+        "{SELECT_SCHEDULED} WHERE guild_id = ?1 ORDER BY next_run"
+    ))?;
+    let res = stmt
+        .query([SqlGuildId(guild_id)])?
+        .map(scheduled_from_row)
+        .collect()?;
+    Ok(res)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// Convert a value to the raw format sent by Discord for an option type
+fn option_value(kind: u64, value: &str) -> Option<Value> {
+    Some(match kind {
+        3 => Value::from(value),
+        4 => Value::from(value.parse::<i64>().ok()?),
+        5 => Value::from(parse_bool(value)?),
+        // users, channels, roles and mentionables, either as mentions or ids
+        6..=9 => {
+            let id = ID_RE.find(value)?.as_str();
+            Value::from(id)
+        }
+        10 => Value::from(value.parse::<f64>().ok()?),
+        _ => return None,
+    })
+}
+
+// Parse options written as `name:value`, e.g. `user:@someone count:5`, using the option types
+// from the command's registration
+fn parse_options(registration: &Value, input: &str) -> anyhow::Result<Vec<Value>> {
+    let declared = registration
+        .get("options")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let kind_of = |name: &str| {
+        declared
+            .iter()
+            .find(|opt| opt.get("name").and_then(Value::as_str) == Some(name))
+            .and_then(|opt| opt.get("type").and_then(Value::as_u64))
+    };
+    if declared
+        .iter()
+        .any(|opt| matches!(opt.get("type").and_then(Value::as_u64), Some(1 | 2)))
+    {
+        bail!("Commands with subcommands cannot be scheduled");
+    }
+    // only known option names start a new option, so that values can contain colons
+    let starts = OPTION_NAME_RE
+        .captures_iter(input)
+        .filter_map(|cap| {
+            let name = cap.get(1)?;
+            kind_of(name.as_str())?;
+            Some((cap.get(0)?.start(), cap.get(0)?.end(), name.as_str()))
+        })
+        .collect::<Vec<_>>();
+    let unparsed = &input[..starts.first().map_or(input.len(), |start| start.0)];
+    if !unparsed.trim().is_empty() {
+        bail!(
+            "Expected options as `name:value`, got `{}`",
+            unparsed.trim()
+        );
+    }
+    let mut options = Vec::with_capacity(starts.len());
+    for (i, &(_, value_start, name)) in starts.iter().enumerate() {
+        let value_end = starts.get(i + 1).map_or(input.len(), |next| next.0);
+        let value = input[value_start..value_end].trim();
+        let kind = kind_of(name).unwrap_or_default();
+        let value = option_value(kind, value)
+            .ok_or_else(|| anyhow!("Invalid value for {name}: `{value}`"))?;
+        let mut option = json::JsonMap::new();
+        option.insert("name".to_string(), Value::from(name));
+        option.insert("type".to_string(), Value::from(kind));
+        option.insert("value".to_string(), value);
+        options.push(Value::Object(option));
+    }
+    for opt in &declared {
+        let name = opt.get("name").and_then(Value::as_str).unwrap_or_default();
+        let required = opt.get("required").and_then(Value::as_bool) == Some(true);
+        if required && !starts.iter().any(|start| start.2 == name) {
+            bail!("Missing required option `{name}`");
+        }
+    }
+    Ok(options)
+}
+
+// Permissions of the user who scheduled a command, in the channel it runs in
+async fn current_permissions(
+    ctx: &Context,
+    scheduled: &ScheduledCommand,
+) -> anyhow::Result<Permissions> {
+    let member = scheduled
+        .guild_id
+        .member(ctx, scheduled.user_id)
+        .await
+        .map_err(|_| anyhow!("<@{}> is no longer a member", scheduled.user_id))?;
+    let guild = ctx
+        .cache
+        .guild(scheduled.guild_id)
+        .ok_or_else(|| anyhow!("Server not found"))?;
+    let channel = guild
+        .channels
+        .get(&scheduled.channel_id)
+        .ok_or_else(|| anyhow!("Channel not found"))?;
+    Ok(guild.user_permissions_in(channel, &member))
+}
+
+async fn run_scheduled(
+    handler: &Handler,
+    ctx: &Context,
+    scheduled: &ScheduledCommand,
+) -> anyhow::Result<()> {
+    let permissions = current_permissions(ctx, scheduled).await?;
+    if !permissions.contains(SCHEDULE_PERMISSIONS) {
+        bail!("<@{}> can no longer schedule commands", scheduled.user_id);
+    }
+    let commands = handler.commands.read().await;
+    let runner = commands
+        .0
+        .get(&(scheduled.command.as_str(), CommandType::ChatInput))
+        .filter(|runner| runner.schedulable())
+        .ok_or_else(|| anyhow!("`/{}` cannot be scheduled", scheduled.command))?;
+    if !permissions.contains(runner.permissions()) {
+        bail!(
+            "<@{}> is no longer allowed to use `/{}`",
+            scheduled.user_id,
+            scheduled.command
+        );
+    }
+    let mut interaction: Value = json::from_str(&scheduled.interaction)?;
+    interaction["member"]["permissions"] = Value::from(permissions.bits().to_string());
+    let interaction: CommandInteraction = json::from_value(interaction)?;
+    let resp = runner.run(handler, ctx, &interaction).await?;
+    let resp = handler.text_fallback(Some(scheduled.guild_id), resp).await;
+    // private responses are meant for the user, e.g. errors or empty results
+    if let CommandResponse::Private(resp) = resp {
+        bail!(resp.to_content().0.unwrap_or_default());
+    }
+    let policy = handler
+        .mention_policy(Some(scheduled.guild_id), &scheduled.command)
+        .await;
+    scheduled
+        .channel_id
+        .respond(&ctx.http, resp, None, policy)
+        .await?;
+    Ok(())
+}
+
+// Run scheduled commands that are due. Needs a full context as commands may use the cache,
+// so nothing runs until the bot is ready.
+fn run_due_commands<'a>(
+    handler: &'a Handler,
+    _http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let Some(ctx) = handler.module::<ScheduledCommands>()?.ctx.get() else {
+            return Ok(());
+        };
+        let now = handler.clock.now().timestamp();
+        let due = handler.db_read(move |db| due_commands(db, now)).await?;
+        for scheduled in due {
+            let error = run_scheduled(handler, ctx, &scheduled)
+                .await
+                .err()
+                .map(|e| e.to_string());
+            if let Some(e) = &error {
+                tracing::error!("Error running scheduled command {}: {e}", scheduled.id);
+            }
+            let (id, next_run, every) = (scheduled.id, scheduled.next_run, scheduled.every_secs);
+            let res = handler
+                .db_call(move |db| {
+                    match every {
+                        Some(every) => {
+                            // skip runs that were missed while the bot was offline
+                            let missed = (now - next_run) / every;
+                            let next_run = next_run + (missed + 1) * every;
+                            db.conn.execute(
+                                "UPDATE scheduled_command SET next_run = ?2, last_error = ?3
+                                 WHERE id = ?1",
+                                params![id, next_run, error],
+                            )?
+                        }
+                        None if error.is_some() => db.conn.execute(
+                            "UPDATE scheduled_command SET last_error = ?2 WHERE id = ?1",
+                            params![id, error],
+                        )?,
+                        None => db
+                            .conn
+                            .execute("DELETE FROM scheduled_command WHERE id = ?1", [id])?,
+                    };
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = res {
+                tracing::error!("Error updating scheduled command {id}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+fn remember_context<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    _ready: &'a Ready,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        _ = handler.module::<ScheduledCommands>()?.ctx.set(ctx.clone());
+        Ok(())
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "schedule_command",
+    desc = "Run a command later in this channel, once or repeatedly"
+)]
+pub struct ScheduleCommand {
+    #[cmd(desc = "The command to run", autocomplete)]
+    command: String,
+    #[cmd(desc = "When to run it first (e.g. 21:30, in 2h, 2024-06-02 20:00)")]
+    time: String,
+    #[cmd(desc = "The command's options, as name:value (e.g. user:@someone)")]
+    options: Option<String>,
+    #[cmd(desc = "How often to run the command (defaults to once)")]
+    repeat: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for ScheduleCommand {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = SCHEDULE_PERMISSIONS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let name = self.command.trim().trim_start_matches('/');
+        let every_secs = match self.repeat.as_deref() {
+            None => None,
+            Some(repeat) => REPEATS
+                .iter()
+                .find(|(r, _)| *r == repeat)
+                .map(|&(_, secs)| (secs > 0).then_some(secs))
+                .ok_or_else(|| anyhow!("Unknown repeat `{repeat}`"))?,
+        };
+        let registration = {
+            let commands = handler.commands.read().await;
+            let runner = commands
+                .0
+                .get(&(name, CommandType::ChatInput))
+                .ok_or_else(|| anyhow!("Unknown command `/{name}`"))?;
+            if !runner.schedulable() {
+                bail!("`/{name}` cannot be scheduled");
+            }
+            let permissions = command
+                .member
+                .as_ref()
+                .and_then(|member| member.permissions)
+                .unwrap_or_default();
+            if !permissions.contains(runner.permissions()) {
+                bail!("You are not allowed to use `/{name}`");
+            }
+            json::to_value(runner.register())?
+        };
+        let options = self.options.unwrap_or_default();
+        let parsed = parse_options(&registration, &options)?;
+        let mut interaction = json::to_value(command)?;
+        interaction["data"]["name"] = Value::from(name);
+        interaction["data"]["options"] = Value::Array(parsed);
+        // make sure the interaction can be replayed
+        json::from_value::<CommandInteraction>(interaction.clone())?;
+        let next_run = parse_time(&self.time, handler.clock.now())?;
+        let id = {
+            let db = handler.db.lock().await;
+            db.conn.execute(
+                "INSERT INTO scheduled_command (guild_id, channel_id, user_id, command, options,
+                 interaction, next_run, every_secs) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    SqlGuildId(guild_id),
+                    SqlChannelId(command.channel_id),
+                    SqlUserId(command.user.id),
+                    name,
+                    options.trim(),
+                    json::to_string(&interaction)?,
+                    next_run.timestamp(),
+                    every_secs,
+                ],
+            )?;
+            db.conn.last_insert_rowid()
+        };
+        CommandResponse::private(format!(
+            "`/{name}` will run in this channel <t:{}:R> (id {id})",
+            next_run.timestamp()
+        ))
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        if opt_name == "repeat" {
+            REPEATS.iter().fold(opt, |opt, (repeat, _)| {
+                opt.add_string_choice(*repeat, *repeat)
+            })
+        } else {
+            opt
+        }
+    }
+}
+
+fn format_scheduled(scheduled: &ScheduledCommand) -> String {
+    let repeat = REPEATS
+        .iter()
+        .find(|(_, secs)| Some(*secs) == scheduled.every_secs)
+        .map_or("once", |(repeat, _)| repeat);
+    let mut line = format!(
+        "`{}` `/{} {}` in <#{}> by <@{}>, {repeat}",
+        scheduled.id, scheduled.command, scheduled.options, scheduled.channel_id, scheduled.user_id
+    );
+    match (&scheduled.last_error, scheduled.every_secs) {
+        (Some(error), None) => line.push_str(&format!(
+            ", failed <t:{}:R>\n> {error}\n> use `/unschedule_command` to remove it",
+            scheduled.next_run
+        )),
+        (error, _) => {
+            line.push_str(&format!(", next <t:{}:R>", scheduled.next_run));
+            if let Some(error) = error {
+                line.push_str(&format!("\n> last run failed: {error}"));
+            }
+        }
+    }
+    line
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "scheduled_commands",
+    desc = "Show commands scheduled in this server"
+)]
+pub struct ListScheduledCommands {}
+
+#[async_trait]
+impl BotCommand for ListScheduledCommands {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = SCHEDULE_PERMISSIONS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let scheduled = guild_commands(&*handler.db.lock().await, guild_id)?;
+        if scheduled.is_empty() {
+            return CommandResponse::private("No scheduled commands");
+        }
+        Paginator::from_lines("Scheduled commands", scheduled.iter().map(format_scheduled))
+            .ephemeral(true)
+            .respond(handler, ctx, command)
+            .await
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "unschedule_command", desc = "Cancel a scheduled command")]
+pub struct UnscheduleCommand {
+    #[cmd(desc = "The scheduled command's id, from /scheduled_commands", min = 1)]
+    id: i64,
+}
+
+#[async_trait]
+impl BotCommand for UnscheduleCommand {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = SCHEDULE_PERMISSIONS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let removed = handler.db.lock().await.conn.execute(
+            "DELETE FROM scheduled_command WHERE guild_id = ?1 AND id = ?2",
+            params![SqlGuildId(guild_id), self.id],
+        )?;
+        if removed == 0 {
+            bail!("No scheduled command with id {}", self.id);
+        }
+        CommandResponse::private(format!("Scheduled command {} cancelled", self.id))
+    }
+}
+
+fn complete_schedulable<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    key: CommandKey<'a>,
+    ac: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if key != ("schedule_command", CommandType::ChatInput) {
+            return Ok(false);
+        }
+        let current = get_str_opt_ac(&ac.data.options, "command")
+            .unwrap_or("")
+            .trim_start_matches('/')
+            .to_lowercase();
+        let mut names = handler
+            .commands
+            .read()
+            .await
+            .0
+            .iter()
+            .filter(|(_, runner)| runner.schedulable())
+            .map(|((name, _), _)| *name)
+            .filter(|name| name.contains(&current))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        let resp = names
+            .into_iter()
+            .take(25)
+            .fold(CreateAutocompleteResponse::new(), |resp, name| {
+                resp.add_string_choice(format!("/{name}"), name)
+            });
+        ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+#[derive(Default)]
+pub struct ScheduledCommands {
+    ctx: OnceLock<Context>,
+}
+
+#[async_trait]
+impl Module for ScheduledCommands {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ScheduledCommands::default())
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS scheduled_command (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                command STRING NOT NULL,
+                options STRING NOT NULL,
+                interaction STRING NOT NULL,
+                next_run INTEGER NOT NULL,
+                every_secs INTEGER,
+                last_error STRING
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<ScheduleCommand>();
+        store.register::<ListScheduledCommands>();
+        store.register::<UnscheduleCommand>();
+        completions.push(complete_schedulable);
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "scheduled_commands",
+            Schedule::every(Duration::from_secs(60)),
+            run_due_commands,
+        ));
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(remember_context);
+    }
+}
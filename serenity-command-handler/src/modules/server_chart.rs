@@ -0,0 +1,278 @@
+// Top albums and artists of the guild's members with a linked Last.fm account, with
+// /server_chart. Play counts of the same album or artist are added up across members.
+// Charts take a request per member, so they are cached for an hour.
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use fallible_iterator::FallibleIterator;
+use futures::future::join_all;
+use futures::StreamExt;
+use itertools::Itertools;
+use serenity::builder::{
+    CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::model::prelude::{CommandInteraction, GuildId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlGuildId};
+use crate::modules::lastfm::{create_grid_chart, fetch_cover, Lastfm, Period};
+use crate::normalize::{album_key, artist_key};
+use crate::prelude::*;
+
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+// members whose top albums are requested at once
+const CONCURRENT_REQUESTS: usize = 4;
+const LISTED: usize = 10;
+const GRID_SIZE: u32 = 3;
+const CHART_NAME: &str = "server_chart.png";
+
+struct ChartAlbum {
+    artist: String,
+    name: String,
+    plays: u64,
+    listeners: usize,
+    image_url: Option<String>,
+}
+
+pub(crate) struct Chart {
+    albums: Vec<ChartAlbum>,
+    artists: Vec<(String, u64)>,
+    members: usize,
+    image: Vec<u8>,
+}
+
+type CachedCharts = HashMap<(GuildId, Period), (Instant, Arc<Chart>)>;
+
+#[derive(Default)]
+pub(crate) struct ChartCache(Mutex<CachedCharts>);
+
+impl ChartCache {
+    fn get(&self, guild_id: GuildId, period: Period) -> Option<Arc<Chart>> {
+        let cache = self.0.lock().unwrap();
+        let (built_at, chart) = cache.get(&(guild_id, period))?;
+        (built_at.elapsed() < CACHE_TTL).then(|| Arc::clone(chart))
+    }
+
+    fn insert(&self, guild_id: GuildId, period: Period, chart: Arc<Chart>) {
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|_, (built_at, _)| built_at.elapsed() < CACHE_TTL);
+        cache.insert((guild_id, period), (Instant::now(), chart));
+    }
+}
+
+fn guild_usernames(db: &Db, guild_id: GuildId) -> anyhow::Result<Vec<String>> {
+    let usernames = db
+        .conn
+        .prepare(
+            "SELECT u.username FROM lastfm_users u
+             JOIN lastfm_user_guild g ON g.user_id = u.user_id
+             WHERE g.guild_id = ?1",
+        )?
+        .query([SqlGuildId(guild_id)])?
+        .map(|row| row.get(0))
+        .collect()?;
+    Ok(usernames)
+}
+
+async fn build_chart(
+    lastfm: Arc<Lastfm>,
+    usernames: Vec<String>,
+    period: Period,
+) -> anyhow::Result<Chart> {
+    let members = usernames.len();
+    let mut results = futures::stream::iter(usernames)
+        .map(|user| {
+            let lastfm = Arc::clone(&lastfm);
+            async move {
+                let res = lastfm
+                    .get_top_albums(user.clone(), None, Some(period))
+                    .await;
+                (user, res)
+            }
+        })
+        .buffer_unordered(CONCURRENT_REQUESTS);
+    let mut albums = HashMap::<(String, String), ChartAlbum>::new();
+    let mut artists = HashMap::<String, (String, u64)>::new();
+    while let Some((user, res)) = results.next().await {
+        // a member's account may have been deleted or made private
+        let top = match res {
+            Ok(top) => top,
+            Err(e) => {
+                tracing::warn!(user, "Could not get top albums: {e:?}");
+                continue;
+            }
+        };
+        for ab in top.album {
+            let plays: u64 = ab.playcount.parse().unwrap_or_default();
+            let artist = artists
+                .entry(artist_key(&ab.artist.name))
+                .or_insert_with(|| (ab.artist.name.clone(), 0));
+            artist.1 += plays;
+            let key = (artist_key(&ab.artist.name), album_key(&ab.name));
+            let album = albums.entry(key).or_insert_with(|| ChartAlbum {
+                artist: ab.artist.name.clone(),
+                name: ab.name.clone(),
+                plays: 0,
+                listeners: 0,
+                image_url: None,
+            });
+            album.plays += plays;
+            album.listeners += 1;
+            if album.image_url.is_none() {
+                album.image_url = ab
+                    .image
+                    .last()
+                    .map(|img| img.url.clone())
+                    .filter(|url| !url.is_empty());
+            }
+        }
+    }
+    let mut albums = albums
+        .into_values()
+        .sorted_by_key(|ab| (Reverse(ab.plays), Reverse(ab.listeners)))
+        .collect_vec();
+    let artists = artists
+        .into_values()
+        .sorted_by_key(|(_, plays)| Reverse(*plays))
+        .take(LISTED)
+        .collect_vec();
+    let covers = albums
+        .iter()
+        .take((GRID_SIZE * GRID_SIZE) as usize)
+        .map(|ab| async {
+            let url = ab.image_url.as_deref()?;
+            fetch_cover(url).await.unwrap_or_else(|e| {
+                tracing::warn!("Could not get album cover: {e:?}");
+                None
+            })
+        });
+    let covers = join_all(covers).await;
+    let images = covers.iter().map(Option::as_ref).collect_vec();
+    let image = create_grid_chart(&images, GRID_SIZE, false)?;
+    albums.truncate(LISTED);
+    Ok(Chart {
+        albums,
+        artists,
+        members,
+        image,
+    })
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "server_chart",
+    desc = "Top albums and artists of the members with a linked Last.fm account",
+    guild_only
+)]
+pub struct ServerChart {
+    #[cmd(desc = "Period of the chart (defaults to 7 days)", choice)]
+    pub period: Option<Period>,
+}
+
+#[async_trait]
+impl GuildCommand for ServerChart {
+    type Data = Handler;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        let period = self.period.unwrap_or(Period::Week);
+        let lastfm: Arc<Lastfm> = handler.module_arc()?;
+        let cached = lastfm.server_charts.get(guild_id, period);
+        let mut usernames = Vec::new();
+        if cached.is_none() {
+            usernames = handler
+                .db_read(move |db| guild_usernames(db, guild_id))
+                .await?;
+            if usernames.is_empty() {
+                return CommandResponse::private(
+                    "Nobody linked their Last.fm account in this server, see /lastfm_link",
+                );
+            }
+        }
+        // fetching every member's top albums takes a while
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let chart = match cached {
+            Some(chart) => chart,
+            None => match build_chart(Arc::clone(&lastfm), usernames, period).await {
+                Ok(chart) => {
+                    let chart = Arc::new(chart);
+                    lastfm
+                        .server_charts
+                        .insert(guild_id, period, Arc::clone(&chart));
+                    chart
+                }
+                Err(e) => {
+                    let resp = EditInteractionResponse::new().content(e.to_string());
+                    command.edit_response(&ctx.http, resp).await?;
+                    return Ok(CommandResponse::None);
+                }
+            },
+        };
+        command
+            .edit_response(&ctx.http, chart_response(&chart, period))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+fn chart_response(chart: &Chart, period: Period) -> EditInteractionResponse {
+    if chart.albums.is_empty() {
+        return EditInteractionResponse::new().content(format!(
+            "Nobody in this server scrobbled an album over {}",
+            period.describe()
+        ));
+    }
+    let mut albums = String::new();
+    for (i, ab) in chart.albums.iter().enumerate() {
+        _ = writeln!(
+            albums,
+            "{}. {} - {} ({} plays, {} listeners)",
+            i + 1,
+            ab.artist,
+            ab.name,
+            ab.plays,
+            ab.listeners
+        );
+    }
+    let mut artists = String::new();
+    for (i, (artist, plays)) in chart.artists.iter().enumerate() {
+        _ = writeln!(artists, "{}. {artist} ({plays} plays)", i + 1);
+    }
+    let embed = CreateEmbed::new()
+        .title(format!(
+            "Top albums of {} in this server",
+            period.describe()
+        ))
+        .description(albums)
+        .field("Top artists", artists, false)
+        .image(format!("attachment://{CHART_NAME}"))
+        .footer(CreateEmbedFooter::new(format!(
+            "{} members with a linked Last.fm account",
+            chart.members
+        )));
+    let image = CreateAttachment::bytes(Cow::Owned(chart.image.clone()), CHART_NAME);
+    EditInteractionResponse::new()
+        .embed(embed)
+        .new_attachment(image)
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<ServerChart>();
+}
@@ -0,0 +1,336 @@
+use anyhow::{anyhow, bail};
+use fallible_iterator::FallibleIterator;
+use rusqlite::params;
+use serenity::builder::{CreateCommandOption, CreateEmbed, CreateEmbedFooter};
+use serenity::model::prelude::{CommandInteraction, GuildId, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::date_format::{DateFormat, DateOrder};
+use crate::db::{AuditEntry, Db, SqlGuildId};
+use crate::mentions::MentionPolicy;
+use crate::prelude::*;
+use crate::time_parse::parse_offset;
+
+const PAGE_SIZE: usize = 10;
+const MAX_VALUE_LEN: usize = 50;
+const POLICY_CHOICES: [&str; 6] = [
+    "none",
+    "users",
+    "roles",
+    "users,roles",
+    "users,roles,everyone",
+    "default",
+];
+
+fn format_value(value: Option<&str>) -> String {
+    match value {
+        None | Some("") => "(none)".to_string(),
+        Some(v) if v.chars().count() > MAX_VALUE_LEN => {
+            format!("`{}…`", v.chars().take(MAX_VALUE_LEN).collect::<String>())
+        }
+        Some(v) => format!("`{v}`"),
+    }
+}
+
+fn format_entry(entry: &AuditEntry) -> String {
+    format!(
+        "<t:{}:f> <@{}> changed **{}**: {} → {}",
+        entry.ts,
+        entry.user_id,
+        entry.field,
+        format_value(entry.old_value.as_deref()),
+        format_value(entry.new_value.as_deref()),
+    )
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "settings_audit",
+    desc = "Show recent changes to server settings"
+)]
+pub struct SettingsAudit {
+    #[cmd(desc = "Only show changes to this setting")]
+    field: Option<String>,
+    #[cmd(desc = "Only show changes made by this user")]
+    user: Option<UserId>,
+    #[cmd(desc = "Page number", min = 1)]
+    page: Option<i64>,
+}
+
+#[async_trait]
+impl BotCommand for SettingsAudit {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let page = self.page.unwrap_or(1).max(1) as usize;
+        let (field, user) = (self.field, self.user);
+        let entries = handler
+            .db_read(move |db| {
+                db.get_guild_audit(
+                    guild_id,
+                    field.as_deref(),
+                    user,
+                    PAGE_SIZE,
+                    (page - 1) * PAGE_SIZE,
+                )
+            })
+            .await?;
+        if entries.is_empty() {
+            return CommandResponse::private("No setting changes found");
+        }
+        let description = entries
+            .iter()
+            .map(format_entry)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let embed = CreateEmbed::new()
+            .title("Settings audit log")
+            .description(description)
+            .footer(CreateEmbedFooter::new(format!("Page {page}")));
+        CommandResponse::private(embed)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "plain_text",
+    desc = "Send responses as text instead of embeds, for servers with link embeds disabled"
+)]
+pub struct SetPlainText {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetPlainText {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        handler
+            .set_guild_field(guild_id, command.user.id, "plain_text", self.enabled)
+            .await?;
+        let resp = if self.enabled {
+            "Responses will be sent as plain text"
+        } else {
+            "Responses will use embeds"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "mention_policy",
+    desc = "Choose which mentions in bot messages are allowed to ping"
+)]
+pub struct SetMentionPolicy {
+    #[cmd(desc = "Mentions allowed to ping, leave empty to show the current policy")]
+    allow: Option<String>,
+    #[cmd(desc = "Only apply to this command instead of the whole server")]
+    command: Option<String>,
+}
+
+impl SetMentionPolicy {
+    fn show(db: &Db, guild_id: GuildId) -> anyhow::Result<String> {
+        let default: Option<String> = db.get_guild_field(guild_id, "mention_policy")?;
+        let overrides: Vec<(String, String)> = db
+            .conn
+            .prepare(
+                "SELECT command, policy FROM mention_policy WHERE guild_id = ?1 ORDER BY command",
+            )?
+            .query([SqlGuildId(guild_id)])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        let mut resp = format!(
+            "Server default: `{}`",
+            default.unwrap_or_else(|| MentionPolicy::default().to_string())
+        );
+        for (command, policy) in overrides {
+            resp.push_str(&format!("\n`/{command}`: `{policy}`"));
+        }
+        Ok(resp)
+    }
+}
+
+#[async_trait]
+impl BotCommand for SetMentionPolicy {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let Some(allow) = self.allow else {
+            let resp = handler.db_read(move |db| Self::show(db, guild_id)).await?;
+            return CommandResponse::private(resp);
+        };
+        // "default" removes the setting, falling back to the server's or the bot's default
+        let policy = match allow.as_str() {
+            "default" => None,
+            allow => Some(allow.parse::<MentionPolicy>()?.to_string()),
+        };
+        let Some(name) = self.command else {
+            handler
+                .set_guild_field(guild_id, command.user.id, "mention_policy", &policy)
+                .await?;
+            return CommandResponse::private(format!(
+                "Mention policy set to `{}`",
+                policy.unwrap_or_else(|| MentionPolicy::default().to_string())
+            ));
+        };
+        let name = name.trim_start_matches('/');
+        if !handler
+            .commands
+            .read()
+            .await
+            .0
+            .keys()
+            .any(|(n, _)| *n == name)
+        {
+            bail!("Unknown command `/{name}`");
+        }
+        let (sql_name, sql_policy) = (name.to_string(), policy.clone());
+        handler
+            .db_call(move |db| {
+                match sql_policy {
+                    Some(policy) => db.conn.execute(
+                        "INSERT INTO mention_policy (guild_id, command, policy) VALUES (?1, ?2, ?3)
+                         ON CONFLICT(guild_id, command) DO UPDATE SET policy = ?3",
+                        params![SqlGuildId(guild_id), sql_name, policy],
+                    )?,
+                    None => db.conn.execute(
+                        "DELETE FROM mention_policy WHERE guild_id = ?1 AND command = ?2",
+                        params![SqlGuildId(guild_id), sql_name],
+                    )?,
+                };
+                Ok(())
+            })
+            .await?;
+        let resp = match policy {
+            Some(policy) => format!("Mention policy for `/{name}` set to `{policy}`"),
+            None => format!("`/{name}` now uses the server's mention policy"),
+        };
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        if opt_name == "allow" {
+            POLICY_CHOICES
+                .into_iter()
+                .fold(opt, |opt, choice| opt.add_string_choice(choice, choice))
+        } else {
+            opt
+        }
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "date_format",
+    desc = "Set how dates are displayed, leave empty to show the current format"
+)]
+pub struct SetDateFormat {
+    #[cmd(desc = "Locale deciding the order of days and months (e.g. en-GB, en-US, ja)")]
+    locale: Option<String>,
+    #[cmd(desc = "Server timezone, as an offset or abbreviation (e.g. UTC+2, CET)")]
+    timezone: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetDateFormat {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        let user_id = command.user.id;
+        if let Some(locale) = &self.locale {
+            handler
+                .set_guild_field(guild_id, user_id, "date_locale", locale)
+                .await?;
+        }
+        if let Some(timezone) = &self.timezone {
+            let tz = timezone.trim().to_lowercase();
+            let offset =
+                parse_offset(&tz).ok_or_else(|| anyhow!("Unknown timezone `{timezone}`"))?;
+            handler
+                .set_guild_field(
+                    guild_id,
+                    user_id,
+                    "utc_offset",
+                    offset.local_minus_utc() / 60,
+                )
+                .await?;
+        }
+        let format = DateFormat::for_interaction(handler, command).await;
+        let order = match format.order {
+            DateOrder::DayMonth => "day/month",
+            DateOrder::MonthDay => "month/day",
+            DateOrder::YearFirst => "year-month-day",
+        };
+        CommandResponse::private(format!(
+            "Dates are shown as {order} ({}), in UTC{}",
+            format.date(format.today(handler.clock.now())),
+            format.offset
+        ))
+    }
+}
+
+pub struct Settings;
+
+#[async_trait]
+impl Module for Settings {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Settings)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.create_guild_tables()?;
+        db.add_guild_field("plain_text", "BOOLEAN NOT NULL DEFAULT(false)")?;
+        db.add_guild_field("mention_policy", "STRING")?;
+        db.add_guild_field("date_locale", "STRING")?;
+        db.add_guild_field("utc_offset", "INTEGER")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS mention_policy (
+                guild_id INTEGER NOT NULL,
+                command STRING NOT NULL,
+                policy STRING NOT NULL,
+                UNIQUE(guild_id, command)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<SettingsAudit>();
+        store.register::<SetPlainText>();
+        store.register::<SetMentionPolicy>();
+        store.register::<SetDateFormat>();
+    }
+}
@@ -1,40 +1,71 @@
-use std::{borrow::Cow, collections::HashSet, sync::atomic::AtomicU64};
-
-use crate::{CommandStore, CompletionStore, Handler, Module, ModuleMap};
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::{borrow::Cow, collections::HashSet};
+
+use crate::gateway::{GatewayHandlers, MessageCreate, ReactionAdd};
+use crate::health::HealthStatus;
+use crate::modal::{required_value, ModalForm};
+use crate::{
+    CommandStore, CompletionStore, ComponentStore, Handler, ModalStore, Module, ModuleMap,
+};
 use anyhow::{anyhow, bail, Context as _};
+use chrono::{DateTime, Duration, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use itertools::Itertools;
 use regex::Regex;
 use reqwest::redirect::Policy;
 use rspotify::{
     clients::{BaseClient, OAuthClient},
     model::{
-        AlbumId, FullEpisode, FullTrack, Id, PlayableItem, PlaylistId, SearchType,
-        SimplifiedArtist, TrackId,
+        AlbumId, AlbumType, ArtistId, EpisodeId, FullArtist, FullEpisode, FullTrack, Id,
+        PlayableItem, PlaylistId, SearchType, SimplifiedAlbum, SimplifiedArtist, TrackId,
     },
     AuthCodeSpotify, ClientCredsSpotify, Config, Credentials,
 };
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
 use serenity::{
     async_trait,
-    model::prelude::CommandInteraction,
-    model::{channel::Message, prelude::Reaction},
+    model::prelude::{ChannelId, CommandInteraction, ComponentInteraction, ModalInteraction},
+    model::{channel::Message, id::UserId, prelude::Reaction},
 };
 use serenity::{http::Http, model::prelude::ReactionType, prelude::*};
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{BotCommand, CommandError, CommandResponse};
 use serenity_command_derive::Command;
 
-use crate::album::{Album, AlbumProvider};
+use crate::album::{Album, AlbumProvider, Track};
+use crate::metrics::metrics;
+use crate::normalize::{album_key, artist_key, fold};
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
 
 const ALBUM_URL_START: &str = "https://open.spotify.com/album/";
 const PLAYLIST_URL_START: &str = "https://open.spotify.com/playlist/";
 const TRACK_URL_START: &str = "https://open.spotify.com/track/";
+const EPISODE_URL_START: &str = "https://open.spotify.com/episode/";
 const SHORTENED_URL_START: &str = "https://spotify.link/";
 
 const CACHE_PATH: &str = "rspotify_cache";
 
 const UNLINK_REACT: &str = "🔗";
 
+// The OAuth token is refreshed when it expires in less than this
+const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 30;
+const TOKEN_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+// Button opening the form where the URL Spotify redirected to is pasted, and that form
+const AUTH_BUTTON_ID: &str = "spotify-auth";
+const AUTH_FORM_ID: &str = "spotify-auth-submit";
+
 pub struct Spotify<C: BaseClient> {
     // client: ClientCredsSpotify,
     pub client: C,
+    // error from the last OAuth token refresh, see `check_token`
+    refresh_error: StdMutex<Option<String>>,
+    // told when the OAuth account has to be authorized again, see `alert_channel`
+    alert_channel: Option<ChannelId>,
+    alerted: AtomicBool,
 }
 
 pub type SpotifyOAuth = Spotify<AuthCodeSpotify>;
@@ -57,6 +88,7 @@ async fn resolve_redirect(url: &str) -> anyhow::Result<String> {
 
 impl<C: BaseClient> Spotify<C> {
     async fn get_album_from_id(&self, id: &str) -> anyhow::Result<Album> {
+        metrics().increment("spotify_lookups", "album");
         let album = self.client.album(AlbumId::from_id(id)?, None).await?;
         let name = album.name.clone();
         let artist = album
@@ -68,6 +100,15 @@ impl<C: BaseClient> Spotify<C> {
         let genres = album.genres.clone();
         let release_date = Some(album.release_date);
         let duration = album.tracks.items.iter().map(|track| track.duration).sum();
+        let tracks = album
+            .tracks
+            .items
+            .iter()
+            .map(|track| Track {
+                name: track.name.clone(),
+                duration: track.duration,
+            })
+            .collect();
         Ok(Album {
             name: Some(name),
             artist: Some(artist),
@@ -75,6 +116,8 @@ impl<C: BaseClient> Spotify<C> {
             release_date,
             url: Some(album.id.url()),
             duration: Some(duration),
+            cover: album.images.first().map(|img| img.url.clone()),
+            tracks,
             ..Default::default()
         })
     }
@@ -86,11 +129,16 @@ impl<C: BaseClient> Spotify<C> {
             .await?;
         let name = playlist.name.clone();
         let artist = playlist.owner.display_name;
-        let duration = playlist
+        let items = playlist
             .tracks
             .items
             .iter()
-            .flat_map(|item| item.track.as_ref())
+            .flat_map(|item| item.track.as_ref());
+        let episodes = items
+            .clone()
+            .filter(|item| matches!(item, PlayableItem::Episode(_)))
+            .count();
+        let duration = items
             .map(|track| match track {
                 PlayableItem::Track(FullTrack { duration, .. }) => duration,
                 PlayableItem::Episode(FullEpisode { duration, .. }) => duration,
@@ -102,11 +150,29 @@ impl<C: BaseClient> Spotify<C> {
             url: Some(playlist.id.url()),
             duration: Some(duration),
             is_playlist: true,
+            episodes,
+            ..Default::default()
+        })
+    }
+
+    async fn get_episode_from_id(&self, id: &str) -> anyhow::Result<Album> {
+        let episode = self
+            .client
+            .get_an_episode(EpisodeId::from_id(id)?, None)
+            .await?;
+        Ok(Album {
+            name: Some(episode.name),
+            artist: Some(episode.show.name),
+            release_date: Some(episode.release_date),
+            url: Some(episode.id.url()),
+            duration: Some(episode.duration),
+            is_episode: true,
             ..Default::default()
         })
     }
 
     pub async fn get_song_from_id(&self, id: &str) -> anyhow::Result<FullTrack> {
+        metrics().increment("spotify_lookups", "track");
         Ok(self.client.track(TrackId::from_id(id)?, None).await?)
     }
 
@@ -120,6 +186,8 @@ impl<C: BaseClient> Spotify<C> {
             self.get_song_from_id(id.split('?').next().unwrap()).await
         } else if url.starts_with(ALBUM_URL_START) {
             bail!("Expected a spotify track URL, got an album URL")
+        } else if url.starts_with(EPISODE_URL_START) {
+            bail!("Podcast episodes are not supported, expected a spotify track URL")
         } else {
             bail!("Invalid spotify URL")
         }
@@ -141,6 +209,54 @@ fn sanitize_string(s: &str) -> String {
         .collect()
 }
 
+// words indicating an album is likely not the one we are looking for
+const SUSPICIOUS_WORDS: &[&str] = &["karaoke", "tribute", "cover", "covers", "instrumental"];
+
+// similarity between two normalized strings, between 0 and 1
+fn similarity(a: &str, b: &str) -> f32 {
+    if a == b {
+        return 1.0;
+    }
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    if a.contains(b) || b.contains(a) {
+        return 0.7;
+    }
+    let words_a: HashSet<_> = a.split(' ').collect();
+    let words_b: HashSet<_> = b.split(' ').collect();
+    let common = words_a.intersection(&words_b).count();
+    0.6 * common as f32 / words_a.union(&words_b).count() as f32
+}
+
+fn has_suspicious_word(s: &str) -> bool {
+    s.split(' ').any(|word| SUSPICIOUS_WORDS.contains(&word))
+}
+
+// score a search result against the artist and album name we are looking for
+fn album_score(album: &SimplifiedAlbum, artist: &str, name: &str) -> f32 {
+    let album_name = album_key(&album.name);
+    let artist_score = album
+        .artists
+        .iter()
+        .map(|ar| similarity(&artist_key(&ar.name), artist))
+        .fold(0.0, f32::max);
+    let mut score = 2.0 * artist_score + 2.0 * similarity(&album_name, name);
+    if album.album_type.as_deref() == Some("album") {
+        score += 0.1;
+    }
+    // checked on the full name, album_key drops suffixes like "(Karaoke Version)"
+    let suspicious = has_suspicious_word(&fold(&album.name))
+        || album
+            .artists
+            .iter()
+            .any(|ar| has_suspicious_word(&artist_key(&ar.name)));
+    if suspicious && !has_suspicious_word(name) {
+        score -= 1.0;
+    }
+    score
+}
+
 #[async_trait]
 impl<C: BaseClient> AlbumProvider for Spotify<C> {
     fn id(&self) -> &'static str {
@@ -158,6 +274,9 @@ impl<C: BaseClient> AlbumProvider for Spotify<C> {
         } else if let Some(id) = url.strip_prefix(PLAYLIST_URL_START) {
             self.get_playlist_from_id(id.split('?').next().unwrap())
                 .await
+        } else if let Some(id) = url.strip_prefix(EPISODE_URL_START) {
+            self.get_episode_from_id(id.split('?').next().unwrap())
+                .await
         } else {
             bail!("Invalid spotify url")
         }
@@ -166,10 +285,17 @@ impl<C: BaseClient> AlbumProvider for Spotify<C> {
     fn url_matches(&self, url: &str) -> bool {
         url.starts_with(ALBUM_URL_START)
             || url.starts_with(PLAYLIST_URL_START)
+            || url.starts_with(EPISODE_URL_START)
             || url.starts_with(SHORTENED_URL_START)
     }
 
     async fn query_album(&self, query: &str) -> anyhow::Result<Album> {
+        if let Some((artist, name)) = query.split_once(" - ") {
+            // looks like "artist - album", rank results using both
+            if let Ok(Some(album)) = self.get_album(artist, name).await {
+                return Ok(album);
+            }
+        }
         let res = self
             .client
             .search(query, SearchType::Album, None, None, Some(1), None)
@@ -183,6 +309,7 @@ impl<C: BaseClient> AlbumProvider for Spotify<C> {
                     artist: a.artists.first().map(|ar| ar.name.clone()),
                     url: a.id.as_ref().map(|i| i.url()),
                     release_date: a.release_date.clone(),
+                    cover: a.images.first().map(|img| img.url.clone()),
                     ..Default::default()
                 })
                 .ok_or_else(|| anyhow!("Not found"))?)
@@ -223,6 +350,18 @@ impl<C: BaseClient> AlbumProvider for Spotify<C> {
 
 impl<C: BaseClient> Spotify<C> {
     pub async fn get_album(&self, artist: &str, name: &str) -> anyhow::Result<Option<Album>> {
+        self.get_album_with_type(artist, name, false).await
+    }
+
+    // Search for an album and pick the best match among the results.
+    // If `albums_only` is set, singles and compilations are ignored.
+    pub async fn get_album_with_type(
+        &self,
+        artist: &str,
+        name: &str,
+        albums_only: bool,
+    ) -> anyhow::Result<Option<Album>> {
+        metrics().increment("spotify_lookups", "album_search");
         let query = format!(
             r#"album:"{}" artist:"{}""#,
             &sanitize_string(name),
@@ -230,26 +369,54 @@ impl<C: BaseClient> Spotify<C> {
         );
         let res = self
             .client
-            .search(&query, SearchType::Album, None, None, Some(5), None)
+            .search(&query, SearchType::Album, None, None, Some(10), None)
             .await?;
         let rspotify::model::SearchResult::Albums(albums) = res else {
             return Err(anyhow!("Not an album"));
         };
-        let album = albums
+        let artist = artist_key(artist);
+        let name = album_key(name);
+        let mut candidates = albums
             .items
             .iter()
-            .find(|ab| ab.name == name)
-            .or_else(|| albums.items.first());
+            .filter(|ab| !albums_only || ab.album_type.as_deref() == Some("album"))
+            .map(|ab| (album_score(ab, &artist, &name), ab))
+            .collect_vec();
+        // stable sort, ties are kept in search order
+        candidates.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let best_score = candidates.first().map(|(score, _)| *score);
+        let tied = candidates
+            .iter()
+            .take_while(|(score, _)| Some(*score) == best_score)
+            .flat_map(|(_, ab)| ab.id.clone())
+            .collect_vec();
+        let mut album = candidates.first().map(|(_, ab)| *ab);
+        if tied.len() > 1 {
+            // break ties using popularity
+            match self.client.albums(tied, None).await {
+                Ok(full) => {
+                    let most_popular = full.iter().max_by_key(|ab| ab.popularity).map(|ab| &ab.id);
+                    album = candidates
+                        .iter()
+                        .map(|(_, ab)| *ab)
+                        .find(|ab| ab.id.as_ref() == most_popular)
+                        .or(album);
+                }
+                Err(e) => tracing::warn!("could not retrieve album popularity: {e}"),
+            }
+        }
         Ok(album.map(|a| Album {
             name: Some(a.name.clone()),
             artist: a.artists.first().map(|ar| ar.name.clone()),
             url: a.id.as_ref().map(|i| i.url()),
             release_date: a.release_date.clone(),
+            cover: a.images.first().map(|img| img.url.clone()),
             ..Default::default()
         }))
     }
 
     pub async fn query_songs(&self, query: &str) -> anyhow::Result<Vec<(String, String)>> {
+        metrics().increment("spotify_lookups", "track_search");
         let res = self
             .client
             .search(query, SearchType::Track, None, None, Some(10), None)
@@ -276,6 +443,35 @@ impl<C: BaseClient> Spotify<C> {
             })
             .collect())
     }
+
+    pub async fn search_artist(&self, query: &str) -> anyhow::Result<Option<FullArtist>> {
+        metrics().increment("spotify_lookups", "artist_search");
+        let res = self
+            .client
+            .search(query, SearchType::Artist, None, None, Some(1), None)
+            .await?;
+        let rspotify::model::SearchResult::Artists(artists) = res else {
+            return Err(anyhow!("Not an artist"));
+        };
+        Ok(artists.items.into_iter().next())
+    }
+
+    // Most recent albums and singles released by an artist
+    pub async fn artist_releases(&self, artist_id: &str) -> anyhow::Result<Vec<SimplifiedAlbum>> {
+        metrics().increment("spotify_lookups", "artist_releases");
+        let id = ArtistId::from_id(artist_id)?;
+        let page = self
+            .client
+            .artist_albums_manual(
+                id,
+                [AlbumType::Album, AlbumType::Single],
+                None,
+                Some(20),
+                None,
+            )
+            .await?;
+        Ok(page.items)
+    }
 }
 
 impl Spotify<ClientCredsSpotify> {
@@ -289,7 +485,12 @@ impl Spotify<ClientCredsSpotify> {
 
         // Obtaining the access token
         spotify.request_token().await?;
-        Ok(Spotify { client: spotify })
+        Ok(Spotify {
+            client: spotify,
+            refresh_error: Default::default(),
+            alert_channel: None,
+            alerted: AtomicBool::new(false),
+        })
     }
 }
 
@@ -308,14 +509,205 @@ impl Spotify<AuthCodeSpotify> {
         let url = client
             .get_authorize_url(false)
             .context("failed to generate authorization url")?;
-        // eprintln!("url: {url}");
         // }
         client
             .prompt_for_token(&url)
             .await
             .context("failed to prompt for token")?;
-        Ok(Spotify { client })
+        Ok(Spotify {
+            client,
+            refresh_error: Default::default(),
+            alert_channel: None,
+            alerted: AtomicBool::new(false),
+        })
+    }
+
+    // Channel told when the token cannot be refreshed and the account has to be authorized
+    // again. Only one alert is sent until the token works again.
+    pub fn alert_channel(mut self, channel_id: ChannelId) -> Self {
+        self.alert_channel = Some(channel_id);
+        self
+    }
+
+    pub async fn token_status(&self) -> TokenStatus {
+        if let Some(e) = self.refresh_error.lock().unwrap().clone() {
+            return TokenStatus::NeedsReauth(e);
+        }
+        match self.client.token.lock().await.unwrap().as_ref() {
+            None => TokenStatus::Missing,
+            Some(token) if token.is_expired() => TokenStatus::Expired,
+            Some(token) => TokenStatus::Valid(token.expires_at),
+        }
+    }
+
+    // Refresh the token ahead of its expiry, so that a revoked refresh token is noticed
+    // before a command needs the client
+    pub async fn check_token(&self, now: DateTime<Utc>) -> TokenStatus {
+        let margin = Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES);
+        let expires_soon = match self.client.token.lock().await.unwrap().as_ref() {
+            Some(token) => token.expires_at.is_none_or(|at| at - margin < now),
+            None => true,
+        };
+        if expires_soon {
+            let res = self.client.refresh_token().await;
+            *self.refresh_error.lock().unwrap() = res.err().map(|e| e.to_string());
+        }
+        self.token_status().await
+    }
+
+    // Links to authorize the account from Discord instead of the terminal: the authorization
+    // page, and a button opening the form to paste the URL Spotify redirects to
+    pub fn auth_components(&self) -> anyhow::Result<CreateActionRow> {
+        let url = self
+            .client
+            .get_authorize_url(false)
+            .context("failed to generate authorization url")?;
+        Ok(CreateActionRow::Buttons(vec![
+            CreateButton::new_link(url).label("Authorize on Spotify"),
+            CreateButton::new(AUTH_BUTTON_ID).label("Paste redirect URL"),
+        ]))
+    }
+
+    // Exchange the code from the URL Spotify redirected to for a new token
+    async fn authorize(&self, redirect_url: &str) -> anyhow::Result<TokenStatus> {
+        let code = self
+            .client
+            .parse_response_code(redirect_url)
+            .ok_or_else(|| anyhow!("No authorization code found in this URL"))?;
+        self.client
+            .request_token(&code)
+            .await
+            .context("failed to request token")?;
+        *self.refresh_error.lock().unwrap() = None;
+        Ok(self.token_status().await)
+    }
+}
+
+// Authorizing changes the account used for every guild, only bot admins can do it
+async fn can_authorize(handler: &Handler, user: UserId) -> anyhow::Result<bool> {
+    #[cfg(feature = "sql")]
+    return crate::modules::sql::is_admin(&handler.db.lock().await.conn, user);
+    #[cfg(not(feature = "sql"))]
+    {
+        _ = (handler, user);
+        Ok(false)
+    }
+}
+
+fn handle_auth_button<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    component: &'a ComponentInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if component.data.custom_id != AUTH_BUTTON_ID {
+            return Ok(false);
+        }
+        if !can_authorize(handler, component.user.id).await? {
+            return Err(CommandError::PermissionDenied(
+                "Only bot admins can authorize the Spotify account".into(),
+            )
+            .into());
+        }
+        let form = ModalForm::new(AUTH_FORM_ID, "Spotify authorization")
+            .short("url", "URL you were redirected to");
+        component
+            .create_response(&ctx.http, form.response())
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+fn handle_auth_form<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    modal: &'a ModalInteraction,
+) -> BoxFuture<'a, anyhow::Result<bool>> {
+    async move {
+        if modal.data.custom_id != AUTH_FORM_ID {
+            return Ok(false);
+        }
+        if !can_authorize(handler, modal.user.id).await? {
+            return Err(CommandError::PermissionDenied(
+                "Only bot admins can authorize the Spotify account".into(),
+            )
+            .into());
+        }
+        let spotify = handler.module::<SpotifyOAuth>()?;
+        let content = match spotify.authorize(required_value(modal, "url")?).await {
+            Ok(status) => format!("Spotify OAuth token: {status}"),
+            Err(e) => format!("Authorization failed: {e:#}"),
+        };
+        let msg = CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true);
+        modal
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(true)
+    }
+    .boxed()
+}
+
+#[derive(Clone, Debug)]
+pub enum TokenStatus {
+    Valid(Option<DateTime<Utc>>),
+    Expired,
+    Missing,
+    // refreshing failed, the account has to be authorized again
+    NeedsReauth(String),
+}
+
+impl TokenStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, TokenStatus::Valid(_))
+    }
+}
+
+impl Display for TokenStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenStatus::Valid(Some(at)) => write!(f, "valid until <t:{}:f>", at.timestamp()),
+            TokenStatus::Valid(None) => f.write_str("valid"),
+            TokenStatus::Expired => f.write_str("expired"),
+            TokenStatus::Missing => f.write_str("missing, the bot was never authorized"),
+            TokenStatus::NeedsReauth(e) => {
+                write!(f, "refresh failed, authorize the bot again ({e})")
+            }
+        }
+    }
+}
+
+// Keep the OAuth token fresh, and tell the alert channel when the account has to be
+// authorized again
+fn check_oauth_token<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let spotify = handler.module::<SpotifyOAuth>()?;
+        let status = spotify.check_token(handler.clock.now()).await;
+        if status.is_valid() {
+            spotify.alerted.store(false, Ordering::Relaxed);
+            return Ok(());
+        }
+        tracing::info!("Spotify OAuth token: {status}");
+        let Some(channel_id) = spotify.alert_channel else {
+            return Ok(());
+        };
+        if spotify.alerted.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let msg = format!("⚠️ Spotify OAuth token {status}");
+        if let Err(e) = channel_id.say(http, msg).await {
+            spotify.alerted.store(false, Ordering::Relaxed);
+            return Err(e).context("Error sending Spotify token alert");
+        }
+        Ok(())
     }
+    .boxed()
 }
 
 #[derive(Command)]
@@ -331,6 +723,11 @@ impl Module for Spotify<ClientCredsSpotify> {
     fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
         store.register::<Unlink>();
     }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(on_message);
+        handlers.add(on_reaction_add);
+    }
 }
 
 pub async fn resolve_spotify_links(message: &str) -> anyhow::Result<Vec<String>> {
@@ -402,6 +799,22 @@ pub async fn handle_reaction(
     Ok(())
 }
 
+fn on_message<'a>(
+    _: &'a Handler,
+    ctx: &'a Context,
+    event: &'a MessageCreate,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    handle_message(&ctx.http, &event.0).boxed()
+}
+
+fn on_reaction_add<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a ReactionAdd,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    handle_reaction(handler, &ctx.http, &event.0).boxed()
+}
+
 #[async_trait]
 impl BotCommand for Unlink {
     type Data = Handler;
@@ -416,7 +829,7 @@ impl BotCommand for Unlink {
         if urls.is_empty() {
             bail!("No shortened spotify links found in message");
         }
-        let plural_s = (urls.len() > 1).then_some("s").unwrap_or_default();
+        let plural_s = if urls.len() > 1 { "s" } else { "" };
         let mut resp = format!("Resolved spotify link{plural_s} from {}", self.0.link());
         urls.into_iter().for_each(|url| {
             resp.push('\n');
@@ -433,4 +846,95 @@ impl Module for Spotify<AuthCodeSpotify> {
             "Must be initialized with new_auth_code and added using with_module"
         ))
     }
+
+    async fn health_check(&self, handler: &Handler) -> HealthStatus {
+        match self.check_token(handler.clock.now()).await {
+            status if status.is_valid() => HealthStatus::Healthy,
+            status => HealthStatus::Degraded(format!("OAuth token {status}")),
+        }
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(handle_auth_button);
+    }
+
+    fn register_modal_handlers(&self, handlers: &mut ModalStore) {
+        handlers.push(handle_auth_form);
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "spotify_token",
+            Schedule::every(TOKEN_CHECK_INTERVAL),
+            check_oauth_token,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn album(artist: &str, name: &str, album_type: &str) -> SimplifiedAlbum {
+        SimplifiedAlbum {
+            album_type: Some(album_type.to_string()),
+            artists: vec![SimplifiedArtist {
+                name: artist.to_string(),
+                ..Default::default()
+            }],
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn score(result: &SimplifiedAlbum, artist: &str, name: &str) -> f32 {
+        album_score(result, &artist_key(artist), &album_key(name))
+    }
+
+    #[test]
+    fn similarity_bounds() {
+        assert_eq!(similarity("ok computer", "ok computer"), 1.0);
+        assert_eq!(similarity("", "ok computer"), 0.0);
+        assert_eq!(similarity("ok computer", "ok computer oknotok"), 0.7);
+        assert_eq!(similarity("kid a", "amnesiac"), 0.0);
+        let partial = similarity("in rainbows disk 2", "in rainbows from the basement");
+        assert!(partial > 0.0 && partial < 0.7);
+    }
+
+    #[test]
+    fn remasters_and_articles_match_exactly() {
+        let original = album("Fleetwood Mac", "Rumours", "album");
+        let remaster = album("Fleetwood Mac", "Rumours (2004 Remaster)", "album");
+        assert_eq!(
+            score(&original, "Fleetwood Mac", "Rumours"),
+            score(&remaster, "Fleetwood Mac", "Rumours")
+        );
+        let beatles = album("The Beatles", "Abbey Road", "album");
+        assert_eq!(
+            score(&beatles, "Beatles", "Abbey Road"),
+            score(&beatles, "The Beatles", "Abbey Road")
+        );
+    }
+
+    #[test]
+    fn karaoke_and_tributes_rank_below_the_original() {
+        let original = album("Radiohead", "OK Computer", "album");
+        let tribute = album("Various Artists", "A Tribute to OK Computer", "compilation");
+        let karaoke = album("Radiohead", "OK Computer (Karaoke Version)", "album");
+        let best = score(&original, "Radiohead", "OK Computer");
+        assert!(best > score(&tribute, "Radiohead", "OK Computer"));
+        assert!(best > score(&karaoke, "Radiohead", "OK Computer"));
+        // not penalized when that is what was asked for
+        assert_eq!(
+            score(&karaoke, "Radiohead", "OK Computer Karaoke"),
+            score(&original, "Radiohead", "OK Computer Karaoke")
+        );
+    }
+
+    #[test]
+    fn albums_rank_above_singles_of_the_same_name() {
+        let single = album("Charli xcx", "Brat", "single");
+        let full = album("Charli xcx", "Brat", "album");
+        assert!(score(&full, "Charli XCX", "BRAT") > score(&single, "Charli XCX", "BRAT"));
+    }
 }
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, bail, Context as _};
+use anyhow::{anyhow, Context as _};
 use itertools::Itertools;
 use rusqlite::{types::ValueRef, Connection};
 use serenity::{
@@ -6,10 +6,25 @@ use serenity::{
     model::{prelude::CommandInteraction, prelude::UserId, Permissions},
     prelude::Context,
 };
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{BotCommand, CommandError, CommandResponse};
 use serenity_command_derive::Command;
 
-use crate::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+use crate::{
+    db::{Db, SqlUserId},
+    CommandStore, CompletionStore, Handler, Module, ModuleMap,
+};
+
+pub fn is_admin(db: &Connection, user: UserId) -> anyhow::Result<bool> {
+    match db.query_row(
+        "SELECT id FROM admin WHERE id = ?1",
+        [SqlUserId(user)],
+        |row| row.get::<_, SqlUserId>(0),
+    ) {
+        Ok(_) => Ok(true),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
 
 #[derive(Command)]
 #[cmd(name = "query", desc = "Query the database (admin-only)")]
@@ -36,16 +51,13 @@ impl Query {
             String::new()
         };
         // check user is amin
-        match db.query_row(
-            "SELECT id FROM admin WHERE id = ?1",
-            [requester.get()],
-            |row| row.get::<_, u64>(0),
-        ) {
-            Ok(_) => (),
-            Err(rusqlite::Error::QueryReturnedNoRows) => bail!("Admin-only command"),
-            err @ Err(_) => return err.context(qry_context).map(|_| CommandResponse::None),
+        if !is_admin(db, requester).context(qry_context.clone())? {
+            return Err(CommandError::PermissionDenied("Admin-only command".into()).into());
         }
-        let mut stmt = db.prepare(qry)?;
+        // errors in the query are the admin's to fix, show them
+        let mut stmt = db
+            .prepare(qry)
+            .map_err(|e| CommandError::UserError(format!("{qry_context}{e}")))?;
         let n_columns = stmt.column_count();
         let result: Vec<Vec<_>> = stmt
             .query_map([], |row| {
@@ -62,7 +74,8 @@ impl Query {
                     result.push(value)
                 }
                 Ok(result)
-            })?
+            })
+            .map_err(|e| CommandError::UserError(format!("{qry_context}{e}")))?
             .take(10)
             .collect::<Result<_, _>>()
             .map_err(|e| anyhow!("{qry_context}{e}"))?;
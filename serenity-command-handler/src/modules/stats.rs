@@ -0,0 +1,246 @@
+use std::borrow::Cow;
+
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::{
+    CreateAttachment, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse,
+};
+use serenity::model::prelude::{CommandInteraction, GuildId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::charts::{self, Chart, PALETTE};
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::modules::{ModLp, Quotes};
+use crate::prelude::*;
+
+const WEEK: i64 = 7 * 24 * 3600;
+const WEEKS: usize = 12;
+const TOP_HOSTS: usize = 5;
+const PANEL_WIDTH: u32 = 400;
+const PANEL_HEIGHT: u32 = 240;
+const DASHBOARD_NAME: &str = "dashboard.png";
+
+// Keep track of commands run in guilds, for the dashboard.
+// Does nothing unless the Stats module is loaded.
+pub async fn record_command(handler: &Handler, command: &CommandInteraction) -> anyhow::Result<()> {
+    let (Some(_), Some(guild_id)) = (handler.try_module::<Stats>(), command.guild_id) else {
+        return Ok(());
+    };
+    handler.db.lock().await.conn.execute(
+        "INSERT INTO command_usage (guild_id, user_id, name, ts) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            SqlGuildId(guild_id),
+            SqlUserId(command.user.id),
+            command.data.name,
+            chrono::Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+// Number of rows per week over the last WEEKS weeks, oldest first.
+// `query` must select the week index of each row, given the guild and start timestamp.
+fn weekly_counts(db: &Db, query: &str, guild_id: GuildId, start: i64) -> anyhow::Result<Vec<f64>> {
+    let mut counts = vec![0.0; WEEKS];
+    let rows: Vec<(usize, u64)> = db
+        .conn
+        .prepare(query)?
+        .query(params![SqlGuildId(guild_id), start, WEEK])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    for (week, count) in rows {
+        if let Some(c) = counts.get_mut(week) {
+            *c = count as f64;
+        }
+    }
+    Ok(counts)
+}
+
+// Server activity over the last weeks, also shown on the web dashboard
+pub(crate) struct Dashboard {
+    commands: Vec<f64>,
+    lps: Vec<f64>,
+    quotes: Vec<f64>,
+    hosts: Vec<(SqlUserId, u64)>,
+}
+
+impl Dashboard {
+    pub(crate) fn load(db: &Db, guild_id: GuildId) -> anyhow::Result<Self> {
+        let start = chrono::Utc::now().timestamp() - WEEKS as i64 * WEEK;
+        let commands = weekly_counts(
+            db,
+            "SELECT (ts - ?2) / ?3 AS week, COUNT(*) FROM command_usage
+             WHERE guild_id = ?1 AND ts >= ?2 GROUP BY week",
+            guild_id,
+            start,
+        )?;
+        let lps = weekly_counts(
+            db,
+            "SELECT (ts - ?2) / ?3 AS week, COUNT(*) FROM lp_history
+             WHERE guild_id = ?1 AND ts >= ?2 GROUP BY week",
+            guild_id,
+            start,
+        )?;
+        // quote growth is shown as the total number of quotes at the end of each week
+        let added = weekly_counts(
+            db,
+            "SELECT (ts - ?2) / ?3 AS week, COUNT(*) FROM quote
+             WHERE guild_id = ?1 AND ts >= ?2 AND deleted_at IS NULL GROUP BY week",
+            guild_id,
+            start,
+        )?;
+        let before: u64 = db.conn.query_row(
+            "SELECT COUNT(*) FROM quote WHERE guild_id = ?1 AND ts < ?2 AND deleted_at IS NULL",
+            params![SqlGuildId(guild_id), start],
+            |row| row.get(0),
+        )?;
+        let quotes = added
+            .iter()
+            .scan(before as f64, |total, n| {
+                *total += n;
+                Some(*total)
+            })
+            .collect();
+        let hosts = db
+            .conn
+            .prepare(
+                "SELECT user_id, COUNT(*) AS n FROM lp_history
+                 WHERE guild_id = ?1 AND ts >= ?2
+                 GROUP BY user_id ORDER BY n DESC LIMIT ?3",
+            )?
+            .query(params![SqlGuildId(guild_id), start, TOP_HOSTS])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        Ok(Dashboard {
+            commands,
+            lps,
+            quotes,
+            hosts,
+        })
+    }
+
+    pub(crate) fn image(&self) -> anyhow::Result<Vec<u8>> {
+        let host_counts = self.hosts.iter().map(|(_, n)| *n as f64).collect_vec();
+        let panels = [
+            Chart::Bars(&self.commands),
+            Chart::Bars(&self.lps),
+            Chart::Line(&self.quotes),
+            Chart::Bars(&host_counts),
+        ]
+        .iter()
+        .zip(PALETTE)
+        .map(|(chart, color)| charts::draw(chart, PANEL_WIDTH, PANEL_HEIGHT, color))
+        .collect_vec();
+        charts::encode_png(&charts::compose(&panels, 2)?)
+    }
+
+    fn embed(&self) -> CreateEmbed {
+        let total = |values: &[f64]| values.iter().sum::<f64>() as u64;
+        let hosts = self
+            .hosts
+            .iter()
+            .enumerate()
+            .map(|(i, (SqlUserId(user_id), n))| format!("{}. <@{user_id}> ({n})", i + 1))
+            .join("\n");
+        CreateEmbed::new()
+            .title(format!("Server activity over the last {WEEKS} weeks"))
+            .field(
+                "🟦 Commands per week",
+                format!("{} commands", total(&self.commands)),
+                true,
+            )
+            .field(
+                "🟩 Listening parties per week",
+                format!("{} LPs", total(&self.lps)),
+                true,
+            )
+            .field(
+                "🟨 Total quotes",
+                format!("{} quotes", self.quotes.last().copied().unwrap_or(0.0)),
+                true,
+            )
+            .field(
+                "🟪 Top LP hosts",
+                if hosts.is_empty() {
+                    "Nobody".to_string()
+                } else {
+                    hosts
+                },
+                true,
+            )
+            .image(format!("attachment://{DASHBOARD_NAME}"))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "server_dashboard",
+    desc = "Show charts of this server's activity"
+)]
+pub struct ServerDashboard {}
+
+#[async_trait]
+impl BotCommand for ServerDashboard {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = command.guild_id()?;
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let dashboard = Dashboard::load(&*handler.db.lock().await, guild_id);
+        let resp = match dashboard.and_then(|d| Ok((d.image()?, d.embed()))) {
+            Ok((image, embed)) => EditInteractionResponse::new()
+                .embed(embed)
+                .new_attachment(CreateAttachment::bytes(Cow::Owned(image), DASHBOARD_NAME)),
+            Err(e) => EditInteractionResponse::new().content(e.to_string()),
+        };
+        command.edit_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+pub struct Stats;
+
+#[async_trait]
+impl Module for Stats {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Quotes>().await?.module::<ModLp>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Stats)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS command_usage (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                name STRING NOT NULL,
+                ts INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE INDEX IF NOT EXISTS command_usage_guild_ts ON command_usage (guild_id, ts)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<ServerDashboard>();
+    }
+}
@@ -0,0 +1,209 @@
+// Compatibility of two members' Last.fm libraries with /taste, from their top artists.
+// Results are saved for a day, libraries don't change much faster than that.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use anyhow::Context as _;
+use chrono::Utc;
+use futures::future::try_join;
+use itertools::Itertools;
+use rusqlite::{params, OptionalExtension};
+use serenity::model::prelude::{CommandInteraction, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::Db;
+use crate::modules::lastfm::{linked_username, Lastfm, TopArtists};
+use crate::normalize::artist_key;
+use crate::prelude::*;
+
+const TOP_ARTISTS: u64 = 200;
+const FAVORITES: usize = 5;
+const CACHE_TTL_SECS: i64 = 24 * 3600;
+
+#[derive(Clone)]
+struct Compatibility {
+    // percentage of plays shared by both users
+    score: f64,
+    shared: u64,
+    favorites: Vec<String>,
+}
+
+// Each artist's share of the user's plays, by artist key
+fn artist_shares(top: &TopArtists) -> HashMap<String, (&str, f64)> {
+    let plays = top
+        .artist
+        .iter()
+        .map(|artist| (artist, artist.playcount.parse::<u64>().unwrap_or_default()))
+        .filter(|(_, plays)| *plays > 0)
+        .collect_vec();
+    let total: u64 = plays.iter().map(|(_, plays)| plays).sum();
+    plays
+        .into_iter()
+        .map(|(artist, plays)| {
+            let share = plays as f64 / total as f64;
+            (artist_key(&artist.name), (artist.name.as_str(), share))
+        })
+        .collect()
+}
+
+// Sum of the smallest share of each artist in both libraries, so 100% means the same artists
+// were played in the same proportions. Shared favorites are the artists with the most of it.
+fn compare(a: &TopArtists, b: &TopArtists) -> Compatibility {
+    let b = artist_shares(b);
+    let shared = artist_shares(a)
+        .into_iter()
+        .filter_map(|(key, (name, share))| {
+            let (_, other) = b.get(&key)?;
+            Some((name, share.min(*other)))
+        })
+        .sorted_by(|(_, x), (_, y)| y.total_cmp(x))
+        .collect_vec();
+    Compatibility {
+        score: shared.iter().map(|(_, share)| share).sum::<f64>() * 100.0,
+        shared: shared.len() as u64,
+        favorites: shared
+            .iter()
+            .take(FAVORITES)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    }
+}
+
+// Results are the same both ways, so they are saved once per pair of usernames
+fn pair_key(a: &str, b: &str) -> (String, String) {
+    let (a, b) = (a.to_lowercase(), b.to_lowercase());
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn saved_compatibility(
+    db: &Db,
+    user_a: &str,
+    user_b: &str,
+    now: i64,
+) -> anyhow::Result<Option<Compatibility>> {
+    let saved = db
+        .conn
+        .query_row(
+            "SELECT score, shared, favorites FROM lastfm_taste
+             WHERE user_a = ?1 AND user_b = ?2 AND ts > ?3",
+            params![user_a, user_b, now - CACHE_TTL_SECS],
+            |row| {
+                let favorites: String = row.get(2)?;
+                Ok(Compatibility {
+                    score: row.get(0)?,
+                    shared: row.get(1)?,
+                    favorites: favorites.lines().map(String::from).collect(),
+                })
+            },
+        )
+        .optional()?;
+    Ok(saved)
+}
+
+fn save_compatibility(
+    db: &Db,
+    user_a: &str,
+    user_b: &str,
+    compat: &Compatibility,
+    now: i64,
+) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT OR REPLACE INTO lastfm_taste (user_a, user_b, score, shared, favorites, ts)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            user_a,
+            user_b,
+            compat.score,
+            compat.shared,
+            compat.favorites.join("\n"),
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "taste",
+    desc = "Compare your Last.fm library with another member's"
+)]
+pub struct Taste {
+    #[cmd(desc = "Member to compare with")]
+    pub user: UserId,
+}
+
+#[async_trait]
+impl BotCommand for Taste {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let (caller, target) = (command.user.id, self.user);
+        if caller == target {
+            return CommandResponse::private("Pick someone else to compare your taste with");
+        }
+        let (caller_name, target_name) = handler
+            .db_read(move |db| Ok((linked_username(db, caller)?, linked_username(db, target)?)))
+            .await?;
+        let caller_name =
+            caller_name.context("Link your Last.fm account with /lastfm_link first")?;
+        let Some(target_name) = target_name else {
+            return CommandResponse::private(format!(
+                "<@{target}> hasn't linked their Last.fm account"
+            ));
+        };
+
+        let (user_a, user_b) = pair_key(&caller_name, &target_name);
+        let now = Utc::now().timestamp();
+        let saved = {
+            let (user_a, user_b) = (user_a.clone(), user_b.clone());
+            handler
+                .db_read(move |db| saved_compatibility(db, &user_a, &user_b, now))
+                .await?
+        };
+        let compat = match saved {
+            Some(compat) => compat,
+            None => {
+                let lastfm = handler.module::<Lastfm>()?;
+                let (top_a, top_b) = try_join(
+                    lastfm.get_top_artists(&user_a, TOP_ARTISTS, None),
+                    lastfm.get_top_artists(&user_b, TOP_ARTISTS, None),
+                )
+                .await?;
+                let compat = compare(&top_a, &top_b);
+                let saved = compat.clone();
+                handler
+                    .db_call(move |db| save_compatibility(db, &user_a, &user_b, &saved, now))
+                    .await?;
+                compat
+            }
+        };
+
+        let mut resp = format!(
+            "<@{caller}> and <@{target}> are **{:.0}%** compatible, \
+             sharing {} of their top {TOP_ARTISTS} artists",
+            compat.score, compat.shared
+        );
+        if !compat.favorites.is_empty() {
+            resp.push_str("\n\n**Shared favorites**");
+            for artist in &compat.favorites {
+                _ = write!(resp, "\n{artist}");
+            }
+        }
+        CommandResponse::public(resp)
+    }
+}
+
+pub(crate) fn register_commands(store: &mut CommandStore) {
+    store.register::<Taste>();
+}
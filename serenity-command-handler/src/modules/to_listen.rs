@@ -0,0 +1,578 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::{
+    CreateActionRow, CreateAutocompleteResponse, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption, EditInteractionResponse,
+};
+use serenity::http::Http;
+use serenity::model::application::{
+    ButtonStyle, CommandType, ComponentInteraction, ComponentInteractionDataKind,
+};
+use serenity::model::channel::Message;
+use serenity::model::prelude::{ChannelId, CommandInteraction, MessageId, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::command_context::get_str_opt_ac;
+use crate::db::{Db, SqlUserId};
+use crate::modules::AlbumLookup;
+use crate::prelude::*;
+use crate::scheduler::{Job, JobRun, JobStore, Schedule};
+
+const SELECT_PREFIX: &str = "to_listen:";
+const PAGE_PREFIX: &str = "to_listen_page:";
+const DONE_PREFIX: &str = "to_listen_done:";
+const REMOVE_PREFIX: &str = "to_listen_remove:";
+// Discord's limit for select menu options
+const MAX_LINKS: usize = 25;
+// Each entry gets a button in the done and remove rows, which hold 5 buttons at most
+const PAGE_SIZE: usize = 5;
+const DONE_QUERY: &str = "UPDATE to_listen SET done_at = CAST(strftime('%s', 'now') AS INTEGER)
+     WHERE rowid = ?1 AND user_id = ?2";
+const REMOVE_QUERY: &str = "DELETE FROM to_listen WHERE rowid = ?1 AND user_id = ?2";
+const REMINDER_INTERVAL: i64 = 7 * 24 * 3600;
+const REMINDER_ITEMS: usize = 3;
+
+// Url and name of an album
+type Entry = (String, String);
+
+// Music links found in a message's content and embeds, in order of appearance
+fn find_links(lookup: &AlbumLookup, msg: &Message) -> Vec<String> {
+    msg.content
+        .split_whitespace()
+        // links with embeds suppressed are wrapped in <>
+        .map(|word| word.trim_start_matches('<').trim_end_matches('>'))
+        .map(str::to_string)
+        .chain(msg.embeds.iter().filter_map(|embed| embed.url.clone()))
+        .filter(|link| link.starts_with("https://"))
+        .filter(|link| lookup.providers().iter().any(|p| p.url_matches(link)))
+        .unique()
+        .take(MAX_LINKS)
+        .collect()
+}
+
+fn add_entry(db: &Db, user_id: UserId, url: &str, name: &str) -> anyhow::Result<()> {
+    db.conn.execute(
+        "INSERT INTO to_listen (user_id, url, name, added_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(user_id, url) DO UPDATE SET done_at = NULL",
+        params![
+            SqlUserId(user_id),
+            url,
+            name,
+            chrono::Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+async fn save_links(
+    handler: &Handler,
+    user_id: UserId,
+    links: &[String],
+) -> anyhow::Result<String> {
+    let lookup = handler.module::<AlbumLookup>()?;
+    let mut saved = Vec::with_capacity(links.len());
+    for link in links {
+        // keep the link even if the provider can't resolve it
+        let name = match lookup.get_album_info(link).await {
+            Ok(Some(album)) if album.name.is_some() => album.format_name(),
+            Ok(_) => link.clone(),
+            Err(e) => {
+                tracing::warn!("could not resolve {link}: {e:?}");
+                link.clone()
+            }
+        };
+        saved.push((link, name));
+    }
+    let db = handler.db.lock().await;
+    for (link, name) in &saved {
+        add_entry(&db, user_id, link, name)?;
+    }
+    let names = saved.iter().map(|(_, name)| format!("- {name}")).join("\n");
+    Ok(format!("Added to your listening list:\n{names}"))
+}
+
+fn select_menu(channel_id: ChannelId, message_id: MessageId, links: &[String]) -> CreateSelectMenu {
+    let options = links
+        .iter()
+        .enumerate()
+        .map(|(i, link)| {
+            CreateSelectMenuOption::new(link.chars().take(100).collect::<String>(), i.to_string())
+        })
+        .collect();
+    CreateSelectMenu::new(
+        format!("{SELECT_PREFIX}{channel_id}:{message_id}"),
+        CreateSelectMenuKind::String { options },
+    )
+    .placeholder("Links to save")
+    .min_values(1)
+    .max_values(links.len() as u8)
+}
+
+// Pending entries of a user's list, with done/remove buttons for each entry
+fn list_page(
+    db: &Db,
+    user_id: UserId,
+    page: usize,
+) -> anyhow::Result<(CreateEmbed, Vec<CreateActionRow>)> {
+    let total: usize = db.conn.query_row(
+        "SELECT COUNT(*) FROM to_listen WHERE user_id = ?1 AND done_at IS NULL",
+        [SqlUserId(user_id)],
+        |row| row.get(0),
+    )?;
+    if total == 0 {
+        bail!("Your listening list is empty, use `/to_listen_add` or \"Save to listening list\"");
+    }
+    let pages = total.div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let entries: Vec<(i64, String, String)> = db
+        .conn
+        .prepare(
+            "SELECT rowid, url, name FROM to_listen WHERE user_id = ?1 AND done_at IS NULL
+             ORDER BY added_at LIMIT ?2 OFFSET ?3",
+        )?
+        .query(params![SqlUserId(user_id), PAGE_SIZE, page * PAGE_SIZE])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .collect()?;
+    let lines = entries
+        .iter()
+        .enumerate()
+        .map(|(i, (_, url, name))| format!("{}. [{name}]({url})", page * PAGE_SIZE + i + 1))
+        .join("\n");
+    let embed = CreateEmbed::new()
+        .title("Your listening list")
+        .description(lines)
+        .footer(CreateEmbedFooter::new(format!("Page {}/{pages}", page + 1)));
+    let entry_buttons = |prefix: &str, emoji: &str, style: ButtonStyle| {
+        let buttons = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (rowid, _, _))| {
+                CreateButton::new(format!("{prefix}{page}:{rowid}"))
+                    .label(format!("{emoji} {}", page * PAGE_SIZE + i + 1))
+                    .style(style)
+            })
+            .collect();
+        CreateActionRow::Buttons(buttons)
+    };
+    let nav_button = |label: &str, target: usize, disabled: bool| {
+        CreateButton::new(format!("{PAGE_PREFIX}{target}"))
+            .label(label)
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled)
+    };
+    let components = vec![
+        entry_buttons(DONE_PREFIX, "✅", ButtonStyle::Success),
+        entry_buttons(REMOVE_PREFIX, "🗑️", ButtonStyle::Danger),
+        CreateActionRow::Buttons(vec![
+            nav_button("Previous", page.saturating_sub(1), page == 0),
+            nav_button("Next", page + 1, page + 1 >= pages),
+        ]),
+    ];
+    Ok((embed, components))
+}
+
+fn reminders(db: &Db, now: i64) -> anyhow::Result<Vec<(UserId, Vec<Entry>)>> {
+    let users: Vec<SqlUserId> = db
+        .conn
+        .prepare("SELECT user_id FROM to_listen_reminder WHERE last_sent <= ?1")?
+        .query([now - REMINDER_INTERVAL])?
+        .map(|row| row.get(0))
+        .collect()?;
+    let mut stmt = db.conn.prepare(
+        "SELECT url, name FROM to_listen WHERE user_id = ?1 AND done_at IS NULL
+         ORDER BY added_at LIMIT ?2",
+    )?;
+    let mut out = Vec::with_capacity(users.len());
+    for user_id in users {
+        let entries: Vec<Entry> = stmt
+            .query(params![user_id, REMINDER_ITEMS])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        db.conn.execute(
+            "UPDATE to_listen_reminder SET last_sent = ?1 WHERE user_id = ?2",
+            params![now, user_id],
+        )?;
+        if !entries.is_empty() {
+            out.push((user_id.0, entries));
+        }
+    }
+    Ok(out)
+}
+
+async fn send_reminder(http: &Http, user_id: UserId, entries: &[Entry]) -> anyhow::Result<()> {
+    let lines = entries
+        .iter()
+        .map(|(url, name)| format!("- [{name}]({url})"))
+        .join("\n");
+    let embed = CreateEmbed::new()
+        .title("Still on your listening list")
+        .description(lines);
+    user_id
+        .create_dm_channel(http)
+        .await?
+        .send_message(http, CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+// Weekly DMs with the oldest entries of the lists of users who enabled reminders
+fn send_reminders<'a>(
+    handler: &'a Handler,
+    http: &'a Http,
+    _run: JobRun,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    async move {
+        let now = handler.clock.now().timestamp();
+        let due = handler.db_call(move |db| reminders(db, now)).await?;
+        for (user_id, entries) in due {
+            if let Err(e) = send_reminder(http, user_id, &entries).await {
+                tracing::error!("Error sending listening list reminder to {user_id}: {e:?}");
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+#[derive(Command)]
+#[cmd(name = "Save to listening list", message)]
+struct SaveLinks(Message);
+
+impl SaveLinks {
+    async fn save(
+        &self,
+        handler: &Handler,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<EditInteractionResponse> {
+        let links = find_links(handler.module::<AlbumLookup>()?, &self.0);
+        match links.as_slice() {
+            [] => bail!("No music links found in this message"),
+            [_] => {
+                let resp = save_links(handler, command.user.id, &links).await?;
+                Ok(EditInteractionResponse::new().content(resp))
+            }
+            _ => {
+                let menu = select_menu(self.0.channel_id, self.0.id, &links);
+                Ok(EditInteractionResponse::new()
+                    .content("Which links do you want to save?")
+                    .components(vec![CreateActionRow::SelectMenu(menu)]))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BotCommand for SaveLinks {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let resp = match self.save(handler, interaction).await {
+            Ok(resp) => resp,
+            Err(e) => EditInteractionResponse::new().content(e.to_string()),
+        };
+        interaction.edit_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "to_listen_add", desc = "Save an album to listen to later")]
+pub struct AddToListen {
+    #[cmd(desc = "The album to save", autocomplete)]
+    album: String,
+}
+
+#[async_trait]
+impl BotCommand for AddToListen {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let lookup = handler.module::<AlbumLookup>()?;
+        let info = if self.album.starts_with("https://") {
+            lookup.get_album_info(&self.album).await?
+        } else {
+            lookup.lookup_album(&self.album, None).await?
+        };
+        let Some(info) = info else {
+            bail!("Album not found");
+        };
+        let Some(url) = &info.url else {
+            bail!("No link found for {}", info.format_name());
+        };
+        add_entry(
+            &*handler.db.lock().await,
+            command.user.id,
+            url,
+            &info.format_name(),
+        )?;
+        CommandResponse::private(format!(
+            "Added {} to your listening list",
+            info.as_link(None)
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "to_listen", desc = "Show the albums you saved for later")]
+pub struct ShowToListen {}
+
+#[async_trait]
+impl BotCommand for ShowToListen {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let (embed, components) = list_page(&*handler.db.lock().await, command.user.id, 0)?;
+        let msg = CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(components)
+            .ephemeral(true);
+        command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(msg))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "to_listen_reminders",
+    desc = "Get a weekly DM with albums from your listening list"
+)]
+pub struct SetToListenReminders {
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetToListenReminders {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let db = handler.db.lock().await;
+        let user_id = SqlUserId(command.user.id);
+        let resp = if self.enabled {
+            // the first reminder is sent a week from now
+            db.conn.execute(
+                "INSERT OR IGNORE INTO to_listen_reminder (user_id, last_sent) VALUES (?1, ?2)",
+                params![user_id, chrono::Utc::now().timestamp()],
+            )?;
+            "You will get a weekly reminder of your listening list"
+        } else {
+            db.conn.execute(
+                "DELETE FROM to_listen_reminder WHERE user_id = ?1",
+                [user_id],
+            )?;
+            "You will no longer get reminders of your listening list"
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+pub struct ToListen;
+
+impl ToListen {
+    fn select_links<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(ids) = component.data.custom_id.strip_prefix(SELECT_PREFIX) else {
+                return Ok(false);
+            };
+            let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind else {
+                return Ok(false);
+            };
+            let (channel_id, message_id) = ids
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid listening list id: {ids}"))?;
+            let channel_id = ChannelId::new(channel_id.parse()?);
+            let message_id = MessageId::new(message_id.parse()?);
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+                .await?;
+            // links are looked up again rather than trusting the select values
+            let msg = channel_id.message(&ctx.http, message_id).await?;
+            let links = find_links(handler.module::<AlbumLookup>()?, &msg);
+            let selected = values
+                .iter()
+                .filter_map(|i| links.get(i.parse::<usize>().ok()?).cloned())
+                .collect_vec();
+            let resp = match save_links(handler, component.user.id, &selected).await {
+                Ok(resp) => resp,
+                Err(e) => e.to_string(),
+            };
+            component
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content(resp)
+                        .components(vec![]),
+                )
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    // Page changes as well as the done and remove buttons
+    fn update_list<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        component: &'a ComponentInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let custom_id = component.data.custom_id.as_str();
+            let (ids, query) = if let Some(ids) = custom_id.strip_prefix(PAGE_PREFIX) {
+                (ids, None)
+            } else if let Some(ids) = custom_id.strip_prefix(DONE_PREFIX) {
+                (ids, Some(DONE_QUERY))
+            } else if let Some(ids) = custom_id.strip_prefix(REMOVE_PREFIX) {
+                (ids, Some(REMOVE_QUERY))
+            } else {
+                return Ok(false);
+            };
+            // the list is only shown to its owner
+            let user_id = SqlUserId(component.user.id);
+            let (page, rowid) = ids.split_once(':').unwrap_or((ids, ""));
+            let db = handler.db.lock().await;
+            if let Some(query) = query {
+                let rowid: i64 = rowid.parse()?;
+                db.conn.execute(query, params![rowid, user_id])?;
+            }
+            let msg = match list_page(&db, user_id.0, page.parse()?) {
+                Ok((embed, components)) => CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(components),
+                Err(e) => CreateInteractionResponseMessage::new()
+                    .content(e.to_string())
+                    .embeds(vec![])
+                    .components(vec![]),
+            };
+            drop(db);
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(msg))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn complete_album<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if key != ("to_listen_add", CommandType::ChatInput) {
+                return Ok(false);
+            }
+            let query = get_str_opt_ac(&ac.data.options, "album").unwrap_or("");
+            let choices = if query.len() >= 3 {
+                handler
+                    .module::<AlbumLookup>()?
+                    .query_albums(query, None)
+                    .await?
+            } else {
+                Vec::new()
+            };
+            let resp = choices
+                .into_iter()
+                .filter(|(_, value)| value.len() < 100)
+                .fold(CreateAutocompleteResponse::new(), |resp, (name, value)| {
+                    resp.add_string_choice(name, value)
+                });
+            ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
+                .await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for ToListen {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<AlbumLookup>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ToListen)
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS to_listen (
+                user_id INTEGER NOT NULL,
+                url STRING NOT NULL,
+                name STRING NOT NULL,
+                added_at INTEGER NOT NULL,
+                done_at INTEGER,
+                UNIQUE(user_id, url)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS to_listen_reminder (
+                user_id INTEGER PRIMARY KEY,
+                last_sent INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<SaveLinks>();
+        store.register::<AddToListen>();
+        store.register::<ShowToListen>();
+        store.register::<SetToListenReminders>();
+        completions.push(ToListen::complete_album);
+    }
+
+    fn register_component_handlers(&self, handlers: &mut ComponentStore) {
+        handlers.push(ToListen::select_links);
+        handlers.push(ToListen::update_list);
+    }
+
+    fn register_jobs(&self, jobs: &mut JobStore) {
+        jobs.push(Job::new(
+            "to_listen_reminders",
+            Schedule::every(Duration::from_secs(3600)),
+            send_reminders,
+        ));
+    }
+}
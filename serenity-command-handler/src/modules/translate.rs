@@ -0,0 +1,359 @@
+// Translate messages when they get a flag reaction, using the DeepL API.
+// The module handles reaction_add events itself, guilds opt in with /translations.
+// Messages without text are translated from their first embed, so quotes posted by /quote
+// can be translated as well.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context as _};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::model::prelude::{ChannelId, CommandInteraction, GuildId, UserId};
+use serenity::model::Permissions;
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{CommandResponse, GuildCommand};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, Migration};
+use crate::gateway::{GatewayHandlers, ReactionAdd};
+use crate::prelude::*;
+
+const API_KEY_VAR: &str = "DEEPL_API_KEY";
+const API_URL: &str = "https://api.deepl.com/v2/translate";
+// keys of the free plan end with :fx and use a different host
+const FREE_API_URL: &str = "https://api-free.deepl.com/v2/translate";
+const USAGE_URL: &str = "https://api.deepl.com/v2/usage";
+const FREE_USAGE_URL: &str = "https://api-free.deepl.com/v2/usage";
+const USER_COOLDOWN: Duration = Duration::from_secs(30);
+const CHANNEL_COOLDOWN: Duration = Duration::from_secs(10);
+// Discord's limit for message contents, minus room for the header
+const MAX_TRANSLATION_LEN: usize = 1900;
+
+// Flags and the DeepL target language they translate to
+const FLAGS: &[(&str, &str)] = &[
+    ("🇬🇧", "EN-GB"),
+    ("🇺🇸", "EN-US"),
+    ("🇫🇷", "FR"),
+    ("🇩🇪", "DE"),
+    ("🇪🇸", "ES"),
+    ("🇮🇹", "IT"),
+    ("🇵🇹", "PT-PT"),
+    ("🇧🇷", "PT-BR"),
+    ("🇳🇱", "NL"),
+    ("🇵🇱", "PL"),
+    ("🇸🇪", "SV"),
+    ("🇩🇰", "DA"),
+    ("🇫🇮", "FI"),
+    ("🇨🇿", "CS"),
+    ("🇬🇷", "EL"),
+    ("🇹🇷", "TR"),
+    ("🇺🇦", "UK"),
+    ("🇷🇺", "RU"),
+    ("🇯🇵", "JA"),
+    ("🇰🇷", "KO"),
+    ("🇨🇳", "ZH"),
+];
+
+#[derive(Deserialize)]
+struct Translations {
+    translations: Vec<Translation>,
+}
+
+#[derive(Deserialize)]
+struct Translation {
+    detected_source_language: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    character_count: u64,
+    character_limit: u64,
+}
+
+pub struct Translate {
+    client: Client,
+    api_key: String,
+    // last translation requested by each user and in each channel
+    user_cooldowns: Mutex<HashMap<UserId, Instant>>,
+    channel_cooldowns: Mutex<HashMap<ChannelId, Instant>>,
+}
+
+fn target_language(emoji: &ReactionType) -> Option<&'static str> {
+    let ReactionType::Unicode(emoji) = emoji else {
+        return None;
+    };
+    FLAGS
+        .iter()
+        .find(|(flag, _)| flag == emoji)
+        .map(|&(_, lang)| lang)
+}
+
+// Text to translate, the first embed's description for messages without contents (e.g.
+// quotes)
+fn message_text(msg: &Message) -> Option<&str> {
+    Some(msg.content.as_str())
+        .filter(|text| !text.trim().is_empty())
+        .or_else(|| msg.embeds.first()?.description.as_deref())
+}
+
+// Take a slot in a cooldown map, false if the key is still cooling down
+fn take_cooldown<K: std::hash::Hash + Eq>(
+    cooldowns: &Mutex<HashMap<K, Instant>>,
+    key: K,
+    cooldown: Duration,
+) -> bool {
+    let now = Instant::now();
+    let mut cooldowns = cooldowns.lock().unwrap();
+    cooldowns.retain(|_, last| now.duration_since(*last) < cooldown);
+    if cooldowns.contains_key(&key) {
+        return false;
+    }
+    cooldowns.insert(key, now);
+    true
+}
+
+impl Translate {
+    pub fn new(api_key: String) -> Self {
+        Translate {
+            client: Client::new(),
+            api_key,
+            user_cooldowns: Default::default(),
+            channel_cooldowns: Default::default(),
+        }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let api_key =
+            std::env::var(API_KEY_VAR).with_context(|| format!("{API_KEY_VAR} is not set"))?;
+        Ok(Translate::new(api_key))
+    }
+
+    // Whether a user can request a translation in a channel now, both are rate limited
+    fn allowed(&self, user_id: UserId, channel_id: ChannelId) -> bool {
+        take_cooldown(&self.user_cooldowns, user_id, USER_COOLDOWN)
+            && take_cooldown(&self.channel_cooldowns, channel_id, CHANNEL_COOLDOWN)
+    }
+
+    // Returns the detected source language and the translation
+    async fn translate(&self, text: &str, target: &str) -> anyhow::Result<(String, String)> {
+        let url = match self.api_key.ends_with(":fx") {
+            true => FREE_API_URL,
+            false => API_URL,
+        };
+        let resp: Translations = self
+            .client
+            .post(url)
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+            .form(&[("text", text), ("target_lang", target)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let translation = resp
+            .translations
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No translation returned"))?;
+        Ok((translation.detected_source_language, translation.text))
+    }
+
+    // Characters translated and the plan's limit for the current billing period
+    async fn usage(&self) -> anyhow::Result<(u64, u64)> {
+        let url = match self.api_key.ends_with(":fx") {
+            true => FREE_USAGE_URL,
+            false => USAGE_URL,
+        };
+        let usage: Usage = self
+            .client
+            .get(url)
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok((usage.character_count, usage.character_limit))
+    }
+
+    // Translate a text, using the cached translation if it was already requested
+    pub async fn translate_cached(
+        &self,
+        db: &tokio::sync::Mutex<Db>,
+        text: &str,
+        target: &str,
+    ) -> anyhow::Result<(String, String)> {
+        let cached = db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT source, translation FROM translation_cache
+                 WHERE text = ?1 AND target = ?2",
+                params![text, target],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+        let (source, translation) = self.translate(text, target).await?;
+        db.lock().await.conn.execute(
+            "INSERT INTO translation_cache (text, target, source, translation, ts)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(text, target) DO UPDATE SET source = ?3, translation = ?4, ts = ?5",
+            params![
+                text,
+                target,
+                source,
+                translation,
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        Ok((source, translation))
+    }
+}
+
+// Reply to a message with its translation when it gets a flag reaction, in guilds that
+// enabled translations
+pub async fn translate_reaction(
+    handler: &Handler,
+    ctx: &Context,
+    react: &Reaction,
+) -> anyhow::Result<()> {
+    let (Some(guild_id), Some(user_id)) = (react.guild_id, react.user_id) else {
+        return Ok(());
+    };
+    let Some(target) = target_language(&react.emoji) else {
+        return Ok(());
+    };
+    if Some(&user_id) == handler.self_id.get()
+        || !handler
+            .get_guild_field::<bool>(guild_id, "translations")
+            .await?
+    {
+        return Ok(());
+    }
+    let msg = react.message(&ctx.http).await?;
+    // checked before the cooldowns, which reactions on empty messages shouldn't use up
+    let Some(text) = message_text(&msg) else {
+        return Ok(());
+    };
+    let translate = handler.module::<Translate>()?;
+    if !translate.allowed(user_id, react.channel_id) {
+        return Ok(());
+    }
+    let (source, mut translation) = translate
+        .translate_cached(&handler.db, text, target)
+        .await?;
+    if translation.len() > MAX_TRANSLATION_LEN {
+        let end = translation.floor_char_boundary(MAX_TRANSLATION_LEN);
+        translation.truncate(end);
+        translation.push('…');
+    }
+    let flag = react.emoji.to_string();
+    react
+        .channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(format!("{flag} Translated from {source}:\n{translation}"))
+                .reference_message(&msg)
+                .allowed_mentions(CreateAllowedMentions::new()),
+        )
+        .await?;
+    Ok(())
+}
+
+fn on_reaction_add<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    event: &'a ReactionAdd,
+) -> BoxFuture<'a, anyhow::Result<()>> {
+    translate_reaction(handler, ctx, &event.0).boxed()
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "translations",
+    desc = "Translate messages when they get a flag reaction",
+    guild_only
+)]
+pub struct SetTranslations {
+    enabled: bool,
+}
+
+#[async_trait]
+impl GuildCommand for SetTranslations {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run_in_guild(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        command: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse> {
+        handler
+            .set_guild_field(guild_id, command.user.id, "translations", self.enabled)
+            .await
+            .context("updating 'translations' guild field")?;
+        CommandResponse::private(if self.enabled {
+            "Will translate messages reacted to with a flag"
+        } else {
+            "Will not translate messages anymore"
+        })
+    }
+}
+
+#[async_trait]
+impl Module for Translate {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Translate::from_env()
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.add_guild_field("translations", "BOOLEAN NOT NULL DEFAULT(false)")?;
+        Ok(())
+    }
+
+    const NAME: &'static str = "Translate";
+    const MIGRATIONS: &'static [Migration] = &[Migration::sql(
+        1,
+        "create translation_cache",
+        "CREATE TABLE IF NOT EXISTS translation_cache (
+            text STRING NOT NULL,
+            target STRING NOT NULL,
+            source STRING NOT NULL,
+            translation STRING NOT NULL,
+            ts INTEGER NOT NULL,
+            UNIQUE(text, target)
+        )",
+    )];
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<SetTranslations>();
+    }
+
+    fn register_gateway_handlers(&self, handlers: &mut GatewayHandlers) {
+        handlers.add(on_reaction_add);
+    }
+
+    async fn health_check(&self, _handler: &Handler) -> HealthStatus {
+        match self.usage().await {
+            Ok((count, limit)) if count >= limit => {
+                HealthStatus::Degraded(format!("quota used up ({count}/{limit} characters)"))
+            }
+            Ok(_) => HealthStatus::Healthy,
+            Err(e) => HealthStatus::Degraded(format!("DeepL API unreachable: {e}")),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use chrono::Utc;
+use fallible_iterator::FallibleIterator;
+use futures::future::join_all;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::builder::{
+    CreateAttachment, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse,
+};
+use serenity::model::prelude::{CommandInteraction, GuildId, UserId};
+use serenity::{async_trait, prelude::Context};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+
+use crate::db::{Db, SqlGuildId, SqlUserId};
+use crate::modules::lastfm::{
+    create_aoty_chart, linked_username, AlbumWithImage, ChartBuilder, Period,
+};
+use crate::modules::{Lastfm, ModLp, Quotes};
+use crate::prelude::*;
+
+const YEAR_SECS: i64 = 365 * 24 * 3600;
+const TOP_ALBUMS: usize = 9;
+const LISTED: usize = 5;
+const CHART_NAME: &str = "year_in_review.png";
+
+fn quote_stats(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    since: i64,
+) -> anyhow::Result<Option<CreateEmbed>> {
+    let quotes: Vec<(u64, String)> = db
+        .conn
+        .prepare(
+            "SELECT quote_number, contents FROM quote
+             WHERE guild_id = ?1 AND author_id = ?2 AND ts >= ?3 AND deleted_at IS NULL
+             ORDER BY ts DESC",
+        )?
+        .query(params![SqlGuildId(guild_id), SqlUserId(user_id), since])?
+        .map(|row| Ok((row.get(0)?, crate::db::column_as_string(row.get_ref(1)?)?)))
+        .collect()?;
+    let Some((number, latest)) = quotes.first() else {
+        return Ok(None);
+    };
+    let excerpt = latest.chars().take(200).collect::<String>();
+    Ok(Some(CreateEmbed::new().title("Quotes").description(
+        format!(
+            "Quoted **{}** times\nLatest, #{number}:\n> {excerpt}",
+            quotes.len()
+        ),
+    )))
+}
+
+fn lp_stats(
+    db: &Db,
+    guild_id: GuildId,
+    user_id: UserId,
+    since: i64,
+) -> anyhow::Result<Option<CreateEmbed>> {
+    let lps: Vec<(i64, String)> = db
+        .conn
+        .prepare(
+            "SELECT ts, name FROM lp_history
+             WHERE guild_id = ?1 AND user_id = ?2 AND ts >= ?3
+             ORDER BY ts DESC",
+        )?
+        .query(params![SqlGuildId(guild_id), SqlUserId(user_id), since])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    if lps.is_empty() {
+        return Ok(None);
+    }
+    let recent = lps
+        .iter()
+        .take(LISTED)
+        .map(|(ts, name)| format!("<t:{ts}:d> {name}"))
+        .join("\n");
+    Ok(Some(
+        CreateEmbed::new()
+            .title("Listening parties")
+            .description(format!(
+                "Hosted **{}** listening parties\n{recent}",
+                lps.len()
+            )),
+    ))
+}
+
+// Top albums of the last 12 months, along with a chart of their covers
+async fn lastfm_stats(
+    lastfm: Arc<Lastfm>,
+    username: &str,
+) -> anyhow::Result<Option<(CreateEmbed, Vec<u8>)>> {
+    let top = lastfm
+        .get_top_albums(username.to_string(), None, Some(Period::Year))
+        .await?;
+    let albums = top.album.into_iter().take(TOP_ALBUMS).collect_vec();
+    if albums.is_empty() {
+        return Ok(None);
+    }
+    let listed = albums
+        .iter()
+        .take(LISTED)
+        .enumerate()
+        .map(|(i, ab)| {
+            format!(
+                "{}. {} - {} ({} plays)",
+                i + 1,
+                ab.artist.name,
+                ab.name,
+                ab.playcount
+            )
+        })
+        .join("\n");
+    let with_images = join_all(albums.into_iter().map(AlbumWithImage::fetch))
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let chart = create_aoty_chart(&with_images, ChartBuilder::new()).await?;
+    let embed = CreateEmbed::new()
+        .title(format!("Top albums for {username}"))
+        .description(listed)
+        .image(format!("attachment://{CHART_NAME}"));
+    Ok(Some((embed, chart)))
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "year_in_review",
+    desc = "A look back at the last 12 months in this server"
+)]
+pub struct YearInReview {
+    #[cmd(desc = "Member to show the report for (defaults to you)")]
+    user: Option<UserId>,
+    #[cmd(desc = "Last.fm username, to include top albums (defaults to the linked account)")]
+    lastfm_user: Option<String>,
+}
+
+impl YearInReview {
+    async fn report(
+        &self,
+        handler: &Handler,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<EditInteractionResponse> {
+        let guild_id = command.guild_id()?;
+        let user_id = self.user.unwrap_or(command.user.id);
+        let since = Utc::now().timestamp() - YEAR_SECS;
+        let mut embeds = Vec::new();
+        {
+            let db = handler.db.lock().await;
+            if handler.modules.contains::<Quotes>() {
+                embeds.extend(quote_stats(&db, guild_id, user_id, since)?);
+            }
+            if handler.modules.contains::<ModLp>() {
+                embeds.extend(lp_stats(&db, guild_id, user_id, since)?);
+            }
+        }
+        let mut chart = None;
+        let username = match &self.lastfm_user {
+            Some(username) => Some(username.clone()),
+            // accounts are linked through the Lastfm module
+            None if handler.modules.contains::<Lastfm>() => {
+                handler
+                    .db_read(move |db| linked_username(db, user_id))
+                    .await?
+            }
+            None => None,
+        };
+        if let Some(username) = &username {
+            if let Some((embed, image)) =
+                lastfm_stats(handler.module_arc::<Lastfm>()?, username).await?
+            {
+                embeds.push(embed);
+                chart = Some(image);
+            }
+        }
+        if embeds.is_empty() {
+            anyhow::bail!("Nothing to report for <@{user_id}> this year");
+        }
+        let header = CreateEmbed::new()
+            .title("Year in review")
+            .description(format!("The last 12 months of <@{user_id}>"));
+        let mut resp =
+            EditInteractionResponse::new().embeds(std::iter::once(header).chain(embeds).collect());
+        if let Some(chart) = chart {
+            resp = resp.new_attachment(CreateAttachment::bytes(Cow::Owned(chart), CHART_NAME));
+        }
+        Ok(resp)
+    }
+}
+
+#[async_trait]
+impl BotCommand for YearInReview {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        command: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        command
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new()),
+            )
+            .await?;
+        let resp = match self.report(handler, command).await {
+            Ok(resp) => resp,
+            Err(e) => EditInteractionResponse::new().content(e.to_string()),
+        };
+        command.edit_response(&ctx.http, resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+pub struct YearReview;
+
+#[async_trait]
+impl Module for YearReview {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder.module::<Lastfm>().await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(YearReview)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _: &mut CompletionStore) {
+        store.register::<YearInReview>();
+    }
+}
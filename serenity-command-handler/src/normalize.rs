@@ -0,0 +1,73 @@
+// Normalization of artist and album names, so that names coming from different providers,
+// users and caches can be compared and used as keys.
+use itertools::Itertools;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// words indicating a reissue of an album, e.g. "Album (2011 Remaster)" or "Album - Deluxe Edition"
+const EDITION_WORDS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "deluxe",
+    "edition",
+    "expanded",
+    "anniversary",
+    "bonus",
+    "version",
+    "reissue",
+    "mono",
+    "stereo",
+];
+
+// punctuation that separates words, other punctuation is removed
+const SEPARATORS: &[char] = &['-', '_', '/', '\\', ',', ':', ';', '+', '|', '~'];
+
+// Decompose, remove accents, case fold and strip punctuation, e.g. "Beyoncé: Renaissance"
+// becomes "beyonce renaissance".
+pub fn fold(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.nfkd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ß' | 'ẞ' => out.push_str("ss"),
+            '&' => out.push_str(" and "),
+            c if c.is_alphanumeric() => out.extend(c.to_lowercase()),
+            c if c.is_whitespace() || SEPARATORS.contains(&c) => out.push(' '),
+            _ => {}
+        }
+    }
+    out.split_whitespace().join(" ")
+}
+
+fn is_edition_suffix(s: &str) -> bool {
+    fold(s).split(' ').any(|word| EDITION_WORDS.contains(&word))
+}
+
+// Remove edition suffixes, e.g. "Album (2011 Remaster)" or "Album - Deluxe Edition"
+pub fn strip_edition(s: &str) -> &str {
+    let mut s = s.trim();
+    loop {
+        let stripped = if let Some((start, suffix)) = s.rsplit_once(" - ") {
+            is_edition_suffix(suffix).then_some(start)
+        } else if let Some(pos) = s.rfind(['(', '[']).filter(|_| s.ends_with([')', ']'])) {
+            is_edition_suffix(&s[pos..]).then(|| &s[..pos])
+        } else {
+            None
+        };
+        match stripped {
+            Some(stripped) => s = stripped.trim(),
+            None => return s,
+        }
+    }
+}
+
+// Remove a leading "the" from a folded name, unless that is the whole name
+pub fn strip_article(s: &str) -> &str {
+    s.strip_prefix("the ").unwrap_or(s)
+}
+
+pub fn artist_key(artist: &str) -> String {
+    strip_article(&fold(artist)).to_string()
+}
+
+pub fn album_key(album: &str) -> String {
+    fold(strip_edition(album))
+}
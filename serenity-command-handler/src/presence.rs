@@ -0,0 +1,137 @@
+// Bot presence shared between modules.
+// Modules push entries under a key (e.g. one per guild with an active LP), the entries with
+// the highest priority that are currently active are shown in rotation by `presence_loop`.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serenity::gateway::ActivityData;
+use serenity::model::user::OnlineStatus;
+use serenity::prelude::Context;
+use tokio::time::interval;
+
+use crate::clock::Clock;
+
+const ROTATION_INTERVAL: Duration = Duration::from_secs(60);
+const MEMBERS_KEY: &str = "members";
+
+// Shown when nothing else is going on
+pub const PRIORITY_IDLE: i32 = 0;
+// Temporary activity, e.g. an ongoing listening party
+pub const PRIORITY_EVENT: i32 = 10;
+// Set by the bot's owner
+pub const PRIORITY_OVERRIDE: i32 = 100;
+// Shown while the bot is in maintenance mode
+pub const PRIORITY_MAINTENANCE: i32 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct PresenceEntry {
+    pub activity: ActivityData,
+    pub priority: i32,
+    pub from: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl PresenceEntry {
+    pub fn new(activity: ActivityData, priority: i32) -> Self {
+        PresenceEntry {
+            activity,
+            priority,
+            from: None,
+            until: None,
+        }
+    }
+
+    // Only show the entry during a time window, it is removed once the window is over
+    pub fn between(mut self, from: DateTime<Utc>, until: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self.until = Some(until);
+        self
+    }
+
+    fn active(&self, now: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| from <= now) && self.until.is_none_or(|until| now < until)
+    }
+}
+
+pub struct Presence {
+    entries: Mutex<BTreeMap<String, PresenceEntry>>,
+    status: Mutex<Option<OnlineStatus>>,
+    rotation: AtomicUsize,
+    clock: Arc<dyn Clock>,
+}
+
+impl Presence {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Presence {
+            entries: Default::default(),
+            status: Default::default(),
+            rotation: Default::default(),
+            clock,
+        }
+    }
+
+    pub fn set(&self, key: impl Into<String>, entry: PresenceEntry) {
+        self.entries.lock().unwrap().insert(key.into(), entry);
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn set_status(&self, status: OnlineStatus) {
+        *self.status.lock().unwrap() = Some(status);
+    }
+
+    pub fn entries(&self) -> BTreeMap<String, PresenceEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    // The activity to show now, rotating between the active entries of highest priority
+    pub fn current(&self) -> Option<ActivityData> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.until.is_none_or(|until| now < until));
+        let active = entries.values().filter(|entry| entry.active(now));
+        let priority = active.clone().map(|entry| entry.priority).max()?;
+        let top = active
+            .filter(|entry| entry.priority == priority)
+            .collect::<Vec<_>>();
+        let rotation = self.rotation.load(Ordering::Relaxed);
+        Some(top[rotation % top.len()].activity.clone())
+    }
+
+    // Send the current presence to Discord
+    pub fn refresh(&self, ctx: &Context) {
+        let status = self.status.lock().unwrap().unwrap_or(OnlineStatus::Online);
+        ctx.set_presence(self.current(), status);
+    }
+
+    fn rotate(&self, ctx: &Context) {
+        self.rotation.fetch_add(1, Ordering::Relaxed);
+        self.refresh(ctx);
+    }
+}
+
+// Rotate the presence, shows the number of members the bot serves when idle.
+// Should be spawned once the bot is ready, e.g. with the context passed to `ready`.
+#[tracing::instrument(name = "presence", skip_all)]
+pub async fn presence_loop(presence: Arc<Presence>, ctx: Context) {
+    let mut interval = interval(ROTATION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let members: u64 = ctx
+            .cache
+            .guilds()
+            .into_iter()
+            .filter_map(|guild_id| ctx.cache.guild(guild_id).map(|guild| guild.member_count))
+            .sum();
+        if members > 0 {
+            let activity = ActivityData::watching(format!("{members} members"));
+            presence.set(MEMBERS_KEY, PresenceEntry::new(activity, PRIORITY_IDLE));
+        }
+        presence.rotate(&ctx);
+    }
+}
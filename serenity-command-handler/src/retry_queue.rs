@@ -0,0 +1,212 @@
+// Retry queue for scheduled posts (birthdays, on this day...) that failed to send, e.g.
+// because of missing permissions or Discord errors.
+// Failed deliveries are stored with a kind identifying the loop that owns them and a payload
+// it can resend from. Each loop retries its own deliveries on every tick with `retry_due`,
+// backing off exponentially until the delivery expires.
+// Expired deliveries are kept for a while so admins can inspect and requeue them.
+use std::future::Future;
+
+use chrono::{DateTime, Days, TimeZone};
+use fallible_iterator::FallibleIterator;
+use rusqlite::params;
+use serenity::model::prelude::GuildId;
+
+use crate::db::{Db, SqlGuildId};
+use crate::Handler;
+
+const BASE_DELAY_SECS: i64 = 3600;
+const MAX_BACKOFF_SHIFT: u32 = 6;
+const KEEP_EXPIRED_SECS: i64 = 7 * 24 * 3600;
+// How long a requeued delivery has to succeed if it had already expired
+pub const REQUEUE_VALIDITY_SECS: i64 = 24 * 3600;
+
+#[derive(Debug)]
+pub struct Delivery {
+    pub id: i64,
+    pub guild_id: GuildId,
+    pub kind: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub next_attempt: i64,
+    pub expires_at: i64,
+    pub last_error: String,
+}
+
+impl Delivery {
+    pub fn expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+const COLUMNS: &str = "id, guild_id, kind, payload, attempts, next_attempt, expires_at, last_error";
+
+fn delivery_from_row(row: &rusqlite::Row) -> rusqlite::Result<Delivery> {
+    Ok(Delivery {
+        id: row.get(0)?,
+        guild_id: row.get::<_, SqlGuildId>(1)?.0,
+        kind: row.get(2)?,
+        payload: row.get(3)?,
+        attempts: row.get(4)?,
+        next_attempt: row.get(5)?,
+        expires_at: row.get(6)?,
+        last_error: row.get(7)?,
+    })
+}
+
+impl Db {
+    pub fn create_retry_queue(&self) -> anyhow::Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS delivery_retry (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                kind STRING NOT NULL,
+                payload STRING NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT(1),
+                next_attempt INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL,
+                last_error STRING NOT NULL,
+                UNIQUE(guild_id, kind, payload)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // Record a failed delivery, to be retried until `expires_at` (unix timestamp)
+    pub fn queue_retry(
+        &self,
+        guild_id: GuildId,
+        kind: &str,
+        payload: &str,
+        expires_at: i64,
+        error: &anyhow::Error,
+        now: i64,
+    ) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT INTO delivery_retry
+                (guild_id, kind, payload, next_attempt, expires_at, last_error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(guild_id, kind, payload) DO UPDATE
+             SET attempts = 1, next_attempt = ?4, expires_at = ?5, last_error = ?6",
+            params![
+                SqlGuildId(guild_id),
+                kind,
+                payload,
+                now + BASE_DELAY_SECS,
+                expires_at,
+                format!("{error:#}")
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Deliveries of a kind that should be attempted again now
+    pub fn due_retries(&self, kind: &str, now: i64) -> anyhow::Result<Vec<Delivery>> {
+        self.conn.execute(
+            "DELETE FROM delivery_retry WHERE expires_at < ?1",
+            [now - KEEP_EXPIRED_SECS],
+        )?;
+        let deliveries = self
+            .conn
+            .prepare(&format!(
+                "SELECT {COLUMNS} FROM delivery_retry
+                 WHERE kind = ?1 AND next_attempt <= ?2 AND expires_at > ?2"
+            ))?
+            .query(params![kind, now])?
+            .map(delivery_from_row)
+            .collect()?;
+        Ok(deliveries)
+    }
+
+    pub fn retry_succeeded(&self, id: i64) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM delivery_retry WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    pub fn retry_failed(
+        &self,
+        delivery: &Delivery,
+        error: &anyhow::Error,
+        now: i64,
+    ) -> anyhow::Result<()> {
+        let delay = BASE_DELAY_SECS << delivery.attempts.min(MAX_BACKOFF_SHIFT);
+        self.conn.execute(
+            "UPDATE delivery_retry
+             SET attempts = attempts + 1, next_attempt = ?2, last_error = ?3
+             WHERE id = ?1",
+            params![delivery.id, now + delay, format!("{error:#}")],
+        )?;
+        Ok(())
+    }
+
+    // Pending and expired deliveries of a guild, most recently failed first
+    pub fn guild_retries(&self, guild_id: GuildId) -> anyhow::Result<Vec<Delivery>> {
+        let deliveries = self
+            .conn
+            .prepare(&format!(
+                "SELECT {COLUMNS} FROM delivery_retry WHERE guild_id = ?1
+                 ORDER BY next_attempt DESC"
+            ))?
+            .query([SqlGuildId(guild_id)])?
+            .map(delivery_from_row)
+            .collect()?;
+        Ok(deliveries)
+    }
+
+    // Attempt a delivery again on the next tick, extending its validity if it had expired.
+    // Returns false if there is no such delivery in the guild.
+    pub fn requeue_retry(&self, guild_id: GuildId, id: i64, now: i64) -> anyhow::Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE delivery_retry
+             SET next_attempt = ?3, expires_at = max(expires_at, ?4)
+             WHERE id = ?1 AND guild_id = ?2",
+            params![id, SqlGuildId(guild_id), now, now + REQUEUE_VALIDITY_SECS],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+// Expiry for deliveries that only make sense on the day they were scheduled
+pub fn end_of_day<Tz: TimeZone>(now: DateTime<Tz>) -> i64 {
+    now.date_naive()
+        .checked_add_days(Days::new(1))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| now.timezone().from_local_datetime(&midnight).earliest())
+        .map(|midnight| midnight.timestamp())
+        .unwrap_or_else(|| now.timestamp() + 24 * 3600)
+}
+
+// Attempt the due deliveries of a kind again with `send`, given their guild and payload.
+// Meant to be called from the loop that owns them on every tick.
+pub async fn retry_due<F, Fut>(handler: &Handler, kind: &str, send: F)
+where
+    F: Fn(GuildId, String) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let now = handler.clock.now().timestamp();
+    let due = match handler.db.lock().await.due_retries(kind, now) {
+        Ok(due) => due,
+        Err(e) => {
+            tracing::error!("Error retrieving {kind} retries: {e:?}");
+            return;
+        }
+    };
+    for delivery in due {
+        let res = send(delivery.guild_id, delivery.payload.clone()).await;
+        let db = handler.db.lock().await;
+        let updated = match res {
+            Ok(()) => db.retry_succeeded(delivery.id),
+            Err(e) => {
+                tracing::error!(
+                    "Retry {} of {kind} delivery failed: {e:?}",
+                    delivery.attempts
+                );
+                db.retry_failed(&delivery, &e, handler.clock.now().timestamp())
+            }
+        };
+        if let Err(e) = updated {
+            tracing::error!("Error updating {kind} retry: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// Jobs run at set times, e.g. every day at 10:00, instead of modules checking the hour in
+// loops of their own.
+// Modules register jobs in `Module::register_jobs`, and the bot starts the scheduler once:
+//
+//     handler.spawn_task("scheduler", scheduler::run(Arc::clone(&handler), ctx.http.clone()));
+//
+// Jobs can run once per guild, for the guilds listed by a query, at the scheduled time in the
+// guild's timezone (`utc_offset`, set with /date_format) or the bot's own timezone if it has
+// none. The last time each job
+// was due is saved: after downtime, a job that was due in the meantime runs as soon as the
+// bot is back, once, however many runs were missed. Only the instance holding the
+// scheduler's lease runs jobs.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, Offset, TimeZone, Utc};
+use fallible_iterator::FallibleIterator;
+use futures::future::BoxFuture;
+use rusqlite::params;
+use serenity::http::Http;
+use serenity::model::prelude::GuildId;
+use tokio::time::interval;
+
+use crate::db::{Db, SqlGuildId};
+use crate::lease::holds_lease;
+use crate::Handler;
+
+// Jobs can't run more often than this, e.g. blind LPs are revealed within 15 seconds
+const TICK: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    Daily(NaiveTime),
+    // at a fixed interval, e.g. to retry failed deliveries
+    Every(Duration),
+}
+
+impl Schedule {
+    pub fn daily_at(hour: u32, minute: u32) -> Self {
+        Schedule::Daily(NaiveTime::from_hms_opt(hour, minute, 0).expect("invalid time of day"))
+    }
+
+    pub fn every(period: Duration) -> Self {
+        Schedule::Every(period)
+    }
+
+    // Latest time the job was due at or before `now`, daily times being in `offset`
+    fn last_due(&self, now: DateTime<Utc>, offset: FixedOffset) -> DateTime<Utc> {
+        match *self {
+            Schedule::Daily(time) => {
+                let local = now.with_timezone(&offset).naive_local();
+                let mut due = local.date().and_time(time);
+                if due > local {
+                    due -= chrono::Duration::days(1);
+                }
+                due.and_utc() - chrono::Duration::seconds(offset.local_minus_utc() as i64)
+            }
+            Schedule::Every(period) => {
+                let period = period.as_secs().max(1) as i64;
+                let ts = now.timestamp();
+                DateTime::from_timestamp(ts - ts.rem_euclid(period), 0).unwrap_or(now)
+            }
+        }
+    }
+}
+
+// A run of a job, for a guild if the job runs per guild
+#[derive(Debug, Clone, Copy)]
+pub struct JobRun {
+    pub guild_id: Option<GuildId>,
+    // when the run was due, in the guild's timezone
+    pub due: DateTime<FixedOffset>,
+}
+
+pub type JobFn = for<'a> fn(&'a Handler, &'a Http, JobRun) -> BoxFuture<'a, anyhow::Result<()>>;
+
+pub struct Job {
+    pub name: &'static str,
+    pub schedule: Schedule,
+    // query listing the ids of the guilds to run the job for
+    pub guilds: Option<&'static str>,
+    pub run: JobFn,
+}
+
+impl Job {
+    pub fn new(name: &'static str, schedule: Schedule, run: JobFn) -> Self {
+        Job {
+            name,
+            schedule,
+            guilds: None,
+            run,
+        }
+    }
+
+    // Run once for each guild returned by `guilds`, e.g. "SELECT DISTINCT guild_id FROM bdays",
+    // at the scheduled time in the guild's timezone
+    pub fn per_guild(mut self, guilds: &'static str) -> Self {
+        self.guilds = Some(guilds);
+        self
+    }
+}
+
+pub type JobStore = Vec<Job>;
+
+impl Db {
+    pub fn create_scheduler_tables(&mut self) -> anyhow::Result<()> {
+        self.create_lease_table()?;
+        self.add_guild_field("utc_offset", "INTEGER")?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS scheduled_job (
+                job STRING NOT NULL,
+                guild_id INTEGER NOT NULL,
+                last_due INTEGER NOT NULL,
+                UNIQUE(job, guild_id)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // When a job was last due, by guild id (0 for jobs not run per guild)
+    fn job_runs(&self, job: &str) -> anyhow::Result<HashMap<i64, i64>> {
+        let runs = self
+            .conn
+            .prepare("SELECT guild_id, last_due FROM scheduled_job WHERE job = ?1")?
+            .query([job])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        Ok(runs)
+    }
+
+    fn set_job_run(&self, job: &str, guild_id: i64, due: i64) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT INTO scheduled_job (job, guild_id, last_due) VALUES (?1, ?2, ?3)
+             ON CONFLICT(job, guild_id) DO UPDATE SET last_due = ?3",
+            params![job, guild_id, due],
+        )?;
+        Ok(())
+    }
+
+    // Guilds listed by a query, with their offset from UTC in minutes if they set one
+    fn guild_offsets(&self, guilds: &str) -> anyhow::Result<Vec<(GuildId, Option<i32>)>> {
+        let guilds = self
+            .conn
+            .prepare(&format!(
+                "WITH ids(id) AS ({guilds})
+                 SELECT ids.id, guild.utc_offset FROM ids LEFT JOIN guild ON guild.id = ids.id"
+            ))?
+            .query([])?
+            .map(|row| Ok((row.get::<_, SqlGuildId>(0)?.0, row.get(1)?)))
+            .collect()?;
+        Ok(guilds)
+    }
+}
+
+async fn run_due(handler: &Handler, http: &Http, job: &Job) -> anyhow::Result<()> {
+    let now = handler.clock.now();
+    let local = Local.offset_from_utc_datetime(&now.naive_utc()).fix();
+    let targets = if let Some(guilds) = job.guilds {
+        handler
+            .db_read(move |db| db.guild_offsets(guilds))
+            .await?
+            .into_iter()
+            .map(|(guild_id, offset)| {
+                let offset = offset.and_then(|minutes| FixedOffset::east_opt(minutes * 60));
+                (Some(guild_id), offset.unwrap_or(local))
+            })
+            .collect()
+    } else {
+        vec![(None, local)]
+    };
+    let name = job.name;
+    let runs = handler.db_read(move |db| db.job_runs(name)).await?;
+    for (guild_id, offset) in targets {
+        let key = guild_id.map_or(0, |g| g.get() as i64);
+        let due = job.schedule.last_due(now, offset);
+        let last = runs.get(&key).copied();
+        if last.is_some_and(|last| last >= due.timestamp()) {
+            continue;
+        }
+        let due_ts = due.timestamp();
+        handler
+            .db_call(move |db| db.set_job_run(name, key, due_ts))
+            .await?;
+        // new jobs and guilds start from now rather than catching up
+        if last.is_none() {
+            continue;
+        }
+        let run = JobRun {
+            guild_id,
+            due: due.with_timezone(&offset),
+        };
+        if let Err(e) = (job.run)(handler, http, run).await {
+            tracing::error!(job = name, ?guild_id, "Error running job: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+// Run the registered jobs when they are due, until the bot shuts down
+#[tracing::instrument(name = "scheduler", skip_all)]
+pub async fn run(handler: Arc<Handler>, http: Arc<Http>) {
+    if let Err(e) = handler.db_call(|db| db.create_scheduler_tables()).await {
+        tracing::error!("Error creating scheduler tables: {e:?}");
+        return;
+    }
+    let mut interval = interval(TICK);
+    loop {
+        interval.tick().await;
+        if !holds_lease(&handler, "scheduler", TICK).await {
+            continue;
+        }
+        for job in &handler.jobs {
+            if let Err(e) = run_due(&handler, &http, job).await {
+                tracing::error!("Error scheduling job {}: {e:?}", job.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn daily_due_in_guild_timezone() {
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let schedule = Schedule::daily_at(10, 0);
+        // 09:30 in the guild's timezone, today's run isn't due yet
+        let clock = MockClock::new(utc("2024-01-01T07:30:00Z"));
+        assert_eq!(
+            schedule.last_due(clock.now(), offset),
+            utc("2023-12-31T08:00:00Z")
+        );
+        clock.advance(ChronoDuration::minutes(30));
+        assert_eq!(
+            schedule.last_due(clock.now(), offset),
+            utc("2024-01-01T08:00:00Z")
+        );
+        clock.advance(ChronoDuration::hours(23));
+        assert_eq!(
+            schedule.last_due(clock.now(), offset),
+            utc("2024-01-01T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn interval_due_on_period_boundaries() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let schedule = Schedule::every(Duration::from_secs(15 * 60));
+        let clock = MockClock::new(utc("2024-01-01T07:52:10Z"));
+        assert_eq!(
+            schedule.last_due(clock.now(), offset),
+            utc("2024-01-01T07:45:00Z")
+        );
+        clock.set(utc("2024-01-01T08:00:00Z"));
+        assert_eq!(
+            schedule.last_due(clock.now(), offset),
+            utc("2024-01-01T08:00:00Z")
+        );
+    }
+}
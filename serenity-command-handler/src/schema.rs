@@ -0,0 +1,291 @@
+// Declarations of the core tables, and a small query builder using them.
+// Queries built from declared columns fail to compile if a column is renamed or misspelled,
+// and `Db::check_schema` compares a declaration with the database when its module is set
+// up, so that the declarations cannot silently drift from the migrated schema:
+//
+//     let sql = Select::new(&quote::TABLE)
+//         .columns([quote::quote_number, quote::contents])
+//         .eq(quote::guild_id, 1)
+//         .is_null(quote::deleted_at)
+//         .sql();
+//     // SELECT quote_number, contents FROM quote WHERE guild_id = ?1 AND deleted_at IS NULL
+//
+// Conditions the builder does not cover can be written with `filter`, formatting columns
+// into the SQL so they are still checked.
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::bail;
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+
+use crate::db::Db;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub table: &'static str,
+    pub name: &'static str,
+    pub def: &'static str,
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [Column],
+    pub constraints: &'static [&'static str],
+}
+
+impl Table {
+    pub fn create_sql(&self) -> String {
+        let defs = self
+            .columns
+            .iter()
+            .map(|c| format!("{} {}", c.name, c.def))
+            .chain(self.constraints.iter().map(|c| c.to_string()))
+            .join(",\n    ");
+        format!("CREATE TABLE IF NOT EXISTS {} (\n    {defs}\n)", self.name)
+    }
+}
+
+macro_rules! table {
+    ($(
+        $(#[$meta:meta])*
+        $table:ident {
+            $($col:ident: $def:literal),* $(,)?
+        } $([$($constraint:literal),* $(,)?])?
+    )*) => {$(
+        $(#[$meta])*
+        #[allow(non_upper_case_globals)]
+        pub mod $table {
+            use super::{Column, Table};
+
+            $(pub const $col: Column = Column {
+                table: stringify!($table),
+                name: stringify!($col),
+                def: $def,
+            };)*
+
+            pub const TABLE: Table = Table {
+                name: stringify!($table),
+                columns: &[$($col),*],
+                constraints: &[$($($constraint),*)?],
+            };
+        }
+    )*};
+}
+
+table! {
+    // Messages saved with /quote, numbered per guild
+    quote {
+        guild_id: "INTEGER",
+        channel_id: "INTEGER",
+        message_id: "INTEGER",
+        ts: "INTEGER",
+        quote_number: "INTEGER",
+        author_id: "INTEGER",
+        author_name: "STRING",
+        contents: "STRING",
+        image: "STRING",
+        deleted_at: "INTEGER",
+    } ["UNIQUE(guild_id, quote_number)", "UNIQUE(guild_id, message_id)"]
+
+    // Reaction counts of quoted messages, refreshed in the background by /quote_stats
+    quote_reactions {
+        guild_id: "INTEGER NOT NULL",
+        quote_number: "INTEGER NOT NULL",
+        total: "INTEGER NOT NULL DEFAULT(0)",
+        top_emoji: "STRING",
+        refreshed_at: "INTEGER NOT NULL",
+    } ["UNIQUE(guild_id, quote_number)"]
+
+    bdays {
+        guild_id: "INTEGER NOT NULL",
+        user_id: "INTEGER NOT NULL",
+        day: "INTEGER NOT NULL",
+        month: "INTEGER NOT NULL",
+        year: "INTEGER",
+    } ["UNIQUE(guild_id, user_id)"]
+
+    autoreact {
+        guild_id: "INTEGER NOT NULL",
+        trigger: "STRING NOT NULL",
+        emote: "STRING NOT NULL",
+        deleted_at: "INTEGER",
+    }
+
+    // Release years of albums, keyed by normalized artist and album names
+    album_cache {
+        artist: "STRING NOT NULL",
+        album: "STRING NOT NULL",
+        year: "INTEGER",
+        last_checked: "INTEGER",
+    } ["UNIQUE(artist, album)"]
+
+    // Only the fixed columns, settings are added by modules with `Db::add_guild_field`
+    guild {
+        id: "INTEGER PRIMARY KEY",
+        version: "INTEGER NOT NULL DEFAULT(0)",
+    }
+}
+
+// INSERT statement binding the columns to ?1, ?2...
+pub fn insert(table: &Table, columns: &[Column]) -> String {
+    columns.iter().for_each(|&c| check_column(table.name, c));
+    let names = columns.iter().map(|c| c.name).join(", ");
+    let params = (1..=columns.len()).map(|i| format!("?{i}")).join(", ");
+    format!("INSERT INTO {} ({names}) VALUES ({params})", table.name)
+}
+
+fn check_column(table: &str, column: Column) {
+    debug_assert_eq!(
+        column.table, table,
+        "column {column} used in a query on {table}"
+    );
+}
+
+pub struct Select {
+    table: &'static str,
+    columns: Vec<String>,
+    filters: Vec<String>,
+    order: Vec<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+impl Select {
+    pub fn new(table: &Table) -> Self {
+        Select {
+            table: table.name,
+            columns: Vec::new(),
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+        }
+    }
+
+    fn check(&self, column: Column) {
+        check_column(self.table, column);
+    }
+
+    pub fn columns(mut self, columns: impl IntoIterator<Item = Column>) -> Self {
+        for column in columns {
+            self.check(column);
+            self.columns.push(column.name.to_string());
+        }
+        self
+    }
+
+    pub fn count(mut self) -> Self {
+        self.columns.push("COUNT(*)".to_string());
+        self
+    }
+
+    // `column = ?param`
+    pub fn eq(mut self, column: Column, param: usize) -> Self {
+        self.check(column);
+        self.filters.push(format!("{column} = ?{param}"));
+        self
+    }
+
+    // `column = ?param`, or no condition if the parameter is NULL
+    pub fn eq_opt(mut self, column: Column, param: usize) -> Self {
+        self.check(column);
+        self.filters
+            .push(format!("(?{param} IS NULL OR {column} = ?{param})"));
+        self
+    }
+
+    // `column` contains the text bound to `?param`
+    pub fn contains(mut self, column: Column, param: usize) -> Self {
+        self.check(column);
+        self.filters
+            .push(format!("{column} LIKE '%'||?{param}||'%'"));
+        self
+    }
+
+    pub fn is_null(mut self, column: Column) -> Self {
+        self.check(column);
+        self.filters.push(format!("{column} IS NULL"));
+        self
+    }
+
+    pub fn filter(mut self, condition: impl Into<String>) -> Self {
+        self.filters.push(condition.into());
+        self
+    }
+
+    pub fn order_by(mut self, column: Column, desc: bool) -> Self {
+        self.check(column);
+        let dir = if desc { "DESC" } else { "ASC" };
+        self.order.push(format!("{column} {dir}"));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn sql(&self) -> String {
+        let columns = match self.columns.as_slice() {
+            [] => "*".to_string(),
+            columns => columns.join(", "),
+        };
+        let mut sql = format!("SELECT {columns} FROM {}", self.table);
+        if !self.filters.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.filters.join(" AND "));
+        }
+        if !self.order.is_empty() {
+            sql.push_str(" ORDER BY ");
+            sql.push_str(&self.order.join(", "));
+        }
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+        if let Some(offset) = self.offset {
+            sql.push_str(&format!(" OFFSET {offset}"));
+        }
+        sql
+    }
+}
+
+impl Db {
+    // Create a declared table if it does not exist yet
+    pub fn create_table(&self, table: &Table) -> anyhow::Result<()> {
+        self.conn.execute(&table.create_sql(), [])?;
+        Ok(())
+    }
+
+    // Fail if columns of a declared table are missing from the database, e.g. because a
+    // migration was forgotten
+    pub fn check_schema(&self, table: &Table) -> anyhow::Result<()> {
+        let existing: HashSet<String> = self
+            .conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")?
+            .query([table.name])?
+            .map(|row| row.get(0))
+            .collect()?;
+        let missing = table
+            .columns
+            .iter()
+            .filter(|c| !existing.contains(c.name))
+            .map(|c| c.name)
+            .join(", ");
+        if !missing.is_empty() {
+            bail!("Table {} is missing columns: {missing}", table.name);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+// Soft deletion with a time-limited "Undo" button, for destructive commands.
+// A table opts in with `Db::add_soft_delete`, which adds a nullable `deleted_at` column.
+// Queries reading the table must then filter on `deleted_at IS NULL`.
+// Rows stay restorable for UNDO_WINDOW_SECS and are purged on the next deletion after that.
+use anyhow::{anyhow, bail};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::model::application::{ButtonStyle, ComponentInteraction};
+use serenity::model::prelude::GuildId;
+use serenity::prelude::Context;
+
+use crate::db::{Db, SqlGuildId};
+use crate::Handler;
+
+pub const UNDO_WINDOW_SECS: i64 = 600;
+
+const UNDO_PREFIX: &str = "undo:";
+
+impl Db {
+    pub fn add_soft_delete(&mut self, table: &str) -> anyhow::Result<()> {
+        let exists: bool = self.conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = 'deleted_at'",
+            [table],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN deleted_at INTEGER"),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Mark the rows of `table` matching `filter` as deleted.
+    // Returns the undo token identifying this deletion, or None if no rows matched.
+    pub fn soft_delete<P: rusqlite::Params>(
+        &mut self,
+        table: &str,
+        filter: &str,
+        params: P,
+    ) -> anyhow::Result<Option<i64>> {
+        // deletion time in milliseconds, doubles as the undo token
+        let token = chrono::Utc::now().timestamp_millis();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE deleted_at < ?1"),
+            [token - UNDO_WINDOW_SECS * 1000],
+        )?;
+        let deleted = tx.execute(
+            &format!(
+                "UPDATE {table} SET deleted_at = {token} WHERE deleted_at IS NULL AND ({filter})"
+            ),
+            params,
+        )?;
+        tx.commit()?;
+        Ok((deleted > 0).then_some(token))
+    }
+
+    // Restore the rows removed by a deletion, returns the number of restored rows
+    pub fn restore_deleted(
+        &mut self,
+        table: &str,
+        guild_id: GuildId,
+        token: i64,
+    ) -> anyhow::Result<usize> {
+        Ok(self.conn.execute(
+            &format!(
+                "UPDATE {table} SET deleted_at = NULL WHERE guild_id = ?1 AND deleted_at = ?2"
+            ),
+            rusqlite::params![SqlGuildId(guild_id), token],
+        )?)
+    }
+}
+
+// Private confirmation message for a deletion, with a button to undo it
+pub fn undo_response(
+    table: &str,
+    guild_id: GuildId,
+    token: i64,
+    content: impl Into<String>,
+) -> CreateInteractionResponse {
+    let button = CreateButton::new(format!("{UNDO_PREFIX}{table}:{guild_id}:{token}"))
+        .label("Undo")
+        .style(ButtonStyle::Secondary);
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .components(vec![CreateActionRow::Buttons(vec![button])])
+            .ephemeral(true),
+    )
+}
+
+// Handle a click on an undo button created for `table`.
+// Returns Ok(Some(n)) with the number of restored rows if the interaction was for this table,
+// so that modules can refresh their caches, and Ok(None) otherwise.
+pub async fn handle_undo(
+    handler: &Handler,
+    ctx: &Context,
+    component: &ComponentInteraction,
+    table: &str,
+) -> anyhow::Result<Option<usize>> {
+    let Some(rest) = component
+        .data
+        .custom_id
+        .strip_prefix(UNDO_PREFIX)
+        .and_then(|rest| rest.strip_prefix(table))
+        .and_then(|rest| rest.strip_prefix(':'))
+    else {
+        return Ok(None);
+    };
+    let (guild_id, token) = rest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid undo id: {rest}"))?;
+    let guild_id = GuildId::new(guild_id.parse()?);
+    let token: i64 = token.parse()?;
+    if component.guild_id != Some(guild_id) {
+        bail!("undo used outside of its guild");
+    }
+    let expired = chrono::Utc::now().timestamp_millis() - token > UNDO_WINDOW_SECS * 1000;
+    let restored = if expired {
+        0
+    } else {
+        handler
+            .db
+            .lock()
+            .await
+            .restore_deleted(table, guild_id, token)?
+    };
+    let content = if restored > 0 {
+        "Deletion undone"
+    } else {
+        "Too late to undo this deletion"
+    };
+    let msg = CreateInteractionResponseMessage::new()
+        .content(content)
+        .components(Vec::new());
+    component
+        .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(msg))
+        .await?;
+    Ok(Some(restored))
+}
@@ -0,0 +1,87 @@
+// Background tasks of the bot, stopped together by `Handler::shutdown`.
+// Loops and poll tasks started with `Handler::spawn_task` are cancelled at their next await
+// point on shutdown. Commands being run are waited for, and new ones are turned away with a
+// notice while the bot stops.
+//
+//     handler.spawn_task("scheduler", scheduler::run(Arc::clone(&handler), http));
+//     ...
+//     handler.shutdown(Duration::from_secs(30)).await;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::Handler;
+
+pub(crate) const SHUTDOWN_NOTICE: &str = "The bot is restarting, please try again in a moment";
+
+#[derive(Default)]
+pub struct TaskManager {
+    cancel: CancellationToken,
+    background: TaskTracker,
+    commands: TaskTracker,
+}
+
+impl TaskManager {
+    // Spawn a task that stops when the bot shuts down
+    pub fn spawn<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let cancel = self.cancel.clone();
+        self.background.spawn(async move {
+            tokio::select! {
+                _ = task => (),
+                _ = cancel.cancelled() => tracing::debug!("stopped {name}"),
+            }
+        });
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // Run a command, tracked so that shutdown waits for it
+    pub(crate) async fn track_command<F: Future>(&self, command: F) -> F::Output {
+        self.commands.track_future(command).await
+    }
+}
+
+impl Handler {
+    pub fn spawn_task<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(name, task)
+    }
+
+    // Stop background tasks, wait up to `grace` for commands being run, then flush the
+    // database and release this instance's loop leases so another instance takes over
+    // right away
+    pub async fn shutdown(&self, grace: Duration) -> anyhow::Result<()> {
+        let tasks = &self.tasks;
+        tasks.cancel.cancel();
+        tasks.background.close();
+        tasks.commands.close();
+        let stopped = async {
+            tasks.background.wait().await;
+            tasks.commands.wait().await;
+        };
+        if timeout(grace, stopped).await.is_err() {
+            tracing::warn!(
+                "{} commands still running after {grace:?}, shutting down anyway",
+                tasks.commands.len()
+            );
+        }
+        self.db_call(|db| {
+            db.release_leases()?;
+            // move the write-ahead log into the database file
+            db.conn
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,181 @@
+// Parsing of user-supplied times, e.g. for scheduling listening parties.
+// Times without a timezone are interpreted in the server's local time.
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use regex::Regex;
+
+pub const ACCEPTED_FORMATS: &str = "Accepted formats: `now`, `XX:20` (next time the clock \
+    shows :20), `+5` or `in 1h15` (from now), `21:30` or `9pm` (server time), `21:30 CET` or \
+    `21:30 UTC+2`, and ISO dates such as `2024-06-01T21:30:00+02:00`";
+
+// Relative times further away than this are most likely typos
+const MAX_RELATIVE: i64 = 7 * 24 * 60;
+
+// Offsets in minutes of common timezone abbreviations
+const TIMEZONES: &[(&str, i32)] = &[
+    ("utc", 0),
+    ("gmt", 0),
+    ("z", 0),
+    ("wet", 0),
+    ("west", 60),
+    ("bst", 60),
+    ("cet", 60),
+    ("cest", 120),
+    ("eet", 120),
+    ("eest", 180),
+    ("msk", 180),
+    ("ist", 330),
+    ("jst", 540),
+    ("aest", 600),
+    ("aedt", 660),
+    ("est", -300),
+    ("edt", -240),
+    ("cst", -360),
+    ("cdt", -300),
+    ("mst", -420),
+    ("mdt", -360),
+    ("pst", -480),
+    ("pdt", -420),
+];
+
+fn invalid(input: &str) -> anyhow::Error {
+    anyhow!("Invalid time `{input}`. {ACCEPTED_FORMATS}")
+}
+
+// Parse a time, relative to `now`. Times of day always refer to their next occurrence.
+pub fn parse_time(input: &str, now: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+    let trimmed = input.trim();
+    let lower = trimmed.to_lowercase();
+    if lower == "now" {
+        return Ok(now);
+    }
+    if let Some(time) = parse_absolute_date(trimmed)? {
+        if time < now - Duration::minutes(1) {
+            bail!("{time} is in the past");
+        }
+        return Ok(time);
+    }
+    let xx_re = Regex::new("^(xx:?)?([0-5][0-9])$")?; // e.g. XX:15, xx15 or 15
+    if let Some(cap) = xx_re.captures(&lower) {
+        let min: i64 = cap[2].parse()?;
+        let cur_min = now.minute() as i64;
+        let to_add = if cur_min <= min {
+            min - cur_min
+        } else {
+            (60 - cur_min) + min
+        };
+        return Ok(now + Duration::minutes(to_add));
+    }
+    if let Some(minutes) = parse_relative(&lower) {
+        if minutes > MAX_RELATIVE {
+            bail!("`{trimmed}` is too far in the future");
+        }
+        return Ok(now + Duration::minutes(minutes));
+    }
+    parse_time_of_day(&lower, now)?.ok_or_else(|| invalid(trimmed))
+}
+
+// Full dates, e.g. 2024-06-01T21:30:00+02:00 or 2024-06-01 21:30 (server time)
+fn parse_absolute_date(input: &str) -> anyhow::Result<Option<DateTime<Utc>>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    for fmt in ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(input, fmt) {
+            let local = naive
+                .and_local_timezone(Local)
+                .earliest()
+                .ok_or_else(|| anyhow!("`{input}` does not exist in server time"))?;
+            return Ok(Some(local.with_timezone(&Utc)));
+        }
+    }
+    Ok(None)
+}
+
+// Durations such as +5, 5m, in 1h15 or in 2 hours, in minutes
+fn parse_relative(input: &str) -> Option<i64> {
+    let re = Regex::new(
+        r"^(in\s+|\+\s*)?(?:(\d+)\s*h(?:ours?|rs?)?)?\s*(?:(\d+)\s*(?:m|mins?|minutes?)?)?$",
+    )
+    .unwrap();
+    let cap = re.captures(input)?;
+    let hours = cap
+        .get(2)
+        .map(|m| m.as_str().parse::<i64>())
+        .transpose()
+        .ok()?;
+    let minutes = cap
+        .get(3)
+        .map(|m| m.as_str().parse::<i64>())
+        .transpose()
+        .ok()?;
+    if hours.is_none() && minutes.is_none() {
+        return None;
+    }
+    Some(hours.unwrap_or(0) * 60 + minutes.unwrap_or(0))
+}
+
+// Timezone abbreviation or UTC offset, e.g. cest, utc+2 or -05:30 (lowercase)
+pub fn parse_offset(tz: &str) -> Option<FixedOffset> {
+    if let Some((_, minutes)) = TIMEZONES.iter().find(|(name, _)| *name == tz) {
+        return FixedOffset::east_opt(minutes * 60);
+    }
+    let re = Regex::new(r"^(?:utc|gmt)?\s*([+-])(\d{1,2})(?::?(\d{2}))?$").unwrap();
+    let cap = re.captures(tz)?;
+    let hours: i32 = cap[2].parse().ok()?;
+    let minutes: i32 = cap.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+    let sign = if &cap[1] == "-" { -1 } else { 1 };
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+// Next occurrence of a time of day in the given timezone
+fn next_at<Tz: TimeZone>(
+    now: DateTime<Utc>,
+    tz: &Tz,
+    hour: u32,
+    minute: u32,
+) -> Option<DateTime<Utc>> {
+    let date = now.with_timezone(tz).date_naive();
+    let mut time = date
+        .and_hms_opt(hour, minute, 0)?
+        .and_local_timezone(tz.clone())
+        .earliest()?
+        .with_timezone(&Utc);
+    if time < now {
+        time += Duration::days(1);
+    }
+    Some(time)
+}
+
+// Times of day, e.g. 21:30, 9pm, 9:30 pm CEST or 21:30+02:00
+fn parse_time_of_day(input: &str, now: DateTime<Utc>) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let re = Regex::new(r"^(\d{1,2})(?::(\d{2}))?\s*(am|pm)?\s*(.*)$").unwrap();
+    let Some(cap) = re.captures(input) else {
+        return Ok(None);
+    };
+    let meridiem = cap.get(3).map(|m| m.as_str());
+    // a bare number is not a time of day
+    if cap.get(2).is_none() && meridiem.is_none() {
+        return Ok(None);
+    }
+    let mut hour: u32 = cap[1].parse()?;
+    let minute: u32 = cap.get(2).map_or(Ok(0), |m| m.as_str().parse())?;
+    if let Some(meridiem) = meridiem {
+        if !(1..=12).contains(&hour) {
+            bail!("Invalid hour `{hour}{meridiem}`");
+        }
+        hour = hour % 12 + if meridiem == "pm" { 12 } else { 0 };
+    }
+    if hour > 23 || minute > 59 {
+        bail!("Invalid time of day `{input}`");
+    }
+    let tz = cap[4].trim();
+    let time = if tz.is_empty() {
+        next_at(now, &Local, hour, minute)
+    } else {
+        let offset = parse_offset(tz).ok_or_else(|| anyhow!("Unknown timezone `{tz}`"))?;
+        next_at(now, &offset, hour, minute)
+    };
+    time.map(Some)
+        .ok_or_else(|| anyhow!("`{input}` does not exist in server time"))
+}
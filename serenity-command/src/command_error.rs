@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::NotInGuild;
+
+// Why a command failed, deciding what its user is told.
+// Commands keep returning anyhow errors, `CommandError::from` classifies them: a
+// `CommandError` returned as is, `NotInGuild`, messages from `bail!`/`anyhow!` are shown to
+// users, anything else (e.g. database or HTTP errors, with or without context) is internal.
+#[derive(Debug)]
+pub enum CommandError {
+    // Invalid input or state the user can fix, shown as is
+    UserError(String),
+    PermissionDenied(String),
+    NotInGuild,
+    // A third-party API (Spotify, Last.fm...) failed, the details are only logged
+    ExternalServiceError {
+        service: &'static str,
+        source: anyhow::Error,
+    },
+    // Bugs and failures of the bot itself, the details are only logged
+    Internal(anyhow::Error),
+}
+
+impl CommandError {
+    pub fn external(service: &'static str, source: impl Into<anyhow::Error>) -> Self {
+        CommandError::ExternalServiceError {
+            service,
+            source: source.into(),
+        }
+    }
+
+    // What the user who ran the command is told
+    pub fn user_message(&self) -> String {
+        match self {
+            CommandError::UserError(msg) | CommandError::PermissionDenied(msg) => msg.clone(),
+            CommandError::NotInGuild => NotInGuild.to_string(),
+            CommandError::ExternalServiceError { service, .. } => {
+                format!("{service} is unavailable right now, please try again later")
+            }
+            CommandError::Internal(_) => "Something went wrong while running this command".into(),
+        }
+    }
+
+    // Errors worth logging with their details, the others are expected
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            CommandError::ExternalServiceError { .. } | CommandError::Internal(_)
+        )
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::ExternalServiceError { service, source } => {
+                write!(f, "{service} error: {source:#}")
+            }
+            CommandError::Internal(e) => write!(f, "{e:#}"),
+            _ => f.write_str(&self.user_message()),
+        }
+    }
+}
+
+impl Error for CommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommandError::ExternalServiceError { source: e, .. } | CommandError::Internal(e) => {
+                Some(e.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<CommandError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        if e.is::<NotInGuild>() {
+            return CommandError::NotInGuild;
+        }
+        // errors wrapped in a context have more than one link, their message is the context
+        let is_message = e.chain().count() == 1 && (e.is::<String>() || e.is::<&'static str>());
+        if is_message {
+            CommandError::UserError(e.to_string())
+        } else {
+            CommandError::Internal(e)
+        }
+    }
+}
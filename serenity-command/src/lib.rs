@@ -1,17 +1,75 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use serenity::async_trait;
 use serenity::builder::{CreateCommand, CreateCommandOption};
-use serenity::model::application::{CommandData, CommandInteraction, CommandType};
+use serenity::model::application::{
+    CommandData, CommandDataResolved, CommandInteraction, CommandType,
+};
+use serenity::model::id::{GenericId, RoleId, UserId};
 use serenity::model::prelude::GuildId;
 use serenity::model::Permissions;
 use serenity::prelude::Context;
 
+mod command_error;
 mod command_response;
+pub use command_error::CommandError;
 pub use command_response::*;
 
 pub type CommandKey<'a> = (&'a str, CommandType);
 
+// Returned when a command marked `#[cmd(guild_only)]` is run outside of a guild
+#[derive(Debug, Clone, Copy)]
+pub struct NotInGuild;
+
+impl fmt::Display for NotInGuild {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("This command can only be used in a server")
+    }
+}
+
+impl std::error::Error for NotInGuild {}
+
+// Value of a mentionable command option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mentionable {
+    User(UserId),
+    Role(RoleId),
+}
+
+impl Mentionable {
+    // Find whether an option's id is a user or a role from the interaction's resolved data
+    pub fn resolve(id: GenericId, resolved: &CommandDataResolved) -> Option<Self> {
+        if resolved.users.contains_key(&UserId::new(id.get())) {
+            Some(Mentionable::User(UserId::new(id.get())))
+        } else if resolved.roles.contains_key(&RoleId::new(id.get())) {
+            Some(Mentionable::Role(RoleId::new(id.get())))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Mentionable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mentionable::User(id) => write!(f, "<@{id}>"),
+            Mentionable::Role(id) => write!(f, "<@&{id}>"),
+        }
+    }
+}
+
+// Fieldless enums used as string options with a fixed set of choices, implemented with
+// `#[derive(CommandChoice)]`. Command fields of these types are marked `#[cmd(choice)]`.
+pub trait CommandChoice: Sized {
+    // Names shown to users, and the values they are parsed back from
+    const CHOICES: &'static [(&'static str, &'static str)];
+
+    fn from_value(value: &str) -> Option<Self>;
+
+    fn value(&self) -> &'static str;
+}
+
 pub struct CommandStore<'a, T>(
     pub HashMap<CommandKey<'a>, Box<dyn CommandRunner<T> + Send + Sync>>,
 );
@@ -39,12 +97,67 @@ pub trait BotCommand {
         interaction: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse>;
 
+    // Called before serving autocompletions for the command, after checking that the user
+    // has its PERMISSIONS. Commands whose completions reveal data only some users may see
+    // (e.g. admin-only resources) return false to send an empty list instead.
+    async fn can_complete(
+        _data: &Self::Data,
+        _ctx: &Context,
+        _interaction: &CommandInteraction,
+    ) -> anyhow::Result<bool>
+    where
+        Self::Data: Sync,
+    {
+        Ok(true)
+    }
+
+    // Customize options beyond what the derive attributes cover, e.g. add choices computed at
+    // runtime. Min/max values, lengths and channel types are set with `#[cmd(min = 1)]`,
+    // `#[cmd(max_len = 100)]` or `#[cmd(channel_types = "text,forum")]` on the field.
     fn setup_options(_opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
         opt
     }
 
     const PERMISSIONS: Permissions = Permissions::empty();
     const GUILD: Option<GuildId> = None;
+    // Whether the command can be run without a user, e.g. by a scheduler.
+    // Such commands must not respond to the interaction themselves.
+    const SCHEDULABLE: bool = false;
+}
+
+// Implemented instead of `BotCommand` by commands marked `#[cmd(guild_only)]`, which are
+// only run once the interaction is known to come from a guild. The derive implements
+// `BotCommand` for them, with a `run` failing with `NotInGuild`.
+#[async_trait]
+pub trait GuildCommand {
+    type Data;
+    async fn run_in_guild(
+        self,
+        data: &Self::Data,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+        guild_id: GuildId,
+    ) -> anyhow::Result<CommandResponse>;
+
+    // Same as `BotCommand::can_complete`
+    async fn can_complete(
+        _data: &Self::Data,
+        _ctx: &Context,
+        _interaction: &CommandInteraction,
+    ) -> anyhow::Result<bool>
+    where
+        Self::Data: Sync,
+    {
+        Ok(true)
+    }
+
+    fn setup_options(_opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        opt
+    }
+
+    const PERMISSIONS: Permissions = Permissions::empty();
+    const GUILD: Option<GuildId> = None;
+    const SCHEDULABLE: bool = false;
 }
 
 pub trait CommandBuilder<'a>: BotCommand + From<&'a CommandData> + 'static {
@@ -55,6 +168,7 @@ pub trait CommandBuilder<'a>: BotCommand + From<&'a CommandData> + 'static {
     fn create(builder: CreateCommand) -> CreateCommand;
     const NAME: &'static str;
     const TYPE: CommandType = CommandType::ChatInput;
+    const GUILD_ONLY: bool = false;
     fn runner() -> Box<dyn CommandRunner<Self::Data> + Send + Sync>;
 }
 
@@ -66,10 +180,28 @@ pub trait CommandRunner<T> {
         ctx: &Context,
         interaction: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse>;
+    async fn can_complete(
+        &self,
+        data: &T,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<bool>;
     fn name(&self) -> CommandKey<'static>;
     fn register(&self) -> CreateCommand;
 
     fn guild(&self) -> Option<GuildId> {
         None
     }
+
+    fn permissions(&self) -> Permissions {
+        Permissions::empty()
+    }
+
+    fn schedulable(&self) -> bool {
+        false
+    }
+
+    fn guild_only(&self) -> bool {
+        false
+    }
 }